@@ -93,6 +93,24 @@ pub enum PowerType {
     #[serde(rename = "")]
     None
 }
+impl PowerType {
+    /// Returns the CIF code for this power type.
+    pub fn as_str(self) -> &'static str {
+        use self::PowerType::*;
+
+        match self {
+            Diesel => "D",
+            DieselElectricMultipleUnit => "DEM",
+            DieselMechanicalMultipleUnit => "DMU",
+            Electric => "E",
+            ElectroDiesel => "ED",
+            EmuPlusLocomotive => "EML",
+            ElectricMultipleUnit => "EMU",
+            HighSpeedTrain => "HST",
+            None => ""
+        }
+    }
+}
 #[derive(Serialize, Deserialize, Copy, Clone, Debug, is_enum_variant, Display)]
 #[cfg_attr(feature = "postgres-traits", derive(FromSql, ToSql))]
 pub enum TrainCategory {
@@ -207,3 +225,67 @@ pub enum TrainCategory {
     #[serde(rename="")]
     None
 }
+impl TrainCategory {
+    /// Returns the CIF code for this train category.
+    pub fn as_str(self) -> &'static str {
+        use self::TrainCategory::*;
+
+        match self {
+            LondonUnderground => "OL",
+            UnadvertisedOrdinaryPassenger => "OU",
+            OrdinaryPassenger => "OO",
+            StaffTrain => "OS",
+            Mixed => "OW",
+            ChannelTunnel => "XC",
+            SleeperEuropeNightServices => "XD",
+            International => "XI",
+            Motorail => "XR",
+            UnadvertisedExpress => "XU",
+            ExpressPassenger => "XX",
+            SleeperDomestic => "XZ",
+            BusReplacement => "BR",
+            Bus => "BS",
+            Ship => "SS",
+            EmptyCoachingStock => "EE",
+            EcsLondonUnderground => "EL",
+            EcsAndStaff => "ES",
+            Postal => "JJ",
+            PostOfficeControlledParcels => "PM",
+            Parcels => "PP",
+            EmptyNpccs => "PV",
+            Departmental => "DD",
+            CivilEngineer => "DH",
+            MechanicalAndElectricalEngineer => "DI",
+            Stores => "DQ",
+            Test => "DT",
+            SignalAndTelecommunicationsEngineer => "DY",
+            LocomotiveAndBrakeVan => "ZB",
+            LightLocomotive => "ZZ",
+            RfDAutomotiveComponents => "J2",
+            RfDAutomotiveVehicles => "H2",
+            RfDEdibleProducts => "J3",
+            RfDIndustrialMinerals => "J4",
+            RfDChemicals => "J5",
+            RfDBuildingMaterials => "J6",
+            RfDGeneralMerchandise => "J8",
+            RfDEuropean => "H8",
+            RfDFreightlinerContracts => "J9",
+            RfDFreightlinerOther => "H9",
+            Coal => "A0",
+            CoalMGR => "E0",
+            CoalAndNuclear => "B0",
+            Metals => "B1",
+            Aggregates => "B4",
+            DomesticandIndustrialWaste => "B5",
+            BuildingMaterials => "B6",
+            PetroleumProducts => "B7",
+            RfDEct => "H0",
+            RfDEctIntermodal => "H1",
+            RfDEctAutomotive => "H3",
+            RfDEctContractServices => "H4",
+            RfDEctHaulmark => "H5",
+            RfDEctJointVenture => "H6",
+            None => ""
+        }
+    }
+}
@@ -0,0 +1,542 @@
+//! Parsing the fixed-width CIF format (as used for `.MCA`/`.CIF` files from the
+//! RDG data feeds, as well as the NROD `CIF_ALL_FULL_DAILY` CIF-format download).
+//!
+//! Records are turned into the same `schedule::Record` values that the JSON
+//! SCHEDULE feed deserializes into, so consumers can handle both formats
+//! the same way. Field offsets are from the CIF End User Specification
+//! (version 29).
+
+use chrono::*;
+use chrono_tz::Europe::London;
+use chrono_tz::Tz;
+use serde::de::{Deserialize, IntoDeserializer};
+use serde::de::value::{StrDeserializer, Error as ValueError};
+use std::collections::VecDeque;
+use std::io::{self, BufRead};
+use std::error::Error;
+use std::fmt;
+use super::fns::*;
+use super::cif::*;
+use super::schedule::*;
+
+/// Length of a CIF record (shorter lines are padded with spaces).
+pub const RECORD_LENGTH: usize = 80;
+
+#[derive(Debug)]
+pub enum CifError {
+    /// An I/O error occurred reading the file.
+    Io(io::Error),
+    /// A line contained non-ASCII characters.
+    NotAscii { line: usize },
+    /// A field couldn't be parsed.
+    InvalidField { line: usize, field: &'static str, value: String },
+    /// A record appeared somewhere it doesn't make sense (e.g. a location
+    /// record outside of a schedule).
+    UnexpectedRecord { line: usize, record: String }
+}
+impl fmt::Display for CifError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::CifError::*;
+
+        match *self {
+            Io(ref e) => write!(f, "I/O error: {}", e),
+            NotAscii { line } => write!(f, "line {}: contains non-ASCII characters", line),
+            InvalidField { line, field, ref value } => write!(f, "line {}: invalid {}: {:?}", line, field, value),
+            UnexpectedRecord { line, ref record } => write!(f, "line {}: unexpected {} record", line, record)
+        }
+    }
+}
+impl Error for CifError {
+    fn description(&self) -> &str {
+        "CIF parse error"
+    }
+}
+impl From<io::Error> for CifError {
+    fn from(e: io::Error) -> CifError {
+        CifError::Io(e)
+    }
+}
+
+pub type CifResult<T> = Result<T, CifError>;
+
+/// A single (padded) line of a CIF file.
+struct CifLine<'a> {
+    data: &'a str,
+    line: usize
+}
+impl<'a> CifLine<'a> {
+    fn err(&self, field: &'static str, value: &str) -> CifError {
+        CifError::InvalidField { line: self.line, field, value: value.to_owned() }
+    }
+    fn raw(&self, start: usize, len: usize) -> &'a str {
+        &self.data[start..(start + len)]
+    }
+    fn opt(&self, start: usize, len: usize) -> Option<String> {
+        let ret = self.raw(start, len).trim();
+        if ret == "" { None } else { Some(ret.to_owned()) }
+    }
+    fn string(&self, field: &'static str, start: usize, len: usize) -> CifResult<String> {
+        self.opt(start, len)
+            .ok_or_else(|| self.err(field, ""))
+    }
+    /// Parses a `YYMMDD` date.
+    fn date(&self, field: &'static str, start: usize) -> CifResult<NaiveDate> {
+        let raw = self.raw(start, 6);
+        NaiveDate::parse_from_str(raw, "%y%m%d")
+            .map_err(|_| self.err(field, raw))
+    }
+    /// Parses a `DDMMYY` date (only used in the header).
+    fn header_date(&self, field: &'static str, start: usize) -> CifResult<NaiveDate> {
+        let raw = self.raw(start, 6);
+        NaiveDate::parse_from_str(raw, "%d%m%y")
+            .map_err(|_| self.err(field, raw))
+    }
+    /// Parses a working time (`HHMM`, with an optional `H` for half-minutes).
+    fn time(&self, field: &'static str, start: usize) -> CifResult<NaiveTime> {
+        let raw = self.raw(start, 5);
+        str_to_time(raw.trim())
+            .ok_or_else(|| self.err(field, raw))
+    }
+    /// Parses a public time.
+    ///
+    /// These are `0000` if there's no public time, which means we can't tell
+    /// the difference between that and a call at midnight (but neither can the
+    /// JSON feed).
+    fn public_time(&self, field: &'static str, start: usize) -> CifResult<Option<NaiveTime>> {
+        let raw = self.raw(start, 4);
+        match raw.trim() {
+            "" | "0000" => Ok(None),
+            x => str_to_time(x).map(Some).ok_or_else(|| self.err(field, raw))
+        }
+    }
+    fn days(&self, start: usize) -> CifResult<Days> {
+        let raw = self.raw(start, 7);
+        let de: StrDeserializer<ValueError> = raw.into_deserializer();
+        parse_days(de).map_err(|_| self.err("days run", raw))
+    }
+    /// Parses a field using the code in its `#[serde(rename)]` attribute.
+    ///
+    /// The field isn't trimmed, since some codes contain spaces.
+    fn code<T: Deserialize<'a>>(&self, field: &'static str, start: usize, len: usize) -> CifResult<T> {
+        let raw = self.raw(start, len);
+        let de: StrDeserializer<ValueError> = raw.into_deserializer();
+        T::deserialize(de).map_err(|_| self.err(field, raw))
+    }
+    /// Like `code`, but trims the field and returns `None` if it's blank.
+    fn code_opt<T: Deserialize<'a>>(&self, field: &'static str, start: usize, len: usize) -> CifResult<Option<T>> {
+        let raw = self.raw(start, len).trim();
+        if raw == "" {
+            return Ok(None);
+        }
+        let de: StrDeserializer<ValueError> = raw.into_deserializer();
+        T::deserialize(de).map(Some).map_err(|_| self.err(field, raw))
+    }
+}
+
+fn date_to_london(d: NaiveDate) -> Date<Tz> {
+    London.from_local_date(&d).single()
+        .expect("dates in London are never ambiguous")
+}
+
+/// Reads records out of a CIF file.
+///
+/// This is an iterator over `schedule::Record`s. A schedule is returned once
+/// all of its location records have been read; `BX` records fill in the
+/// ATOC code and applicable timetable flag, and `CR` (change en route)
+/// records are skipped, since the `ScheduleSegment` only describes the train
+/// as it leaves its origin (the JSON feed does the same).
+///
+/// Parse errors are returned for the offending line, and reading continues
+/// afterwards; if a schedule has a bad line in it, the whole schedule is
+/// skipped. I/O errors end the iteration.
+pub struct CifReader<R> {
+    inner: R,
+    buf: String,
+    line: usize,
+    /// Records ready to be returned.
+    pending: VecDeque<Record>,
+    /// The schedule we're currently reading locations for.
+    cur: Option<ScheduleRecord>,
+    /// Whether to ignore schedule records until the next `BS`.
+    skip_schedule: bool,
+    /// The user end date from the header, used for open-ended schedules.
+    user_end_date: Option<NaiveDate>,
+    done: bool
+}
+impl<R: BufRead> CifReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            buf: String::new(),
+            line: 0,
+            pending: VecDeque::new(),
+            cur: None,
+            skip_schedule: false,
+            user_end_date: None,
+            done: false
+        }
+    }
+    /// Finishes off the current schedule, if there is one.
+    fn flush(&mut self) {
+        self.skip_schedule = false;
+        if let Some(sched) = self.cur.take() {
+            self.pending.push_back(Record::Schedule(sched));
+        }
+    }
+    /// Parses an end date, which may be `999999` for an open-ended entry.
+    fn end_date(&self, line: &CifLine, field: &'static str, start: usize) -> CifResult<NaiveDate> {
+        if line.raw(start, 6) == "999999" {
+            self.user_end_date.ok_or_else(|| line.err(field, "999999"))
+        }
+        else {
+            line.date(field, start)
+        }
+    }
+    fn header(&mut self, line: &CifLine) -> CifResult<()> {
+        let extract_date = line.header_date("date of extract", 22)?;
+        let extract_time = NaiveTime::parse_from_str(line.raw(28, 4), "%H%M")
+            .map_err(|_| line.err("time of extract", line.raw(28, 4)))?;
+        let ty = match line.raw(46, 1) {
+            "F" => "full",
+            "U" => "update",
+            x => return Err(line.err("update indicator", x))
+        };
+        self.user_end_date = Some(line.header_date("user end date", 54)?);
+        let timestamp = London.from_local_datetime(&extract_date.and_time(extract_time))
+            .earliest()
+            .ok_or_else(|| line.err("time of extract", line.raw(28, 4)))?
+            .timestamp();
+        let identity = line.string("file mainframe identity", 2, 20)?;
+        self.pending.push_back(Record::Timetable(TimetableRecord {
+            classification: "public".into(),
+            timestamp: timestamp as u32,
+            owner: identity.clone(),
+            sender: Sender {
+                organisation: identity,
+                application: "CIF".into(),
+                component: line.string("current file reference", 32, 7)?
+            },
+            metadata: TimetableMetadata {
+                ty: ty.into(),
+                // CIF files don't have a sequence number, so use the
+                // date of extract, like `TimetableRecord::file_sequence`.
+                sequence: extract_date.signed_duration_since(NaiveDate::from_ymd(1970, 1, 1)).num_days() as u32
+            }
+        }));
+        Ok(())
+    }
+    fn basic_schedule(&mut self, line: &CifLine) -> CifResult<()> {
+        let train_uid = line.string("train UID", 3, 6)?;
+        let schedule_start_date = date_to_london(line.date("date runs from", 9)?);
+        let stp_indicator = line.code_opt("STP indicator", 79, 1)?
+            .unwrap_or(StpIndicator::None);
+        match line.raw(2, 1) {
+            "D" => {
+                self.pending.push_back(Record::Schedule(ScheduleRecord::Delete {
+                    train_uid,
+                    transaction_type: DeleteType::Delete,
+                    schedule_start_date,
+                    stp_indicator
+                }));
+            },
+            // Revisions replace the whole schedule, which is what
+            // a Create with the same key does anyway.
+            "N" | "R" => {
+                let speed = match line.opt(57, 3) {
+                    Some(s) => Some(s.parse().map_err(|_| line.err("speed", &s))?),
+                    None => None
+                };
+                self.cur = Some(ScheduleRecord::Create {
+                    train_uid,
+                    transaction_type: CreateType::Create,
+                    schedule_start_date,
+                    schedule_end_date: date_to_london(self.end_date(line, "date runs to", 15)?),
+                    schedule_days_runs: line.days(21)?,
+                    bank_holiday_running: line.opt(28, 1),
+                    train_status: line.code("train status", 29, 1)?,
+                    stp_indicator,
+                    applicable_timetable: YesOrNo::default(),
+                    atoc_code: None,
+                    schedule_segment: ScheduleSegment {
+                        train_category: line.code_opt("train category", 30, 2)?,
+                        signalling_id: line.opt(32, 4),
+                        headcode: line.opt(36, 4),
+                        business_sector: line.opt(49, 1),
+                        power_type: line.code_opt("power type", 50, 3)?,
+                        timing_load: line.opt(53, 4),
+                        speed,
+                        operating_characteristics: line.opt(60, 6),
+                        train_class: line.opt(66, 1),
+                        sleepers: line.opt(67, 1),
+                        reservations: line.opt(68, 1),
+                        catering_code: line.opt(70, 4),
+                        service_branding: line.opt(74, 4),
+                        schedule_location: vec![]
+                    }
+                });
+            },
+            x => return Err(line.err("transaction type", x))
+        }
+        Ok(())
+    }
+    fn basic_schedule_extra(&mut self, line: &CifLine) -> CifResult<()> {
+        let atoc = line.opt(11, 2);
+        let applicable = line.code_opt("applicable timetable code", 13, 1)?;
+        match self.cur {
+            Some(ScheduleRecord::Create { ref mut atoc_code, ref mut applicable_timetable, .. }) => {
+                *atoc_code = atoc;
+                if let Some(a) = applicable {
+                    *applicable_timetable = a;
+                }
+                Ok(())
+            },
+            _ => Err(CifError::UnexpectedRecord { line: line.line, record: "BX".into() })
+        }
+    }
+    fn location(&mut self, ty: &str, line: &CifLine) -> CifResult<()> {
+        let tiploc_code = line.string("location", 2, 7)?;
+        let loc = match ty {
+            "LO" => LocationRecord::Originating {
+                record_identity: OriginatingLocation::Originating,
+                tiploc_code,
+                departure: line.time("scheduled departure", 10)?,
+                public_departure: line.public_time("public departure", 15)?,
+                platform: line.opt(19, 3),
+                line: line.opt(22, 3),
+                activity: line.opt(29, 12),
+                engineering_allowance: line.opt(25, 2),
+                pathing_allowance: line.opt(27, 2),
+                performance_allowance: line.opt(41, 2)
+            },
+            "LI" if line.opt(20, 5).is_some() => LocationRecord::Pass {
+                record_identity: IntermediateLocation::Intermediate,
+                tiploc_code,
+                pass: line.time("scheduled pass", 20)?,
+                engineering_allowance: line.opt(54, 2),
+                pathing_allowance: line.opt(56, 2),
+                performance_allowance: line.opt(58, 2)
+            },
+            "LI" => LocationRecord::Intermediate {
+                record_identity: IntermediateLocation::Intermediate,
+                tiploc_code,
+                arrival: line.time("scheduled arrival", 10)?,
+                departure: line.time("scheduled departure", 15)?,
+                public_arrival: line.public_time("public arrival", 25)?,
+                public_departure: line.public_time("public departure", 29)?,
+                platform: line.opt(33, 3),
+                line: line.opt(36, 3),
+                path: line.opt(39, 3),
+                activity: line.opt(42, 12),
+                engineering_allowance: line.opt(54, 2),
+                pathing_allowance: line.opt(56, 2),
+                performance_allowance: line.opt(58, 2)
+            },
+            "LT" => LocationRecord::Terminating {
+                record_identity: TerminatingLocation::Terminating,
+                tiploc_code,
+                arrival: line.time("scheduled arrival", 10)?,
+                public_arrival: line.public_time("public arrival", 15)?,
+                platform: line.opt(19, 3),
+                path: line.opt(22, 3),
+                activity: line.opt(25, 12)
+            },
+            _ => unreachable!()
+        };
+        match self.cur {
+            Some(ScheduleRecord::Create { ref mut schedule_segment, .. }) => {
+                schedule_segment.schedule_location.push(loc);
+            },
+            _ => return Err(CifError::UnexpectedRecord { line: line.line, record: ty.into() })
+        }
+        if ty == "LT" {
+            self.flush();
+        }
+        Ok(())
+    }
+    fn association(&mut self, line: &CifLine) -> CifResult<()> {
+        let main_train_uid = line.string("main train UID", 3, 6)?;
+        let assoc_train_uid = line.string("associated train UID", 9, 6)?;
+        let assoc_start_date = DateTime::from_utc(line.date("association start date", 15)?.and_hms(0, 0, 0), Utc);
+        let location = line.string("association location", 37, 7)?;
+        let base_location_suffix = line.opt(44, 1);
+        let stp_indicator = line.code_opt("STP indicator", 79, 1)?
+            .unwrap_or(StpIndicator::None);
+        let rec = match line.raw(2, 1) {
+            "D" => AssociationRecord::Delete {
+                transaction_type: DeleteType::Delete,
+                main_train_uid,
+                assoc_train_uid,
+                assoc_start_date,
+                location,
+                base_location_suffix,
+                stp_indicator
+            },
+            "N" | "R" => AssociationRecord::Create {
+                transaction_type: CreateType::Create,
+                main_train_uid,
+                assoc_train_uid,
+                assoc_start_date,
+                assoc_end_date: DateTime::from_utc(self.end_date(line, "association end date", 21)?.and_hms(0, 0, 0), Utc),
+                assoc_days: line.days(27)?,
+                category: line.code("association category", 34, 2)?,
+                date_indicator: line.code_opt("date indicator", 36, 1)?,
+                location,
+                base_location_suffix,
+                assoc_location_suffix: line.opt(45, 1),
+                stp_indicator
+            },
+            x => return Err(line.err("transaction type", x))
+        };
+        self.pending.push_back(Record::Association(rec));
+        Ok(())
+    }
+    fn tiploc(&mut self, ty: &str, line: &CifLine) -> CifResult<()> {
+        let tiploc_code = line.string("TIPLOC", 2, 7)?;
+        if ty == "TD" {
+            self.pending.push_back(Record::Tiploc(TiplocRecord {
+                transaction_type: CreateOrDelete::Delete,
+                tiploc_code,
+                nalco: None,
+                stanox: None,
+                crs_code: None,
+                description: None,
+                tps_description: None
+            }));
+            return Ok(());
+        }
+        let mut rec = TiplocRecord {
+            transaction_type: CreateOrDelete::Create,
+            tiploc_code,
+            nalco: line.opt(11, 6),
+            stanox: line.opt(44, 5),
+            crs_code: line.opt(53, 3),
+            description: line.opt(56, 16),
+            tps_description: line.opt(18, 26)
+        };
+        if ty == "TA" {
+            // Amendments can rename the TIPLOC; turn those into a
+            // delete of the old one, followed by a create.
+            if let Some(new) = line.opt(72, 7) {
+                if new != rec.tiploc_code {
+                    let old = ::std::mem::replace(&mut rec.tiploc_code, new);
+                    self.pending.push_back(Record::Tiploc(TiplocRecord {
+                        transaction_type: CreateOrDelete::Delete,
+                        tiploc_code: old,
+                        nalco: None,
+                        stanox: None,
+                        crs_code: None,
+                        description: None,
+                        tps_description: None
+                    }));
+                }
+            }
+        }
+        self.pending.push_back(Record::Tiploc(rec));
+        Ok(())
+    }
+    /// Handles the line currently in `self.buf`.
+    fn process_line(&mut self) -> CifResult<()> {
+        let data = ::std::mem::replace(&mut self.buf, String::new());
+        let ret = self.process_data(&data);
+        self.buf = data;
+        ret
+    }
+    fn process_data(&mut self, data: &str) -> CifResult<()> {
+        let data = data.trim_end_matches(|c| c == '\r' || c == '\n');
+        if !data.is_ascii() {
+            return Err(CifError::NotAscii { line: self.line });
+        }
+        let padded;
+        let data = if data.len() < RECORD_LENGTH {
+            padded = format!("{:1$}", data, RECORD_LENGTH);
+            &padded as &str
+        }
+        else {
+            data
+        };
+        let line = CifLine { data, line: self.line };
+        let ty = line.raw(0, 2);
+        match ty {
+            "BX" | "LO" | "LI" | "LT" | "CR" if self.skip_schedule => Ok(()),
+            "BX" | "LO" | "LI" | "LT" => {
+                let ret = if ty == "BX" {
+                    self.basic_schedule_extra(&line)
+                }
+                else {
+                    self.location(ty, &line)
+                };
+                if ret.is_err() {
+                    // Don't return half a schedule.
+                    self.cur = None;
+                    self.skip_schedule = true;
+                }
+                ret
+            },
+            "CR" => {
+                if self.cur.is_none() {
+                    return Err(CifError::UnexpectedRecord { line: self.line, record: ty.into() });
+                }
+                Ok(())
+            },
+            "BS" => {
+                self.flush();
+                let ret = self.basic_schedule(&line);
+                if ret.is_err() {
+                    self.skip_schedule = true;
+                }
+                ret
+            },
+            "HD" => {
+                self.flush();
+                self.header(&line)
+            },
+            "AA" => {
+                self.flush();
+                self.association(&line)
+            },
+            "TI" | "TA" | "TD" => {
+                self.flush();
+                self.tiploc(ty, &line)
+            },
+            "ZZ" => {
+                self.flush();
+                self.pending.push_back(Record::Eof(true));
+                Ok(())
+            },
+            // Train and location notes (TN/LN) live inside schedules,
+            // so don't flush for those; just ignore them.
+            _ => Ok(())
+        }
+    }
+}
+impl<R: BufRead> Iterator for CifReader<R> {
+    type Item = CifResult<Record>;
+
+    fn next(&mut self) -> Option<CifResult<Record>> {
+        loop {
+            if let Some(rec) = self.pending.pop_front() {
+                return Some(Ok(rec));
+            }
+            if self.done {
+                return None;
+            }
+            self.buf.clear();
+            match self.inner.read_line(&mut self.buf) {
+                Ok(0) => {
+                    self.done = true;
+                    self.flush();
+                },
+                Ok(_) => {
+                    self.line += 1;
+                    if let Err(e) = self.process_line() {
+                        return Some(Err(e));
+                    }
+                },
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e.into()));
+                }
+            }
+        }
+    }
+}
@@ -2,6 +2,7 @@ use chrono::*;
 use chrono_tz::Europe::London;
 use chrono_tz::Tz;
 use serde::*;
+use std::borrow::Cow;
 use std::fmt::Display;
 use std::str::FromStr;
 use super::schedule::Days;
@@ -25,10 +26,16 @@ pub fn parse_vstp_time_force<'de, D>(d: D) -> Result<NaiveTime, D::Error> where
         Err(e) => Err(de::Error::custom(format!("failed to parse a VSTP time {}: {}", x, e)))
     }
 }
+/// A string that borrows from the input where it can (i.e. if it has no escapes).
+#[derive(Deserialize)]
+#[serde(transparent)]
+struct CowStr<'a>(#[serde(borrow)] Cow<'a, str>);
+
 pub fn str_to_time(x: &str) -> Option<NaiveTime> {
-    let half = x.contains("H");
-    let x = x.replace("H", "");
-    let time = NaiveTime::parse_from_str(&x, "%H%M");
+    // Half-minutes are written with a trailing "H" (e.g. "1230H").
+    let half = x.ends_with('H');
+    let x = x.trim_end_matches('H');
+    let time = NaiveTime::parse_from_str(x, "%H%M");
     if let Ok(t) = time {
         if half {
             t.with_second(30)
@@ -43,9 +50,9 @@ pub fn str_to_time(x: &str) -> Option<NaiveTime> {
 }
 pub fn parse_24h_time<'de, D>(d: D) -> Result<Option<NaiveTime>, D::Error> where D: Deserializer<'de> {
     Deserialize::deserialize(d)
-        .map(|x: Option<String>| {
+        .map(|x: Option<CowStr>| {
             if let Some(x) = x {
-                str_to_time(x.trim())
+                str_to_time(x.0.trim())
             }
             else {
                 None
@@ -53,10 +60,10 @@ pub fn parse_24h_time<'de, D>(d: D) -> Result<Option<NaiveTime>, D::Error> where
         })
 }
 pub fn parse_24h_time_force<'de, D>(d: D) -> Result<NaiveTime, D::Error> where D: Deserializer<'de> {
-    let x: String = Deserialize::deserialize(d)?;
-    match str_to_time(x.trim()) {
+    let x: CowStr = Deserialize::deserialize(d)?;
+    match str_to_time(x.0.trim()) {
         Some(res) => Ok(res),
-        None => Err(de::Error::custom(format!("failed to parse a 24h time: {}", x)))
+        None => Err(de::Error::custom(format!("failed to parse a 24h time: {}", x.0)))
     }
 }
 pub fn naive_date_to_london<'de, D>(d: D) -> Result<Date<Tz>, D::Error> where D: Deserializer<'de> {
@@ -75,8 +82,8 @@ pub fn fix_buggy_schedule_type<'de, D>(d: D) -> Result<StpIndicator, D::Error> w
 }
 pub fn parse_days<'de, D>(d: D) -> Result<Days, D::Error> where D: Deserializer<'de> {
     Deserialize::deserialize(d)
-        .map(|x: String| {
-            let mut chars = x.chars();
+        .map(|x: CowStr| {
+            let mut chars = x.0.chars();
             Days {
                 mon: chars.next().map(|x| x == '1').unwrap_or(false),
                 tue: chars.next().map(|x| x == '1').unwrap_or(false),
@@ -120,7 +127,26 @@ pub fn non_empty_str_opt<'de, D>(deserializer: D) -> Result<Option<String>, D::E
         None
     })
 }
-
+pub fn non_empty_cow<'de, 'a, D>(deserializer: D) -> Result<Cow<'a, str>, D::Error>
+    where D: Deserializer<'de>, 'de: 'a
+{
+    let s: CowStr = Deserialize::deserialize(deserializer)?;
+    if s.0.trim() == "" {
+        Err(de::Error::custom("expected non-empty string, got empty string"))
+    }
+    else {
+        Ok(s.0)
+    }
+}
+pub fn non_empty_cow_opt<'de, 'a, D>(deserializer: D) -> Result<Option<Cow<'a, str>>, D::Error>
+    where D: Deserializer<'de>, 'de: 'a
+{
+    let s: Option<CowStr> = Deserialize::deserialize(deserializer)?;
+    Ok(match s {
+        Some(ref s) if s.0.trim() == "" => None,
+        s => s.map(|x| x.0)
+    })
+}
 
 pub fn from_str_opt<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
     where T: FromStr,
@@ -13,9 +13,11 @@ extern crate serde_json;
 
 mod fns;
 pub mod schedule;
+pub mod schedule_ref;
 pub mod vstp;
 pub mod movements;
 pub mod cif;
+pub mod cif_reader;
 pub mod reference;
 #[cfg(test)]
 mod tests;
@@ -2,6 +2,7 @@ use chrono::*;
 use super::fns::*;
 use super::cif::*;
 use chrono_tz::Tz;
+use chrono_tz::Europe::London;
 use std::fmt;
 
 #[derive(Deserialize, Clone, Debug, is_enum_variant)]
@@ -194,6 +195,19 @@ pub struct TimetableRecord {
     #[serde(rename = "Metadata")]
     pub metadata: TimetableMetadata
 }
+impl TimetableRecord {
+    /// The sequence number trainsplorer uses for this file: the number of
+    /// days between 1970-01-01 and the date (in London) it was extracted on.
+    ///
+    /// JSON files come with NROD's own sequence number in `metadata`, but CIF
+    /// files don't have one, and files in either format can end up being
+    /// applied to the same database, so both get numbered this way instead.
+    /// Like NROD's numbers, this goes up by one with each daily file.
+    pub fn file_sequence(&self) -> u32 {
+        let date = London.timestamp(self.timestamp as i64, 0).naive_local().date();
+        date.signed_duration_since(NaiveDate::from_ymd(1970, 1, 1)).num_days() as u32
+    }
+}
 // FIXMEs (c.f. schedule_assoc_delete_1.json):
 // - the DateTime isn't parsed like the other ones; why?
 // - diagram_type field is strangely missing
@@ -211,6 +225,8 @@ pub enum AssociationRecord {
         #[serde(deserialize_with = "parse_days")]
         assoc_days: Days,
         category: AssociationType,
+        #[serde(default)]
+        date_indicator: Option<DateIndicator>,
         location: String,
         #[serde(deserialize_with = "non_empty_str_opt")]
         base_location_suffix: Option<String>,
@@ -346,6 +362,8 @@ pub enum LocationRecord {
         platform: Option<String>,
         #[serde(deserialize_with = "non_empty_str_opt")]
         line: Option<String>,
+        #[serde(default, deserialize_with = "non_empty_str_opt")]
+        activity: Option<String>,
         #[serde(deserialize_with = "non_empty_str_opt")]
         engineering_allowance: Option<String>,
         #[serde(deserialize_with = "non_empty_str_opt")]
@@ -371,6 +389,8 @@ pub enum LocationRecord {
         line: Option<String>,
         #[serde(deserialize_with = "non_empty_str_opt")]
         path: Option<String>,
+        #[serde(default, deserialize_with = "non_empty_str_opt")]
+        activity: Option<String>,
         #[serde(deserialize_with = "non_empty_str_opt")]
         engineering_allowance: Option<String>,
         #[serde(deserialize_with = "non_empty_str_opt")]
@@ -403,5 +423,7 @@ pub enum LocationRecord {
         platform: Option<String>,
         #[serde(deserialize_with = "non_empty_str_opt")]
         path: Option<String>,
+        #[serde(default, deserialize_with = "non_empty_str_opt")]
+        activity: Option<String>
     }
 }
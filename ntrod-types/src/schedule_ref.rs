@@ -0,0 +1,309 @@
+//! Borrowed versions of the `schedule` types, for importing large SCHEDULE
+//! extracts without allocating a `String` for every field.
+//!
+//! Schedule records borrow from the line being parsed wherever they can
+//! (strings with escapes in them still need allocating, hence the `Cow`s).
+//! Other record types are rare enough that they just use the owned types.
+
+use chrono::*;
+use chrono_tz::Tz;
+use std::borrow::Cow;
+use super::fns::*;
+use super::cif::*;
+use super::schedule::*;
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(bound(deserialize = "'de: 'a"))]
+pub enum RecordRef<'a> {
+    #[serde(rename = "JsonScheduleV1")]
+    Schedule(ScheduleRecordRef<'a>),
+    #[serde(rename = "JsonAssociationV1")]
+    Association(AssociationRecord),
+    #[serde(rename = "JsonTimetableV1")]
+    Timetable(TimetableRecord),
+    #[serde(rename = "TiplocV1")]
+    Tiploc(TiplocRecord),
+    #[serde(rename = "EOF")]
+    Eof(bool)
+}
+#[derive(Deserialize, Clone, Debug)]
+#[serde(bound(deserialize = "'de: 'a"))]
+pub struct ScheduleSegmentRef<'a> {
+    #[serde(rename = "CIF_train_category")]
+    pub train_category: Option<TrainCategory>,
+    #[serde(deserialize_with = "non_empty_cow_opt")]
+    pub signalling_id: Option<Cow<'a, str>>,
+    #[serde(rename = "CIF_headcode", deserialize_with = "non_empty_cow_opt")]
+    pub headcode: Option<Cow<'a, str>>,
+    #[serde(rename = "CIF_business_sector", deserialize_with = "non_empty_cow_opt")]
+    pub business_sector: Option<Cow<'a, str>>,
+    #[serde(rename = "CIF_power_type")]
+    pub power_type: Option<PowerType>,
+    #[serde(rename = "CIF_timing_load", deserialize_with = "non_empty_cow_opt")]
+    pub timing_load: Option<Cow<'a, str>>,
+    #[serde(rename = "CIF_speed", deserialize_with = "from_str_opt")]
+    pub speed: Option<u32>,
+    #[serde(rename = "CIF_operating_characteristics", deserialize_with = "non_empty_cow_opt")]
+    pub operating_characteristics: Option<Cow<'a, str>>,
+    #[serde(rename = "CIF_train_class", deserialize_with = "non_empty_cow_opt")]
+    pub train_class: Option<Cow<'a, str>>,
+    #[serde(rename = "CIF_sleepers", deserialize_with = "non_empty_cow_opt")]
+    pub sleepers: Option<Cow<'a, str>>,
+    #[serde(rename = "CIF_reservations", deserialize_with = "non_empty_cow_opt")]
+    pub reservations: Option<Cow<'a, str>>,
+    #[serde(rename = "CIF_catering_code", deserialize_with = "non_empty_cow_opt")]
+    pub catering_code: Option<Cow<'a, str>>,
+    #[serde(rename = "CIF_service_branding", deserialize_with = "non_empty_cow_opt")]
+    pub service_branding: Option<Cow<'a, str>>,
+    #[serde(default)]
+    pub schedule_location: Vec<LocationRecordRef<'a>>
+}
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged, bound(deserialize = "'de: 'a"))]
+pub enum ScheduleRecordRef<'a> {
+    Create {
+        #[serde(rename = "CIF_train_uid", deserialize_with = "non_empty_cow")]
+        train_uid: Cow<'a, str>,
+        transaction_type: CreateType,
+        #[serde(deserialize_with = "naive_date_to_london")]
+        schedule_start_date: Date<Tz>,
+        #[serde(deserialize_with = "naive_date_to_london")]
+        schedule_end_date: Date<Tz>,
+        #[serde(deserialize_with = "parse_days")]
+        schedule_days_runs: Days,
+        #[serde(rename = "CIF_bank_holiday_running", deserialize_with = "non_empty_cow_opt")]
+        bank_holiday_running: Option<Cow<'a, str>>,
+        train_status: TrainStatus,
+        #[serde(rename = "CIF_stp_indicator")]
+        stp_indicator: StpIndicator,
+        #[serde(default)]
+        applicable_timetable: YesOrNo,
+        #[serde(default, deserialize_with = "non_empty_cow_opt")]
+        atoc_code: Option<Cow<'a, str>>,
+        schedule_segment: ScheduleSegmentRef<'a>,
+    },
+    Delete {
+        #[serde(rename = "CIF_train_uid", deserialize_with = "non_empty_cow")]
+        train_uid: Cow<'a, str>,
+        transaction_type: DeleteType,
+        #[serde(deserialize_with = "naive_date_to_london")]
+        schedule_start_date: Date<Tz>,
+        #[serde(rename = "CIF_stp_indicator")]
+        stp_indicator: StpIndicator,
+    }
+}
+#[derive(Deserialize, Clone, Debug)]
+#[serde(untagged, bound(deserialize = "'de: 'a"))]
+pub enum LocationRecordRef<'a> {
+    Originating {
+        record_identity: OriginatingLocation,
+        #[serde(deserialize_with = "non_empty_cow")]
+        tiploc_code: Cow<'a, str>,
+        #[serde(deserialize_with = "parse_24h_time_force")]
+        departure: NaiveTime,
+        #[serde(deserialize_with = "parse_24h_time")]
+        public_departure: Option<NaiveTime>,
+        #[serde(deserialize_with = "non_empty_cow_opt")]
+        platform: Option<Cow<'a, str>>,
+        #[serde(deserialize_with = "non_empty_cow_opt")]
+        line: Option<Cow<'a, str>>,
+        #[serde(default, deserialize_with = "non_empty_cow_opt")]
+        activity: Option<Cow<'a, str>>,
+        #[serde(deserialize_with = "non_empty_cow_opt")]
+        engineering_allowance: Option<Cow<'a, str>>,
+        #[serde(deserialize_with = "non_empty_cow_opt")]
+        pathing_allowance: Option<Cow<'a, str>>,
+        #[serde(deserialize_with = "non_empty_cow_opt")]
+        performance_allowance: Option<Cow<'a, str>>
+    },
+    Intermediate {
+        record_identity: IntermediateLocation,
+        #[serde(deserialize_with = "non_empty_cow")]
+        tiploc_code: Cow<'a, str>,
+        #[serde(deserialize_with = "parse_24h_time_force")]
+        arrival: NaiveTime,
+        #[serde(deserialize_with = "parse_24h_time_force")]
+        departure: NaiveTime,
+        #[serde(deserialize_with = "parse_24h_time")]
+        public_arrival: Option<NaiveTime>,
+        #[serde(deserialize_with = "parse_24h_time")]
+        public_departure: Option<NaiveTime>,
+        #[serde(deserialize_with = "non_empty_cow_opt")]
+        platform: Option<Cow<'a, str>>,
+        #[serde(deserialize_with = "non_empty_cow_opt")]
+        line: Option<Cow<'a, str>>,
+        #[serde(deserialize_with = "non_empty_cow_opt")]
+        path: Option<Cow<'a, str>>,
+        #[serde(default, deserialize_with = "non_empty_cow_opt")]
+        activity: Option<Cow<'a, str>>,
+        #[serde(deserialize_with = "non_empty_cow_opt")]
+        engineering_allowance: Option<Cow<'a, str>>,
+        #[serde(deserialize_with = "non_empty_cow_opt")]
+        pathing_allowance: Option<Cow<'a, str>>,
+        #[serde(deserialize_with = "non_empty_cow_opt")]
+        performance_allowance: Option<Cow<'a, str>>
+    },
+    Pass {
+        record_identity: IntermediateLocation,
+        #[serde(deserialize_with = "non_empty_cow")]
+        tiploc_code: Cow<'a, str>,
+        #[serde(deserialize_with = "parse_24h_time_force")]
+        pass: NaiveTime,
+        #[serde(deserialize_with = "non_empty_cow_opt")]
+        engineering_allowance: Option<Cow<'a, str>>,
+        #[serde(deserialize_with = "non_empty_cow_opt")]
+        pathing_allowance: Option<Cow<'a, str>>,
+        #[serde(deserialize_with = "non_empty_cow_opt")]
+        performance_allowance: Option<Cow<'a, str>>
+    },
+    Terminating {
+        record_identity: TerminatingLocation,
+        #[serde(deserialize_with = "non_empty_cow")]
+        tiploc_code: Cow<'a, str>,
+        #[serde(deserialize_with = "parse_24h_time_force")]
+        arrival: NaiveTime,
+        #[serde(deserialize_with = "parse_24h_time")]
+        public_arrival: Option<NaiveTime>,
+        #[serde(deserialize_with = "non_empty_cow_opt")]
+        platform: Option<Cow<'a, str>>,
+        #[serde(deserialize_with = "non_empty_cow_opt")]
+        path: Option<Cow<'a, str>>,
+        #[serde(default, deserialize_with = "non_empty_cow_opt")]
+        activity: Option<Cow<'a, str>>
+    }
+}
+
+// Conversions from the owned types, so records from elsewhere (e.g. the
+// CIF reader) can go through the same import code.
+
+fn cow(s: String) -> Cow<'static, str> {
+    Cow::Owned(s)
+}
+fn cow_opt(s: Option<String>) -> Option<Cow<'static, str>> {
+    s.map(Cow::Owned)
+}
+impl From<Record> for RecordRef<'static> {
+    fn from(rec: Record) -> Self {
+        match rec {
+            Record::Schedule(s) => RecordRef::Schedule(s.into()),
+            Record::Association(a) => RecordRef::Association(a),
+            Record::Timetable(t) => RecordRef::Timetable(t),
+            Record::Tiploc(t) => RecordRef::Tiploc(t),
+            Record::Eof(e) => RecordRef::Eof(e)
+        }
+    }
+}
+impl From<ScheduleSegment> for ScheduleSegmentRef<'static> {
+    fn from(seg: ScheduleSegment) -> Self {
+        ScheduleSegmentRef {
+            train_category: seg.train_category,
+            signalling_id: cow_opt(seg.signalling_id),
+            headcode: cow_opt(seg.headcode),
+            business_sector: cow_opt(seg.business_sector),
+            power_type: seg.power_type,
+            timing_load: cow_opt(seg.timing_load),
+            speed: seg.speed,
+            operating_characteristics: cow_opt(seg.operating_characteristics),
+            train_class: cow_opt(seg.train_class),
+            sleepers: cow_opt(seg.sleepers),
+            reservations: cow_opt(seg.reservations),
+            catering_code: cow_opt(seg.catering_code),
+            service_branding: cow_opt(seg.service_branding),
+            schedule_location: seg.schedule_location.into_iter().map(|x| x.into()).collect()
+        }
+    }
+}
+impl From<ScheduleRecord> for ScheduleRecordRef<'static> {
+    fn from(rec: ScheduleRecord) -> Self {
+        match rec {
+            ScheduleRecord::Create {
+                train_uid, transaction_type, schedule_start_date, schedule_end_date,
+                schedule_days_runs, bank_holiday_running, train_status, stp_indicator,
+                applicable_timetable, atoc_code, schedule_segment
+            } => ScheduleRecordRef::Create {
+                train_uid: cow(train_uid),
+                transaction_type,
+                schedule_start_date,
+                schedule_end_date,
+                schedule_days_runs,
+                bank_holiday_running: cow_opt(bank_holiday_running),
+                train_status,
+                stp_indicator,
+                applicable_timetable,
+                atoc_code: cow_opt(atoc_code),
+                schedule_segment: schedule_segment.into()
+            },
+            ScheduleRecord::Delete { train_uid, transaction_type, schedule_start_date, stp_indicator } => {
+                ScheduleRecordRef::Delete {
+                    train_uid: cow(train_uid),
+                    transaction_type,
+                    schedule_start_date,
+                    stp_indicator
+                }
+            }
+        }
+    }
+}
+impl From<LocationRecord> for LocationRecordRef<'static> {
+    fn from(loc: LocationRecord) -> Self {
+        match loc {
+            LocationRecord::Originating {
+                record_identity, tiploc_code, departure, public_departure, platform,
+                line, activity, engineering_allowance, pathing_allowance, performance_allowance
+            } => LocationRecordRef::Originating {
+                record_identity,
+                tiploc_code: cow(tiploc_code),
+                departure,
+                public_departure,
+                platform: cow_opt(platform),
+                line: cow_opt(line),
+                activity: cow_opt(activity),
+                engineering_allowance: cow_opt(engineering_allowance),
+                pathing_allowance: cow_opt(pathing_allowance),
+                performance_allowance: cow_opt(performance_allowance)
+            },
+            LocationRecord::Intermediate {
+                record_identity, tiploc_code, arrival, departure, public_arrival,
+                public_departure, platform, line, path, activity,
+                engineering_allowance, pathing_allowance, performance_allowance
+            } => LocationRecordRef::Intermediate {
+                record_identity,
+                tiploc_code: cow(tiploc_code),
+                arrival,
+                departure,
+                public_arrival,
+                public_departure,
+                platform: cow_opt(platform),
+                line: cow_opt(line),
+                path: cow_opt(path),
+                activity: cow_opt(activity),
+                engineering_allowance: cow_opt(engineering_allowance),
+                pathing_allowance: cow_opt(pathing_allowance),
+                performance_allowance: cow_opt(performance_allowance)
+            },
+            LocationRecord::Pass {
+                record_identity, tiploc_code, pass, engineering_allowance,
+                pathing_allowance, performance_allowance
+            } => LocationRecordRef::Pass {
+                record_identity,
+                tiploc_code: cow(tiploc_code),
+                pass,
+                engineering_allowance: cow_opt(engineering_allowance),
+                pathing_allowance: cow_opt(pathing_allowance),
+                performance_allowance: cow_opt(performance_allowance)
+            },
+            LocationRecord::Terminating {
+                record_identity, tiploc_code, arrival, public_arrival, platform, path,
+                activity
+            } => LocationRecordRef::Terminating {
+                record_identity,
+                tiploc_code: cow(tiploc_code),
+                arrival,
+                public_arrival,
+                platform: cow_opt(platform),
+                path: cow_opt(path),
+                activity: cow_opt(activity)
+            }
+        }
+    }
+}
@@ -1,5 +1,6 @@
 use serde_json;
-use {schedule, movements, vstp};
+use {schedule, schedule_ref, movements, vstp};
+use cif_reader::CifReader;
 
 macro_rules! mktest {
     ($($name:ident, $path:expr, $ty:ty),*) => {
@@ -24,6 +25,15 @@ mktest! {
     parse_association_v1, "association_v1.json", schedule::Record,
     parse_timetable_v1, "timetable_v1.json", schedule::Record,
     parse_tiploc_v1, "tiploc_v1.json", schedule::Record,
+    parse_ref_schedule_v1, "schedule_v1.json", schedule_ref::RecordRef,
+    parse_ref_schedule_v1_2, "schedule_v1_2.json", schedule_ref::RecordRef,
+    parse_ref_schedule_v1_3, "schedule_v1_3.json", schedule_ref::RecordRef,
+    parse_ref_schedule_v1_4, "schedule_v1_4.json", schedule_ref::RecordRef,
+    parse_ref_schedule_v1_5, "schedule_v1_5.json", schedule_ref::RecordRef,
+    parse_ref_schedule_v1_6, "schedule_v1_6.json", schedule_ref::RecordRef,
+    parse_ref_schedule_delete_1, "schedule_delete_1.json", schedule_ref::RecordRef,
+    parse_ref_association_v1, "association_v1.json", schedule_ref::RecordRef,
+    parse_ref_timetable_v1, "timetable_v1.json", schedule_ref::RecordRef,
     parse_vstp_v1, "vstp_v1.json", vstp::Record,
     parse_vstp_delete_1, "vstp_delete_1.json", vstp::Record,
     parse_vstp_201806070750, "vstp-201806070750.json", vstp::Record,
@@ -44,3 +54,119 @@ mktest! {
     parse_movements_0002, "movements_0002.json", movements::Cancellation,
     parse_movements_0001, "movements_0001.json", movements::Activation
 }
+
+#[test]
+fn schedule_ref_borrows() {
+    use std::borrow::Cow;
+    use schedule_ref::{RecordRef, ScheduleRecordRef, LocationRecordRef};
+
+    let data = include_str!(concat!(env!("CARGO_MANIFEST_DIR"), "/src/tests/schedule_v1.json"));
+    let owned: schedule::Record = serde_json::from_str(data).unwrap();
+    let borrowed: RecordRef = serde_json::from_str(data).unwrap();
+    let from_owned: RecordRef = owned.into();
+    match (borrowed, from_owned) {
+        (RecordRef::Schedule(ScheduleRecordRef::Create { train_uid, schedule_segment, .. }),
+         RecordRef::Schedule(ScheduleRecordRef::Create { train_uid: uid2, schedule_segment: seg2, .. })) => {
+            match train_uid {
+                Cow::Borrowed(_) => {},
+                Cow::Owned(_) => panic!("train UID wasn't borrowed")
+            }
+            assert_eq!(train_uid, uid2);
+            assert_eq!(schedule_segment.schedule_location.len(), seg2.schedule_location.len());
+            for (a, b) in schedule_segment.schedule_location.iter().zip(seg2.schedule_location.iter()) {
+                let tiploc = |l: &LocationRecordRef| match *l {
+                    LocationRecordRef::Originating { ref tiploc_code, .. } |
+                    LocationRecordRef::Intermediate { ref tiploc_code, .. } |
+                    LocationRecordRef::Pass { ref tiploc_code, .. } |
+                    LocationRecordRef::Terminating { ref tiploc_code, .. } => tiploc_code.to_string()
+                };
+                assert_eq!(tiploc(a), tiploc(b));
+            }
+        },
+        x => panic!("expected two schedule creates, got {:?}", x)
+    }
+}
+
+fn read_cif(path: &str) -> Vec<schedule::Record> {
+    let data = ::std::fs::read(format!("{}/src/tests/{}", env!("CARGO_MANIFEST_DIR"), path)).unwrap();
+    CifReader::new(&data as &[u8])
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap()
+}
+#[test]
+fn parse_cif_v1() {
+    use schedule::Record::*;
+    use schedule::{ScheduleRecord, AssociationRecord, LocationRecord, CreateOrDelete};
+
+    let recs = read_cif("cif_v1.cif");
+    assert_eq!(recs.len(), 10);
+    match recs[0] {
+        Timetable(ref t) => {
+            assert_eq!(t.metadata.ty, "full");
+            assert_eq!(t.sender.component, "DFROC1F");
+            // Extracted on 2019-05-20.
+            assert_eq!(t.metadata.sequence, 18036);
+            assert_eq!(t.file_sequence(), 18036);
+        },
+        ref x => panic!("expected timetable, got {:?}", x)
+    }
+    match (&recs[2], &recs[3], &recs[4]) {
+        (&Tiploc(ref old), &Tiploc(ref new), &Tiploc(ref del)) => {
+            assert!(old.transaction_type.is_delete());
+            assert_eq!(old.tiploc_code, "WATFDJ");
+            assert!(new.transaction_type.is_create());
+            assert_eq!(new.tiploc_code, "WATFJN");
+            assert_eq!(new.crs_code.as_ref().map(|x| x as &str), Some("WFJ"));
+            match del.transaction_type {
+                CreateOrDelete::Delete => assert_eq!(del.tiploc_code, "OLDTPL"),
+                _ => panic!("expected delete")
+            }
+        },
+        x => panic!("expected TIPLOC records, got {:?}", x)
+    }
+    match recs[5] {
+        Association(AssociationRecord::Create { ref location, category, .. }) => {
+            assert_eq!(location, "CREWE");
+            assert!(category.is_divide());
+        },
+        ref x => panic!("expected association, got {:?}", x)
+    }
+    match recs[6] {
+        Schedule(ScheduleRecord::Create { ref train_uid, schedule_end_date, ref atoc_code, ref schedule_segment, .. }) => {
+            assert_eq!(train_uid, "C12345");
+            // 999999 means the end of the extract
+            assert_eq!(schedule_end_date.naive_utc(), ::chrono::NaiveDate::from_ymd(2020, 5, 19));
+            assert_eq!(atoc_code.as_ref().map(|x| x as &str), Some("VT"));
+            assert_eq!(schedule_segment.speed, Some(125));
+            let locs = &schedule_segment.schedule_location;
+            assert_eq!(locs.len(), 4);
+            assert!(locs[0].is_originating());
+            assert!(locs[1].is_intermediate());
+            assert!(locs[2].is_pass());
+            assert!(locs[3].is_terminating());
+            match locs[1] {
+                LocationRecord::Intermediate { arrival, ref activity, .. } => {
+                    assert_eq!(arrival, ::chrono::NaiveTime::from_hms(9, 15, 30));
+                    assert_eq!(activity.as_ref().map(|x| x as &str), Some("T"));
+                },
+                _ => unreachable!()
+            }
+            match locs[3] {
+                LocationRecord::Terminating { ref activity, .. } => {
+                    assert_eq!(activity.as_ref().map(|x| x as &str), Some("TF"));
+                },
+                _ => unreachable!()
+            }
+        },
+        ref x => panic!("expected schedule, got {:?}", x)
+    }
+    assert!(recs[7].is_schedule());
+    match recs[8] {
+        Schedule(ScheduleRecord::Create { stp_indicator, ref schedule_segment, .. }) => {
+            assert!(stp_indicator.is_cancellation());
+            assert!(schedule_segment.schedule_location.is_empty());
+        },
+        ref x => panic!("expected cancellation, got {:?}", x)
+    }
+    assert!(recs[9].is_eof());
+}
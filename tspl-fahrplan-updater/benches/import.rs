@@ -0,0 +1,80 @@
+//! Benchmarks importing a JSON schedule file.
+//!
+//! By default, this imports `fixtures/schedule_sample.json` with its schedules
+//! copied lots of times (with different train UIDs) to make a file of a
+//! reasonable size. Set `TSPL_BENCH_FILE` to the path of a (decompressed)
+//! SCHEDULE extract to import that instead, and `TSPL_BENCH_COPIES` to change
+//! the number of copies.
+
+use std::env;
+use std::fs;
+use std::time::Instant;
+use serde_json::Value;
+use tspl_fahrplan_updater::import;
+
+static FIXTURE: &str = include_str!("fixtures/schedule_sample.json");
+static DEFAULT_COPIES: usize = 5000;
+
+/// Makes a schedule file out of the fixture, with each schedule and association
+/// repeated `copies` times.
+fn make_file(copies: usize) -> (String, usize) {
+    let mut lines = FIXTURE.lines();
+    let mut ret = String::new();
+    // The first line is the Timetable record.
+    ret.push_str(lines.next().unwrap());
+    ret.push('\n');
+    let records = lines.map(|l| serde_json::from_str(l).unwrap())
+        .collect::<Vec<Value>>();
+    let mut n = 0;
+    for i in 0..copies {
+        for rec in records.iter() {
+            let mut rec = rec.clone();
+            // Give every copy a different (but valid-looking) UID, so they
+            // all get inserted instead of being treated as duplicates.
+            let uid = format!("B{:05}", n % 100000);
+            if let Some(s) = rec.get_mut("JsonScheduleV1") {
+                s["CIF_train_uid"] = Value::String(uid);
+            }
+            else if let Some(a) = rec.get_mut("JsonAssociationV1") {
+                a["main_train_uid"] = Value::String(uid);
+                a["assoc_train_uid"] = Value::String(format!("C{:05}", i % 100000));
+            }
+            ret.push_str(&rec.to_string());
+            ret.push('\n');
+            n += 1;
+        }
+    }
+    (ret, n)
+}
+
+fn main() {
+    let (data, n_records) = match env::var("TSPL_BENCH_FILE") {
+        Ok(path) => {
+            let data = fs::read_to_string(&path).unwrap();
+            let n = data.lines().count();
+            (data, n)
+        },
+        Err(_) => {
+            let copies = env::var("TSPL_BENCH_COPIES")
+                .map(|x| x.parse().unwrap())
+                .unwrap_or(DEFAULT_COPIES);
+            make_file(copies)
+        }
+    };
+    let db_path = env::temp_dir().join(format!("tspl-import-bench-{}.sqlite", std::process::id()));
+    let db_path = db_path.to_str().unwrap();
+    let mut conn = tspl_sqlite::initialize_db(db_path, &tspl_fahrplan::types::MIGRATIONS).unwrap();
+    println!("importing {} records ({} bytes)...", n_records, data.len());
+    let start = Instant::now();
+    import::apply_schedule_records(&mut conn, data.as_bytes()).unwrap();
+    let elapsed = start.elapsed();
+    let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1_000_000_000.0;
+    println!("took {:.3}s ({:.0} records/sec, {:.1} MiB/sec)",
+             secs,
+             n_records as f64 / secs,
+             data.len() as f64 / secs / (1024.0 * 1024.0));
+    drop(conn);
+    for suffix in &["", "-wal", "-shm"] {
+        let _ = fs::remove_file(format!("{}{}", db_path, suffix));
+    }
+}
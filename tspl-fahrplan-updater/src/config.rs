@@ -2,19 +2,66 @@
 
 use serde_derive::Deserialize;
 use tspl_util::{ConfigExt, crate_name};
+use tspl_gcs::StorageConfig;
+use failure::format_err;
+use crate::errors::Result;
 
 #[derive(Deserialize, Debug)]
 pub struct Config {
-    pub bucket_name: String,
-    pub service_account_key_path: String,
-    pub username: String,
-    pub password: String,
+    /// Where to store the database (not needed in offline mode).
+    #[serde(flatten)]
+    pub storage: StorageConfig,
+    /// NROD username (not needed in offline mode).
+    #[serde(default)]
+    pub username: Option<String>,
+    /// NROD password (not needed in offline mode).
+    #[serde(default)]
+    pub password: Option<String>,
     #[serde(default)]
     pub base_url: Option<String>,
     #[serde(default)]
     pub update_timeout_ms: Option<u32>,
     #[serde(default)]
-    pub update_retries: Option<u32>
+    pub update_retries: Option<u32>,
+    /// If set, also export a GTFS feed, uploading each file with
+    /// this prefix (e.g. `gtfs/` gives `gtfs/stops.txt`).
+    #[serde(default)]
+    pub gtfs_object_prefix: Option<String>,
+    /// Name of the `tspl-nennen` reference database object, used to
+    /// get stop names and locations for the GTFS export.
+    #[serde(default)]
+    pub refdata_object_name: Option<String>,
+    /// Name to upload the data-quality report (see the `report` module)
+    /// as (defaults to `validation-report.json`).
+    #[serde(default)]
+    pub report_object_name: Option<String>,
+    /// If set, run in offline mode: import these local schedule files (JSON
+    /// or CIF, gzipped or not) in order, instead of talking to NROD, and
+    /// don't upload anything. Directories are expanded to the files
+    /// inside them, sorted by name.
+    #[serde(default)]
+    pub offline_files: Option<Vec<String>>,
+    /// Where to write the database in offline mode (defaults to
+    /// `./current.sqlite`). If a valid database exists here already, it
+    /// gets updated.
+    #[serde(default)]
+    pub offline_db_path: Option<String>,
+    /// Path to a `tspl-nennen` reference database to check TIPLOCs against
+    /// in offline mode (if unset, that check gets skipped).
+    #[serde(default)]
+    pub offline_refdata_path: Option<String>,
+    /// Where to write the data-quality report in offline mode (defaults to
+    /// `./validation-report.json`).
+    #[serde(default)]
+    pub offline_report_path: Option<String>
+}
+
+impl Config {
+    /// Gets a setting that's only optional in offline mode.
+    pub fn required<'a>(val: &'a Option<String>, name: &str) -> Result<&'a String> {
+        val.as_ref()
+            .ok_or_else(|| format_err!("config option `{}` must be set, unless running in offline mode", name))
+    }
 }
 
 impl ConfigExt for Config {
@@ -93,3 +93,12 @@ pub fn load_or_create_db(path: &str) -> Result<(Connection, bool)> {
     let ret = sqlite_initialize_db(path)?;
     Ok((ret, false))
 }
+/// Throw away the database at `path` (whose connection is `conn`), and create a new one.
+pub fn recreate_db(path: &str, conn: Connection) -> Result<Connection> {
+    // Close the connection first.
+    drop(conn);
+    warn!("Deleting database at {}...", path);
+    std::fs::remove_file(path)?;
+    info!("Initializing database at {}...", path);
+    sqlite_initialize_db(path)
+}
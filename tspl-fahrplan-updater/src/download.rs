@@ -4,46 +4,148 @@ use chrono::*;
 use crate::errors::*;
 use log::*;
 use tspl_sqlite::traits::*;
-use crate::import;
+use tspl_fahrplan::types::ScheduleFile;
+use ntrod_types::schedule;
+use failure::bail;
+use failure_derive::Fail;
+use std::io::BufRead;
+use crate::import::{self, ImportIssues};
 use crate::config::Config;
 use tspl_util::nrod::NrodDownloader;
 
+/// How many days' worth of update files NROD keeps around.
+pub static MAX_BACKFILL_DAYS: i64 = 7;
+
+/// Returned when the update files NROD has available don't join up with the
+/// last file we imported, meaning the database needs a full reload.
+#[derive(Fail, Debug)]
+#[fail(display = "can't fill gap after schedule file {} (earliest available update is {:?})", last_sequence, earliest_available)]
+pub struct UpdateGapError {
+    /// Sequence number of the last file in the database.
+    pub last_sequence: u32,
+    /// Sequence number of the earliest update file we could find.
+    pub earliest_available: Option<u32>
+}
+
+/// Gets the name of the update file produced on the given day.
+fn update_file_name(weekd: Weekday) -> &'static str {
+    match weekd {
+        Weekday::Mon => "toc-update-mon",
+        Weekday::Tue => "toc-update-tue",
+        Weekday::Wed => "toc-update-wed",
+        Weekday::Thu => "toc-update-thu",
+        Weekday::Fri => "toc-update-fri",
+        Weekday::Sat => "toc-update-sat",
+        Weekday::Sun => "toc-update-sun",
+    }
+}
+
 pub struct Downloader {
     inner: NrodDownloader
 }
 
 impl Downloader {
-    pub fn new(cfg: &Config) -> Self {
-        let inner = NrodDownloader::new(cfg.username.clone(), cfg.password.clone(), cfg.base_url.clone());
-        Self { inner }
+    pub fn new(cfg: &Config) -> Result<Self> {
+        let username = Config::required(&cfg.username, "username")?.clone();
+        let password = Config::required(&cfg.password, "password")?.clone();
+        let inner = NrodDownloader::new(username, password, cfg.base_url.clone());
+        Ok(Self { inner })
     }
-    fn do_full(&mut self, conn: &mut Connection) -> Result<()> {
+    fn do_full(&mut self, conn: &mut Connection) -> Result<ImportIssues> {
         let data = self.inner.download("/ntrod/CifFileAuthenticate?type=CIF_ALL_FULL_DAILY&day=toc-full")?;
-        import::apply_schedule_records(conn, data)?;
-        Ok(())
+        import::apply_schedule_records(conn, data)
     }
-    pub fn do_init(&mut self, conn: &mut Connection) -> Result<()> {
-        info!("Initializing schedule database...");
-        self.do_full(conn)?;
-        Ok(())
-    }
-    pub fn do_update(&mut self, conn: &mut Connection) -> Result<()> {
-        let time = Utc::now();
-        let weekd = time.weekday().pred();
-        let file = match weekd {
-            Weekday::Mon => "toc-update-mon",
-            Weekday::Tue => "toc-update-tue",
-            Weekday::Wed => "toc-update-wed",
-            Weekday::Thu => "toc-update-thu",
-            Weekday::Fri => "toc-update-fri",
-            Weekday::Sat => "toc-update-sat",
-            Weekday::Sun => "toc-update-sun",
-        };
-        info!("Fetching update file {}...", file);
-        let data = self.inner.download(
+    fn download_update(&mut self, file: &str) -> Result<impl BufRead> {
+        let ret = self.inner.download(
             &format!("/ntrod/CifFileAuthenticate?type=CIF_ALL_UPDATE_DAILY&day={}",
                      file))?;
-        import::apply_schedule_records(conn, data)?;
-        Ok(())
+        Ok(ret)
+    }
+    /// Gets the sequence number of an update file (as given by
+    /// `TimetableRecord::file_sequence`), by reading its first record.
+    ///
+    /// (The rest of the file doesn't get downloaded.)
+    fn peek_sequence(&mut self, file: &str) -> Result<u32> {
+        let mut data = self.download_update(file)?;
+        let mut line = String::new();
+        data.read_line(&mut line)?;
+        match serde_json::from_str(&line)? {
+            schedule::Record::Timetable(rec) => Ok(rec.file_sequence()),
+            _ => bail!("update file {} doesn't start with a Timetable record", file)
+        }
+    }
+    pub fn do_init(&mut self, conn: &mut Connection) -> Result<ImportIssues> {
+        info!("Initializing schedule database...");
+        self.do_full(conn)
+    }
+    /// Brings the database up to date, applying any update files that are
+    /// missing (up to `MAX_BACKFILL_DAYS` of them) in order.
+    ///
+    /// Returns an `UpdateGapError` if the files available don't follow on
+    /// from the last one in the database.
+    pub fn do_update(&mut self, conn: &mut Connection) -> Result<ImportIssues> {
+        let last = last_sequence(conn)?;
+        let missing = find_missing_updates(last, Utc::now().date().naive_utc(), |file| {
+            let seq = self.peek_sequence(file)?;
+            debug!("update file {} has sequence {}", file, seq);
+            Ok(seq)
+        })?;
+        if missing.is_empty() {
+            info!("Already up to date (last file is {})", last);
+            return Ok(ImportIssues::default());
+        }
+        if missing.len() > 1 {
+            warn!("Backfilling {} missed update files", missing.len() - 1);
+        }
+        let mut issues = ImportIssues::default();
+        for (file, seq) in missing {
+            info!("Fetching update file {} (sequence {})...", file, seq);
+            let data = self.download_update(file)?;
+            issues.extend(import::apply_schedule_records(conn, data)?);
+        }
+        Ok(issues)
+    }
+}
+
+/// Gets the sequence number of the last file imported into the database.
+///
+/// Returns an `UpdateGapError` if there aren't any.
+pub fn last_sequence(conn: &Connection) -> Result<u32> {
+    let ret = ScheduleFile::from_select(conn, "ORDER BY sequence DESC LIMIT 1", NO_PARAMS)?
+        .into_iter()
+        .next()
+        .map(|x| x.sequence)
+        .ok_or_else(|| UpdateGapError { last_sequence: 0, earliest_available: None })?;
+    Ok(ret)
+}
+
+/// Works out which update files need applying (oldest first) to follow on
+/// from the file with sequence number `last`, using `peek` to get the
+/// sequence number of each file.
+///
+/// Works backwards from the file produced the day before `today`, for up to
+/// `MAX_BACKFILL_DAYS`, until it finds the file following `last`; if it
+/// doesn't, returns an `UpdateGapError`.
+pub fn find_missing_updates<F>(last: u32, today: NaiveDate, mut peek: F) -> Result<Vec<(&'static str, u32)>>
+    where F: FnMut(&'static str) -> Result<u32> {
+    let mut missing = vec![];
+    for days_ago in 1..=MAX_BACKFILL_DAYS {
+        let file = update_file_name((today - Duration::days(days_ago)).weekday());
+        let seq = peek(file)?;
+        if seq <= last {
+            break;
+        }
+        missing.push((file, seq));
+        if seq == last + 1 {
+            missing.reverse();
+            return Ok(missing);
+        }
+    }
+    if missing.is_empty() {
+        return Ok(missing);
     }
+    Err(UpdateGapError {
+        last_sequence: last,
+        earliest_available: missing.last().map(|x| x.1)
+    }.into())
 }
@@ -0,0 +1,406 @@
+//! Exporting the schedule database as a GTFS static feed.
+//!
+//! Each ITPS schedule with at least two public calls becomes a GTFS trip, with
+//! its own service (in `calendar.txt`). Where a schedule is overridden by a
+//! higher-precedence STP schedule with the same UID (i.e. an overlay or a
+//! cancellation), it gets a "service removed" exception in
+//! `calendar_dates.txt` for each affected date; the overlay, if any, is a
+//! trip of its own.
+//!
+//! Stops are identified by TIPLOC, with names and coordinates taken from
+//! `tspl-nennen` reference data. Calls at TIPLOCs without coordinates are
+//! left out, since GTFS requires them.
+//!
+//! Bank holiday running flags aren't exported.
+
+use tspl_sqlite::traits::*;
+use tspl_fahrplan::types::{Schedule, ScheduleMvt, gtfs_trip_id};
+use tspl_nennen::types::{StationName, WrappedMsnStation};
+use std::collections::{HashMap, HashSet, BTreeSet};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use chrono::*;
+use log::*;
+
+use crate::errors::*;
+
+/// The names of the files produced by the exporter.
+pub static GTFS_FILES: [&str; 8] = [
+    "agency.txt", "stops.txt", "routes.txt", "trips.txt",
+    "stop_times.txt", "calendar.txt", "calendar_dates.txt", "feed_info.txt"
+];
+
+/// GTFS route type for rail services.
+static ROUTE_TYPE_RAIL: u32 = 2;
+/// GTFS route type for bus services.
+static ROUTE_TYPE_BUS: u32 = 3;
+
+/// Quotes a CSV field, if necessary.
+fn csv_field(field: &str) -> String {
+    if field.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    }
+    else {
+        field.to_owned()
+    }
+}
+fn write_row<W: Write>(w: &mut W, fields: &[&str]) -> Result<()> {
+    let line = fields.iter()
+        .map(|x| csv_field(x))
+        .collect::<Vec<_>>()
+        .join(",");
+    writeln!(w, "{}", line)?;
+    Ok(())
+}
+fn gtfs_date(date: NaiveDate) -> String {
+    date.format("%Y%m%d").to_string()
+}
+/// Formats a time for GTFS, which counts hours past midnight on the service
+/// day (so times after midnight are written as e.g. 24:30:00).
+fn gtfs_time(time: NaiveTime, day_offset: u8) -> String {
+    format!("{:02}:{:02}:{:02}", time.hour() + 24 * day_offset as u32, time.minute(), time.second())
+}
+
+/// Converts an Ordnance Survey National Grid reference (in metres) to a
+/// latitude and longitude (in degrees).
+///
+/// This is the inverse transverse Mercator projection on the Airy 1830
+/// ellipsoid, as described in OS's "A guide to coordinate systems in Great
+/// Britain". The resulting coordinates are on the OSGB36 datum, not WGS84; the
+/// difference (up to ~120m) is about the same as the MSN's precision anyway.
+fn osgb_to_latlon(easting: f64, northing: f64) -> (f64, f64) {
+    let a = 6377563.396_f64;
+    let b = 6356256.909_f64;
+    let f0 = 0.9996012717_f64;
+    let lat0 = 49.0_f64.to_radians();
+    let lon0 = (-2.0_f64).to_radians();
+    let n0 = -100000.0_f64;
+    let e0 = 400000.0_f64;
+    let e2 = 1.0 - (b * b) / (a * a);
+    let n = (a - b) / (a + b);
+    let (n2, n3) = (n * n, n * n * n);
+
+    let meridional_arc = |lat: f64| {
+        b * f0 * (
+            (1.0 + n + 1.25 * n2 + 1.25 * n3) * (lat - lat0)
+            - (3.0 * n + 3.0 * n2 + 2.625 * n3) * (lat - lat0).sin() * (lat + lat0).cos()
+            + (1.875 * n2 + 1.875 * n3) * (2.0 * (lat - lat0)).sin() * (2.0 * (lat + lat0)).cos()
+            - (35.0 / 24.0) * n3 * (3.0 * (lat - lat0)).sin() * (3.0 * (lat + lat0)).cos()
+        )
+    };
+    let mut lat = lat0;
+    let mut m = 0.0;
+    loop {
+        lat += (northing - n0 - m) / (a * f0);
+        m = meridional_arc(lat);
+        if (northing - n0 - m).abs() < 0.00001 {
+            break;
+        }
+    }
+    let sin_lat = lat.sin();
+    let nu = a * f0 / (1.0 - e2 * sin_lat * sin_lat).sqrt();
+    let rho = a * f0 * (1.0 - e2) / (1.0 - e2 * sin_lat * sin_lat).powf(1.5);
+    let eta2 = nu / rho - 1.0;
+    let tan_lat = lat.tan();
+    let (t2, t4, t6) = (tan_lat.powi(2), tan_lat.powi(4), tan_lat.powi(6));
+    let sec_lat = 1.0 / lat.cos();
+
+    let vii = tan_lat / (2.0 * rho * nu);
+    let viii = tan_lat / (24.0 * rho * nu.powi(3)) * (5.0 + 3.0 * t2 + eta2 - 9.0 * t2 * eta2);
+    let ix = tan_lat / (720.0 * rho * nu.powi(5)) * (61.0 + 90.0 * t2 + 45.0 * t4);
+    let x = sec_lat / nu;
+    let xi = sec_lat / (6.0 * nu.powi(3)) * (nu / rho + 2.0 * t2);
+    let xii = sec_lat / (120.0 * nu.powi(5)) * (5.0 + 28.0 * t2 + 24.0 * t4);
+    let xiia = sec_lat / (5040.0 * nu.powi(7)) * (61.0 + 662.0 * t2 + 1320.0 * t4 + 720.0 * t6);
+
+    let de = easting - e0;
+    let lat = lat - vii * de.powi(2) + viii * de.powi(4) - ix * de.powi(6);
+    let lon = lon0 + x * de - xi * de.powi(3) + xii * de.powi(5) - xiia * de.powi(7);
+    (lat.to_degrees(), lon.to_degrees())
+}
+
+/// A stop, from the reference data.
+struct Stop {
+    name: String,
+    lat: f64,
+    lon: f64
+}
+
+/// Loads stops from a `tspl-nennen` reference database.
+fn load_stops(refdata: &Connection) -> Result<HashMap<String, Stop>> {
+    let mut names = HashMap::new();
+    for sn in StationName::from_select(refdata, "WHERE tiploc IS NOT NULL", NO_PARAMS)? {
+        names.insert(sn.tiploc.unwrap(), sn.name);
+    }
+    let mut ret = HashMap::new();
+    for msn in WrappedMsnStation::from_select(refdata, "", NO_PARAMS)? {
+        let msn = msn.0;
+        // MSN grid references are in units of 100m, with a fixed offset
+        // added to avoid negative values.
+        let easting = (msn.easting as f64 - 10000.0) * 100.0;
+        let northing = (msn.northing as f64 - 60000.0) * 100.0;
+        let (lat, lon) = osgb_to_latlon(easting, northing);
+        let name = names.get(&msn.tiploc)
+            .cloned()
+            .unwrap_or(msn.name);
+        ret.insert(msn.tiploc, Stop { name, lat, lon });
+    }
+    info!("Loaded {} stops from reference data", ret.len());
+    Ok(ret)
+}
+
+/// A GTFS `stop_times.txt` entry (without its trip or sequence).
+struct StopTime {
+    tiploc: String,
+    arrival: String,
+    departure: String
+}
+
+/// Turns a schedule's movements into calls for passengers.
+///
+/// Arrivals and departures at the same place are merged into one call;
+/// passes, and movements without a public time, are ignored.
+fn mvts_to_stop_times(mvts: &[ScheduleMvt], stops: &HashMap<String, Stop>) -> Vec<StopTime> {
+    let mut ret: Vec<StopTime> = vec![];
+    let mut last_was_arrival = false;
+    for mvt in mvts {
+        let time = match mvt.public_time {
+            Some(t) => gtfs_time(t, mvt.day_offset),
+            None => {
+                last_was_arrival = false;
+                continue;
+            }
+        };
+        if !stops.contains_key(&mvt.tiploc) {
+            continue;
+        }
+        match mvt.action {
+            ScheduleMvt::ACTION_ARRIVAL => {
+                ret.push(StopTime {
+                    tiploc: mvt.tiploc.clone(),
+                    arrival: time.clone(),
+                    departure: time
+                });
+                last_was_arrival = true;
+                continue;
+            },
+            ScheduleMvt::ACTION_DEPARTURE => {
+                if last_was_arrival {
+                    if let Some(last) = ret.last_mut() {
+                        if last.tiploc == mvt.tiploc {
+                            last.departure = time;
+                            last_was_arrival = false;
+                            continue;
+                        }
+                    }
+                }
+                ret.push(StopTime {
+                    tiploc: mvt.tiploc.clone(),
+                    arrival: time.clone(),
+                    departure: time
+                });
+            },
+            _ => {}
+        }
+        last_was_arrival = false;
+    }
+    ret
+}
+
+/// Works out which dates `sched` is overridden on by higher-precedence
+/// schedules in `group` (all of which have the same UID).
+fn removed_dates(sched: &Schedule, group: &[Schedule]) -> BTreeSet<NaiveDate> {
+    let mut ret = BTreeSet::new();
+    for other in group {
+        if other.stp_indicator >= sched.stp_indicator {
+            continue;
+        }
+        let start = ::std::cmp::max(sched.start_date, other.start_date);
+        let end = ::std::cmp::min(sched.end_date, other.end_date);
+        let mut date = start;
+        while date <= end {
+            let wd = date.weekday().number_from_monday();
+            if sched.days.runs_on_iso_weekday(wd) && other.days.runs_on_iso_weekday(wd) {
+                ret.insert(date);
+            }
+            date = date.succ();
+        }
+    }
+    ret
+}
+
+struct GtfsWriters {
+    trips: BufWriter<File>,
+    stop_times: BufWriter<File>,
+    calendar: BufWriter<File>,
+    calendar_dates: BufWriter<File>
+}
+
+pub struct GtfsExporter<'a> {
+    conn: &'a Connection,
+    stops: HashMap<String, Stop>,
+    used_stops: HashSet<String>,
+    /// Routes used, as (agency, route type).
+    routes: BTreeSet<(String, u32)>,
+    start_date: Option<NaiveDate>,
+    end_date: Option<NaiveDate>,
+    trips: usize
+}
+impl<'a> GtfsExporter<'a> {
+    /// Makes a new exporter for the schedule database `conn`, using stops from
+    /// the `tspl-nennen` reference database `refdata`.
+    pub fn new(conn: &'a Connection, refdata: &Connection) -> Result<Self> {
+        Ok(Self {
+            conn,
+            stops: load_stops(refdata)?,
+            used_stops: HashSet::new(),
+            routes: BTreeSet::new(),
+            start_date: None,
+            end_date: None,
+            trips: 0
+        })
+    }
+    fn route_for(sched: &Schedule) -> (String, u32) {
+        let agency = sched.meta.atoc_code.clone()
+            .unwrap_or_else(|| "ZZ".into());
+        let route_type = match sched.meta.train_category.as_ref().map(|x| x as &str) {
+            Some("BR") | Some("BS") => ROUTE_TYPE_BUS,
+            _ => ROUTE_TYPE_RAIL
+        };
+        (agency, route_type)
+    }
+    fn route_id(route: &(String, u32)) -> String {
+        format!("{}-{}", route.0, route.1)
+    }
+    /// Exports all the schedules with a given UID.
+    fn export_group(&mut self, w: &mut GtfsWriters, group: &[Schedule]) -> Result<()> {
+        let conn = self.conn;
+        let mut stmt = conn.prepare_cached("SELECT * FROM schedule_movements WHERE parent_sched = ? ORDER BY day_offset, time, action ASC")?;
+        for sched in group {
+            if sched.stp_indicator == "C" {
+                // Cancellations only show up as exceptions to other schedules.
+                continue;
+            }
+            let mvts = stmt.query_map(params![sched.id], |row| ScheduleMvt::from_row(row, 0))?
+                .collect::<RowResult<Vec<_>>>()?;
+            let stop_times = mvts_to_stop_times(&mvts, &self.stops);
+            if stop_times.len() < 2 {
+                continue;
+            }
+            let trip_id = gtfs_trip_id(&sched.uid, sched.start_date, &sched.stp_indicator);
+            let service_id = trip_id.clone();
+            let route = Self::route_for(sched);
+            let headsign = self.stops.get(&stop_times[stop_times.len() - 1].tiploc)
+                .map(|x| x.name.clone())
+                .unwrap_or_default();
+            write_row(&mut w.trips, &[
+                &Self::route_id(&route),
+                &service_id,
+                &trip_id,
+                &headsign,
+                sched.signalling_id.as_ref().map(|x| x as &str).unwrap_or("")
+            ])?;
+            for (i, st) in stop_times.iter().enumerate() {
+                write_row(&mut w.stop_times, &[
+                    &trip_id,
+                    &st.arrival,
+                    &st.departure,
+                    &st.tiploc,
+                    &(i + 1).to_string()
+                ])?;
+                self.used_stops.insert(st.tiploc.clone());
+            }
+            let d = sched.days;
+            let day = |x| if d.runs_on_iso_weekday(x) { "1" } else { "0" };
+            write_row(&mut w.calendar, &[
+                &service_id,
+                day(1), day(2), day(3), day(4), day(5), day(6), day(7),
+                &gtfs_date(sched.start_date),
+                &gtfs_date(sched.end_date)
+            ])?;
+            for date in removed_dates(sched, group) {
+                write_row(&mut w.calendar_dates, &[&service_id, &gtfs_date(date), "2"])?;
+            }
+            self.routes.insert(route);
+            self.start_date = Some(self.start_date.map(|x| ::std::cmp::min(x, sched.start_date)).unwrap_or(sched.start_date));
+            self.end_date = Some(self.end_date.map(|x| ::std::cmp::max(x, sched.end_date)).unwrap_or(sched.end_date));
+            self.trips += 1;
+        }
+        Ok(())
+    }
+    /// Writes the GTFS feed into the directory `out`.
+    pub fn export(&mut self, out: &Path) -> Result<()> {
+        std::fs::create_dir_all(out)?;
+        let open = |name: &str| -> Result<BufWriter<File>> {
+            Ok(BufWriter::new(File::create(out.join(name))?))
+        };
+        let mut w = GtfsWriters {
+            trips: open("trips.txt")?,
+            stop_times: open("stop_times.txt")?,
+            calendar: open("calendar.txt")?,
+            calendar_dates: open("calendar_dates.txt")?
+        };
+        write_row(&mut w.trips, &["route_id", "service_id", "trip_id", "trip_headsign", "trip_short_name"])?;
+        write_row(&mut w.stop_times, &["trip_id", "arrival_time", "departure_time", "stop_id", "stop_sequence"])?;
+        write_row(&mut w.calendar, &["service_id", "monday", "tuesday", "wednesday", "thursday", "friday", "saturday", "sunday", "start_date", "end_date"])?;
+        write_row(&mut w.calendar_dates, &["service_id", "date", "exception_type"])?;
+
+        info!("Exporting schedules as GTFS...");
+        let scheds = Schedule::from_select(self.conn, "WHERE source = ? ORDER BY uid", &[&Schedule::SOURCE_ITPS])?;
+        let mut group: Vec<Schedule> = vec![];
+        for sched in scheds {
+            if group.len() > 0 && group[0].uid != sched.uid {
+                self.export_group(&mut w, &group)?;
+                group.clear();
+            }
+            group.push(sched);
+        }
+        self.export_group(&mut w, &group)?;
+        w.trips.flush()?;
+        w.stop_times.flush()?;
+        w.calendar.flush()?;
+        w.calendar_dates.flush()?;
+
+        let mut stops = open("stops.txt")?;
+        write_row(&mut stops, &["stop_id", "stop_name", "stop_lat", "stop_lon"])?;
+        for tpl in self.used_stops.iter() {
+            let stop = &self.stops[tpl];
+            write_row(&mut stops, &[tpl, &stop.name, &format!("{:.6}", stop.lat), &format!("{:.6}", stop.lon)])?;
+        }
+        stops.flush()?;
+
+        let mut agency = open("agency.txt")?;
+        let mut routes = open("routes.txt")?;
+        write_row(&mut agency, &["agency_id", "agency_name", "agency_url", "agency_timezone"])?;
+        write_row(&mut routes, &["route_id", "agency_id", "route_short_name", "route_long_name", "route_type"])?;
+        let mut agencies = BTreeSet::new();
+        for route in self.routes.iter() {
+            if agencies.insert(route.0.clone()) {
+                write_row(&mut agency, &[&route.0, &route.0, "https://www.nationalrail.co.uk/", "Europe/London"])?;
+            }
+            let long_name = if route.1 == ROUTE_TYPE_BUS {
+                format!("{} replacement buses", route.0)
+            }
+            else {
+                format!("{} trains", route.0)
+            };
+            write_row(&mut routes, &[&Self::route_id(route), &route.0, &route.0, &long_name, &route.1.to_string()])?;
+        }
+        agency.flush()?;
+        routes.flush()?;
+
+        let mut feed_info = open("feed_info.txt")?;
+        write_row(&mut feed_info, &["feed_publisher_name", "feed_publisher_url", "feed_lang", "feed_start_date", "feed_end_date"])?;
+        write_row(&mut feed_info, &[
+            "trainsplorer",
+            "https://github.com/eeeeeta/trainsplorer",
+            "en",
+            &self.start_date.map(gtfs_date).unwrap_or_default(),
+            &self.end_date.map(gtfs_date).unwrap_or_default()
+        ])?;
+        feed_info.flush()?;
+        info!("Exported {} trips, {} stops and {} routes as GTFS", self.trips, self.used_stops.len(), self.routes.len());
+        Ok(())
+    }
+}
@@ -1,11 +1,20 @@
-//! Functions for importing ITPS JSON schedule records.
+//! Functions for importing ITPS schedule records (from JSON or CIF files).
+//!
+//! Full extracts are big (a few hundred thousand schedules), so this tries
+//! fairly hard not to be slow: JSON records are deserialized borrowing from
+//! the line buffer, statements are cached, and movements get inserted in
+//! batches.
 
-use ntrod_types::schedule::{ScheduleRecord, ScheduleSegment, self};
+use ntrod_types::schedule::{AssociationRecord, AssociationType, DateIndicator};
+use ntrod_types::schedule_ref::{RecordRef, ScheduleRecordRef, ScheduleSegmentRef};
+use ntrod_types::cif_reader::{CifReader, CifError};
 use log::*;
 use tspl_sqlite::traits::*;
 use tspl_fahrplan::types::*;
 use crate::errors::Result;
+use crate::report::ScheduleKey;
 use std::io::BufRead;
+use std::time::Instant;
 use tspl_sqlite::rusqlite::OptionalExtension;
 use chrono::NaiveTime;
 use is_sorted::IsSorted;
@@ -18,237 +27,513 @@ fn midnight_check(day_offset: &mut u8, last_time: &mut NaiveTime, time: NaiveTim
     *last_time = time;
 }
 
-/// Imports a single `ScheduleRecord` into the database.
+/// Overwrites the metadata of the existing schedule `sched` with `meta`,
+/// if any of it has changed. Returns whether anything was written.
 ///
-/// Can be called with a record that's already in the database; in this case, the record will be
-/// updated.
-pub fn apply_schedule_record(conn: &Connection, rec: ScheduleRecord, metaseq: u32) -> Result<()> {
+/// Metadata isn't versioned like the movements are, so whatever was there
+/// before is lost.
+pub fn update_schedule_meta(conn: &Connection, sched: &Schedule, meta: &ScheduleMeta) -> Result<bool> {
+    if sched.meta == *meta {
+        return Ok(false);
+    }
+    conn.prepare_cached("UPDATE schedules
+                         SET train_category = ?, headcode = ?, business_sector = ?,
+                             power_type = ?, timing_load = ?, speed = ?,
+                             operating_characteristics = ?, train_class = ?,
+                             sleepers = ?, reservations = ?, catering_code = ?,
+                             service_branding = ?, atoc_code = ?,
+                             bank_holiday_running = ?
+                         WHERE id = ?")?
+        .execute(params![meta.train_category, meta.headcode, meta.business_sector,
+                 meta.power_type, meta.timing_load, meta.speed,
+                 meta.operating_characteristics, meta.train_class,
+                 meta.sleepers, meta.reservations, meta.catering_code,
+                 meta.service_branding, meta.atoc_code,
+                 meta.bank_holiday_running, sched.id])?;
+    Ok(true)
+}
+
+/// Imports a single `AssociationRecord` into the database.
+///
+/// As with `Importer::apply_schedule_record`, existing associations with the same
+/// identifying fields get replaced.
+pub fn apply_association_record(conn: &Connection, rec: AssociationRecord, metaseq: u32) -> Result<()> {
     match rec {
-        ScheduleRecord::Delete { train_uid, schedule_start_date, stp_indicator, ..} => {
-            info!("deleting schedules (UID {}, start {}, stp_indicator {:?})",
-            train_uid, schedule_start_date, stp_indicator);
-            conn.execute("DELETE FROM schedules
-                          WHERE uid = ? AND start_date = ? AND stp_indicator = ? AND source = ?",
-                          params![train_uid, schedule_start_date.naive_utc(), stp_indicator.as_char().to_string(), Schedule::SOURCE_ITPS])?;
+        AssociationRecord::Delete { main_train_uid, assoc_train_uid, assoc_start_date, location, base_location_suffix, stp_indicator, .. } => {
+            debug!("deleting associations ({} -> {} at {}, start {}, stp_indicator {:?})",
+            main_train_uid, assoc_train_uid, location, assoc_start_date, stp_indicator);
+            conn.prepare_cached("DELETE FROM associations
+                                 WHERE main_uid = ? AND assoc_uid = ? AND start_date = ?
+                                 AND tiploc = ? AND base_suffix IS ? AND stp_indicator = ? AND source = ?")?
+                .execute(params![main_train_uid, assoc_train_uid, assoc_start_date.naive_utc().date(),
+                         location, base_location_suffix, stp_indicator.as_char().to_string(), Schedule::SOURCE_ITPS])?;
             Ok(())
         },
-        ScheduleRecord::Create {
-            train_uid,
-            schedule_start_date,
-            schedule_end_date,
-            schedule_days_runs,
+        AssociationRecord::Create {
+            main_train_uid,
+            assoc_train_uid,
+            assoc_start_date,
+            assoc_end_date,
+            assoc_days,
+            category,
+            date_indicator,
+            location,
+            base_location_suffix,
+            assoc_location_suffix,
             stp_indicator,
-            schedule_segment,
             ..
         } => {
-            use ntrod_types::schedule::LocationRecord::*;
+            debug!("inserting association ({} -> {} at {}, start {}, stp_indicator {:?})",
+            main_train_uid, assoc_train_uid, location, assoc_start_date, stp_indicator);
+            let category = match category {
+                AssociationType::Join => Association::CATEGORY_JOIN,
+                AssociationType::Divide => Association::CATEGORY_DIVIDE,
+                AssociationType::Next => Association::CATEGORY_NEXT,
+                AssociationType::None => Association::CATEGORY_NONE,
+            };
+            let date_indicator = match date_indicator {
+                Some(DateIndicator::NextMidnight) => Association::DATE_NEXT_MIDNIGHT,
+                Some(DateIndicator::PrevMidnight) => Association::DATE_PREV_MIDNIGHT,
+                _ => Association::DATE_STANDARD
+            };
+            let assoc = Association {
+                id: -1,
+                main_uid: main_train_uid,
+                assoc_uid: assoc_train_uid,
+                start_date: assoc_start_date.naive_utc().date(),
+                end_date: assoc_end_date.naive_utc().date(),
+                days: assoc_days.into(),
+                category,
+                date_indicator,
+                tiploc: location,
+                base_suffix: base_location_suffix,
+                assoc_suffix: assoc_location_suffix,
+                stp_indicator: stp_indicator.as_char().to_string(),
+                source: Schedule::SOURCE_ITPS,
+                file_metaseq: Some(metaseq)
+            };
+            // Associations don't have anything hanging off them, so
+            // replacing them wholesale is fine.
+            conn.prepare_cached("DELETE FROM associations
+                                 WHERE main_uid = ? AND assoc_uid = ? AND start_date = ?
+                                 AND tiploc = ? AND base_suffix IS ? AND stp_indicator = ? AND source = ?")?
+                .execute(params![assoc.main_uid, assoc.assoc_uid, assoc.start_date,
+                         assoc.tiploc, assoc.base_suffix, assoc.stp_indicator, assoc.source])?;
+            assoc.insert_self(conn)?;
+            Ok(())
+        }
+    }
+}
 
-            info!("inserting record (UID {}, start {}, stp_indicator {:?})",
-            train_uid, schedule_start_date, stp_indicator);
+/// Problems noticed while importing schedule files, which can't be found
+/// by looking at the database afterwards (see the `report` module).
+#[derive(Default, Debug)]
+pub struct ImportIssues {
+    /// Schedules whose movements weren't in order (and had to be sorted).
+    pub unsorted_mvts: Vec<ScheduleKey>
+}
+impl ImportIssues {
+    /// Adds the issues from another import to this one.
+    pub fn extend(&mut self, other: ImportIssues) {
+        self.unsorted_mvts.extend(other.unsorted_mvts);
+    }
+}
 
-            let ScheduleSegment {
-                schedule_location,
-                signalling_id,
-                ..
-            } = schedule_segment;
+/// State for importing one schedule file.
+struct Importer<'a> {
+    conn: &'a Connection,
+    /// Sequence number of the file (from its Timetable record).
+    metaseq: Option<u32>,
+    /// Movements waiting to be inserted with `ScheduleMvt::insert_batch`.
+    pending_mvts: Vec<ScheduleMvt>,
+    /// Number of schedules & associations applied so far.
+    applied: usize,
+    issues: ImportIssues,
+    started: Instant
+}
 
-            // We *could* use an UPSERT here. (in fact, the postgres version did).
-            // However, it makes the INSERT query mighty complicated,
-            // and it probably isn't worth having the spaghetti SQL.
-            //
-            // Also because this is single-threaded, we don't need to worry about
-            // racing.
-            let sid: Option<i64> = conn.query_row(
-                "SELECT id FROM schedules
-                 WHERE uid = ? AND start_date = ? AND stp_indicator = ? AND source = ?",
-                 params![train_uid, schedule_start_date.naive_utc(), stp_indicator.as_char().to_string(), Schedule::SOURCE_ITPS],
-                 |row| row.get(0))
-                .optional()?;
-            let (sid, updated) = match sid {
-                Some(s) => (s, true),
-                None => {
-                    let sched = Schedule {
-                        id: -1,
-                        tspl_id: Uuid::new_v4(),
-                        uid: train_uid.clone(),
-                        start_date: schedule_start_date.naive_utc(),
-                        end_date: schedule_end_date.naive_utc(),
-                        days: schedule_days_runs.into(),
-                        stp_indicator: stp_indicator.as_char().to_string(),
-                        signalling_id,
-                        source: Schedule::SOURCE_ITPS,
-                        file_metaseq: Some(metaseq),
-                        darwin_id: None,
-                        crosses_midnight: false
-                    };
-                    (sched.insert_self(conn)?, false)
+impl<'a> Importer<'a> {
+    fn new(conn: &'a Connection) -> Self {
+        Self {
+            conn,
+            metaseq: None,
+            pending_mvts: Vec::with_capacity(ScheduleMvt::BATCH_SIZE),
+            applied: 0,
+            issues: ImportIssues::default(),
+            started: Instant::now()
+        }
+    }
+    fn metaseq(&self) -> Result<u32> {
+        match self.metaseq {
+            Some(ms) => Ok(ms),
+            None => bail!("file contained no Timetable record!")
+        }
+    }
+    /// Queues a movement for insertion, inserting a batch of them if there
+    /// are enough.
+    fn queue_mvt(&mut self, mvt: ScheduleMvt) -> Result<()> {
+        self.pending_mvts.push(mvt);
+        if self.pending_mvts.len() >= ScheduleMvt::BATCH_SIZE {
+            self.flush_mvts()?;
+        }
+        Ok(())
+    }
+    /// Inserts all queued movements.
+    ///
+    /// Must be called before doing anything that looks at (or deletes)
+    /// existing movements.
+    fn flush_mvts(&mut self) -> Result<()> {
+        ScheduleMvt::insert_batch(self.conn, &self.pending_mvts)?;
+        self.pending_mvts.clear();
+        Ok(())
+    }
+    /// Imports a single record from a schedule file.
+    fn apply_record(&mut self, rec: RecordRef) -> Result<()> {
+        match rec {
+            RecordRef::Schedule(rec) => {
+                let ms = self.metaseq()?;
+                self.apply_schedule_record(rec, ms)?;
+                self.applied += 1;
+            },
+            RecordRef::Association(rec) => {
+                let ms = self.metaseq()?;
+                apply_association_record(self.conn, rec, ms)?;
+                self.applied += 1;
+            },
+            RecordRef::Timetable(rec) => {
+                // NROD's sequence numbers don't exist for CIF files, so
+                // number every file by its extract date instead.
+                let sequence = rec.file_sequence();
+                info!("this is a {}-type timetable (seq {}, NROD seq {}) from {} (ts: {})",
+                       rec.metadata.ty, sequence, rec.metadata.sequence, rec.owner, rec.timestamp);
+                debug!("checking whether this timetable is new...");
+                let files = ScheduleFile::from_select(self.conn, "WHERE timestamp = ?", params![rec.timestamp])?;
+                if files.len() > 0 {
+                    bail!("schedule inserted already!");
                 }
-            };
+                let full = ScheduleFile::from_select(self.conn, "WHERE sequence > ?", params![sequence])?;
+                if full.len() > 0 {
+                    bail!("a schedule with a greater sequence number has been inserted!");
+                }
+                debug!("inserting file record...");
+                let file = ScheduleFile {
+                    timestamp: rec.timestamp,
+                    sequence
+                };
+                self.metaseq = Some(sequence);
+                file.insert_self(self.conn)?;
+                debug!("timetable OK");
+            },
+            _ => {}
+        }
+        Ok(())
+    }
+    /// Imports a single `ScheduleRecordRef` into the database.
+    ///
+    /// Can be called with a record that's already in the database; in this case, the record will be
+    /// updated.
+    fn apply_schedule_record(&mut self, rec: ScheduleRecordRef, metaseq: u32) -> Result<()> {
+        let conn = self.conn;
+        match rec {
+            ScheduleRecordRef::Delete { train_uid, schedule_start_date, stp_indicator, ..} => {
+                debug!("deleting schedules (UID {}, start {}, stp_indicator {:?})",
+                train_uid, schedule_start_date, stp_indicator);
+                // The movements get deleted too, so don't leave any of them queued.
+                self.flush_mvts()?;
+                let uid: &str = &train_uid;
+                // Keep the deleted version around, so its trainsplorer ID
+                // can still be looked up.
+                let sid: Option<i64> = conn.prepare_cached("SELECT id FROM schedules
+                                                            WHERE uid = ? AND start_date = ? AND stp_indicator = ? AND source = ?")?
+                    .query_row(params![uid, schedule_start_date.naive_utc(), stp_indicator.as_char().to_string(), Schedule::SOURCE_ITPS],
+                               |row| row.get(0))
+                    .optional()?;
+                if let Some(sid) = sid {
+                    ScheduleVersion::delete_schedule(conn, sid, Some(metaseq))?;
+                }
+                Ok(())
+            },
+            ScheduleRecordRef::Create {
+                train_uid,
+                schedule_start_date,
+                schedule_end_date,
+                schedule_days_runs,
+                bank_holiday_running,
+                stp_indicator,
+                atoc_code,
+                schedule_segment,
+                ..
+            } => {
+                use ntrod_types::schedule_ref::LocationRecordRef::*;
 
-            // Convert the schedule's movements into ScheduleMvts (without ids).
-            let mut mvts = vec![];
-            let mut day_offset = 0;
-            let mut last_time = NaiveTime::from_hms(0, 0, 0);
+                debug!("inserting record (UID {}, start {}, stp_indicator {:?})",
+                train_uid, schedule_start_date, stp_indicator);
 
-            for loc in schedule_location {
-                match loc {
-                    Originating { tiploc_code, departure, platform, public_departure, .. } => {
-                        // The `midnight_check` function is called every time we get a time
-                        // to update the `day_offset` if we just went past midnight.
-                        midnight_check(&mut day_offset, &mut last_time, departure);
-                        mvts.push(ScheduleMvt {
-                            tiploc: tiploc_code,
-                            time: departure,
-                            public_time: public_departure,
-                            action: ScheduleMvt::ACTION_DEPARTURE,
-                            platform,
-                            day_offset,
-                            ..ScheduleMvt::dummy()
-                        });
-                    },
-                    Intermediate { tiploc_code, arrival, public_arrival, public_departure, departure, platform, .. } => {
-                        midnight_check(&mut day_offset, &mut last_time, arrival);
-                        mvts.push(ScheduleMvt {
-                            tiploc: tiploc_code.clone(),
-                            time: arrival,
-                            public_time: public_arrival,
-                            action: ScheduleMvt::ACTION_ARRIVAL,
-                            platform: platform.clone(),
-                            day_offset,
-                            ..ScheduleMvt::dummy()
-                        });
-                        midnight_check(&mut day_offset, &mut last_time, departure);
-                        mvts.push(ScheduleMvt {
-                            tiploc: tiploc_code.clone(),
-                            time: departure,
-                            public_time: public_departure,
-                            action: ScheduleMvt::ACTION_DEPARTURE,
-                            platform,
-                            day_offset,
-                            ..ScheduleMvt::dummy()
-                        });
-                    },
-                    Pass { tiploc_code, pass, .. } => {
-                        midnight_check(&mut day_offset, &mut last_time, pass);
-                        mvts.push(ScheduleMvt {
-                            tiploc: tiploc_code.clone(),
-                            time: pass,
-                            action: ScheduleMvt::ACTION_PASS,
-                            day_offset,
-                            ..ScheduleMvt::dummy()
-                        });
+                let ScheduleSegmentRef {
+                    schedule_location,
+                    signalling_id,
+                    train_category,
+                    headcode,
+                    business_sector,
+                    power_type,
+                    timing_load,
+                    speed,
+                    operating_characteristics,
+                    train_class,
+                    sleepers,
+                    reservations,
+                    catering_code,
+                    service_branding,
+                } = schedule_segment;
+                let meta = ScheduleMeta {
+                    train_category: train_category.map(|x| x.as_str().to_owned()).filter(|x| x != ""),
+                    power_type: power_type.map(|x| x.as_str().to_owned()).filter(|x| x != ""),
+                    headcode: headcode.map(|x| x.into_owned()),
+                    business_sector: business_sector.map(|x| x.into_owned()),
+                    timing_load: timing_load.map(|x| x.into_owned()),
+                    speed,
+                    operating_characteristics: operating_characteristics.map(|x| x.into_owned()),
+                    train_class: train_class.map(|x| x.into_owned()),
+                    sleepers: sleepers.map(|x| x.into_owned()),
+                    reservations: reservations.map(|x| x.into_owned()),
+                    catering_code: catering_code.map(|x| x.into_owned()),
+                    service_branding: service_branding.map(|x| x.into_owned()),
+                    atoc_code: atoc_code.map(|x| x.into_owned()),
+                    bank_holiday_running: bank_holiday_running.map(|x| x.into_owned())
+                };
+
+                // We *could* use an UPSERT here. (in fact, the postgres version did).
+                // However, it makes the INSERT query mighty complicated,
+                // and it probably isn't worth having the spaghetti SQL.
+                //
+                // Also because this is single-threaded, we don't need to worry about
+                // racing.
+                let uid: &str = &train_uid;
+                let existing: Option<Schedule> = conn.prepare_cached(
+                    "SELECT * FROM schedules
+                     WHERE uid = ? AND start_date = ? AND stp_indicator = ? AND source = ?")?
+                    .query_row(params![uid, schedule_start_date.naive_utc(), stp_indicator.as_char().to_string(), Schedule::SOURCE_ITPS],
+                     |row| Schedule::from_row(row, 0))
+                    .optional()?;
+                let (sid, updated) = match existing {
+                    Some(s) => {
+                        if update_schedule_meta(conn, &s, &meta)? {
+                            debug!("updated metadata (UID {}, start {}, stp_indicator {:?})", train_uid, schedule_start_date, stp_indicator);
+                        }
+                        (s.id, true)
                     },
-                    Terminating { tiploc_code, arrival, public_arrival, platform, .. } => {
-                        midnight_check(&mut day_offset, &mut last_time, arrival);
-                        mvts.push(ScheduleMvt {
-                            tiploc: tiploc_code.clone(),
-                            time: arrival,
-                            public_time: public_arrival,
-                            action: ScheduleMvt::ACTION_ARRIVAL,
-                            platform,
-                            day_offset,
-                            ..ScheduleMvt::dummy()
-                        });
+                    None => {
+                        let sched = Schedule {
+                            id: -1,
+                            tspl_id: Uuid::new_v4(),
+                            uid: uid.to_owned(),
+                            start_date: schedule_start_date.naive_utc(),
+                            end_date: schedule_end_date.naive_utc(),
+                            days: schedule_days_runs.into(),
+                            stp_indicator: stp_indicator.as_char().to_string(),
+                            signalling_id: signalling_id.map(|x| x.into_owned()),
+                            source: Schedule::SOURCE_ITPS,
+                            file_metaseq: Some(metaseq),
+                            darwin_id: None,
+                            crosses_midnight: false,
+                            meta
+                        };
+                        (sched.insert_self(conn)?, false)
+                    }
+                };
+
+                // Convert the schedule's movements into ScheduleMvts (without ids).
+                let mut mvts = Vec::with_capacity(schedule_location.len() + 8);
+                let mut day_offset = 0;
+                let mut last_time = NaiveTime::from_hms(0, 0, 0);
+                // CIF allowances apply between the location that shows them and
+                // the next timing point, so they get attached to the movement
+                // at that next timing point instead.
+                let mut next_allowance = 0;
+
+                for loc in schedule_location {
+                    match loc {
+                        Originating { tiploc_code, departure, platform, public_departure, activity,
+                                      engineering_allowance, pathing_allowance, performance_allowance, .. } => {
+                            // The `midnight_check` function is called every time we get a time
+                            // to update the `day_offset` if we just went past midnight.
+                            midnight_check(&mut day_offset, &mut last_time, departure);
+                            mvts.push(ScheduleMvt {
+                                parent_sched: sid,
+                                tiploc: tiploc_code.into_owned(),
+                                time: departure,
+                                public_time: public_departure,
+                                action: ScheduleMvt::ACTION_DEPARTURE,
+                                platform: platform.map(|x| x.into_owned()),
+                                day_offset,
+                                allowance: next_allowance,
+                                activity: activity.map(|x| x.into_owned()),
+                                ..ScheduleMvt::dummy()
+                            });
+                            next_allowance = ScheduleMvt::total_allowance(&[&engineering_allowance, &pathing_allowance, &performance_allowance]);
+                        },
+                        Intermediate { tiploc_code, arrival, public_arrival, public_departure, departure, platform, activity,
+                                       engineering_allowance, pathing_allowance, performance_allowance, .. } => {
+                            let platform = platform.map(|x| x.into_owned());
+                            let activity = activity.map(|x| x.into_owned());
+                            midnight_check(&mut day_offset, &mut last_time, arrival);
+                            mvts.push(ScheduleMvt {
+                                parent_sched: sid,
+                                tiploc: tiploc_code.to_string(),
+                                time: arrival,
+                                public_time: public_arrival,
+                                action: ScheduleMvt::ACTION_ARRIVAL,
+                                platform: platform.clone(),
+                                day_offset,
+                                allowance: next_allowance,
+                                activity: activity.clone(),
+                                ..ScheduleMvt::dummy()
+                            });
+                            midnight_check(&mut day_offset, &mut last_time, departure);
+                            mvts.push(ScheduleMvt {
+                                parent_sched: sid,
+                                tiploc: tiploc_code.into_owned(),
+                                time: departure,
+                                public_time: public_departure,
+                                action: ScheduleMvt::ACTION_DEPARTURE,
+                                platform,
+                                day_offset,
+                                activity,
+                                ..ScheduleMvt::dummy()
+                            });
+                            next_allowance = ScheduleMvt::total_allowance(&[&engineering_allowance, &pathing_allowance, &performance_allowance]);
+                        },
+                        Pass { tiploc_code, pass, engineering_allowance, pathing_allowance, performance_allowance, .. } => {
+                            midnight_check(&mut day_offset, &mut last_time, pass);
+                            mvts.push(ScheduleMvt {
+                                parent_sched: sid,
+                                tiploc: tiploc_code.into_owned(),
+                                time: pass,
+                                action: ScheduleMvt::ACTION_PASS,
+                                day_offset,
+                                allowance: next_allowance,
+                                ..ScheduleMvt::dummy()
+                            });
+                            next_allowance = ScheduleMvt::total_allowance(&[&engineering_allowance, &pathing_allowance, &performance_allowance]);
+                        },
+                        Terminating { tiploc_code, arrival, public_arrival, platform, activity, .. } => {
+                            midnight_check(&mut day_offset, &mut last_time, arrival);
+                            mvts.push(ScheduleMvt {
+                                parent_sched: sid,
+                                tiploc: tiploc_code.into_owned(),
+                                time: arrival,
+                                public_time: public_arrival,
+                                action: ScheduleMvt::ACTION_ARRIVAL,
+                                platform: platform.map(|x| x.into_owned()),
+                                day_offset,
+                                allowance: next_allowance,
+                                activity: activity.map(|x| x.into_owned()),
+                                ..ScheduleMvt::dummy()
+                            });
+                        }
                     }
                 }
-            }
-            // (`is_sorted` is unstable, so we use a crate)
-            if !IsSorted::is_sorted(&mut mvts.iter()) {
-                // If the movements aren't sorted, it means:
-                // - ITPS borked and gave us something in a weird order
-                // - Our ordering algorithm is wrong
-                //
-                // Either way, yell loudly. We still want to sort the movements,
-                // just so the /rest/ of the code doesn't break >_>
-                // (which assumes proper sorting)
-                error!("mvts not sorted! (UID {}, start {}, stp_indicator {:?})", train_uid, schedule_start_date, stp_indicator);
-                error!("mvts: {:#?}", mvts);
-                mvts.sort_unstable();
-            }
-            if updated {
-                // If the record already exists, check if the movements are equal.
-                // If they aren't, we just update the trainsplorer ID and replace all
-                // the movements; since we've got our own DB, nobody's going to care about
-                // the movements we deleted. If they are, wisely do nothing.
-                //
-                // (Yay, microservices!)
-                info!("duplicate record (UID {}, start {}, stp_indicator {:?})", train_uid, schedule_start_date, stp_indicator);
-                let orig_mvts = ScheduleMvt::from_select(conn, "WHERE parent_sched = ? ORDER BY day_offset, time, action ASC", &[&sid])?;
-                if orig_mvts != mvts {
-                    info!("replacing movements; record is new version");
-                    // Ahahaha, so much simpler!
-                    conn.execute("DELETE FROM schedule_movements WHERE parent_sched = ?", params![sid])?;
-                    conn.execute("UPDATE schedules SET tspl_id = ? WHERE id = ?", params![Uuid::new_v4(), sid])?;
+                // (`is_sorted` is unstable, so we use a crate)
+                if !IsSorted::is_sorted(&mut mvts.iter()) {
+                    // If the movements aren't sorted, it means:
+                    // - ITPS borked and gave us something in a weird order
+                    // - Our ordering algorithm is wrong
+                    //
+                    // Either way, yell loudly. We still want to sort the movements,
+                    // just so the /rest/ of the code doesn't break >_>
+                    // (which assumes proper sorting)
+                    error!("mvts not sorted! (UID {}, start {}, stp_indicator {:?})", train_uid, schedule_start_date, stp_indicator);
+                    error!("mvts: {:#?}", mvts);
+                    mvts.sort_unstable();
+                    self.issues.unsorted_mvts.push(ScheduleKey {
+                        uid: train_uid.to_string(),
+                        start_date: schedule_start_date.naive_utc(),
+                        stp_indicator: stp_indicator.as_char().to_string(),
+                        source: Schedule::SOURCE_ITPS
+                    });
                 }
-                else {
-                    debug!("leaving movements untouched");
-                    return Ok(());
+                if updated {
+                    // If the record already exists, check if the movements are equal.
+                    // If they aren't, we archive the current version (so people holding
+                    // its trainsplorer ID can still find it), then update the trainsplorer
+                    // ID and replace all the movements. If they are, wisely do nothing.
+                    debug!("duplicate record (UID {}, start {}, stp_indicator {:?})", train_uid, schedule_start_date, stp_indicator);
+                    // Make sure we're looking at all of the existing movements.
+                    self.flush_mvts()?;
+                    let orig_mvts = ScheduleMvt::from_select(conn, "WHERE parent_sched = ? ORDER BY day_offset, time, action ASC", &[&sid])?;
+                    if orig_mvts != mvts {
+                        info!("replacing movements; record (UID {}, start {}, stp_indicator {:?}) is new version",
+                              train_uid, schedule_start_date, stp_indicator);
+                        let new_id = Uuid::new_v4();
+                        ScheduleVersion::archive(conn, sid, Some(new_id), Some(metaseq))?;
+                        conn.prepare_cached("DELETE FROM schedule_movements WHERE parent_sched = ?")?
+                            .execute(params![sid])?;
+                        conn.prepare_cached("UPDATE schedules SET tspl_id = ?, file_metaseq = ? WHERE id = ?")?
+                            .execute(params![new_id, metaseq, sid])?;
+                    }
+                    else {
+                        debug!("leaving movements untouched");
+                        return Ok(());
+                    }
                 }
+                for mvt in mvts {
+                    self.queue_mvt(mvt)?;
+                }
+                Ok(())
             }
-            for mut mvt in mvts {
-                mvt.parent_sched = sid;
-                mvt.insert_self(conn)?;
-            }
-            Ok(())
         }
     }
+    /// Inserts anything left over, logs how fast the import went, and
+    /// returns the issues noticed along the way.
+    fn finish(mut self) -> Result<ImportIssues> {
+        self.flush_mvts()?;
+        let elapsed = self.started.elapsed();
+        let secs = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1_000_000_000.0;
+        info!("applied {} schedule entries in {:.1}s ({:.0} entries/sec)",
+              self.applied, secs, self.applied as f64 / secs.max(0.001));
+        Ok(self.issues)
+    }
 }
 
-/// Imports a file containing schedule records into the database.
-pub fn apply_schedule_records<R: BufRead>(conn: &mut Connection, mut rdr: R) -> Result<()> {
-    let mut inserted = 0;
+/// Imports a file containing JSON schedule records into the database.
+pub fn apply_schedule_records<R: BufRead>(conn: &mut Connection, mut rdr: R) -> Result<ImportIssues> {
     let trans = conn.transaction()?;
-    let mut metaseq = None;
+    let mut imp = Importer::new(&trans);
     let mut line = String::new();
-    // Reduce allocations!
+    // Reuse the same line buffer, and borrow strings from it where possible,
+    // to avoid allocating a new `String` for every single field.
     while rdr.read_line(&mut line)? > 0 {
-        // (I mean, we still allocate here...)
-        // FIXME(perf): could potentially use serde zero-copy here
-        let rec: ::std::result::Result<schedule::Record, _> = serde_json::from_str(&line);
+        match serde_json::from_str::<RecordRef>(&line) {
+            Ok(rec) => imp.apply_record(rec)?,
+            Err(e) => {
+                warn!("error parsing: {}", e);
+                debug!("line was: {}", line);
+            }
+        }
+        line.clear();
+    }
+    let issues = imp.finish()?;
+    trans.commit()?;
+    Ok(issues)
+}
+
+/// Imports a fixed-width CIF file (e.g. an RDG `.MCA` timetable file) into
+/// the database.
+pub fn apply_cif_records<R: BufRead>(conn: &mut Connection, rdr: R) -> Result<ImportIssues> {
+    let trans = conn.transaction()?;
+    let mut imp = Importer::new(&trans);
+    for rec in CifReader::new(rdr) {
         let rec = match rec {
             Ok(r) => r,
+            Err(CifError::Io(e)) => Err(e)?,
             Err(e) => {
                 warn!("error parsing: {}", e);
-                debug!("line was: {}", line);
                 continue;
             }
         };
-        match rec {
-            schedule::Record::Schedule(rec) => {
-                if let Some(ms) = metaseq {
-                    apply_schedule_record(&trans, rec, ms)?;
-                    inserted += 1;
-                }
-                else {
-                    bail!("file contained no Timetable record!");
-                }
-            },
-            schedule::Record::Timetable(rec) => {
-                info!("this is a {}-type timetable (seq {}) from {} (ts: {})",
-                       rec.metadata.ty, rec.metadata.sequence, rec.owner, rec.timestamp);
-                debug!("checking whether this timetable is new...");
-                let files = ScheduleFile::from_select(&trans, "WHERE timestamp = ?", params![rec.timestamp])?;
-                if files.len() > 0 {
-                    bail!("schedule inserted already!");
-                }
-                let full = ScheduleFile::from_select(&trans, "WHERE sequence > ?", params![rec.metadata.sequence])?;
-                if full.len() > 0 {
-                    bail!("a schedule with a greater sequence number has been inserted!");
-                }
-                debug!("inserting file record...");
-                let file = ScheduleFile {
-                    timestamp: rec.timestamp,
-                    sequence: rec.metadata.sequence
-                };
-                metaseq = Some(rec.metadata.sequence);
-                file.insert_self(&trans)?;
-                debug!("timetable OK");
-            },
-            _ => {}
-        }
-        line.clear();
+        imp.apply_record(rec.into())?;
     }
+    let issues = imp.finish()?;
     trans.commit()?;
-    info!("applied {} schedule entries", inserted);
-    Ok(())
+    Ok(issues)
 }
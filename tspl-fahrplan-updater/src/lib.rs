@@ -0,0 +1,10 @@
+//! Bits of the updater that are useful outside the binary (e.g. for benchmarking the import).
+
+pub mod errors;
+pub mod import;
+pub mod report;
+pub mod config;
+pub mod download;
+
+#[cfg(test)]
+mod tests;
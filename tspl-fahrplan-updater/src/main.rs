@@ -1,34 +1,69 @@
 //! Downloads and updates the tspl-fahrplan ITPS schedules.
 
-pub mod errors;
-pub mod import;
-pub mod config;
-pub mod download;
 pub mod db_init;
 pub mod updater;
+pub mod gtfs;
+pub mod offline;
+
+// These live in the library crate, so the benchmarks and tests can use them too.
+use tspl_fahrplan_updater::{errors, import, report, config, download};
 
 use crate::config::Config;
 use crate::updater::DatabaseUpdater;
+use crate::download::UpdateGapError;
+use crate::gtfs::GtfsExporter;
+use crate::report::ValidationReport;
 use tspl_util::ConfigExt;
-use tspl_gcs::CloudStorage;
+use tspl_gcs::ObjectStorage;
 use tspl_gcs::errors::GcsError;
+use tspl_sqlite::rusqlite::Connection;
 use std::path::Path;
 use log::*;
 
 static CURRENT_OBJ: &str = "current.sqlite";
 static CURRENT_PATH: &str = "./current.sqlite";
 static BACKUP_PATH: &str = "./backup.sqlite";
+static REFDATA_OBJ: &str = "refdata.sqlite";
+static REFDATA_PATH: &str = "./refdata.sqlite";
+static GTFS_PATH: &str = "./gtfs";
+static REPORT_OBJ: &str = "validation-report.json";
+static REPORT_PATH: &str = "./validation-report.json";
+
+/// Downloads and opens the `tspl-nennen` reference database.
+fn download_refdata(storage: &mut dyn ObjectStorage, cfg: &Config) -> errors::Result<Connection> {
+    let refdata_obj = cfg.refdata_object_name.as_ref().map(|x| x as &str).unwrap_or(REFDATA_OBJ);
+    storage.download_object(refdata_obj, REFDATA_PATH)?;
+    Ok(tspl_sqlite::initialize_db(REFDATA_PATH, &tspl_nennen::types::MIGRATIONS)?)
+}
 
 fn main() -> errors::Result<()> {
     tspl_util::setup_logging()?;
     info!("tspl-zugfuhrer-updater, but not yet");
     info!("loading config");
     let cfg = Config::load()?;
-    info!("initialising GCS (bucket = {})", cfg.bucket_name);
-    let mut gcs = CloudStorage::init(cfg.bucket_name.clone(), &cfg.service_account_key_path)?;
+    if let Some(ref files) = cfg.offline_files {
+        info!("running in offline mode");
+        let db_path = cfg.offline_db_path.as_ref().map(|x| x as &str).unwrap_or(CURRENT_PATH);
+        let (db, issues) = offline::import_local_files(files, db_path)?;
+        let refdata = match cfg.offline_refdata_path {
+            Some(ref path) => Some(tspl_sqlite::initialize_db(path, &tspl_nennen::types::MIGRATIONS)?),
+            None => None
+        };
+        let report_path = cfg.offline_report_path.as_ref().map(|x| x as &str).unwrap_or(REPORT_PATH);
+        info!("writing data-quality report to {}", report_path);
+        let report = ValidationReport::generate(&db, refdata.as_ref(), issues)?;
+        report.log_summary();
+        report.write(Path::new(report_path))?;
+        info!("done!");
+        return Ok(());
+    }
+    let mut storage = cfg.storage.open()?;
+    // Only upload the report if it's from this run (rather than some
+    // leftover from a previous one).
+    let mut report_written = false;
     if !Path::new(BACKUP_PATH).exists() {
         info!("attempting to download current database...");
-        match gcs.download_object(CURRENT_OBJ, CURRENT_PATH) {
+        match storage.download_object(CURRENT_OBJ, CURRENT_PATH) {
             Ok(_) => info!("download successful"),
             Err(GcsError::NotFound) => warn!("current database not found"),
             Err(e) => {
@@ -38,14 +73,39 @@ fn main() -> errors::Result<()> {
         }
         info!("opening database");
         let (mut db, update) = db_init::load_or_create_db(CURRENT_PATH)?;
-        let mut updater = DatabaseUpdater::new(&mut db, &cfg);
+        let mut issues = None;
         if update {
-            updater.update()?;
-        }
-        else {
-            info!("downloading full schedule file...");
-            updater.init()?;
+            let ret = DatabaseUpdater::new(&mut db, &cfg)?.update();
+            match ret {
+                Ok(i) => issues = Some(i),
+                Err(e) => {
+                    if e.downcast_ref::<UpdateGapError>().is_none() {
+                        return Err(e);
+                    }
+                    warn!("{}; falling back to a full reload", e);
+                    db = db_init::recreate_db(CURRENT_PATH, db)?;
+                }
+            }
         }
+        let issues = match issues {
+            Some(i) => i,
+            None => {
+                info!("downloading full schedule file...");
+                DatabaseUpdater::new(&mut db, &cfg)?.init()?
+            }
+        };
+        info!("generating data-quality report...");
+        let refdata = match download_refdata(&mut *storage, &cfg) {
+            Ok(rd) => Some(rd),
+            Err(e) => {
+                warn!("failed to get reference data for the report: {}", e);
+                None
+            }
+        };
+        let report = ValidationReport::generate(&db, refdata.as_ref(), issues)?;
+        report.log_summary();
+        report.write(Path::new(REPORT_PATH))?;
+        report_written = true;
         info!("vacuuming into backup file...");
         db.execute_batch(&format!("VACUUM INTO '{}';", BACKUP_PATH))?;
     }
@@ -53,7 +113,25 @@ fn main() -> errors::Result<()> {
         warn!("backup file exists already; just uploading");
     }
     info!("uploading vacuumed backup file...");
-    gcs.upload_object(BACKUP_PATH, CURRENT_OBJ)?;
+    storage.upload_object(BACKUP_PATH, CURRENT_OBJ)?;
+    if report_written {
+        info!("uploading data-quality report...");
+        let report_obj = cfg.report_object_name.as_ref().map(|x| x as &str).unwrap_or(REPORT_OBJ);
+        storage.upload_object(REPORT_PATH, report_obj)?;
+    }
+    if let Some(ref prefix) = cfg.gtfs_object_prefix {
+        info!("downloading reference data for GTFS export...");
+        let refdata = download_refdata(&mut *storage, &cfg)?;
+        let db = tspl_sqlite::initialize_db(BACKUP_PATH, &tspl_fahrplan::types::MIGRATIONS)?;
+        info!("exporting GTFS feed...");
+        GtfsExporter::new(&db, &refdata)?
+            .export(Path::new(GTFS_PATH))?;
+        info!("uploading GTFS feed...");
+        for file in gtfs::GTFS_FILES.iter() {
+            let path = Path::new(GTFS_PATH).join(file);
+            storage.upload_object(&path.to_string_lossy(), &format!("{}{}", prefix, file))?;
+        }
+    }
     info!("done!");
     Ok(())
 }
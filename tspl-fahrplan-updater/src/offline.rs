@@ -0,0 +1,99 @@
+//! Importing schedule files from the local filesystem, without talking to
+//! NROD or GCS.
+//!
+//! This is used for rebuilding old timetables from archived extracts, and
+//! for testing imports without any credentials.
+
+use flate2::bufread::GzDecoder;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use failure::{bail, format_err};
+use log::*;
+
+use crate::db_init;
+use crate::import::{self, ImportIssues};
+use tspl_sqlite::rusqlite::Connection;
+use crate::errors::*;
+
+/// A format of schedule file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum FileFormat {
+    /// NROD JSON SCHEDULE format (one record per line).
+    Json,
+    /// Fixed-width CIF format.
+    Cif
+}
+
+/// Opens a schedule file, decompressing it if it's gzipped.
+pub fn open_schedule_file(path: &Path) -> Result<Box<dyn BufRead>> {
+    let mut rdr = BufReader::new(File::open(path)?);
+    // Check for the gzip magic number.
+    if rdr.fill_buf()?.starts_with(&[0x1f, 0x8b]) {
+        debug!("{} is gzipped", path.display());
+        Ok(Box::new(BufReader::new(GzDecoder::new(rdr))))
+    }
+    else {
+        Ok(Box::new(rdr))
+    }
+}
+
+/// Works out what format a schedule file is in, by looking at its first few bytes.
+pub fn detect_format(rdr: &mut dyn BufRead) -> Result<FileFormat> {
+    let buf = rdr.fill_buf()?;
+    if buf.starts_with(b"HD") {
+        return Ok(FileFormat::Cif);
+    }
+    match buf.iter().find(|x| !x.is_ascii_whitespace()) {
+        Some(&b'{') => Ok(FileFormat::Json),
+        _ => bail!("file is neither a JSON nor a CIF schedule file")
+    }
+}
+
+/// Expands any directories in `paths` into the files they contain, sorted by name.
+fn expand_paths(paths: &[String]) -> Result<Vec<PathBuf>> {
+    let mut ret = vec![];
+    for path in paths {
+        let path = PathBuf::from(path);
+        if path.is_dir() {
+            let mut files = fs::read_dir(&path)?
+                .map(|ent| ent.map(|x| x.path()))
+                .collect::<::std::io::Result<Vec<_>>>()?;
+            files.retain(|x| x.is_file());
+            files.sort();
+            ret.extend(files);
+        }
+        else {
+            ret.push(path);
+        }
+    }
+    Ok(ret)
+}
+
+/// Imports the schedule files at `paths` (in order) into the database at
+/// `db_path`, creating it if it doesn't exist (or isn't valid).
+///
+/// Returns the database, and the issues noticed while importing.
+pub fn import_local_files(paths: &[String], db_path: &str) -> Result<(Connection, ImportIssues)> {
+    let paths = expand_paths(paths)?;
+    if paths.len() == 0 {
+        bail!("no schedule files to import");
+    }
+    let (mut db, update) = db_init::load_or_create_db(db_path)?;
+    if update {
+        info!("updating existing database at {}", db_path);
+    }
+    let mut issues = ImportIssues::default();
+    for path in paths {
+        let mut rdr = open_schedule_file(&path)?;
+        let format = detect_format(&mut rdr)?;
+        info!("importing {} ({:?})...", path.display(), format);
+        let ret = match format {
+            FileFormat::Json => import::apply_schedule_records(&mut db, rdr),
+            FileFormat::Cif => import::apply_cif_records(&mut db, rdr)
+        };
+        issues.extend(ret.map_err(|e| format_err!("failed to import {}: {}", path.display(), e))?);
+    }
+    info!("offline import complete; database written to {}", db_path);
+    Ok((db, issues))
+}
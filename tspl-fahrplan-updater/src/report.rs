@@ -0,0 +1,261 @@
+//! Machine-readable data-quality report, written after each import.
+//!
+//! Lots of things can make a service look wrong without anything in
+//! trainsplorer actually being broken (ITPS has plenty of quirks), so this
+//! collects the usual suspects into one JSON file, instead of leaving them
+//! to be grepped out of the logs.
+//!
+//! Only schedules that haven't expired yet are looked at.
+
+use chrono::{DateTime, NaiveDate, Utc};
+use serde_derive::Serialize;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use log::*;
+use tspl_sqlite::traits::*;
+use tspl_fahrplan::types::{ScheduleFile, ScheduleMvt};
+
+use crate::import::ImportIssues;
+use crate::errors::*;
+
+/// Train categories that carry members of the public, and so should have
+/// public times at their calls.
+///
+/// (Unadvertised and staff trains are left out on purpose.)
+pub static PASSENGER_CATEGORIES: [&str; 12] = [
+    "OL", "OO", "OW", "XC", "XD", "XI", "XR", "XX", "XZ", "BR", "BS", "SS"
+];
+
+/// Identifies a schedule (in the same way ITPS does).
+#[derive(Serialize, Debug, Clone)]
+pub struct ScheduleKey {
+    pub uid: String,
+    pub start_date: NaiveDate,
+    pub stp_indicator: String,
+    pub source: u8
+}
+impl ScheduleKey {
+    fn from_row(row: &Row, s: usize) -> RowResult<Self> {
+        Ok(Self {
+            uid: row.get(s + 0)?,
+            start_date: row.get(s + 1)?,
+            stp_indicator: row.get(s + 2)?,
+            source: row.get(s + 3)?
+        })
+    }
+}
+
+/// Two schedules for the same UID with the same STP indicator, which both
+/// apply on some dates (so which one wins is down to our tie-breaking).
+///
+/// Overlapping cancellations aren't reported, since it doesn't matter
+/// which of them wins.
+#[derive(Serialize, Debug)]
+pub struct StpConflict {
+    pub first: ScheduleKey,
+    pub second: ScheduleKey,
+    /// First date on which both schedules are valid.
+    pub overlap_start: NaiveDate,
+    /// Last date on which both schedules are valid.
+    pub overlap_end: NaiveDate
+}
+
+/// A TIPLOC used in schedules that CORPUS doesn't know about.
+#[derive(Serialize, Debug)]
+pub struct UnknownTiploc {
+    pub tiploc: String,
+    /// Number of schedules calling at or passing it.
+    pub schedules: u32
+}
+
+/// A passenger schedule with calls that have no public time.
+///
+/// Only calls with activity codes saying that passengers get on or off
+/// there are counted. Movements without any activity codes (e.g. from ITPS
+/// JSON, which doesn't include them) are assumed to be passenger calls if
+/// they're arrivals or departures, since passes never are.
+#[derive(Serialize, Debug)]
+pub struct MissingPublicTimes {
+    pub schedule: ScheduleKey,
+    /// TIPLOCs of the calls without public times, in order.
+    pub tiplocs: Vec<String>
+}
+
+/// The data-quality report itself.
+#[derive(Serialize, Debug)]
+pub struct ValidationReport {
+    pub generated_at: DateTime<Utc>,
+    /// Schedules that expired before this date aren't checked.
+    pub as_of: NaiveDate,
+    /// Sequence number of the last schedule file imported.
+    pub last_sequence: Option<u32>,
+    /// Schedules whose movements were out of order in the import.
+    pub unsorted_mvts: Vec<ScheduleKey>,
+    /// Overlapping schedules with equal STP indicators.
+    pub stp_conflicts: Vec<StpConflict>,
+    /// TIPLOCs not found in CORPUS (`None` if no reference data was
+    /// available to check against).
+    pub unknown_tiplocs: Option<Vec<UnknownTiploc>>,
+    /// Passenger schedules with calls that have no public time.
+    pub missing_public_times: Vec<MissingPublicTimes>,
+    /// Overlay schedules without a permanent schedule to overlay.
+    pub orphaned_overlays: Vec<ScheduleKey>
+}
+
+impl ValidationReport {
+    /// Generates a report for the schedule database `conn`, using CORPUS
+    /// entries from the `tspl-nennen` reference database `refdata` (if
+    /// provided), and including `issues` from the import that just happened.
+    pub fn generate(conn: &Connection, refdata: Option<&Connection>, issues: ImportIssues) -> Result<Self> {
+        let generated_at = Utc::now();
+        let as_of = generated_at.naive_utc().date();
+        let last_sequence = ScheduleFile::from_select(conn, "ORDER BY sequence DESC LIMIT 1", NO_PARAMS)?
+            .into_iter()
+            .next()
+            .map(|x| x.sequence);
+        let unknown_tiplocs = match refdata {
+            Some(rd) => Some(Self::unknown_tiplocs(conn, rd, as_of)?),
+            None => {
+                warn!("no reference data; not checking for unknown TIPLOCs");
+                None
+            }
+        };
+        Ok(Self {
+            generated_at,
+            as_of,
+            last_sequence,
+            unsorted_mvts: issues.unsorted_mvts,
+            stp_conflicts: Self::stp_conflicts(conn, as_of)?,
+            unknown_tiplocs,
+            missing_public_times: Self::missing_public_times(conn, as_of)?,
+            orphaned_overlays: Self::orphaned_overlays(conn, as_of)?
+        })
+    }
+    fn stp_conflicts(conn: &Connection, as_of: NaiveDate) -> Result<Vec<StpConflict>> {
+        let mut stmt = conn.prepare("SELECT a.uid, a.start_date, a.stp_indicator, a.source,
+                                            b.uid, b.start_date, b.stp_indicator, b.source,
+                                            MAX(a.start_date, b.start_date),
+                                            MIN(a.end_date, b.end_date)
+                                     FROM schedules AS a
+                                     INNER JOIN schedules AS b
+                                     ON a.uid = b.uid AND a.source = b.source
+                                        AND a.stp_indicator = b.stp_indicator
+                                        AND a.id < b.id
+                                     WHERE a.stp_indicator != 'C'
+                                       AND a.start_date <= b.end_date
+                                       AND b.start_date <= a.end_date
+                                       AND (a.days & b.days) != 0
+                                       AND a.end_date >= :as_of
+                                       AND b.end_date >= :as_of
+                                     ORDER BY a.uid, a.start_date")?;
+        let ret = stmt.query_map_named(named_params! { ":as_of": as_of }, |row| {
+            Ok(StpConflict {
+                first: ScheduleKey::from_row(row, 0)?,
+                second: ScheduleKey::from_row(row, 4)?,
+                overlap_start: row.get(8)?,
+                overlap_end: row.get(9)?
+            })
+        })?.collect::<RowResult<Vec<_>>>()?;
+        Ok(ret)
+    }
+    fn unknown_tiplocs(conn: &Connection, refdata: &Connection, as_of: NaiveDate) -> Result<Vec<UnknownTiploc>> {
+        let mut stmt = refdata.prepare("SELECT tiploc FROM corpus_entries WHERE tiploc IS NOT NULL")?;
+        let known = stmt.query_map(NO_PARAMS, |row| row.get(0))?
+            .collect::<RowResult<HashSet<String>>>()?;
+        let mut stmt = conn.prepare("SELECT m.tiploc, COUNT(DISTINCT m.parent_sched)
+                                     FROM schedule_movements AS m
+                                     INNER JOIN schedules AS s ON s.id = m.parent_sched
+                                     WHERE s.end_date >= ?
+                                     GROUP BY m.tiploc
+                                     ORDER BY m.tiploc")?;
+        let mut ret = vec![];
+        for res in stmt.query_map(params![as_of], |row| Ok(UnknownTiploc {
+            tiploc: row.get(0)?,
+            schedules: row.get(1)?
+        }))? {
+            let ut = res?;
+            if !known.contains(&ut.tiploc) {
+                ret.push(ut);
+            }
+        }
+        Ok(ret)
+    }
+    fn missing_public_times(conn: &Connection, as_of: NaiveDate) -> Result<Vec<MissingPublicTimes>> {
+        let categories = PASSENGER_CATEGORIES.iter()
+            .map(|x| format!("'{}'", x))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let query = format!("SELECT s.uid, s.start_date, s.stp_indicator, s.source, s.id, m.tiploc, m.activity
+                             FROM schedules AS s
+                             INNER JOIN schedule_movements AS m ON m.parent_sched = s.id
+                             WHERE s.train_category IN ({})
+                               AND s.stp_indicator != 'C'
+                               AND s.end_date >= ?
+                               AND m.action IN ({}, {})
+                               AND m.public_time IS NULL
+                             ORDER BY s.uid, s.id, m.day_offset, m.time, m.action",
+                            categories, ScheduleMvt::ACTION_ARRIVAL, ScheduleMvt::ACTION_DEPARTURE);
+        let mut stmt = conn.prepare(&query)?;
+        let mut ret: Vec<MissingPublicTimes> = vec![];
+        let mut last_id = None;
+        for res in stmt.query_map(params![as_of], |row| {
+            Ok((ScheduleKey::from_row(row, 0)?, row.get::<_, i64>(4)?, row.get::<_, String>(5)?,
+                row.get::<_, Option<String>>(6)?))
+        })? {
+            let (schedule, id, tiploc, activity) = res?;
+            // (the query only returns arrivals and departures, so movements
+            // without activity codes are passenger calls as far as we know)
+            if let Some(act) = activity {
+                if !ScheduleMvt::is_passenger_activity(&act) {
+                    continue;
+                }
+            }
+            if last_id != Some(id) {
+                ret.push(MissingPublicTimes { schedule, tiplocs: vec![] });
+                last_id = Some(id);
+            }
+            if let Some(mpt) = ret.last_mut() {
+                // (arrivals and departures at the same place count as one call)
+                if mpt.tiplocs.last() != Some(&tiploc) {
+                    mpt.tiplocs.push(tiploc);
+                }
+            }
+        }
+        Ok(ret)
+    }
+    fn orphaned_overlays(conn: &Connection, as_of: NaiveDate) -> Result<Vec<ScheduleKey>> {
+        let mut stmt = conn.prepare("SELECT o.uid, o.start_date, o.stp_indicator, o.source
+                                     FROM schedules AS o
+                                     WHERE o.stp_indicator = 'O'
+                                       AND o.end_date >= ?
+                                       AND NOT EXISTS (
+                                           SELECT * FROM schedules AS p
+                                           WHERE p.uid = o.uid AND p.source = o.source
+                                             AND p.stp_indicator = 'P'
+                                             AND p.start_date <= o.end_date
+                                             AND o.start_date <= p.end_date
+                                             AND (p.days & o.days) != 0
+                                       )
+                                     ORDER BY o.uid, o.start_date")?;
+        let ret = stmt.query_map(params![as_of], |row| ScheduleKey::from_row(row, 0))?
+            .collect::<RowResult<Vec<_>>>()?;
+        Ok(ret)
+    }
+    /// Logs how many of each kind of issue the report found.
+    pub fn log_summary(&self) {
+        info!("data-quality report: {} unsorted, {} STP conflicts, {} unknown TIPLOCs, {} missing public times, {} orphaned overlays",
+              self.unsorted_mvts.len(),
+              self.stp_conflicts.len(),
+              self.unknown_tiplocs.as_ref().map(|x| x.len().to_string()).unwrap_or_else(|| "(unchecked)".into()),
+              self.missing_public_times.len(),
+              self.orphaned_overlays.len());
+    }
+    /// Writes the report out as JSON to `path`.
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+}
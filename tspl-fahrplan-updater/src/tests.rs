@@ -0,0 +1,223 @@
+use chrono::*;
+use tspl_sqlite::traits::*;
+use tspl_fahrplan::types::*;
+
+use crate::download::*;
+use crate::import::{ImportIssues, update_schedule_meta};
+use crate::report::*;
+
+/// A date well after anything the report would consider expired.
+fn far_future() -> NaiveDate {
+    NaiveDate::from_ymd(2099, 12, 31)
+}
+fn schedule_db() -> Connection {
+    tspl_sqlite::initialize_db(":memory:", &tspl_fahrplan::types::MIGRATIONS).unwrap()
+}
+fn refdata_db(tiplocs: &[&str]) -> Connection {
+    let conn = tspl_sqlite::initialize_db(":memory:", &tspl_nennen::types::MIGRATIONS).unwrap();
+    for tiploc in tiplocs {
+        conn.execute("INSERT INTO corpus_entries (tiploc) VALUES (?)", params![tiploc]).unwrap();
+    }
+    conn
+}
+fn insert_sched(conn: &Connection, uid: &str, stp: &str, end_date: NaiveDate, category: Option<&str>) -> i64 {
+    let sched = Schedule {
+        id: -1,
+        tspl_id: Uuid::new_v4(),
+        uid: uid.into(),
+        start_date: NaiveDate::from_ymd(2019, 01, 01),
+        end_date,
+        days: ScheduleDays::all(),
+        stp_indicator: stp.into(),
+        signalling_id: None,
+        source: Schedule::SOURCE_ITPS,
+        file_metaseq: None,
+        darwin_id: None,
+        crosses_midnight: false,
+        meta: ScheduleMeta {
+            train_category: category.map(|x| x.into()),
+            ..Default::default()
+        }
+    };
+    sched.insert_self(conn).unwrap()
+}
+fn insert_mvt(conn: &Connection, parent_sched: i64, tiploc: &str, action: u8, mins: u32, public: bool, activity: Option<&str>) {
+    let time = NaiveTime::from_hms(12, 0, 0) + Duration::minutes(mins as _);
+    let mvt = ScheduleMvt {
+        parent_sched,
+        tiploc: tiploc.into(),
+        action,
+        time,
+        public_time: if public { Some(time) } else { None },
+        activity: activity.map(|x| x.into()),
+        ..ScheduleMvt::dummy()
+    };
+    mvt.insert_self(conn).unwrap();
+}
+fn key(uid: &str, stp: &str) -> ScheduleKey {
+    ScheduleKey {
+        uid: uid.into(),
+        start_date: NaiveDate::from_ymd(2019, 01, 01),
+        stp_indicator: stp.into(),
+        source: Schedule::SOURCE_ITPS
+    }
+}
+fn uids(keys: &[ScheduleKey]) -> Vec<(&str, &str)> {
+    keys.iter()
+        .map(|x| (&x.uid as &str, &x.stp_indicator as &str))
+        .collect()
+}
+
+#[test]
+fn report_unsorted_mvts() {
+    let conn = schedule_db();
+    let issues = ImportIssues {
+        unsorted_mvts: vec![key("A00001", "P")]
+    };
+    let report = ValidationReport::generate(&conn, None, issues).unwrap();
+    assert_eq!(uids(&report.unsorted_mvts), vec![("A00001", "P")]);
+}
+#[test]
+fn report_stp_conflicts() {
+    let conn = schedule_db();
+    insert_sched(&conn, "A00001", "P", far_future(), None);
+    insert_sched(&conn, "A00001", "P", far_future(), None);
+    // Different STP indicators are how overlays are meant to work.
+    insert_sched(&conn, "A00001", "O", far_future(), None);
+    // Overlapping cancellations don't matter.
+    insert_sched(&conn, "A00002", "C", far_future(), None);
+    insert_sched(&conn, "A00002", "C", far_future(), None);
+    // Neither do expired schedules.
+    insert_sched(&conn, "A00003", "P", NaiveDate::from_ymd(2019, 06, 01), None);
+    insert_sched(&conn, "A00003", "P", NaiveDate::from_ymd(2019, 06, 01), None);
+    let report = ValidationReport::generate(&conn, None, ImportIssues::default()).unwrap();
+    assert_eq!(report.stp_conflicts.len(), 1);
+    let conflict = &report.stp_conflicts[0];
+    assert_eq!(conflict.first.uid, "A00001");
+    assert_eq!(conflict.second.uid, "A00001");
+    assert_eq!(conflict.overlap_start, NaiveDate::from_ymd(2019, 01, 01));
+    assert_eq!(conflict.overlap_end, far_future());
+}
+#[test]
+fn report_unknown_tiplocs() {
+    let conn = schedule_db();
+    let a = insert_sched(&conn, "A00001", "P", far_future(), None);
+    let b = insert_sched(&conn, "A00002", "P", far_future(), None);
+    insert_mvt(&conn, a, "EUSTON", ScheduleMvt::ACTION_DEPARTURE, 0, true, None);
+    insert_mvt(&conn, a, "NOWHERE", ScheduleMvt::ACTION_ARRIVAL, 10, true, None);
+    insert_mvt(&conn, b, "NOWHERE", ScheduleMvt::ACTION_DEPARTURE, 0, true, None);
+    let report = ValidationReport::generate(&conn, None, ImportIssues::default()).unwrap();
+    assert!(report.unknown_tiplocs.is_none());
+    let refdata = refdata_db(&["EUSTON"]);
+    let report = ValidationReport::generate(&conn, Some(&refdata), ImportIssues::default()).unwrap();
+    let unknown = report.unknown_tiplocs.unwrap();
+    assert_eq!(unknown.len(), 1);
+    assert_eq!(unknown[0].tiploc, "NOWHERE");
+    assert_eq!(unknown[0].schedules, 2);
+}
+#[test]
+fn report_missing_public_times() {
+    let conn = schedule_db();
+    let a = insert_sched(&conn, "A00001", "P", far_future(), Some("OO"));
+    insert_mvt(&conn, a, "ORIGIN", ScheduleMvt::ACTION_DEPARTURE, 0, true, Some("TB"));
+    insert_mvt(&conn, a, "MIDDLE", ScheduleMvt::ACTION_ARRIVAL, 10, false, Some("T "));
+    insert_mvt(&conn, a, "MIDDLE", ScheduleMvt::ACTION_DEPARTURE, 11, false, Some("T "));
+    insert_mvt(&conn, a, "PASSING", ScheduleMvt::ACTION_PASS, 15, false, None);
+    insert_mvt(&conn, a, "REQUEST", ScheduleMvt::ACTION_ARRIVAL, 20, false, Some("R "));
+    insert_mvt(&conn, a, "REQUEST", ScheduleMvt::ACTION_DEPARTURE, 21, false, Some("R "));
+    insert_mvt(&conn, a, "OPSTOP", ScheduleMvt::ACTION_ARRIVAL, 25, false, Some("OP"));
+    insert_mvt(&conn, a, "OPSTOP", ScheduleMvt::ACTION_DEPARTURE, 27, false, Some("OP"));
+    insert_mvt(&conn, a, "NOACT", ScheduleMvt::ACTION_ARRIVAL, 30, false, None);
+    insert_mvt(&conn, a, "NOACT", ScheduleMvt::ACTION_DEPARTURE, 31, false, None);
+    insert_mvt(&conn, a, "DEST", ScheduleMvt::ACTION_ARRIVAL, 40, true, Some("TF"));
+    // Empty stock moves aren't for passengers.
+    let b = insert_sched(&conn, "A00002", "P", far_future(), Some("EE"));
+    insert_mvt(&conn, b, "MIDDLE", ScheduleMvt::ACTION_ARRIVAL, 10, false, Some("T "));
+    let report = ValidationReport::generate(&conn, None, ImportIssues::default()).unwrap();
+    assert_eq!(report.missing_public_times.len(), 1);
+    let mpt = &report.missing_public_times[0];
+    assert_eq!(mpt.schedule.uid, "A00001");
+    assert_eq!(mpt.tiplocs, vec!["MIDDLE", "REQUEST", "NOACT"]);
+}
+#[test]
+fn report_orphaned_overlays() {
+    let conn = schedule_db();
+    insert_sched(&conn, "A00001", "P", far_future(), None);
+    insert_sched(&conn, "A00001", "O", far_future(), None);
+    insert_sched(&conn, "A00002", "O", far_future(), None);
+    let report = ValidationReport::generate(&conn, None, ImportIssues::default()).unwrap();
+    assert_eq!(uids(&report.orphaned_overlays), vec![("A00002", "O")]);
+}
+
+/// A Friday, so yesterday's update file is "toc-update-thu".
+fn friday() -> NaiveDate {
+    NaiveDate::from_ymd(2019, 05, 24)
+}
+/// Pretends that the update file from `days_ago` days before `friday()`
+/// has sequence number `latest - days_ago + 1`.
+fn peek_from(latest: u32) -> impl FnMut(&'static str) -> crate::errors::Result<u32> {
+    move |file| {
+        let days = ["toc-update-thu", "toc-update-wed", "toc-update-tue", "toc-update-mon",
+                    "toc-update-sun", "toc-update-sat", "toc-update-fri"];
+        let days_ago = days.iter().position(|x| *x == file).unwrap() as u32;
+        Ok(latest - days_ago)
+    }
+}
+fn gap(e: failure::Error) -> (u32, Option<u32>) {
+    let e = e.downcast_ref::<UpdateGapError>().expect("expected an UpdateGapError");
+    (e.last_sequence, e.earliest_available)
+}
+
+#[test]
+fn backfill_up_to_date() {
+    assert_eq!(find_missing_updates(100, friday(), peek_from(100)).unwrap(), vec![]);
+}
+#[test]
+fn backfill_one_day() {
+    assert_eq!(find_missing_updates(99, friday(), peek_from(100)).unwrap(), vec![("toc-update-thu", 100)]);
+}
+#[test]
+fn backfill_several_days() {
+    assert_eq!(find_missing_updates(97, friday(), peek_from(100)).unwrap(), vec![
+        ("toc-update-tue", 98),
+        ("toc-update-wed", 99),
+        ("toc-update-thu", 100)
+    ]);
+}
+#[test]
+fn backfill_gap() {
+    // Seven files available, but the one after 50 isn't one of them.
+    let e = find_missing_updates(50, friday(), peek_from(100)).unwrap_err();
+    assert_eq!(gap(e), (50, Some(94)));
+}
+#[test]
+fn backfill_needs_a_file() {
+    let conn = schedule_db();
+    assert_eq!(gap(last_sequence(&conn).unwrap_err()), (0, None));
+    ScheduleFile { sequence: 18036, timestamp: 1558385000 }.insert_self(&conn).unwrap();
+    ScheduleFile { sequence: 18037, timestamp: 1558471400 }.insert_self(&conn).unwrap();
+    assert_eq!(last_sequence(&conn).unwrap(), 18037);
+}
+#[test]
+fn backfill_peek_errors() {
+    // Failing to download a file isn't a gap, so it gets retried instead of
+    // falling back to a full reload.
+    let e = find_missing_updates(99, friday(), |_| Err(failure::format_err!("connection reset"))).unwrap_err();
+    assert!(e.downcast_ref::<UpdateGapError>().is_none());
+}
+#[test]
+fn metadata_only_updated_when_changed() {
+    let conn = schedule_db();
+    let id = insert_sched(&conn, "A00001", "P", far_future(), Some("OO"));
+    let get = || Schedule::from_select(&conn, "WHERE id = ?", &[&id]).unwrap().remove(0);
+    let sched = get();
+    assert!(!update_schedule_meta(&conn, &sched, &sched.meta.clone()).unwrap());
+    let meta = ScheduleMeta {
+        atoc_code: Some("LM".into()),
+        ..sched.meta.clone()
+    };
+    assert!(update_schedule_meta(&conn, &sched, &meta).unwrap());
+    let sched = get();
+    assert_eq!(sched.meta, meta);
+    assert!(!update_schedule_meta(&conn, &sched, &meta).unwrap());
+}
@@ -5,8 +5,9 @@ use log::*;
 use std::thread;
 use failure::format_err;
 
-use crate::download::Downloader;
+use crate::download::{Downloader, UpdateGapError};
 use crate::config::Config;
+use crate::import::ImportIssues;
 use crate::errors::*;
 
 /// How many milliseconds to wait before retrying a failed update job.
@@ -25,30 +26,33 @@ pub struct DatabaseUpdater<'a> {
 }
 
 impl<'a> DatabaseUpdater<'a> {
-    pub fn new(conn: &'a mut Connection, cfg: &Config) -> Self {
-        let dl = Downloader::new(cfg);
-        Self {
+    pub fn new(conn: &'a mut Connection, cfg: &Config) -> Result<Self> {
+        let dl = Downloader::new(cfg)?;
+        Ok(Self {
             inner: conn,
             dl,
             update_timeout_ms: cfg.update_timeout_ms.unwrap_or(UPDATE_TIMEOUT_MS),
             update_retries: cfg.update_retries.unwrap_or(UPDATE_RETRIES),
-        }
+        })
     }
-    pub fn init(&mut self) -> Result<()> {
-        self.dl.do_init(self.inner)?;
-        Ok(())
+    pub fn init(&mut self) -> Result<ImportIssues> {
+        self.dl.do_init(self.inner)
     }
-    pub fn update(&mut self) -> Result<()> {
+    pub fn update(&mut self) -> Result<ImportIssues> {
         let mut cur = 0;
         let mut timeout_ms = self.update_timeout_ms;
         loop {
             info!("Attempting to download update (try #{})", cur+1); 
             match self.dl.do_update(self.inner) {
-                Ok(_) => {
+                Ok(issues) => {
                     info!("Update successfully downloaded.");
-                    break Ok(());
+                    break Ok(issues);
                 },
                 Err(e) => {
+                    if e.downcast_ref::<UpdateGapError>().is_some() {
+                        // Retrying won't make the missing files appear.
+                        break Err(e);
+                    }
                     warn!("Failed to fetch update: {}", e);
                     cur += 1;
                     if cur > self.update_retries {
@@ -0,0 +1,23 @@
+-- tspl-fahrplan: associations (joins, divides and next workings)
+
+CREATE TABLE associations (
+	id INTEGER PRIMARY KEY,
+	main_uid TEXT NOT NULL,
+	assoc_uid TEXT NOT NULL,
+	start_date TEXT NOT NULL,
+	end_date TEXT NOT NULL,
+	days INT NOT NULL,
+	category INT NOT NULL,
+	date_indicator INT NOT NULL,
+	tiploc TEXT NOT NULL,
+	base_suffix TEXT,
+	assoc_suffix TEXT,
+	stp_indicator TEXT NOT NULL,
+	source INT NOT NULL,
+	file_metaseq INT
+	-- (no UNIQUE constraint here, because the location suffixes are
+	-- part of the key and can be NULL)
+);
+
+CREATE INDEX associations_main_uid ON associations (main_uid);
+CREATE INDEX associations_assoc_uid ON associations (assoc_uid);
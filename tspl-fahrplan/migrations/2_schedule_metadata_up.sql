@@ -0,0 +1,18 @@
+-- tspl-fahrplan: store extra schedule metadata from the CIF schedule segment
+
+ALTER TABLE schedules ADD COLUMN train_category TEXT;
+ALTER TABLE schedules ADD COLUMN headcode TEXT;
+ALTER TABLE schedules ADD COLUMN business_sector TEXT;
+ALTER TABLE schedules ADD COLUMN power_type TEXT;
+ALTER TABLE schedules ADD COLUMN timing_load TEXT;
+ALTER TABLE schedules ADD COLUMN speed INT;
+ALTER TABLE schedules ADD COLUMN operating_characteristics TEXT;
+ALTER TABLE schedules ADD COLUMN train_class TEXT;
+ALTER TABLE schedules ADD COLUMN sleepers TEXT;
+ALTER TABLE schedules ADD COLUMN reservations TEXT;
+ALTER TABLE schedules ADD COLUMN catering_code TEXT;
+ALTER TABLE schedules ADD COLUMN service_branding TEXT;
+ALTER TABLE schedules ADD COLUMN atoc_code TEXT;
+
+CREATE INDEX schedules_train_category ON schedules (train_category);
+CREATE INDEX schedules_power_type ON schedules (power_type);
@@ -0,0 +1,3 @@
+-- tspl-fahrplan: store the CIF bank holiday running flag
+
+ALTER TABLE schedules ADD COLUMN bank_holiday_running TEXT;
@@ -0,0 +1,26 @@
+-- tspl-fahrplan: keep superseded versions of schedules around
+
+CREATE TABLE schedule_versions (
+	id INTEGER PRIMARY KEY,
+	parent_sched INT NOT NULL REFERENCES schedules ON DELETE CASCADE,
+	tspl_id BLOB UNIQUE NOT NULL,
+	file_metaseq INT,
+	superseded_by BLOB NOT NULL,
+	superseded_metaseq INT,
+	superseded_at TEXT NOT NULL
+);
+
+CREATE INDEX schedule_versions_parent_sched ON schedule_versions (parent_sched);
+
+CREATE TABLE schedule_version_movements (
+	id INTEGER PRIMARY KEY,
+	parent_version INT NOT NULL REFERENCES schedule_versions ON DELETE CASCADE,
+	tiploc TEXT NOT NULL,
+	action INT NOT NULL,
+	time TEXT NOT NULL,
+	day_offset INT NOT NULL,
+	platform TEXT,
+	public_time TEXT
+);
+
+CREATE INDEX schedule_version_movements_parent_version ON schedule_version_movements (parent_version);
@@ -0,0 +1,5 @@
+-- tspl-fahrplan: indexes for searching schedules by headcode and TOC
+
+CREATE INDEX schedules_signalling_id ON schedules (signalling_id);
+CREATE INDEX schedules_headcode ON schedules (headcode);
+CREATE INDEX schedules_atoc_code ON schedules (atoc_code);
@@ -0,0 +1,3 @@
+-- tspl-fahrplan: store allowance time for schedule movements
+
+ALTER TABLE schedule_movements ADD COLUMN allowance INT NOT NULL DEFAULT 0;
@@ -0,0 +1,3 @@
+-- tspl-fahrplan: store allowance time for superseded schedule movements too
+
+ALTER TABLE schedule_version_movements ADD COLUMN allowance INT NOT NULL DEFAULT 0;
@@ -0,0 +1,4 @@
+-- tspl-fahrplan: store CIF activity codes for schedule movements
+
+ALTER TABLE schedule_movements ADD COLUMN activity TEXT;
+ALTER TABLE schedule_version_movements ADD COLUMN activity TEXT;
@@ -0,0 +1,54 @@
+-- tspl-fahrplan: keep the version history of deleted and replaced schedules
+
+CREATE TABLE deleted_schedules (
+	id INTEGER PRIMARY KEY,
+	tspl_id BLOB NOT NULL,
+	uid TEXT NOT NULL,
+	start_date TEXT NOT NULL,
+	end_date TEXT NOT NULL,
+	days INT NOT NULL,
+	stp_indicator TEXT NOT NULL,
+	signalling_id TEXT,
+	source INT NOT NULL,
+	file_metaseq INT,
+	darwin_id TEXT,
+	crosses_midnight BOOL NOT NULL,
+	train_category TEXT,
+	headcode TEXT,
+	business_sector TEXT,
+	power_type TEXT,
+	timing_load TEXT,
+	speed INT,
+	operating_characteristics TEXT,
+	train_class TEXT,
+	sleepers TEXT,
+	reservations TEXT,
+	catering_code TEXT,
+	service_branding TEXT,
+	atoc_code TEXT,
+	bank_holiday_running TEXT
+);
+
+-- Versions have to outlive the schedule they belonged to now, so rebuild
+-- the table without the cascading delete.
+CREATE TABLE schedule_versions_new (
+	id INTEGER PRIMARY KEY,
+	parent_sched INT REFERENCES schedules ON DELETE SET NULL,
+	tspl_id BLOB UNIQUE NOT NULL,
+	file_metaseq INT,
+	superseded_by BLOB,
+	superseded_metaseq INT,
+	superseded_at TEXT NOT NULL,
+	deleted_sched INT REFERENCES deleted_schedules ON DELETE CASCADE
+);
+
+INSERT INTO schedule_versions_new
+	(id, parent_sched, tspl_id, file_metaseq, superseded_by, superseded_metaseq, superseded_at)
+	SELECT id, parent_sched, tspl_id, file_metaseq, superseded_by, superseded_metaseq, superseded_at
+	FROM schedule_versions;
+
+DROP TABLE schedule_versions;
+ALTER TABLE schedule_versions_new RENAME TO schedule_versions;
+
+CREATE INDEX schedule_versions_parent_sched ON schedule_versions (parent_sched);
+CREATE INDEX schedule_versions_deleted_sched ON schedule_versions (deleted_sched);
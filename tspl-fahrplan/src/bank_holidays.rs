@@ -0,0 +1,95 @@
+//! UK bank holiday calendars, used to work out whether schedules with a
+//! bank holiday running flag actually run on a given date.
+//!
+//! The calendar is loaded from a JSON file in the same format as the one
+//! published at https://www.gov.uk/bank-holidays.json, with an optional
+//! extra `glasgow` division (which gov.uk doesn't provide).
+//!
+//! Scottish bank holidays are loaded and reported, but none of the CIF
+//! running flags refer to them, so they don't affect whether schedules run.
+
+use serde_derive::{Serialize, Deserialize};
+use std::collections::HashSet;
+use std::fs::File;
+use chrono::{Datelike, NaiveDate, Weekday};
+use log::*;
+
+use crate::types::Schedule;
+use crate::errors::Result;
+
+#[derive(Deserialize, Debug, Default)]
+struct DivisionFile {
+    events: Vec<EventFile>
+}
+#[derive(Deserialize, Debug)]
+struct EventFile {
+    date: NaiveDate
+}
+#[derive(Deserialize, Debug)]
+struct CalendarFile {
+    #[serde(rename = "england-and-wales", default)]
+    england_and_wales: DivisionFile,
+    #[serde(default)]
+    scotland: DivisionFile,
+    #[serde(default)]
+    glasgow: DivisionFile
+}
+
+/// Which bank holidays fall on a given date.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default)]
+pub struct BankHolidayRegions {
+    pub england_and_wales: bool,
+    pub scotland: bool,
+    pub glasgow: bool
+}
+
+/// A set of bank holiday dates for England & Wales, Scotland, and Glasgow.
+#[derive(Debug, Default)]
+pub struct BankHolidayCalendar {
+    pub(crate) england_and_wales: HashSet<NaiveDate>,
+    pub(crate) scotland: HashSet<NaiveDate>,
+    pub(crate) glasgow: HashSet<NaiveDate>
+}
+impl BankHolidayCalendar {
+    /// Loads a calendar from the JSON file at `path`.
+    pub fn load(path: &str) -> Result<Self> {
+        let file = File::open(path)?;
+        let cal: CalendarFile = serde_json::from_reader(file)?;
+        let conv = |d: DivisionFile| d.events.into_iter().map(|x| x.date).collect::<HashSet<_>>();
+        let ret = Self {
+            england_and_wales: conv(cal.england_and_wales),
+            scotland: conv(cal.scotland),
+            glasgow: conv(cal.glasgow)
+        };
+        info!("Loaded {} E&W, {} Scottish and {} Glasgow bank holidays",
+              ret.england_and_wales.len(), ret.scotland.len(), ret.glasgow.len());
+        Ok(ret)
+    }
+    /// Returns which regions have a bank holiday on `date`.
+    pub fn regions_on(&self, date: NaiveDate) -> BankHolidayRegions {
+        BankHolidayRegions {
+            england_and_wales: self.england_and_wales.contains(&date),
+            scotland: self.scotland.contains(&date),
+            glasgow: self.glasgow.contains(&date)
+        }
+    }
+    /// Whether `date` is one of the "specified Bank Holiday Mondays" that
+    /// the X running flag refers to (i.e. an England & Wales bank holiday
+    /// that falls on a Monday; Good Friday and Christmas don't count).
+    pub fn is_bank_holiday_monday(&self, date: NaiveDate) -> bool {
+        date.weekday() == Weekday::Mon && self.england_and_wales.contains(&date)
+    }
+    /// Whether `sched` runs on `date`, taking only its bank holiday
+    /// running flag into account.
+    ///
+    /// X ("does not run on specified Bank Holiday Mondays") is checked
+    /// using `is_bank_holiday_monday`, and G ("does not run on Glasgow
+    /// bank holidays") against the Glasgow calendar.
+    pub fn schedule_runs_on(&self, sched: &Schedule, date: NaiveDate) -> bool {
+        match sched.meta.bank_holiday_running.as_ref().map(|x| x as &str) {
+            Some("X") => !self.is_bank_holiday_monday(date),
+            Some("G") => !self.glasgow.contains(&date),
+            _ => true
+        }
+    }
+}
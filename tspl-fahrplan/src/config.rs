@@ -2,11 +2,13 @@
 
 use serde_derive::Deserialize;
 use tspl_util::{ConfigExt, crate_name};
+use tspl_gcs::StorageConfig;
 
 #[derive(Deserialize, Debug)]
 pub struct Config {
-    pub bucket_name: String,
-    pub service_account_key_path: String,
+    /// Where to get the schedule database from.
+    #[serde(flatten)]
+    pub storage: StorageConfig,
     #[serde(default)]
     pub object_name: Option<String>,
     pub username: String,
@@ -14,6 +16,14 @@ pub struct Config {
     pub listen_url: String,
     #[serde(default)]
     pub gcs_check_secs: Option<u32>,
+    /// Path to a JSON file of UK bank holidays (in the gov.uk format,
+    /// optionally with an extra `glasgow` division).
+    #[serde(default)]
+    pub bank_holidays_path: Option<String>,
+    /// Name of the `tspl-nennen` reference database object in storage,
+    /// used to get interchange information for journey planning.
+    #[serde(default)]
+    pub refdata_object_name: Option<String>,
 }
 
 impl ConfigExt for Config {
@@ -2,78 +2,378 @@
 
 use tspl_sqlite::TsplPool;
 use tspl_sqlite::traits::*;
-use crate::types::{Schedule, ScheduleMvt, ScheduleDetails, ScheduleDays, MvtQueryResponse, ConnectingMvtQueryResponse};
+use crate::types::{Schedule, ScheduleMvt, ScheduleDetails, ScheduleDays, Association, MvtQueryResponse, ConnectingMvtQueryResponse, JourneyPlanResponse};
+use crate::types::{ScheduleVersion, ScheduleVersionMvt, ScheduleHistory, ScheduleDiff, DeletedSchedule};
+use crate::types::{ScheduleSearchQuery, ScheduleSearchResponse};
+use crate::types::{EffectiveSchedule, OverriddenSchedule, OverrideReason, StpKind};
 use crate::errors::{FahrplanResult, FahrplanError};
+use crate::bank_holidays::{BankHolidayCalendar, BankHolidayRegions};
+use crate::journey::{Interchanges, JourneyPlanner};
 use std::collections::HashMap;
 use tspl_sqlite::rusqlite::Connection;
+use tspl_sqlite::rusqlite::types::ToSql;
 use tspl_util::http::HttpServer;
 use log::*;
 use chrono::*;
 use std::sync::{RwLock, Arc};
+use std::io::Read;
+use std::str::FromStr;
+use ntrod_types::vstp;
 use rouille::{Request, Response, router};
 
-fn process_schedule_join(scheds: &mut HashMap<String, Schedule>, sched: Schedule, updating_sched: Option<Schedule>) {
-    let uid = sched.uid.clone();
-    if !scheds.contains_key(&uid) {
-        scheds.insert(uid.clone(), sched);
-    }
+/// Works out which of `scheds` (schedules for the same train, all valid on
+/// `on_date`) is the one in effect on that date.
+///
+/// The schedule with the lowest STP indicator wins (so cancellations beat
+/// STP new schedules, which beat overlays, which beat permanent schedules).
+/// Ties are broken in favour of the schedule with the latest start date, and
+/// then the one imported most recently.
+///
+/// Returns `None` if `scheds` is empty.
+pub(crate) fn resolve_effective_schedule(bh: &BankHolidayCalendar, on_date: NaiveDate, mut scheds: Vec<Schedule>) -> Option<EffectiveSchedule> {
+    scheds.sort_by(|a, b| {
+        a.stp_indicator.cmp(&b.stp_indicator)
+            .then(b.start_date.cmp(&a.start_date))
+            .then(b.id.cmp(&a.id))
+    });
+    let mut iter = scheds.into_iter();
+    let effective = iter.next()?;
+    let overrides = iter
+        .map(|sched| {
+            let reason = if sched.stp_indicator == effective.stp_indicator {
+                if effective.stp_indicator != "C" {
+                    warn!("Schedules #{} and #{} have the same STP indicator on {}; using #{}",
+                          sched.id, effective.id, on_date, effective.id);
+                }
+                OverrideReason::TieBreak
+            }
+            else {
+                OverrideReason::Superseded
+            };
+            OverriddenSchedule {
+                kind: StpKind::from_indicator(&sched.stp_indicator),
+                schedule: sched,
+                reason
+            }
+        })
+        .collect();
+    let kind = StpKind::from_indicator(&effective.stp_indicator);
+    // The bank holiday running flag applies to whichever schedule is
+    // authoritative; if that one doesn't run, the train doesn't run.
+    let runs = kind != StpKind::Cancellation && bh.schedule_runs_on(&effective, on_date);
+    Some(EffectiveSchedule {
+        date: on_date,
+        effective,
+        kind,
+        runs,
+        overrides
+    })
+}
+
+/// Adds the schedules from one row of a movement query to `scheds`, which
+/// contains the candidate schedules for each UID.
+fn process_schedule_join(scheds: &mut HashMap<String, HashMap<i64, Schedule>>, sched: Schedule, updating_sched: Option<Schedule>) {
+    let cands = scheds.entry(sched.uid.clone()).or_insert_with(HashMap::new);
     if let Some(updating_sched) = updating_sched {
-        let sched = scheds.get_mut(&uid).unwrap();
-        if updating_sched.stp_indicator < sched.stp_indicator {
-            // The updating schedule supersedes the schedule.
-            *sched = updating_sched;
-        }
+        cands.entry(updating_sched.id).or_insert(updating_sched);
     }
+    cands.entry(sched.id).or_insert(sched);
 }
-fn uid_scheds_to_id_scheds(inp: HashMap<String, Schedule>) -> HashMap<i64, Schedule> {
+/// Resolves the candidate schedules for each UID, returning the effective
+/// schedules of trains that run on `on_date` (indexed by their ID).
+///
+/// Trains that have been cancelled (or don't run on bank holidays) aren't
+/// included, so any movements belonging to them get dropped.
+///
+/// Schedules from every source compete with each other here (so e.g. a VSTP
+/// or Darwin schedule can supersede an ITPS one), since the movement queries
+/// are about which trains actually run. `search_schedules` does the same;
+/// only `get_effective_schedule` looks at one source at a time.
+fn resolve_joined_schedules(bh: &BankHolidayCalendar, on_date: NaiveDate, inp: HashMap<String, HashMap<i64, Schedule>>) -> HashMap<i64, Schedule> {
     let mut id_schedules: HashMap<i64, Schedule> = HashMap::new();
-    for (_, schedule) in inp {
-        id_schedules.insert(schedule.id, schedule);
+    for (_, cands) in inp {
+        let eff = resolve_effective_schedule(bh, on_date, cands.into_iter().map(|(_, v)| v).collect());
+        if let Some(eff) = eff {
+            if eff.runs {
+                id_schedules.insert(eff.effective.id, eff.effective);
+            }
+            else {
+                debug!("Train {} doesn't run on {} (effective schedule #{} is {:?})",
+                       eff.effective.uid, on_date, eff.effective.id, eff.kind);
+            }
+        }
     }
     id_schedules
 }
 
-fn get_auth_schedule(conn: &Connection, uid: String, on_date: NaiveDate, source: u8) -> FahrplanResult<Option<Schedule>> {
-    debug!("Finding authoritative schedule for (uid, on_date, source) = ({}, {}, {})", uid, on_date, source);
+/// Gets the effective schedule for the train with UID `uid` on `on_date`,
+/// from the given source.
+///
+/// Unlike `resolve_joined_schedules`, only schedules from `source` are
+/// considered. This is what activations want: tspl-zugfuhrer asks for the
+/// ITPS schedule when a Darwin schedule comes in, so that it can merge the
+/// two, and would just get the Darwin schedule back otherwise.
+fn get_effective_schedule(conn: &Connection, bh: &BankHolidayCalendar, uid: &str, on_date: NaiveDate, source: u8) -> FahrplanResult<Option<EffectiveSchedule>> {
+    debug!("Finding effective schedule for (uid, on_date, source) = ({}, {}, {})", uid, on_date, source);
     let scheds = Schedule::from_select(conn, "WHERE uid = ?1 AND start_date <= ?2 AND end_date >= ?2 AND source = ?3",
-                                       &[&uid, &on_date, &source])?;
-    let mut ret = None;
-    for sched in scheds.into_iter() {
-        debug!("Considering schedule #{}...", sched.id);
-        if !sched.days.runs_on_iso_weekday(on_date.weekday().number_from_monday()) {
-            debug!("...doesn't run on day");
-            continue;
-        }
-        if ret.is_none() {
-            debug!("...first schedule considered");
-            ret = Some(sched);
-            continue;
-        }
-        let other = ret.take().unwrap();
-        if sched.stp_indicator < other.stp_indicator {
-            debug!("...supersedes current schedule");
-            ret = Some(sched);
-        }
-        else if sched.stp_indicator == other.stp_indicator && sched.stp_indicator != "C" {
-            error!("Inconsistency: schedule #{} has a STP indicator equal to #{}", sched.id, other.id);
-            return Err(FahrplanError::StpIndicatorsEqual);
-        }
-        else {
-            debug!("...doesn't supersede current schedule");
-            ret = Some(other);
+                                       &[&uid, &on_date, &source])?
+        .into_iter()
+        .filter(|s| s.days.runs_on_iso_weekday(on_date.weekday().number_from_monday()))
+        .collect();
+    Ok(resolve_effective_schedule(bh, on_date, scheds))
+}
+
+/// Gets the authoritative schedule for a train on a given date.
+///
+/// This might be a cancellation! Returns `None` if there isn't one, or if
+/// it doesn't run because of bank holiday running.
+fn get_auth_schedule(conn: &Connection, bh: &BankHolidayCalendar, uid: String, on_date: NaiveDate, source: u8) -> FahrplanResult<Option<Schedule>> {
+    let eff = match get_effective_schedule(conn, bh, &uid, on_date, source)? {
+        Some(e) => e,
+        None => return Ok(None)
+    };
+    if eff.kind != StpKind::Cancellation && !eff.runs {
+        debug!("Schedule #{} doesn't run on bank holidays", eff.effective.id);
+        return Ok(None);
+    }
+    Ok(Some(eff.effective))
+}
+
+/// Gets the schedule that the version `ver` belongs to, and whether it's
+/// been deleted.
+fn version_parent(conn: &Connection, ver: &ScheduleVersion) -> FahrplanResult<(Schedule, bool)> {
+    if let Some(sid) = ver.parent_sched {
+        let sched = Schedule::from_select(conn, "WHERE id = ?", &[&sid])?
+            .into_iter().nth(0)
+            .ok_or(FahrplanError::NotFound)?;
+        return Ok((sched, false));
+    }
+    let did = ver.deleted_sched.ok_or(FahrplanError::NotFound)?;
+    let sched = DeletedSchedule::from_select(conn, "WHERE id = ?", &[&did])?
+        .into_iter().nth(0)
+        .ok_or(FahrplanError::NotFound)?;
+    Ok((sched.0, true))
+}
+
+/// Gets the details of the schedule version with trainsplorer ID `uu`, which may
+/// either be the current version or a superseded one.
+///
+/// (Metadata isn't versioned, so superseded versions get the current metadata,
+/// or the metadata the schedule had when it was deleted.)
+pub(crate) fn get_schedule_details(conn: &Connection, uu: Uuid) -> FahrplanResult<ScheduleDetails> {
+    let scheds = Schedule::from_select(conn, "WHERE tspl_id = ?", &[&uu])?;
+    if let Some(sched) = scheds.into_iter().nth(0) {
+        let mvts = ScheduleMvt::from_select(conn, "WHERE parent_sched = ? ORDER BY day_offset, time, action ASC", &[&sched.id])?;
+        return Ok(ScheduleDetails {
+            sched,
+            mvts
+        });
+    }
+    let ver = ScheduleVersion::from_select(conn, "WHERE tspl_id = ?", &[&uu])?
+        .into_iter().nth(0)
+        .ok_or(FahrplanError::NotFound)?;
+    debug!("{} is superseded version #{} of schedule #{:?} (deleted #{:?})", uu, ver.id, ver.parent_sched, ver.deleted_sched);
+    let mut sched = version_parent(conn, &ver)?.0;
+    sched.tspl_id = ver.tspl_id;
+    sched.file_metaseq = ver.file_metaseq;
+    let mvts = ScheduleVersionMvt::from_select(conn, "WHERE parent_version = ? ORDER BY day_offset, time, action ASC", &[&ver.id])?
+        .into_iter()
+        .map(|x| x.0)
+        .collect();
+    Ok(ScheduleDetails {
+        sched,
+        mvts
+    })
+}
+
+/// Finds the trains running on `on_date` that match `query`.
+///
+/// Candidate schedules (every schedule valid on `on_date` for any UID with
+/// a schedule matching the filters) are found in SQL, and then resolved with
+/// `resolve_effective_schedule`, across all sources, in the same way as the
+/// movement queries. Only effective schedules are checked against the filters
+/// and returned.
+pub(crate) fn search_schedules(conn: &Connection, bh: &BankHolidayCalendar, on_date: NaiveDate, query: &ScheduleSearchQuery) -> FahrplanResult<ScheduleSearchResponse> {
+    let days = ScheduleDays::from_iso_weekday(on_date.weekday().number_from_monday()).unwrap().bits();
+    let per_page = query.per_page();
+    let upper = |x: &Option<String>| x.as_ref().map(|x| x.to_uppercase());
+    let headcode = upper(&query.headcode);
+    let origin = upper(&query.origin);
+    let destination = upper(&query.destination);
+    let stp_indicator = upper(&query.stp_indicator);
+    let atoc_code = upper(&query.atoc_code);
+    let train_category = upper(&query.train_category);
+
+    // Filters on the schedule itself, which are also used to narrow down
+    // which UIDs are worth looking at.
+    let mut conds = vec![];
+    let mut args: Vec<(&str, &dyn ToSql)> = vec![
+        (":date", &on_date as &dyn ToSql),
+        (":days", &days as &dyn ToSql),
+    ];
+    if let Some(ref h) = headcode {
+        conds.push("(s.signalling_id = :headcode OR s.headcode = :headcode)");
+        args.push((":headcode", h as &dyn ToSql));
+    }
+    if let Some(ref src) = query.source {
+        conds.push("s.source = :source");
+        args.push((":source", src as &dyn ToSql));
+    }
+    if let Some(ref stp) = stp_indicator {
+        conds.push("s.stp_indicator = :stp_indicator");
+        args.push((":stp_indicator", stp as &dyn ToSql));
+    }
+    if let Some(ref a) = atoc_code {
+        conds.push("s.atoc_code = :atoc_code");
+        args.push((":atoc_code", a as &dyn ToSql));
+    }
+    if let Some(ref c) = train_category {
+        conds.push("s.train_category = :train_category");
+        args.push((":train_category", c as &dyn ToSql));
+    }
+    let uid_filter = if !conds.is_empty() {
+        format!("AND uid IN (
+                     SELECT s.uid FROM schedules AS s
+                      WHERE :date BETWEEN s.start_date AND s.end_date
+                        AND s.days & :days
+                        AND {}
+                 )", conds.join(" AND "))
+    }
+    else {
+        String::new()
+    };
+    // Filters on where the schedule starts and ends.
+    if let Some(ref o) = origin {
+        conds.push("o.tiploc = :origin");
+        args.push((":origin", o as &dyn ToSql));
+    }
+    if let Some(ref d) = destination {
+        conds.push("d.tiploc = :destination");
+        args.push((":destination", d as &dyn ToSql));
+    }
+    let matches = if !conds.is_empty() {
+        conds.join(" AND ")
+    }
+    else {
+        "1".into()
+    };
+    let mut stmt = conn.prepare(&format!("WITH cands AS (
+                                              SELECT * FROM schedules
+                                               WHERE :date BETWEEN start_date AND end_date
+                                                 AND days & :days
+                                                 {}
+                                          ),
+                                          ends AS (
+                                              SELECT parent_sched, tiploc, time,
+                                                     ROW_NUMBER() OVER (PARTITION BY parent_sched
+                                                                        ORDER BY day_offset, time, action) AS fwd,
+                                                     ROW_NUMBER() OVER (PARTITION BY parent_sched
+                                                                        ORDER BY day_offset DESC, time DESC, action DESC) AS rev
+                                                FROM schedule_movements
+                                               WHERE parent_sched IN (SELECT id FROM cands)
+                                          )
+                                          SELECT s.*, o.time, COALESCE(({}), 0) FROM cands AS s
+                                     LEFT OUTER JOIN ends AS o
+                                                  ON o.parent_sched = s.id AND o.fwd = 1
+                                     LEFT OUTER JOIN ends AS d
+                                                  ON d.parent_sched = s.id AND d.rev = 1",
+                                         uid_filter, matches))?;
+    info!("Searching schedules running on {} (page {}, {} per page): {:?}", on_date, query.page, per_page, query);
+    let rows = stmt.query_map_named(&args, |row| {
+        Ok((
+            Schedule::from_row(row, 0)?,
+            // Time the schedule starts.
+            row.get::<_, Option<NaiveTime>>(Schedule::FIELDS)?,
+            // Whether the schedule matches all of the filters.
+            row.get::<_, bool>(Schedule::FIELDS + 1)?
+        ))
+    })?;
+    let mut cands: HashMap<String, Vec<Schedule>> = HashMap::new();
+    let mut extra: HashMap<i64, (Option<NaiveTime>, bool)> = HashMap::new();
+    for row in rows {
+        let (sched, start_time, matches) = row?;
+        extra.insert(sched.id, (start_time, matches));
+        cands.entry(sched.uid.clone()).or_insert_with(Vec::new).push(sched);
+    }
+    let mut schedules = vec![];
+    for (_, scheds) in cands {
+        let eff = match resolve_effective_schedule(bh, on_date, scheds) {
+            Some(e) => e,
+            None => continue
+        };
+        let (start_time, matches) = extra[&eff.effective.id];
+        // Cancelled trains don't run, so they only show up if asked for.
+        let wanted = eff.runs || (stp_indicator.is_some() && eff.kind == StpKind::Cancellation);
+        if matches && wanted {
+            schedules.push((start_time, eff.effective));
         }
     }
-    Ok(ret)
+    schedules.sort_by(|a, b| {
+        a.0.cmp(&b.0)
+            .then(a.1.uid.cmp(&b.1.uid))
+            .then(a.1.id.cmp(&b.1.id))
+    });
+    let offset = query.page as usize * per_page as usize;
+    let more = schedules.len() > offset + per_page as usize;
+    let schedules = schedules.into_iter()
+        .skip(offset)
+        .take(per_page as usize)
+        .map(|x| x.1)
+        .collect();
+    Ok(ScheduleSearchResponse {
+        schedules,
+        page: query.page,
+        per_page,
+        more
+    })
+}
+
+fn read_body(req: &Request) -> FahrplanResult<String> {
+    let mut body = String::new();
+    if let Some(mut data) = req.data() {
+        data.read_to_string(&mut body)
+            .map_err(|e| FahrplanError::InvalidBody(e.to_string()))?;
+    }
+    Ok(body)
+}
+
+/// Gets an optional query string parameter, parsing it as a `T`.
+///
+/// Empty parameters are treated as if they weren't there.
+fn query_param<T: FromStr>(req: &Request, name: &str) -> FahrplanResult<Option<T>> {
+    match req.get_param(name).filter(|x| x.trim() != "") {
+        Some(x) => {
+            x.trim().parse()
+                .map(Some)
+                .map_err(|_| FahrplanError::InvalidQuery(format!("{}={}", name, x)))
+        },
+        None => Ok(None)
+    }
+}
+
+fn search_query_from_request(req: &Request) -> FahrplanResult<ScheduleSearchQuery> {
+    Ok(ScheduleSearchQuery {
+        headcode: query_param(req, "headcode")?,
+        origin: query_param(req, "origin")?,
+        destination: query_param(req, "destination")?,
+        source: query_param(req, "source")?,
+        stp_indicator: query_param(req, "stp_indicator")?,
+        atoc_code: query_param(req, "atoc_code")?,
+        train_category: query_param(req, "train_category")?,
+        page: query_param(req, "page")?.unwrap_or(0),
+        per_page: query_param(req, "per_page")?
+    })
 }
 
 pub struct App {
     pub(crate) pool: Arc<RwLock<TsplPool>>,
+    pub(crate) bank_holidays: BankHolidayCalendar,
+    pub(crate) interchanges: Interchanges
 }
 
 impl App {
     pub fn get_connecting_mvts(&self, tpl: String, ts: NaiveDateTime, within_dur: Duration, conn: String) -> FahrplanResult<ConnectingMvtQueryResponse> {
-        let (start_time, end_time) = tspl_util::time::calculate_non_midnight_aware_times(ts, within_dur);
-        info!("Finding mvts passing through {} and {} on {} between {} and {}", tpl, conn, ts.date(), start_time, end_time);
+        let windows = tspl_util::time::calculate_midnight_aware_windows(ts, within_dur);
         let db = self.pool.read().unwrap().get()?;
         let mut stmt = db.prepare("    SELECT * FROM schedule_movements AS smvts 
                                    INNER JOIN schedule_movements AS connecting
@@ -84,62 +384,70 @@ impl App {
                                            ON s.uid = s2.uid
                                         WHERE smvts.tiploc = :tpl
                                           AND smvts.time BETWEEN :start_time AND :end_time
-                                          AND smvts.day_offset = 0 
+                                          AND smvts.day_offset = :day_offset
                                           AND :date BETWEEN s.start_date AND s.end_date
                                           AND :date BETWEEN s2.start_date AND s2.end_date
                                           AND s.days & :days
                                           AND s2.days & :days
                                           AND connecting.tiploc = :tpl_conn
                                           ")?;
-        let days = ScheduleDays::from_iso_weekday(ts.date().weekday().number_from_monday()).unwrap();
-        let args = named_params! {
-            ":tpl": tpl,
-            ":start_time": start_time,
-            ":end_time": end_time,
-            ":days": days.bits(),
-            ":date": ts.date(),
-            ":tpl_conn": conn
+        let mut ret = ConnectingMvtQueryResponse {
+            mvts: HashMap::new(),
+            connecting_mvts: HashMap::new(),
+            schedules: HashMap::new()
         };
-        let rows = stmt.query_map_named(args, |row| {
-            Ok((
-                // The original schedule movement, passing through `tpl`.
-                ScheduleMvt::from_row(row, 0)?,
-                // The connecting schedule movement, passing through `conn`.
-                ScheduleMvt::from_row(row, ScheduleMvt::FIELDS)?,
-                // Its parent schedule.
-                Schedule::from_row(row, 2 * ScheduleMvt::FIELDS)?,
-                // Another schedule which might supersede the parent, if it exists.
-                Schedule::from_row(row, 2 * ScheduleMvt::FIELDS + Schedule::FIELDS).ok()
-            ))
-        })?;
-        let mut smvts: HashMap<i64, ScheduleMvt> = HashMap::new();
-        let mut connecting_smvts: HashMap<i64, ScheduleMvt> = HashMap::new();
-        let mut schedules: HashMap<String, Schedule> = HashMap::new();
-        let mut proc = 0;
-        for row in rows {
-            let (smvt, connecting_smvt, sched, updating_sched) = row?;
-            proc += 1;
-            connecting_smvts.insert(smvt.id, connecting_smvt);
-            smvts.insert(smvt.id , smvt);
-            process_schedule_join(&mut schedules, sched, updating_sched);
+        // Each window is processed separately, since which schedule is
+        // authoritative depends on the date the schedule is running on.
+        for w in windows {
+            info!("Finding mvts passing through {} and {} on {} (day offset {}) between {} and {}",
+                  tpl, conn, w.date, w.day_offset, w.start_time, w.end_time);
+            let days = ScheduleDays::from_iso_weekday(w.date.weekday().number_from_monday()).unwrap();
+            let args = named_params! {
+                ":tpl": tpl,
+                ":start_time": w.start_time,
+                ":end_time": w.end_time,
+                ":day_offset": w.day_offset,
+                ":days": days.bits(),
+                ":date": w.date,
+                ":tpl_conn": conn
+            };
+            let rows = stmt.query_map_named(args, |row| {
+                Ok((
+                    // The original schedule movement, passing through `tpl`.
+                    ScheduleMvt::from_row(row, 0)?,
+                    // The connecting schedule movement, passing through `conn`.
+                    ScheduleMvt::from_row(row, ScheduleMvt::FIELDS)?,
+                    // Its parent schedule.
+                    Schedule::from_row(row, 2 * ScheduleMvt::FIELDS)?,
+                    // Another schedule which might supersede the parent, if it exists.
+                    Schedule::from_row(row, 2 * ScheduleMvt::FIELDS + Schedule::FIELDS).ok()
+                ))
+            })?;
+            let mut smvts: HashMap<i64, ScheduleMvt> = HashMap::new();
+            let mut connecting_smvts: HashMap<i64, ScheduleMvt> = HashMap::new();
+            let mut schedules: HashMap<String, HashMap<i64, Schedule>> = HashMap::new();
+            let mut proc = 0;
+            for row in rows {
+                let (smvt, connecting_smvt, sched, updating_sched) = row?;
+                proc += 1;
+                connecting_smvts.insert(smvt.id, connecting_smvt);
+                smvts.insert(smvt.id , smvt);
+                process_schedule_join(&mut schedules, sched, updating_sched);
+            }
+            let id_schedules = resolve_joined_schedules(&self.bank_holidays, w.date, schedules);
+            let orig_smvts = smvts.len();
+            smvts.retain(|_, mvt| id_schedules.contains_key(&mvt.parent_sched));
+            connecting_smvts.retain(|id, _| smvts.contains_key(&id));
+            info!("Processed {} rows for a total of {} valid smvts ({} invalid) and {} schedules.",
+                  proc, smvts.len(), orig_smvts - smvts.len(), id_schedules.len());
+            ret.mvts.extend(smvts);
+            ret.connecting_mvts.extend(connecting_smvts);
+            ret.schedules.extend(id_schedules);
         }
-        let id_schedules = uid_scheds_to_id_scheds(schedules);
-        let orig_smvts = smvts.len();
-        smvts.retain(|_, mvt| id_schedules.contains_key(&mvt.parent_sched));
-        connecting_smvts.retain(|id, _| smvts.contains_key(&id));
-        info!("Processed {} rows for a total of {} valid smvts ({} invalid) and {} schedules.",
-              proc, smvts.len(), orig_smvts - smvts.len(), id_schedules.len());
-        Ok(ConnectingMvtQueryResponse {
-            mvts: smvts,
-            connecting_mvts: connecting_smvts,
-            schedules: id_schedules
-        })
+        Ok(ret)
     }
-    // FIXME: This function doesn't yet handle trains crossing over midnight, like its friends in
-    // the `tspl-zugfuhrer` crate.
     pub fn get_mvts_passing_through(&self, tpl: String, ts: NaiveDateTime, within_dur: Duration) -> FahrplanResult<MvtQueryResponse> {
-        let (start_time, end_time) = tspl_util::time::calculate_non_midnight_aware_times(ts, within_dur);
-        info!("Finding mvts passing through {} on {} between {} and {}", tpl, ts.date(), start_time, end_time);
+        let windows = tspl_util::time::calculate_midnight_aware_windows(ts, within_dur);
         let db = self.pool.read().unwrap().get()?;
         let mut stmt = db.prepare("    SELECT * FROM schedule_movements AS smvts 
                                    INNER JOIN schedules AS s
@@ -148,48 +456,60 @@ impl App {
                                            ON s.uid = s2.uid
                                         WHERE smvts.tiploc = :tpl
                                           AND smvts.time BETWEEN :start_time AND :end_time
-                                          AND smvts.day_offset = 0 
+                                          AND smvts.day_offset = :day_offset
                                           AND :date BETWEEN s.start_date AND s.end_date
                                           AND :date BETWEEN s2.start_date AND s2.end_date
                                           AND s.days & :days
                                           AND s2.days & :days
                                           ")?;
-        let days = ScheduleDays::from_iso_weekday(ts.date().weekday().number_from_monday()).unwrap();
-        let args = named_params! {
-            ":tpl": tpl,
-            ":start_time": start_time,
-            ":end_time": end_time,
-            ":days": days.bits(),
-            ":date": ts.date()
+        let mut ret = MvtQueryResponse {
+            mvts: HashMap::new(),
+            schedules: HashMap::new()
         };
-        let rows = stmt.query_map_named(args, |row| {
-            Ok((
-                // The original schedule movement, passing through `tpl`.
-                ScheduleMvt::from_row(row, 0)?,
-                // Its parent schedule.
-                Schedule::from_row(row, ScheduleMvt::FIELDS)?,
-                // Another schedule which might supersede the parent, if it exists.
-                Schedule::from_row(row, ScheduleMvt::FIELDS + Schedule::FIELDS).ok()
-            ))
-        })?;
-        let mut smvts: HashMap<i64, ScheduleMvt> = HashMap::new();
-        let mut schedules: HashMap<String, Schedule> = HashMap::new();
-        let mut proc = 0;
-        for row in rows {
-            let (smvt, sched, updating_sched) = row?;
-            proc += 1;
-            smvts.insert(smvt.id, smvt);
-            process_schedule_join(&mut schedules, sched, updating_sched);
+        // (see the comment in `get_connecting_mvts`)
+        for w in windows {
+            info!("Finding mvts passing through {} on {} (day offset {}) between {} and {}",
+                  tpl, w.date, w.day_offset, w.start_time, w.end_time);
+            let days = ScheduleDays::from_iso_weekday(w.date.weekday().number_from_monday()).unwrap();
+            let args = named_params! {
+                ":tpl": tpl,
+                ":start_time": w.start_time,
+                ":end_time": w.end_time,
+                ":day_offset": w.day_offset,
+                ":days": days.bits(),
+                ":date": w.date
+            };
+            let rows = stmt.query_map_named(args, |row| {
+                Ok((
+                    // The original schedule movement, passing through `tpl`.
+                    ScheduleMvt::from_row(row, 0)?,
+                    // Its parent schedule.
+                    Schedule::from_row(row, ScheduleMvt::FIELDS)?,
+                    // Another schedule which might supersede the parent, if it exists.
+                    Schedule::from_row(row, ScheduleMvt::FIELDS + Schedule::FIELDS).ok()
+                ))
+            })?;
+            let mut smvts: HashMap<i64, ScheduleMvt> = HashMap::new();
+            let mut schedules: HashMap<String, HashMap<i64, Schedule>> = HashMap::new();
+            let mut proc = 0;
+            for row in rows {
+                let (smvt, sched, updating_sched) = row?;
+                proc += 1;
+                smvts.insert(smvt.id, smvt);
+                process_schedule_join(&mut schedules, sched, updating_sched);
+            }
+            let id_schedules = resolve_joined_schedules(&self.bank_holidays, w.date, schedules);
+            let orig_smvts = smvts.len();
+            smvts.retain(|_, mvt| id_schedules.contains_key(&mvt.parent_sched));
+            info!("Processed {} rows for a total of {} valid smvts ({} invalid) and {} schedules.",
+                  proc, smvts.len(), orig_smvts - smvts.len(), id_schedules.len());
+            ret.mvts.extend(smvts);
+            ret.schedules.extend(id_schedules);
         }
-        let id_schedules = uid_scheds_to_id_scheds(schedules);
-        let orig_smvts = smvts.len();
-        smvts.retain(|_, mvt| id_schedules.contains_key(&mvt.parent_sched));
-        info!("Processed {} rows for a total of {} valid smvts ({} invalid) and {} schedules.",
-              proc, smvts.len(), orig_smvts - smvts.len(), id_schedules.len());
-        Ok(MvtQueryResponse {
-            mvts: smvts,
-            schedules: id_schedules
-        })
+        Ok(ret)
+    }
+    pub fn plan_journeys(&self, from: String, to: String, ts: NaiveDateTime, horizon: Duration) -> FahrplanResult<JourneyPlanResponse> {
+        JourneyPlanner::new(self).plan(&from, &to, ts, horizon)
     }
     pub fn find_schedules_with_uid(&self, uid: String) -> FahrplanResult<Vec<Schedule>> {
         let db = self.pool.read().unwrap().get()?;
@@ -204,21 +524,138 @@ impl App {
     }
     pub fn find_schedule_on_date(&self, uid: String, on_date: NaiveDate, source: u8) -> FahrplanResult<Schedule> {
         let db = self.pool.read().unwrap().get()?;
-        let auth_sched = get_auth_schedule(&db, uid, on_date, source)?
+        let auth_sched = get_auth_schedule(&db, &self.bank_holidays, uid, on_date, source)?
             .ok_or(FahrplanError::NotFound)?;
         Ok(auth_sched)
     }
-    pub fn request_schedule_details(&self, uu: Uuid) -> FahrplanResult<ScheduleDetails> {
+    pub fn search_schedules(&self, on_date: NaiveDate, query: ScheduleSearchQuery) -> FahrplanResult<ScheduleSearchResponse> {
         let db = self.pool.read().unwrap().get()?;
-        let scheds = Schedule::from_select(&db, "WHERE tspl_id = ?", &[&uu])?;
-        let sched = scheds.into_iter().nth(0)
+        search_schedules(&db, &self.bank_holidays, on_date, &query)
+    }
+    pub fn find_effective_schedule(&self, uid: String, on_date: NaiveDate, source: u8) -> FahrplanResult<EffectiveSchedule> {
+        let db = self.pool.read().unwrap().get()?;
+        let eff = get_effective_schedule(&db, &self.bank_holidays, &uid, on_date, source)?
             .ok_or(FahrplanError::NotFound)?;
-        let mvts = ScheduleMvt::from_select(&db, "WHERE parent_sched = ? ORDER BY day_offset, time, action ASC", &[&sched.id])?;
-        Ok(ScheduleDetails {
-            sched,
-            mvts
+        Ok(eff)
+    }
+    pub fn get_bank_holidays_on(&self, date: NaiveDate) -> BankHolidayRegions {
+        self.bank_holidays.regions_on(date)
+    }
+    pub fn find_associations_for_uid_on_date(&self, uid: String, on_date: NaiveDate) -> FahrplanResult<Vec<Association>> {
+        let db = self.pool.read().unwrap().get()?;
+        let assocs = Association::from_select(&db, "WHERE main_uid = ?1 OR assoc_uid = ?1", &[&uid])?;
+        // Associations are keyed by the main train's UID and location, and
+        // superseded in the same way as schedules (lowest STP indicator wins).
+        let mut ret: HashMap<(String, String, String), Association> = HashMap::new();
+        for assoc in assocs {
+            // The dates on the association refer to the main train, so
+            // if we're the associated train, work out when that runs.
+            let main_date = if assoc.main_uid == uid {
+                on_date
+            }
+            else {
+                assoc.main_date_for_assoc_date(on_date)
+            };
+            if !assoc.runs_on_main_date(main_date) {
+                continue;
+            }
+            let key = (assoc.main_uid.clone(), assoc.assoc_uid.clone(), assoc.tiploc.clone());
+            let supersedes = ret.get(&key)
+                .map(|other| assoc.stp_indicator < other.stp_indicator)
+                .unwrap_or(true);
+            if supersedes {
+                ret.insert(key, assoc);
+            }
+        }
+        Ok(ret.into_iter()
+           .map(|(_, v)| v)
+           .filter(|a| a.stp_indicator != "C")
+           .collect())
+    }
+    pub fn process_vstp_message(&self, body: &str) -> FahrplanResult<Option<Schedule>> {
+        let rec: vstp::Record = serde_json::from_str(body)
+            .map_err(|e| FahrplanError::InvalidBody(e.to_string()))?;
+        let mut db = self.pool.read().unwrap().get()?;
+        let trans = db.transaction()?;
+        let ret = crate::vstp::apply_vstp_record(&trans, rec)?;
+        trans.commit()?;
+        Ok(ret)
+    }
+    pub fn process_darwin_schedule(&self, body: &str) -> FahrplanResult<Schedule> {
+        let mut details: ScheduleDetails = serde_json::from_str(body)
+            .map_err(|e| FahrplanError::InvalidBody(e.to_string()))?;
+        let rid = details.sched.darwin_id.clone()
+            .ok_or(FahrplanError::InvalidBody("Darwin schedule has no RID".into()))?;
+        info!("Processing Darwin schedule for RID {} (uid {}, ssd {})", rid, details.sched.uid, details.sched.start_date);
+        let mut db = self.pool.read().unwrap().get()?;
+        let trans = db.transaction()?;
+        let old = Schedule::from_select(&trans, "WHERE darwin_id = ? AND source = ?",
+                                        &[&rid, &Schedule::SOURCE_DARWIN])?
+            .into_iter().nth(0);
+        if let Some(ref old) = old {
+            let orig_mvts = ScheduleMvt::from_select(&trans, "WHERE parent_sched = ? ORDER BY day_offset, time, action ASC", &[&old.id])?;
+            if orig_mvts == details.mvts {
+                debug!("Darwin schedule identical to existing schedule #{}", old.id);
+                return Ok(old.clone());
+            }
+        }
+        details.sched.tspl_id = Uuid::new_v4();
+        details.sched.source = Schedule::SOURCE_DARWIN;
+        details.sched.file_metaseq = None;
+        details.sched.id = match old {
+            Some(old) => {
+                info!("Replacing existing Darwin schedule #{}", old.id);
+                ScheduleVersion::replace_schedule(&trans, old.id, &details, None)?
+            },
+            None => details.insert_self(&trans)?
+        };
+        trans.commit()?;
+        Ok(details.sched)
+    }
+    pub fn delete_darwin_schedule(&self, rid: String) -> FahrplanResult<()> {
+        let mut db = self.pool.read().unwrap().get()?;
+        info!("Deleting Darwin schedule for RID {}", rid);
+        let trans = db.transaction()?;
+        let scheds = Schedule::from_select(&trans, "WHERE darwin_id = ? AND source = ?",
+                                           &[&rid, &Schedule::SOURCE_DARWIN])?;
+        if scheds.is_empty() {
+            Err(FahrplanError::NotFound)?
+        }
+        for sched in scheds {
+            ScheduleVersion::delete_schedule(&trans, sched.id, None)?;
+        }
+        trans.commit()?;
+        Ok(())
+    }
+    pub fn request_schedule_details(&self, uu: Uuid) -> FahrplanResult<ScheduleDetails> {
+        let db = self.pool.read().unwrap().get()?;
+        get_schedule_details(&db, uu)
+    }
+    pub fn request_schedule_history(&self, uu: Uuid) -> FahrplanResult<ScheduleHistory> {
+        let db = self.pool.read().unwrap().get()?;
+        let (current, deleted) = match Schedule::from_select(&db, "WHERE tspl_id = ?", &[&uu])?.into_iter().nth(0) {
+            Some(s) => (s, false),
+            None => {
+                let ver = ScheduleVersion::from_select(&db, "WHERE tspl_id = ?", &[&uu])?
+                    .into_iter().nth(0)
+                    .ok_or(FahrplanError::NotFound)?;
+                version_parent(&db, &ver)?
+            }
+        };
+        let column = if deleted { "deleted_sched" } else { "parent_sched" };
+        let versions = ScheduleVersion::from_select(&db, &format!("WHERE {} = ? ORDER BY superseded_at, id ASC", column), &[&current.id])?;
+        Ok(ScheduleHistory {
+            current,
+            deleted,
+            versions
         })
     }
+    pub fn request_schedule_diff(&self, from: Uuid, to: Uuid) -> FahrplanResult<ScheduleDiff> {
+        let db = self.pool.read().unwrap().get()?;
+        let from = get_schedule_details(&db, from)?;
+        let to = get_schedule_details(&db, to)?;
+        Ok(ScheduleDiff::between(&from.mvts, &to.mvts))
+    }
 }
 impl HttpServer for App {
     type Error = FahrplanError;
@@ -235,14 +672,52 @@ impl HttpServer for App {
                 self.find_schedule_on_date(uid, on_date, source)
                     .map(|x| Response::json(&x))
             },
+            (GET) (/schedules/effective/{uid}/{on_date: NaiveDate}/{source: u8}) => {
+                self.find_effective_schedule(uid, on_date, source)
+                    .map(|x| Response::json(&x))
+            },
             (GET) (/schedules/for-activation/{uid}/{start_date}/{stp_indicator}/{source}) => {
                 self.find_schedule_for_activation(uid, stp_indicator, start_date, source)
                     .map(|x| Response::json(&x))
             },
+            (GET) (/schedules/running-on/{on_date: NaiveDate}) => {
+                let query = search_query_from_request(req)?;
+                self.search_schedules(on_date, query)
+                    .map(|x| Response::json(&x))
+            },
+            (GET) (/associations/for-uid/{uid}/on/{on_date: NaiveDate}) => {
+                self.find_associations_for_uid_on_date(uid, on_date)
+                    .map(|x| Response::json(&x))
+            },
+            (GET) (/bank-holidays/on/{date: NaiveDate}) => {
+                Ok(Response::json(&self.get_bank_holidays_on(date)))
+            },
             (GET) (/schedule/{uuid}) => {
                 self.request_schedule_details(uuid)
                     .map(|x| Response::json(&x))
             },
+            (GET) (/schedule/{uuid}/history) => {
+                self.request_schedule_history(uuid)
+                    .map(|x| Response::json(&x))
+            },
+            (GET) (/schedule/{uuid}/diff/{other}) => {
+                self.request_schedule_diff(uuid, other)
+                    .map(|x| Response::json(&x))
+            },
+            (POST) (/vstp) => {
+                let body = read_body(req)?;
+                self.process_vstp_message(&body)
+                    .map(|x| Response::json(&x))
+            },
+            (POST) (/schedules/darwin) => {
+                let body = read_body(req)?;
+                self.process_darwin_schedule(&body)
+                    .map(|x| Response::json(&x))
+            },
+            (POST) (/schedules/darwin/{rid}/delete) => {
+                self.delete_darwin_schedule(rid)
+                    .map(|x| Response::json(&x))
+            },
             (GET) (/schedule-movements/through/{tiploc}/at/{ts: NaiveDateTime}/within-secs/{dur: u32}) => {
                 self.get_mvts_passing_through(tiploc, ts, Duration::seconds(dur as _))
                     .map(|x| Response::json(&x))
@@ -251,6 +726,10 @@ impl HttpServer for App {
                 self.get_connecting_mvts(tiploc, ts, Duration::seconds(dur as _), conn)
                     .map(|x| Response::json(&x))
             },
+            (GET) (/journeys/from/{from}/to/{to}/at/{ts: NaiveDateTime}/within-secs/{dur: u32}) => {
+                self.plan_journeys(from, to, ts, Duration::seconds(dur as _))
+                    .map(|x| Response::json(&x))
+            },
             _ => {
                 Err(FahrplanError::InvalidPath)
             }
@@ -15,8 +15,12 @@ pub enum FahrplanError {
     /// The API path doesn't exist.
     #[fail(display = "invalid path")]
     InvalidPath,
-    #[fail(display = "STP indicators equal")]
-    StpIndicatorsEqual,
+    /// The request body couldn't be read or parsed.
+    #[fail(display = "invalid request body: {}", _0)]
+    InvalidBody(String),
+    /// A query string parameter was invalid.
+    #[fail(display = "invalid query parameter: {}", _0)]
+    InvalidQuery(String),
     /// SQL error from tspl-sqlite.
     #[fail(display = "tspl-sqlite: {}", _0)]
     Sql(SqlError),
@@ -38,6 +42,8 @@ impl StatusCode for FahrplanError {
         match *self {
             NotFound => 404,
             InvalidPath => 400,
+            InvalidBody(_) => 400,
+            InvalidQuery(_) => 400,
             _ => 500
         }
     }
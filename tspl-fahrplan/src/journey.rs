@@ -0,0 +1,288 @@
+//! Journey planning, using a round-based (RAPTOR-style) search.
+//!
+//! This is loosely based on "Round-Based Public Transit Routing" (Delling, Pajor
+//! & Werneck, 2012). The main difference is that we don't precompute routes or
+//! trip lists; instead, each round asks the database for departures from the
+//! stations improved in the previous round (using the same query as the movement
+//! search, so STP overlays and bank holidays are resolved in the same way).
+//!
+//! Only public (GBTT) times are used, so trains can't be boarded or alighted at
+//! places where they don't call for passengers. Changing trains at a station
+//! takes the minimum connection time from the MSN, if we have one.
+
+use tspl_sqlite::traits::*;
+use tspl_nennen::types::WrappedMsnStation;
+use std::collections::{HashMap, HashSet};
+use chrono::*;
+use log::*;
+
+use crate::ctx::App;
+use crate::types::{Schedule, ScheduleMvt, JourneyLeg, Journey, JourneyPlanResponse};
+use crate::errors::{Result, FahrplanResult};
+
+/// Connection time to use for stations without an MSN entry, in minutes.
+pub static DEFAULT_CHANGE_TIME: u32 = 5;
+/// Maximum number of changes to consider when planning a journey.
+pub static MAX_CHANGES: u32 = 3;
+
+/// Information about which TIPLOCs make up which stations, and how long it
+/// takes to change trains at them.
+///
+/// Stations are identified by their CRS code, if they have one in the MSN;
+/// otherwise, they're just identified by their TIPLOC.
+#[derive(Debug, Default)]
+pub struct Interchanges {
+    change_times: HashMap<String, u32>,
+    station_for_tiploc: HashMap<String, String>,
+    tiplocs_for_station: HashMap<String, Vec<String>>
+}
+impl Interchanges {
+    /// Loads interchange information from a `tspl-nennen` reference database.
+    pub fn load(path: &str) -> Result<Self> {
+        let db = tspl_sqlite::initialize_db(path, &tspl_nennen::types::MIGRATIONS)?;
+        let mut ret = Self::default();
+        for msn in WrappedMsnStation::from_select(&db, "", NO_PARAMS)? {
+            let msn = msn.0;
+            ret.change_times.insert(msn.crs.clone(), msn.change_time as _);
+            ret.station_for_tiploc.insert(msn.tiploc.clone(), msn.crs.clone());
+            ret.tiplocs_for_station.entry(msn.crs)
+                .or_insert_with(Vec::new)
+                .push(msn.tiploc);
+        }
+        info!("Loaded interchange information for {} stations", ret.change_times.len());
+        Ok(ret)
+    }
+    /// Resolves a CRS code or TIPLOC to a station.
+    pub fn resolve(&self, code: &str) -> String {
+        if self.tiplocs_for_station.contains_key(code) {
+            code.to_owned()
+        }
+        else {
+            self.station_for_tiploc(code)
+        }
+    }
+    /// Returns the station a TIPLOC is part of.
+    pub fn station_for_tiploc(&self, tpl: &str) -> String {
+        self.station_for_tiploc.get(tpl)
+            .cloned()
+            .unwrap_or_else(|| tpl.to_owned())
+    }
+    /// Returns the TIPLOCs making up a station.
+    pub fn tiplocs_for_station(&self, stn: &str) -> Vec<String> {
+        self.tiplocs_for_station.get(stn)
+            .cloned()
+            .unwrap_or_else(|| vec![stn.to_owned()])
+    }
+    /// Returns the minimum connection time at a station.
+    pub fn change_time(&self, stn: &str) -> Duration {
+        let mins = self.change_times.get(stn)
+            .cloned()
+            .unwrap_or(DEFAULT_CHANGE_TIME);
+        Duration::minutes(mins as _)
+    }
+}
+
+/// Gets the public time of a movement, for a schedule running on `date`.
+fn public_datetime(mvt: &ScheduleMvt, date: NaiveDate) -> Option<NaiveDateTime> {
+    mvt.public_time
+        .map(|t| (date + Duration::days(mvt.day_offset as _)).and_time(t))
+}
+
+/// Works out which date a schedule must be running on for `mvt` to happen
+/// between `start` and `end` (going by its working time).
+pub(crate) fn run_date_between(mvt: &ScheduleMvt, start: NaiveDateTime, end: NaiveDateTime) -> Option<NaiveDate> {
+    let base = start.date() - Duration::days(mvt.day_offset as _);
+    (-1..=1)
+        .map(|d| base + Duration::days(d))
+        .find(|date| {
+            let dt = (*date + Duration::days(mvt.day_offset as _)).and_time(mvt.time);
+            dt >= start && dt <= end
+        })
+}
+
+/// The earliest known arrival at a station, in a given round.
+#[derive(Clone, Debug)]
+struct Label {
+    /// When we get there.
+    arrival: NaiveDateTime,
+    /// The round in which this label was found.
+    round: usize,
+    /// The station we came from, and the leg we used to get here (or `None`
+    /// if this is where the journey starts).
+    leg: Option<(String, JourneyLeg)>
+}
+
+/// Departures from a TIPLOC found by the movement search, for one window.
+struct CachedDepartures {
+    after: NaiveDateTime,
+    until: NaiveDateTime,
+    deps: Vec<(Schedule, ScheduleMvt)>
+}
+
+pub struct JourneyPlanner<'a> {
+    app: &'a App,
+    mvt_cache: HashMap<i64, Vec<ScheduleMvt>>,
+    /// Departures searched for so far, by TIPLOC. A station gets searched
+    /// again whenever a round improves on it, so this saves going back to
+    /// the database for windows we already have the departures for.
+    dep_cache: HashMap<String, Vec<CachedDepartures>>
+}
+impl<'a> JourneyPlanner<'a> {
+    pub fn new(app: &'a App) -> Self {
+        Self {
+            app,
+            mvt_cache: HashMap::new(),
+            dep_cache: HashMap::new()
+        }
+    }
+    /// Finds departures from `tpl` between `after` and `until`, or some
+    /// wider window, using the cache if possible.
+    fn search_departures(&mut self, tpl: String, after: NaiveDateTime, until: NaiveDateTime) -> FahrplanResult<&[(Schedule, ScheduleMvt)]> {
+        let pos = self.dep_cache.get(&tpl)
+            .and_then(|x| x.iter().position(|c| c.after <= after && c.until >= until));
+        let pos = match pos {
+            Some(p) => p,
+            None => {
+                // The movement search looks either side of a timestamp, so
+                // search around the midpoint.
+                let half = (until - after) / 2;
+                let center = after + half;
+                let resp = self.app.get_mvts_passing_through(tpl.clone(), center, half)?;
+                let mut deps = vec![];
+                for (_, mvt) in resp.mvts {
+                    if mvt.action != ScheduleMvt::ACTION_DEPARTURE {
+                        continue;
+                    }
+                    if let Some(s) = resp.schedules.get(&mvt.parent_sched) {
+                        deps.push((s.clone(), mvt));
+                    }
+                }
+                let entries = self.dep_cache.entry(tpl.clone()).or_insert_with(Vec::new);
+                entries.push(CachedDepartures { after, until, deps });
+                entries.len() - 1
+            }
+        };
+        Ok(&self.dep_cache[&tpl][pos].deps)
+    }
+    /// Finds public departures from a station between `after` and `until`,
+    /// together with the date each schedule is running on.
+    pub(crate) fn departures_from(&mut self, stn: &str, after: NaiveDateTime, until: NaiveDateTime) -> FahrplanResult<Vec<(Schedule, ScheduleMvt, NaiveDate)>> {
+        let mut ret = vec![];
+        if until <= after {
+            return Ok(ret);
+        }
+        for tpl in self.app.interchanges.tiplocs_for_station(stn) {
+            for (sched, mvt) in self.search_departures(tpl, after, until)? {
+                let date = match run_date_between(mvt, after, until) {
+                    Some(d) => d,
+                    None => continue
+                };
+                match public_datetime(mvt, date) {
+                    Some(dt) if dt >= after && dt <= until => {},
+                    _ => continue
+                }
+                ret.push((sched.clone(), mvt.clone(), date));
+            }
+        }
+        Ok(ret)
+    }
+    fn load_mvts(&mut self, sched_id: i64) -> FahrplanResult<()> {
+        if !self.mvt_cache.contains_key(&sched_id) {
+            let db = self.app.pool.read().unwrap().get()?;
+            let mvts = ScheduleMvt::from_select(&db, "WHERE parent_sched = ? ORDER BY day_offset, time, action ASC", &[&sched_id])?;
+            self.mvt_cache.insert(sched_id, mvts);
+        }
+        Ok(())
+    }
+    /// Plans journeys from `from` to `to` (CRS codes or TIPLOCs), leaving
+    /// at or after `ts` and arriving no later than `ts + horizon`.
+    pub fn plan(&mut self, from: &str, to: &str, ts: NaiveDateTime, horizon: Duration) -> FahrplanResult<JourneyPlanResponse> {
+        let from = self.app.interchanges.resolve(from);
+        let to = self.app.interchanges.resolve(to);
+        let end = ts + horizon;
+        info!("Planning journeys from {} to {} between {} and {}", from, to, ts, end);
+        // labels[k] contains the earliest arrivals using at most k trains.
+        let mut labels: Vec<HashMap<String, Label>> = vec![HashMap::new()];
+        labels[0].insert(from.clone(), Label { arrival: ts, round: 0, leg: None });
+        // The earliest arrival at each station over all rounds.
+        let mut best: HashMap<String, NaiveDateTime> = HashMap::new();
+        best.insert(from.clone(), ts);
+        let mut marked: HashSet<String> = HashSet::new();
+        marked.insert(from.clone());
+        for k in 1..=(MAX_CHANGES as usize + 1) {
+            if marked.is_empty() {
+                break;
+            }
+            let prev = labels[k - 1].clone();
+            let mut cur = prev.clone();
+            let mut new_marked = HashSet::new();
+            for stn in marked.drain() {
+                let label = &prev[&stn];
+                // If we got here on a train, we need to allow time to change.
+                let earliest = if label.leg.is_some() {
+                    label.arrival + self.app.interchanges.change_time(&stn)
+                }
+                else {
+                    label.arrival
+                };
+                let deps = self.departures_from(&stn, earliest, end)?;
+                debug!("Round {}: {} departures from {} after {}", k, deps.len(), stn, earliest);
+                for (sched, board, date) in deps {
+                    let departure = public_datetime(&board, date).unwrap();
+                    self.load_mvts(sched.id)?;
+                    for mvt in self.mvt_cache[&sched.id].iter().filter(|m| *m > &board) {
+                        if mvt.action != ScheduleMvt::ACTION_ARRIVAL {
+                            continue;
+                        }
+                        let arrival = match public_datetime(mvt, date) {
+                            Some(a) => a,
+                            None => continue
+                        };
+                        if arrival > end || best.get(&to).map(|x| arrival >= *x).unwrap_or(false) {
+                            // Every later stop on this train is later still.
+                            break;
+                        }
+                        let q = self.app.interchanges.station_for_tiploc(&mvt.tiploc);
+                        if q == stn || best.get(&q).map(|x| arrival >= *x).unwrap_or(false) {
+                            continue;
+                        }
+                        best.insert(q.clone(), arrival);
+                        let leg = JourneyLeg {
+                            schedule: sched.clone(),
+                            board_mvt: board.clone(),
+                            alight_mvt: mvt.clone(),
+                            departure,
+                            arrival
+                        };
+                        cur.insert(q.clone(), Label { arrival, round: k, leg: Some((stn.clone(), leg)) });
+                        new_marked.insert(q);
+                    }
+                }
+            }
+            labels.push(cur);
+            marked = new_marked;
+        }
+        // Every round that improved the arrival at the destination gives
+        // us a journey.
+        let mut journeys = vec![];
+        for k in 1..labels.len() {
+            let mut label = match labels[k].get(&to) {
+                Some(l) if l.round == k => l,
+                _ => continue
+            };
+            let mut legs = vec![];
+            while let Some((ref prev_stn, ref leg)) = label.leg {
+                legs.push(leg.clone());
+                label = &labels[label.round - 1][prev_stn];
+            }
+            legs.reverse();
+            journeys.push(Journey {
+                arrival: legs[legs.len() - 1].arrival,
+                changes: legs.len() as u32 - 1,
+                legs
+            });
+        }
+        info!("Found {} journeys from {} to {}", journeys.len(), from, to);
+        Ok(JourneyPlanResponse { journeys })
+    }
+}
@@ -5,6 +5,12 @@ pub mod types;
 pub mod config;
 pub mod ctx;
 pub mod updater;
+pub mod vstp;
+pub mod bank_holidays;
+pub mod journey;
+
+#[cfg(test)]
+mod tests;
 
 use tspl_sqlite::r2d2;
 use tspl_util::ConfigExt;
@@ -12,11 +18,14 @@ use self::config::Config;
 use std::sync::{RwLock, Arc};
 use crate::updater::DbUpdater;
 use crate::ctx::App;
+use crate::bank_holidays::BankHolidayCalendar;
+use crate::journey::Interchanges;
 use log::*;
 
 pub static DATABASE_PATH: &str = "./fahrplan.sqlite";
 pub static DATABASE_PATH_DL: &str = "./fahrplan-dl.sqlite";
 pub static DATABASE_PATH_TEMP: &str = "./fahrplan-temp.sqlite";
+pub static REFDATA_PATH: &str = "./refdata.sqlite";
 
 fn main() -> errors::Result<()> {
     tspl_util::setup_logging()?;
@@ -29,10 +38,32 @@ fn main() -> errors::Result<()> {
     let pool = Arc::new(RwLock::new(pool));
     info!("initializing db updater");
     let mut dbu = DbUpdater::init(&cfg, pool.clone())?;
-    info!("downloading db from GCS");
+    info!("downloading db from storage");
     dbu.download(DATABASE_PATH_DL)?;
     info!("backgrounding updater");
     dbu.run_in_background();
-    let app = Arc::new(App { pool });
+    let bank_holidays = match cfg.bank_holidays_path {
+        Some(ref p) => {
+            info!("loading bank holidays from {}", p);
+            BankHolidayCalendar::load(p)?
+        },
+        None => {
+            warn!("no bank holiday calendar configured; bank holiday running flags will be ignored");
+            BankHolidayCalendar::default()
+        }
+    };
+    let interchanges = match cfg.refdata_object_name {
+        Some(ref obj) => {
+            info!("downloading reference data from storage");
+            let mut storage = cfg.storage.open()?;
+            storage.download_object(obj, REFDATA_PATH)?;
+            Interchanges::load(REFDATA_PATH)?
+        },
+        None => {
+            warn!("no reference data configured; journey planning will use default connection times");
+            Interchanges::default()
+        }
+    };
+    let app = Arc::new(App { pool, bank_holidays, interchanges });
     tspl_util::http::start_server(&cfg.listen_url, app);
 }
@@ -0,0 +1,461 @@
+use chrono::*;
+use std::sync::{Arc, RwLock};
+use tspl_sqlite::traits::*;
+
+use crate::bank_holidays::BankHolidayCalendar;
+use crate::ctx::{App, resolve_effective_schedule, search_schedules, get_schedule_details};
+use crate::journey::{Interchanges, JourneyPlanner, run_date_between};
+use crate::types::*;
+
+/// A Tuesday, with no bank holidays.
+fn tuesday() -> NaiveDate {
+    NaiveDate::from_ymd(2019, 05, 07)
+}
+/// The early May bank holiday (a Monday).
+fn bank_holiday() -> NaiveDate {
+    NaiveDate::from_ymd(2019, 05, 06)
+}
+fn calendar() -> BankHolidayCalendar {
+    let mut ret = BankHolidayCalendar::default();
+    ret.england_and_wales.insert(bank_holiday());
+    ret
+}
+fn db() -> Connection {
+    tspl_sqlite::initialize_db(":memory:", &MIGRATIONS).unwrap()
+}
+fn sched(uid: &str, stp: &str, source: u8, headcode: &str) -> Schedule {
+    Schedule {
+        id: -1,
+        tspl_id: Uuid::new_v4(),
+        uid: uid.into(),
+        start_date: NaiveDate::from_ymd(2019, 01, 01),
+        end_date: NaiveDate::from_ymd(2019, 12, 31),
+        days: ScheduleDays::all(),
+        stp_indicator: stp.into(),
+        signalling_id: Some(headcode.into()),
+        source,
+        file_metaseq: None,
+        darwin_id: None,
+        crosses_midnight: false,
+        meta: ScheduleMeta::default()
+    }
+}
+/// Inserts `sched`, running from `orig` at `h`:00 to `dest` an hour later.
+fn insert(conn: &Connection, sched: Schedule, orig: &str, dest: &str, h: u32) -> Schedule {
+    let mvt = |tiploc: &str, action, time| ScheduleMvt {
+        tiploc: tiploc.into(),
+        action,
+        time,
+        ..ScheduleMvt::dummy()
+    };
+    let mut details = ScheduleDetails {
+        sched,
+        mvts: vec![
+            mvt(orig, ScheduleMvt::ACTION_DEPARTURE, NaiveTime::from_hms(h, 0, 0)),
+            mvt(dest, ScheduleMvt::ACTION_ARRIVAL, NaiveTime::from_hms(h + 1, 0, 0))
+        ]
+    };
+    details.sched.id = details.insert_self(conn).unwrap();
+    details.sched
+}
+fn search(conn: &Connection, on_date: NaiveDate, query: ScheduleSearchQuery) -> Vec<i64> {
+    search_schedules(conn, &calendar(), on_date, &query).unwrap()
+        .schedules
+        .into_iter()
+        .map(|x| x.id)
+        .collect()
+}
+
+#[test]
+fn search_returns_effective_schedules_in_order() {
+    let conn = db();
+    let a = insert(&conn, sched("A00001", "P", Schedule::SOURCE_ITPS, "1A01"), "EUSTON", "MKC", 12);
+    insert(&conn, sched("A00002", "P", Schedule::SOURCE_ITPS, "1A02"), "EUSTON", "RUGBY", 10);
+    let b_overlay = insert(&conn, sched("A00002", "O", Schedule::SOURCE_ITPS, "1A02"), "EUSTON", "RUGBY", 11);
+    assert_eq!(search(&conn, tuesday(), Default::default()), vec![b_overlay.id, a.id]);
+    // The superseded permanent schedule doesn't show up even if asked for.
+    let query = ScheduleSearchQuery {
+        stp_indicator: Some("P".into()),
+        ..Default::default()
+    };
+    assert_eq!(search(&conn, tuesday(), query), vec![a.id]);
+}
+#[test]
+fn search_resolves_across_sources() {
+    let conn = db();
+    insert(&conn, sched("A00001", "P", Schedule::SOURCE_ITPS, "1A01"), "EUSTON", "MKC", 12);
+    let vstp = insert(&conn, sched("A00001", "N", Schedule::SOURCE_VSTP, "1A01"), "EUSTON", "MKC", 13);
+    assert_eq!(search(&conn, tuesday(), Default::default()), vec![vstp.id]);
+    // Filtering by source filters the effective schedule, rather than
+    // resolving within that source.
+    let query = ScheduleSearchQuery {
+        source: Some(Schedule::SOURCE_ITPS),
+        ..Default::default()
+    };
+    assert_eq!(search(&conn, tuesday(), query), vec![]);
+}
+#[test]
+fn search_cancellations() {
+    let conn = db();
+    insert(&conn, sched("A00001", "P", Schedule::SOURCE_ITPS, "1A01"), "EUSTON", "MKC", 12);
+    let canx = insert(&conn, sched("A00001", "C", Schedule::SOURCE_ITPS, "1A01"), "EUSTON", "MKC", 12);
+    assert_eq!(search(&conn, tuesday(), Default::default()), vec![]);
+    let query = ScheduleSearchQuery {
+        stp_indicator: Some("c".into()),
+        ..Default::default()
+    };
+    assert_eq!(search(&conn, tuesday(), query), vec![canx.id]);
+}
+#[test]
+fn search_filters_apply_to_effective_schedule() {
+    let conn = db();
+    insert(&conn, sched("A00001", "P", Schedule::SOURCE_ITPS, "1A01"), "EUSTON", "MKC", 12);
+    let overlay = insert(&conn, sched("A00001", "O", Schedule::SOURCE_ITPS, "2A01"), "EUSTON", "WATFDJ", 12);
+    let query = ScheduleSearchQuery {
+        headcode: Some("1a01".into()),
+        ..Default::default()
+    };
+    assert_eq!(search(&conn, tuesday(), query), vec![]);
+    let query = ScheduleSearchQuery {
+        headcode: Some("2A01".into()),
+        ..Default::default()
+    };
+    assert_eq!(search(&conn, tuesday(), query), vec![overlay.id]);
+    let query = ScheduleSearchQuery {
+        destination: Some("MKC".into()),
+        ..Default::default()
+    };
+    assert_eq!(search(&conn, tuesday(), query), vec![]);
+    let query = ScheduleSearchQuery {
+        origin: Some("EUSTON".into()),
+        destination: Some("WATFDJ".into()),
+        ..Default::default()
+    };
+    assert_eq!(search(&conn, tuesday(), query), vec![overlay.id]);
+}
+#[test]
+fn search_bank_holidays() {
+    let conn = db();
+    let mut no_bh = sched("A00001", "P", Schedule::SOURCE_ITPS, "1A01");
+    no_bh.meta.bank_holiday_running = Some("X".into());
+    let no_bh = insert(&conn, no_bh, "EUSTON", "MKC", 12);
+    let other = insert(&conn, sched("A00002", "P", Schedule::SOURCE_ITPS, "1A02"), "EUSTON", "MKC", 13);
+    assert_eq!(search(&conn, bank_holiday(), Default::default()), vec![other.id]);
+    assert_eq!(search(&conn, tuesday(), Default::default()), vec![no_bh.id, other.id]);
+}
+#[test]
+fn search_pages() {
+    let conn = db();
+    let ids = (0..5)
+        .map(|i| insert(&conn, sched(&format!("A0000{}", i), "P", Schedule::SOURCE_ITPS, "1A01"), "EUSTON", "MKC", 10 + i).id)
+        .collect::<Vec<_>>();
+    let page = |page| {
+        let query = ScheduleSearchQuery {
+            page,
+            per_page: Some(2),
+            ..Default::default()
+        };
+        search_schedules(&conn, &calendar(), tuesday(), &query).unwrap()
+    };
+    let first = page(0);
+    assert_eq!(first.schedules.iter().map(|x| x.id).collect::<Vec<_>>(), &ids[0..2]);
+    assert!(first.more);
+    let last = page(2);
+    assert_eq!(last.schedules.iter().map(|x| x.id).collect::<Vec<_>>(), &ids[4..5]);
+    assert!(!last.more);
+}
+
+/// Makes a schedule with an ID (without inserting it anywhere).
+fn with_id(id: i64, stp: &str, start_date: NaiveDate) -> Schedule {
+    Schedule {
+        id,
+        start_date,
+        ..sched("A00001", stp, Schedule::SOURCE_ITPS, "1A01")
+    }
+}
+fn jan1() -> NaiveDate {
+    NaiveDate::from_ymd(2019, 01, 01)
+}
+
+#[test]
+fn resolve_nothing() {
+    assert!(resolve_effective_schedule(&calendar(), tuesday(), vec![]).is_none());
+}
+#[test]
+fn resolve_stp_precedence() {
+    let scheds = vec![with_id(1, "P", jan1()), with_id(2, "O", jan1()), with_id(3, "N", jan1())];
+    let eff = resolve_effective_schedule(&calendar(), tuesday(), scheds).unwrap();
+    assert_eq!(eff.effective.id, 3);
+    assert_eq!(eff.kind, StpKind::New);
+    assert!(eff.runs);
+    let overrides = eff.overrides.iter()
+        .map(|x| (x.schedule.id, x.kind, x.reason))
+        .collect::<Vec<_>>();
+    assert_eq!(overrides, vec![
+        (2, StpKind::Overlay, OverrideReason::Superseded),
+        (1, StpKind::Permanent, OverrideReason::Superseded)
+    ]);
+    let scheds = vec![with_id(1, "P", jan1()), with_id(2, "O", jan1())];
+    let eff = resolve_effective_schedule(&calendar(), tuesday(), scheds).unwrap();
+    assert_eq!(eff.effective.id, 2);
+    assert_eq!(eff.kind, StpKind::Overlay);
+}
+#[test]
+fn resolve_cancellation() {
+    let scheds = vec![with_id(1, "P", jan1()), with_id(2, "O", jan1()), with_id(3, "C", jan1())];
+    let eff = resolve_effective_schedule(&calendar(), tuesday(), scheds).unwrap();
+    assert_eq!(eff.effective.id, 3);
+    assert_eq!(eff.kind, StpKind::Cancellation);
+    assert!(!eff.runs);
+    assert_eq!(eff.overrides.len(), 2);
+}
+#[test]
+fn resolve_tie_break() {
+    // Later start dates win...
+    let later = NaiveDate::from_ymd(2019, 03, 01);
+    let scheds = vec![with_id(1, "P", later), with_id(2, "P", jan1())];
+    let eff = resolve_effective_schedule(&calendar(), tuesday(), scheds).unwrap();
+    assert_eq!(eff.effective.id, 1);
+    assert_eq!(eff.overrides[0].schedule.id, 2);
+    assert_eq!(eff.overrides[0].reason, OverrideReason::TieBreak);
+    // ...and then the most recently imported one.
+    let scheds = vec![with_id(1, "O", jan1()), with_id(2, "O", jan1()), with_id(3, "P", later)];
+    let eff = resolve_effective_schedule(&calendar(), tuesday(), scheds).unwrap();
+    assert_eq!(eff.effective.id, 2);
+    let overrides = eff.overrides.iter()
+        .map(|x| (x.schedule.id, x.reason))
+        .collect::<Vec<_>>();
+    assert_eq!(overrides, vec![(1, OverrideReason::TieBreak), (3, OverrideReason::Superseded)]);
+}
+#[test]
+fn resolve_bank_holiday_running() {
+    let mut overlay = with_id(2, "O", jan1());
+    overlay.meta.bank_holiday_running = Some("X".into());
+    let scheds = vec![with_id(1, "P", jan1()), overlay];
+    let eff = resolve_effective_schedule(&calendar(), bank_holiday(), scheds.clone()).unwrap();
+    // The overlay is still the one in effect, so the permanent schedule
+    // doesn't run instead.
+    assert_eq!(eff.effective.id, 2);
+    assert!(!eff.runs);
+    let eff = resolve_effective_schedule(&calendar(), tuesday(), scheds).unwrap();
+    assert!(eff.runs);
+}
+
+fn diff_mvt(tiploc: &str, action: u8, h: u32, m: u32) -> ScheduleMvt {
+    ScheduleMvt {
+        tiploc: tiploc.into(),
+        action,
+        time: NaiveTime::from_hms(h, m, 0),
+        ..ScheduleMvt::dummy()
+    }
+}
+fn tiplocs(mvts: &[ScheduleMvt]) -> Vec<&str> {
+    mvts.iter().map(|x| &x.tiploc as &str).collect()
+}
+
+#[test]
+fn diff_identical() {
+    let mvts = vec![diff_mvt("EUSTON", 1, 12, 0), diff_mvt("MKC", 0, 12, 30)];
+    let diff = ScheduleDiff::between(&mvts, &mvts);
+    assert!(diff.added.is_empty() && diff.removed.is_empty() && diff.retimed.is_empty());
+}
+#[test]
+fn diff_added_and_removed() {
+    let old = vec![diff_mvt("EUSTON", 1, 12, 0), diff_mvt("WATFDJ", 2, 12, 15), diff_mvt("MKC", 0, 12, 30)];
+    let new = vec![diff_mvt("EUSTON", 1, 12, 0), diff_mvt("BLTCHLY", 2, 12, 25), diff_mvt("MKC", 0, 12, 30)];
+    let diff = ScheduleDiff::between(&old, &new);
+    assert_eq!(tiplocs(&diff.added), vec!["BLTCHLY"]);
+    assert_eq!(tiplocs(&diff.removed), vec!["WATFDJ"]);
+    assert!(diff.retimed.is_empty());
+    // A pass turning into a call is a different movement.
+    let new = vec![diff_mvt("EUSTON", 1, 12, 0), diff_mvt("WATFDJ", 0, 12, 15), diff_mvt("WATFDJ", 1, 12, 16), diff_mvt("MKC", 0, 12, 30)];
+    let diff = ScheduleDiff::between(&old, &new);
+    assert_eq!(diff.added.iter().map(|x| x.action).collect::<Vec<_>>(), vec![0, 1]);
+    assert_eq!(diff.removed.iter().map(|x| x.action).collect::<Vec<_>>(), vec![2]);
+}
+#[test]
+fn diff_retimed() {
+    let old = vec![diff_mvt("EUSTON", 1, 12, 0), diff_mvt("MKC", 0, 12, 30)];
+    let mut late = diff_mvt("MKC", 0, 12, 30);
+    late.public_time = Some(NaiveTime::from_hms(12, 31, 0));
+    let new = vec![diff_mvt("EUSTON", 1, 12, 5), late];
+    let diff = ScheduleDiff::between(&old, &new);
+    assert!(diff.added.is_empty() && diff.removed.is_empty());
+    let retimed = diff.retimed.iter()
+        .map(|x| (&x.old.tiploc as &str, x.old.time, x.new.time))
+        .collect::<Vec<_>>();
+    assert_eq!(retimed, vec![
+        ("EUSTON", NaiveTime::from_hms(12, 0, 0), NaiveTime::from_hms(12, 5, 0)),
+        ("MKC", NaiveTime::from_hms(12, 30, 0), NaiveTime::from_hms(12, 30, 0))
+    ]);
+}
+#[test]
+fn diff_repeated_calls() {
+    // Trains that call somewhere twice get their calls matched up in order.
+    let old = vec![diff_mvt("CRKLWD", 0, 12, 0), diff_mvt("CRKLWD", 0, 13, 0)];
+    let new = vec![diff_mvt("CRKLWD", 0, 12, 0), diff_mvt("CRKLWD", 0, 13, 10)];
+    let diff = ScheduleDiff::between(&old, &new);
+    assert!(diff.added.is_empty() && diff.removed.is_empty());
+    assert_eq!(diff.retimed.len(), 1);
+    assert_eq!(diff.retimed[0].new.time, NaiveTime::from_hms(13, 10, 0));
+}
+
+#[test]
+fn replaced_schedules_keep_versions() {
+    let conn = db();
+    let first = insert(&conn, sched("A00001", "N", Schedule::SOURCE_VSTP, "1A01"), "EUSTON", "MKC", 12);
+    let mut details = get_schedule_details(&conn, first.tspl_id).unwrap();
+    details.sched.tspl_id = Uuid::new_v4();
+    details.mvts[1].tiploc = "RUGBY".into();
+    let second = ScheduleVersion::replace_schedule(&conn, first.id, &details, None).unwrap();
+    details.sched.tspl_id = Uuid::new_v4();
+    details.mvts[1].tiploc = "CREWE".into();
+    let third = ScheduleVersion::replace_schedule(&conn, second, &details, None).unwrap();
+    // Both old trainsplorer IDs still work, and give the old movements.
+    let old = get_schedule_details(&conn, first.tspl_id).unwrap();
+    assert_eq!(tiplocs(&old.mvts), vec!["EUSTON", "MKC"]);
+    let versions = ScheduleVersion::from_select(&conn, "WHERE parent_sched = ? ORDER BY id ASC", &[&third]).unwrap();
+    assert_eq!(versions.len(), 2);
+    assert_eq!(versions[0].tspl_id, first.tspl_id);
+    assert_eq!(versions[1].superseded_by, Some(details.sched.tspl_id));
+    let current = get_schedule_details(&conn, details.sched.tspl_id).unwrap();
+    assert_eq!(tiplocs(&current.mvts), vec!["EUSTON", "CREWE"]);
+}
+#[test]
+fn deleted_schedules_keep_versions() {
+    let conn = db();
+    let sched = insert(&conn, sched("A00001", "P", Schedule::SOURCE_ITPS, "1A01"), "EUSTON", "MKC", 12);
+    ScheduleVersion::delete_schedule(&conn, sched.id, Some(42)).unwrap();
+    assert!(Schedule::from_select(&conn, "WHERE id = ?", &[&sched.id]).unwrap().is_empty());
+    let details = get_schedule_details(&conn, sched.tspl_id).unwrap();
+    assert_eq!(details.sched.uid, "A00001");
+    assert_eq!(tiplocs(&details.mvts), vec!["EUSTON", "MKC"]);
+    let ver = ScheduleVersion::from_select(&conn, "WHERE tspl_id = ?", &[&sched.tspl_id]).unwrap()
+        .into_iter().nth(0).unwrap();
+    assert_eq!(ver.parent_sched, None);
+    assert_eq!(ver.superseded_by, None);
+    assert_eq!(ver.superseded_metaseq, Some(42));
+    assert!(ver.deleted_sched.is_some());
+    // Deleted schedules don't show up in searches.
+    assert_eq!(search(&conn, tuesday(), Default::default()), vec![]);
+}
+
+/// An app with a schedule database in a temporary file (since every
+/// connection to an in-memory database gets a different database).
+fn journey_app() -> (App, Connection) {
+    let path = std::env::temp_dir().join(format!("tspl-fahrplan-test-{}.sqlite", Uuid::new_v4()));
+    let manager = tspl_sqlite::TsplConnectionManager::initialize(&path, &MIGRATIONS).unwrap();
+    let pool = tspl_sqlite::r2d2::Pool::builder().max_size(2).build(manager).unwrap();
+    let app = App {
+        pool: Arc::new(RwLock::new(pool)),
+        bank_holidays: calendar(),
+        interchanges: Interchanges::default()
+    };
+    (app, tspl_sqlite::initialize_db(&path, &MIGRATIONS).unwrap())
+}
+/// Inserts a train calling at each of `calls` (TIPLOC, action, hour and minute).
+fn insert_train(conn: &Connection, uid: &str, calls: &[(&str, u8, u32, u32)]) -> Schedule {
+    let mvts = calls.iter()
+        .map(|&(tiploc, action, h, m)| ScheduleMvt {
+            tiploc: tiploc.into(),
+            action,
+            time: NaiveTime::from_hms(h, m, 0),
+            public_time: Some(NaiveTime::from_hms(h, m, 0)),
+            ..ScheduleMvt::dummy()
+        })
+        .collect();
+    let mut details = ScheduleDetails {
+        sched: sched(uid, "P", Schedule::SOURCE_ITPS, "1A01"),
+        mvts
+    };
+    details.sched.id = details.insert_self(conn).unwrap();
+    details.sched
+}
+fn at(h: u32, m: u32) -> NaiveDateTime {
+    tuesday().and_hms(h, m, 0)
+}
+/// Euston to Coventry: direct and slow, or quicker with a change at Milton
+/// Keynes into a train leaving there at `change_dep`.
+fn insert_journeys(conn: &Connection, change_dep: (u32, u32)) {
+    insert_train(conn, "A00001", &[
+        ("EUSTON", ScheduleMvt::ACTION_DEPARTURE, 10, 00),
+        ("MKC", ScheduleMvt::ACTION_ARRIVAL, 10, 40),
+        ("MKC", ScheduleMvt::ACTION_DEPARTURE, 10, 41),
+        ("RUGBY", ScheduleMvt::ACTION_ARRIVAL, 11, 00)
+    ]);
+    insert_train(conn, "A00002", &[
+        ("MKC", ScheduleMvt::ACTION_DEPARTURE, change_dep.0, change_dep.1),
+        ("COVNTRY", ScheduleMvt::ACTION_ARRIVAL, 11, 30)
+    ]);
+    insert_train(conn, "A00003", &[
+        ("EUSTON", ScheduleMvt::ACTION_DEPARTURE, 10, 05),
+        ("COVNTRY", ScheduleMvt::ACTION_ARRIVAL, 12, 00)
+    ]);
+}
+fn journey_uids(j: &Journey) -> Vec<&str> {
+    j.legs.iter().map(|x| &x.schedule.uid as &str).collect()
+}
+
+#[test]
+fn run_dates() {
+    let mvt = |h, m, day_offset| ScheduleMvt {
+        time: NaiveTime::from_hms(h, m, 0),
+        day_offset,
+        ..ScheduleMvt::dummy()
+    };
+    let wed = tuesday().succ();
+    assert_eq!(run_date_between(&mvt(10, 0, 0), at(9, 0), at(11, 0)), Some(tuesday()));
+    assert_eq!(run_date_between(&mvt(12, 0, 0), at(9, 0), at(11, 0)), None);
+    // Windows across midnight.
+    assert_eq!(run_date_between(&mvt(23, 30, 0), at(23, 0), wed.and_hms(1, 0, 0)), Some(tuesday()));
+    assert_eq!(run_date_between(&mvt(0, 30, 0), at(23, 0), wed.and_hms(1, 0, 0)), Some(wed));
+    // A movement the day after the schedule's start.
+    assert_eq!(run_date_between(&mvt(0, 30, 1), at(23, 0), wed.and_hms(1, 0, 0)), Some(tuesday()));
+    assert_eq!(run_date_between(&mvt(10, 0, 1), at(9, 0), at(11, 0)), Some(tuesday().pred()));
+}
+#[test]
+fn journey_rounds() {
+    let (app, conn) = journey_app();
+    insert_journeys(&conn, (10, 50));
+    let resp = JourneyPlanner::new(&app).plan("EUSTON", "COVNTRY", at(9, 55), Duration::hours(3)).unwrap();
+    // The direct train is found in the first round, and changing at
+    // Milton Keynes (which is quicker) in the second.
+    assert_eq!(resp.journeys.len(), 2);
+    assert_eq!(resp.journeys[0].changes, 0);
+    assert_eq!(resp.journeys[0].arrival, at(12, 00));
+    assert_eq!(journey_uids(&resp.journeys[0]), vec!["A00003"]);
+    assert_eq!(resp.journeys[1].changes, 1);
+    assert_eq!(resp.journeys[1].arrival, at(11, 30));
+    assert_eq!(journey_uids(&resp.journeys[1]), vec!["A00001", "A00002"]);
+    assert_eq!(resp.journeys[1].legs[0].alight_mvt.tiploc, "MKC");
+    assert_eq!(resp.journeys[1].legs[1].departure, at(10, 50));
+}
+#[test]
+fn journey_change_times() {
+    // Leaving Milton Keynes three minutes after getting there isn't enough
+    // time to change.
+    let (app, conn) = journey_app();
+    insert_journeys(&conn, (10, 43));
+    let resp = JourneyPlanner::new(&app).plan("EUSTON", "COVNTRY", at(9, 55), Duration::hours(3)).unwrap();
+    assert_eq!(resp.journeys.len(), 1);
+    assert_eq!(journey_uids(&resp.journeys[0]), vec!["A00003"]);
+    // Nor is there anything if the horizon is too short.
+    let resp = JourneyPlanner::new(&app).plan("EUSTON", "COVNTRY", at(9, 55), Duration::hours(1)).unwrap();
+    assert!(resp.journeys.is_empty());
+}
+#[test]
+fn journey_departure_cache() {
+    let (app, conn) = journey_app();
+    insert_journeys(&conn, (10, 50));
+    let mut planner = JourneyPlanner::new(&app);
+    let deps = planner.departures_from("EUSTON", at(9, 55), at(12, 55)).unwrap();
+    assert_eq!(deps.len(), 2);
+    conn.execute("DELETE FROM schedule_movements", NO_PARAMS).unwrap();
+    // A narrower window comes out of the cache, but still gets filtered.
+    let deps = planner.departures_from("EUSTON", at(10, 02), at(12, 55)).unwrap();
+    assert_eq!(deps.len(), 1);
+    assert_eq!(deps[0].0.uid, "A00003");
+    assert_eq!(deps[0].2, tuesday());
+    // A wider one has to go back to the database.
+    assert!(planner.departures_from("EUSTON", at(9, 00), at(12, 55)).unwrap().is_empty());
+}
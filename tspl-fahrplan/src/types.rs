@@ -1,6 +1,7 @@
 //! Database types for schedules and the like.
 
 use tspl_sqlite::traits::*;
+use tspl_sqlite::rusqlite::types::ToSql;
 use tspl_sqlite::migrations::Migration;
 use tspl_sqlite::migration;
 use bitflags::bitflags;
@@ -10,8 +11,17 @@ use std::cmp::Ordering;
 use serde_derive::{Serialize, Deserialize};
 use std::collections::HashMap;
 
-pub static MIGRATIONS: [Migration; 1] = [
-    migration!(0, "initial")
+pub static MIGRATIONS: [Migration; 10] = [
+    migration!(0, "initial"),
+    migration!(1, "associations"),
+    migration!(2, "schedule_metadata"),
+    migration!(3, "bank_holiday_running"),
+    migration!(4, "schedule_versions"),
+    migration!(5, "schedule_search"),
+    migration!(6, "allowances"),
+    migration!(7, "version_allowances"),
+    migration!(8, "activities"),
+    migration!(9, "deleted_schedules")
 ];
 bitflags! {
     /// Bitflags showing which days a schedule runs on.
@@ -101,11 +111,14 @@ pub struct Schedule {
     /// The Darwin RID for this schedule, if obtained from Darwin.
     pub darwin_id: Option<String>,
     /// Whether or not this schedule crosses over into the next day.
-    pub crosses_midnight: bool
+    pub crosses_midnight: bool,
+    /// Extra metadata from the schedule segment (category, power type, etc.)
+    #[serde(default)]
+    pub meta: ScheduleMeta
     // NOTE: update `FIELDS` constant below when adding new fields.
 }
 impl Schedule {
-    pub const FIELDS: usize = 12;
+    pub const FIELDS: usize = 12 + ScheduleMeta::FIELDS;
 }
 impl DbType for Schedule {
     fn table_name() -> &'static str {
@@ -126,21 +139,34 @@ impl DbType for Schedule {
             file_metaseq: row.get(s + 9)?,
             darwin_id: row.get(s + 10)?,
             crosses_midnight: row.get(s + 11)?,
+            meta: ScheduleMeta::from_row(row, s + 12)?,
         })
     }
 }
 impl InsertableDbType for Schedule {
     type Id = i64;
     fn insert_self(&self, conn: &Connection) -> RowResult<i64> {
-        let mut stmt = conn.prepare("INSERT INTO schedules
+        let mut stmt = conn.prepare_cached("INSERT INTO schedules
                                      (tspl_id, uid, start_date, end_date,
                                       days, stp_indicator, signalling_id,
-                                      source, file_metaseq, darwin_id, crosses_midnight)
-                                     VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")?;
+                                      source, file_metaseq, darwin_id, crosses_midnight,
+                                      train_category, headcode, business_sector,
+                                      power_type, timing_load, speed,
+                                      operating_characteristics, train_class,
+                                      sleepers, reservations, catering_code,
+                                      service_branding, atoc_code, bank_holiday_running)
+                                     VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?,
+                                             ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")?;
+        let m = &self.meta;
         let rid = stmt.insert(params![self.tspl_id, self.uid, self.start_date, self.end_date,
                             self.days.bits(), self.stp_indicator,
                             self.signalling_id, self.source, self.file_metaseq,
-                            self.darwin_id, self.crosses_midnight])?;
+                            self.darwin_id, self.crosses_midnight,
+                            m.train_category, m.headcode, m.business_sector,
+                            m.power_type, m.timing_load, m.speed,
+                            m.operating_characteristics, m.train_class,
+                            m.sleepers, m.reservations, m.catering_code,
+                            m.service_branding, m.atoc_code, m.bank_holiday_running])?;
         Ok(rid)
     }
 }
@@ -153,6 +179,79 @@ impl Schedule {
     pub const SOURCE_DARWIN: u8 = 2;
 } 
 
+/// Returns the GTFS trip ID for the schedule identified by the given UID,
+/// start date and STP indicator.
+///
+/// This is used both by the static GTFS exporter and by the GTFS-Realtime
+/// feed, so that the two match up.
+pub fn gtfs_trip_id(uid: &str, start_date: NaiveDate, stp_indicator: &str) -> String {
+    format!("{}_{}_{}", uid, start_date.format("%Y%m%d"), stp_indicator)
+}
+
+/// Extra metadata about a schedule, mostly from the CIF schedule segment.
+///
+/// Codes are stored as they appear in CIF (e.g. "OO" for an ordinary
+/// passenger train, "EMU" for an electric multiple unit), since that's
+/// what people filtering on them will expect.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScheduleMeta {
+    /// CIF train category.
+    pub train_category: Option<String>,
+    /// Headcode (not the same as the signalling ID!)
+    pub headcode: Option<String>,
+    /// Business sector (or TOC code, in some cases).
+    pub business_sector: Option<String>,
+    /// CIF power type.
+    pub power_type: Option<String>,
+    /// Timing load (e.g. the class of the unit).
+    pub timing_load: Option<String>,
+    /// Maximum speed, in mph.
+    pub speed: Option<u32>,
+    /// Operating characteristics codes.
+    pub operating_characteristics: Option<String>,
+    /// Seating class.
+    pub train_class: Option<String>,
+    /// Sleeper accommodation.
+    pub sleepers: Option<String>,
+    /// Reservations code.
+    pub reservations: Option<String>,
+    /// Catering codes.
+    pub catering_code: Option<String>,
+    /// Service branding (e.g. "E" for Eurostar).
+    pub service_branding: Option<String>,
+    /// ATOC code of the operating TOC.
+    pub atoc_code: Option<String>,
+    /// CIF bank holiday running flag: "X" if the schedule doesn't run on
+    /// bank holiday Mondays, "G" if it doesn't run on Glasgow bank holidays.
+    pub bank_holiday_running: Option<String>,
+    // NOTE: update `FIELDS` constant below when adding new fields.
+}
+impl ScheduleMeta {
+    pub const FIELDS: usize = 14;
+
+    /// Reads the metadata stored in a `schedules` row, starting at `s`.
+    ///
+    /// (This isn't a `DbType`, because it doesn't have its own table.)
+    pub fn from_row(row: &Row, s: usize) -> RowResult<Self> {
+        Ok(Self {
+            train_category: row.get(s + 0)?,
+            headcode: row.get(s + 1)?,
+            business_sector: row.get(s + 2)?,
+            power_type: row.get(s + 3)?,
+            timing_load: row.get(s + 4)?,
+            speed: row.get(s + 5)?,
+            operating_characteristics: row.get(s + 6)?,
+            train_class: row.get(s + 7)?,
+            sleepers: row.get(s + 8)?,
+            reservations: row.get(s + 9)?,
+            catering_code: row.get(s + 10)?,
+            service_branding: row.get(s + 11)?,
+            atoc_code: row.get(s + 12)?,
+            bank_holiday_running: row.get(s + 13)?,
+        })
+    }
+}
+
 /// Describes a movement a train makes within a schedule.
 ///
 /// ## Sorting
@@ -160,6 +259,12 @@ impl Schedule {
 /// Schedule movements should ideally be sorted by (day_offset, time, action),
 /// for comparison to other lists of movements.
 /// (indeed, this is how `Ord` is implemented)
+///
+/// ## Equality
+///
+/// Movements are equal if everything apart from their IDs is, so that
+/// comparing two lists of movements picks up changes to platforms and
+/// public times as well as to timings.
 #[derive(Debug, Clone, Hash, Serialize, Deserialize)]
 pub struct ScheduleMvt {
     /// Internal primary key.
@@ -186,7 +291,23 @@ pub struct ScheduleMvt {
     pub platform: Option<String>,
     /// Public (GBTT) time for this movement - i.e. the time shown to passengers,
     /// instead of the working time.
-    pub public_time: Option<NaiveTime>
+    pub public_time: Option<NaiveTime>,
+    /// Total (engineering, pathing and performance) allowance time before
+    /// this movement, in seconds.
+    ///
+    /// CIF shows allowances on the location they're taken *after*, so this
+    /// is the allowance from the previous timing point in the schedule.
+    ///
+    /// This is slack in the schedule, which a late-running train can use to
+    /// make up time.
+    #[serde(default)]
+    pub allowance: u32,
+    /// CIF activity codes for this location (e.g. `T` for a stop to take up
+    /// and set down passengers), as a string of two-character codes.
+    ///
+    /// Only filled in for schedules where we get to see them (CIF and VSTP).
+    #[serde(default)]
+    pub activity: Option<String>
     // NOTE: update `FIELDS` constant below when adding new fields.
 }
 impl ScheduleMvt {
@@ -197,7 +318,12 @@ impl ScheduleMvt {
     /// `action` value for a pass.
     pub const ACTION_PASS: u8 = 2;
 
-    pub const FIELDS: usize = 8;
+    pub const FIELDS: usize = 10;
+    /// How many movements `insert_batch` inserts per statement.
+    ///
+    /// (9 parameters per movement, which keeps us well under SQLite's default
+    /// limit of 999 parameters)
+    pub const BATCH_SIZE: usize = 64;
 
     /// Returns a 'dummy' ScheduleMvt with all fields set to useless/default values
     /// and `id`s set to -1.
@@ -210,9 +336,90 @@ impl ScheduleMvt {
             time: NaiveTime::from_hms(0, 0, 0),
             day_offset: 0,
             platform: None,
-            public_time: None
+            public_time: None,
+            allowance: 0,
+            activity: None
         }
     }
+    /// Whether the CIF activity codes in `activity` mark a location as
+    /// somewhere passengers can get on or off.
+    pub fn is_passenger_activity(activity: &str) -> bool {
+        activity.as_bytes()
+            .chunks(2)
+            .map(|x| String::from_utf8_lossy(x))
+            .any(|x| match x.trim() {
+                "T" | "U" | "D" | "R" | "TB" | "TF" => true,
+                _ => false
+            })
+    }
+    /// Adds up CIF allowance values (e.g. "2", "1H" or "H", in minutes and
+    /// half-minutes) into a number of seconds.
+    ///
+    /// Anything that doesn't parse is ignored.
+    pub fn total_allowance<S: AsRef<str>>(alws: &[&Option<S>]) -> u32 {
+        let mut ret = 0;
+        for alw in alws.iter().filter_map(|x| x.as_ref()) {
+            let alw = alw.as_ref().trim();
+            let (mins, half) = if alw.ends_with('H') {
+                (&alw[..alw.len() - 1], true)
+            }
+            else {
+                (alw, false)
+            };
+            let mins: u32 = if mins.is_empty() {
+                0
+            }
+            else {
+                match mins.parse() {
+                    Ok(m) => m,
+                    Err(_) => continue
+                }
+            };
+            ret += mins * 60 + if half { 30 } else { 0 };
+        }
+        ret
+    }
+    /// Inserts a load of movements at once, using multi-row INSERTs.
+    ///
+    /// This is a lot faster than calling `insert_self` for each movement
+    /// when importing big schedule files. The `id` of each movement is ignored,
+    /// and any movements left over after splitting `mvts` into chunks of
+    /// `BATCH_SIZE` get inserted one at a time.
+    pub fn insert_batch(conn: &Connection, mvts: &[ScheduleMvt]) -> RowResult<()> {
+        let mut chunks = mvts.chunks_exact(Self::BATCH_SIZE);
+        if chunks.len() > 0 {
+            let mut stmt = conn.prepare_cached(&mvt_batch_query())?;
+            for chunk in &mut chunks {
+                let mut args: Vec<&dyn ToSql> = Vec::with_capacity(Self::BATCH_SIZE * 9);
+                for mvt in chunk {
+                    args.extend_from_slice(&[&mvt.parent_sched, &mvt.tiploc, &mvt.action,
+                                             &mvt.time, &mvt.day_offset, &mvt.platform,
+                                             &mvt.public_time, &mvt.allowance, &mvt.activity]);
+                }
+                stmt.execute(&args)?;
+            }
+        }
+        // Whatever's left over doesn't fill a batch, so just do it one by one.
+        for mvt in chunks.remainder() {
+            mvt.insert_self(conn)?;
+        }
+        Ok(())
+    }
+}
+/// Makes the multi-row INSERT used by `ScheduleMvt::insert_batch`.
+fn mvt_batch_query() -> String {
+    let mut ret = String::from("INSERT INTO schedule_movements
+                                (parent_sched, tiploc, action, time,
+                                 day_offset, platform, public_time, allowance,
+                                 activity)
+                                VALUES ");
+    for i in 0..ScheduleMvt::BATCH_SIZE {
+        if i > 0 {
+            ret.push_str(", ");
+        }
+        ret.push_str("(?, ?, ?, ?, ?, ?, ?, ?, ?)");
+    }
+    ret
 }
 impl PartialEq for ScheduleMvt {
     fn eq(&self, other: &ScheduleMvt) -> bool {
@@ -220,6 +427,10 @@ impl PartialEq for ScheduleMvt {
             && self.action == other.action
             && self.time == other.time
             && self.day_offset == other.day_offset
+            && self.allowance == other.allowance
+            && self.platform == other.platform
+            && self.public_time == other.public_time
+            && self.activity == other.activity
     }
 }
 impl Eq for ScheduleMvt {}
@@ -249,19 +460,409 @@ impl DbType for ScheduleMvt {
             day_offset: row.get(s + 5)?,
             platform: row.get(s + 6)?,
             public_time: row.get(s + 7)?,
+            allowance: row.get(s + 8)?,
+            activity: row.get(s + 9)?,
         })
     }
 }
 impl InsertableDbType for ScheduleMvt {
     type Id = i64;
     fn insert_self(&self, conn: &Connection) -> RowResult<i64> {
-        let mut stmt = conn.prepare("INSERT INTO schedule_movements
+        let mut stmt = conn.prepare_cached("INSERT INTO schedule_movements
                                      (parent_sched, tiploc, action, time,
-                                      day_offset, platform, public_time)
-                                     VALUES (?, ?, ?, ?, ?, ?, ?)")?;
+                                      day_offset, platform, public_time, allowance,
+                                      activity)
+                                     VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)")?;
         let rid = stmt.insert(params![self.parent_sched, self.tiploc,
                               self.action, self.time, self.day_offset,
-                              self.platform, self.public_time])?;
+                              self.platform, self.public_time, self.allowance,
+                              self.activity])?;
+        Ok(rid)
+    }
+}
+
+/// A superseded version of a schedule.
+///
+/// When a schedule gets updated with different movements, replaced, or
+/// deleted, the old movements are copied into a `ScheduleVersion` (and its
+/// `ScheduleVersionMvt`s) first, so that anyone holding the old trainsplorer
+/// ID can still look it up.
+///
+/// Deleted schedules are copied into the `deleted_schedules` table (see
+/// `DeletedSchedule`), so their versions still have a schedule to belong to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleVersion {
+    /// Internal primary key.
+    pub id: i64,
+    /// The schedule this is a version of, if it hasn't been deleted.
+    pub parent_sched: Option<i64>,
+    /// The trainsplorer ID this version had.
+    pub tspl_id: Uuid,
+    /// The sequence number of the file this version was imported from,
+    /// if imported from CIF/ITPS.
+    pub file_metaseq: Option<u32>,
+    /// The trainsplorer ID of the version that replaced this one, or `None`
+    /// if the schedule was deleted.
+    pub superseded_by: Option<Uuid>,
+    /// The sequence number of the file containing the replacement (or
+    /// deletion), if imported from CIF/ITPS.
+    pub superseded_metaseq: Option<u32>,
+    /// When this version was replaced (UTC).
+    pub superseded_at: NaiveDateTime,
+    /// If the schedule this is a version of was deleted, the internal ID of
+    /// its `DeletedSchedule`.
+    pub deleted_sched: Option<i64>,
+    // NOTE: update `FIELDS` constant below when adding new fields.
+}
+impl ScheduleVersion {
+    pub const FIELDS: usize = 8;
+
+    /// Copies the current version of the schedule with internal ID `sid` into
+    /// the version history, recording that it's about to be replaced by
+    /// `superseded_by` (or deleted, if that's `None`).
+    pub fn archive(conn: &Connection, sid: i64, superseded_by: Option<Uuid>, superseded_metaseq: Option<u32>) -> RowResult<i64> {
+        let (tspl_id, file_metaseq) = conn.query_row(
+            "SELECT tspl_id, file_metaseq FROM schedules WHERE id = ?",
+            params![sid],
+            |row| Ok((row.get(0)?, row.get(1)?)))?;
+        let ver = ScheduleVersion {
+            id: -1,
+            parent_sched: Some(sid),
+            tspl_id,
+            file_metaseq,
+            superseded_by,
+            superseded_metaseq,
+            superseded_at: Utc::now().naive_utc(),
+            deleted_sched: None
+        };
+        let vid = ver.insert_self(conn)?;
+        conn.execute("INSERT INTO schedule_version_movements
+                      (parent_version, tiploc, action, time, day_offset, platform, public_time,
+                       allowance, activity)
+                      SELECT ?, tiploc, action, time, day_offset, platform, public_time,
+                             allowance, activity
+                      FROM schedule_movements WHERE parent_sched = ?",
+                      params![vid, sid])?;
+        Ok(vid)
+    }
+    /// Replaces the schedule with internal ID `sid` with `details` (which
+    /// should have a new trainsplorer ID), archiving the current version
+    /// first. Returns the internal ID of the new schedule.
+    ///
+    /// The old version history is moved over to the new schedule.
+    pub fn replace_schedule(conn: &Connection, sid: i64, details: &ScheduleDetails, superseded_metaseq: Option<u32>) -> RowResult<i64> {
+        Self::archive(conn, sid, Some(details.sched.tspl_id), superseded_metaseq)?;
+        let mut stmt = conn.prepare("SELECT id FROM schedule_versions WHERE parent_sched = ?")?;
+        let rows = stmt.query_map(params![sid], |row| row.get(0))?;
+        let mut versions: Vec<i64> = vec![];
+        for row in rows {
+            versions.push(row?);
+        }
+        conn.execute("DELETE FROM schedules WHERE id = ?", params![sid])?;
+        let new_sid = details.insert_self(conn)?;
+        for vid in versions {
+            conn.execute("UPDATE schedule_versions SET parent_sched = ? WHERE id = ?",
+                         params![new_sid, vid])?;
+        }
+        Ok(new_sid)
+    }
+    /// Deletes the schedule with internal ID `sid`, archiving the current
+    /// version first and keeping a copy of the schedule itself in
+    /// `deleted_schedules`.
+    pub fn delete_schedule(conn: &Connection, sid: i64, superseded_metaseq: Option<u32>) -> RowResult<()> {
+        Self::archive(conn, sid, None, superseded_metaseq)?;
+        conn.execute("INSERT INTO deleted_schedules
+                      (tspl_id, uid, start_date, end_date,
+                       days, stp_indicator, signalling_id,
+                       source, file_metaseq, darwin_id, crosses_midnight,
+                       train_category, headcode, business_sector,
+                       power_type, timing_load, speed,
+                       operating_characteristics, train_class,
+                       sleepers, reservations, catering_code,
+                       service_branding, atoc_code, bank_holiday_running)
+                      SELECT tspl_id, uid, start_date, end_date,
+                             days, stp_indicator, signalling_id,
+                             source, file_metaseq, darwin_id, crosses_midnight,
+                             train_category, headcode, business_sector,
+                             power_type, timing_load, speed,
+                             operating_characteristics, train_class,
+                             sleepers, reservations, catering_code,
+                             service_branding, atoc_code, bank_holiday_running
+                      FROM schedules WHERE id = ?",
+                      params![sid])?;
+        let did = conn.last_insert_rowid();
+        conn.execute("UPDATE schedule_versions SET deleted_sched = ? WHERE parent_sched = ?",
+                     params![did, sid])?;
+        conn.execute("DELETE FROM schedules WHERE id = ?", params![sid])?;
+        Ok(())
+    }
+}
+impl DbType for ScheduleVersion {
+    fn table_name() -> &'static str {
+        "schedule_versions"
+    }
+    fn from_row(row: &Row, s: usize) -> RowResult<Self> {
+        Ok(Self {
+            id: row.get(s + 0)?,
+            parent_sched: row.get(s + 1)?,
+            tspl_id: row.get(s + 2)?,
+            file_metaseq: row.get(s + 3)?,
+            superseded_by: row.get(s + 4)?,
+            superseded_metaseq: row.get(s + 5)?,
+            superseded_at: row.get(s + 6)?,
+            deleted_sched: row.get(s + 7)?,
+        })
+    }
+}
+impl InsertableDbType for ScheduleVersion {
+    type Id = i64;
+    fn insert_self(&self, conn: &Connection) -> RowResult<i64> {
+        let mut stmt = conn.prepare_cached("INSERT INTO schedule_versions
+                                     (parent_sched, tspl_id, file_metaseq,
+                                      superseded_by, superseded_metaseq, superseded_at,
+                                      deleted_sched)
+                                     VALUES (?, ?, ?, ?, ?, ?, ?)")?;
+        let rid = stmt.insert(params![self.parent_sched, self.tspl_id, self.file_metaseq,
+                              self.superseded_by, self.superseded_metaseq,
+                              self.superseded_at, self.deleted_sched])?;
+        Ok(rid)
+    }
+}
+
+/// Wrapper for a `Schedule` that's been deleted, kept around for its
+/// `ScheduleVersion`s.
+///
+/// The wrapped schedule's `id` is the ID of the `DeletedSchedule`, not the
+/// schedule it used to be.
+pub struct DeletedSchedule(pub Schedule);
+
+impl DbType for DeletedSchedule {
+    fn table_name() -> &'static str {
+        "deleted_schedules"
+    }
+    fn from_row(row: &Row, s: usize) -> RowResult<Self> {
+        Ok(DeletedSchedule(Schedule::from_row(row, s)?))
+    }
+}
+
+/// Wrapper for a `ScheduleMvt` belonging to a `ScheduleVersion`.
+///
+/// The wrapped movement's `parent_sched` field contains the ID of the
+/// `ScheduleVersion`, not the schedule.
+pub struct ScheduleVersionMvt(pub ScheduleMvt);
+
+impl DbType for ScheduleVersionMvt {
+    fn table_name() -> &'static str {
+        "schedule_version_movements"
+    }
+    fn from_row(row: &Row, s: usize) -> RowResult<Self> {
+        Ok(ScheduleVersionMvt(ScheduleMvt::from_row(row, s)?))
+    }
+}
+
+/// A movement that's present in both versions of a schedule being compared,
+/// but at a different time.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RetimedMvt {
+    /// The movement in the older version.
+    pub old: ScheduleMvt,
+    /// The movement in the newer version.
+    pub new: ScheduleMvt
+}
+
+/// The differences between two versions of a schedule.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScheduleDiff {
+    /// Movements only present in the newer version.
+    pub added: Vec<ScheduleMvt>,
+    /// Movements only present in the older version.
+    pub removed: Vec<ScheduleMvt>,
+    /// Movements present in both, but with different (working or public) times.
+    pub retimed: Vec<RetimedMvt>
+}
+impl ScheduleDiff {
+    /// Compares two lists of movements (sorted as described on `ScheduleMvt`).
+    ///
+    /// Movements are matched up by their TIPLOC and action (and, for trains that
+    /// call at the same place more than once, which occurrence it is).
+    pub fn between(old: &[ScheduleMvt], new: &[ScheduleMvt]) -> Self {
+        fn keyed(mvts: &[ScheduleMvt]) -> Vec<((String, u8, usize), &ScheduleMvt)> {
+            let mut seen: HashMap<(String, u8), usize> = HashMap::new();
+            mvts.iter()
+                .map(|m| {
+                    let n = seen.entry((m.tiploc.clone(), m.action)).or_insert(0);
+                    *n += 1;
+                    ((m.tiploc.clone(), m.action, *n), m)
+                })
+                .collect()
+        }
+        let old = keyed(old);
+        let new = keyed(new);
+        let old_map: HashMap<_, _> = old.iter().cloned().collect();
+        let new_map: HashMap<_, _> = new.iter().cloned().collect();
+        let mut ret = ScheduleDiff {
+            added: vec![],
+            removed: vec![],
+            retimed: vec![]
+        };
+        for (k, m) in old.iter() {
+            match new_map.get(k) {
+                Some(n) => {
+                    if m.time != n.time || m.day_offset != n.day_offset || m.public_time != n.public_time {
+                        ret.retimed.push(RetimedMvt {
+                            old: (*m).clone(),
+                            new: (*n).clone()
+                        });
+                    }
+                },
+                None => ret.removed.push((*m).clone())
+            }
+        }
+        for (k, n) in new.iter() {
+            if !old_map.contains_key(k) {
+                ret.added.push((*n).clone());
+            }
+        }
+        ret
+    }
+}
+
+/// The version history of a schedule.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScheduleHistory {
+    /// The current version of the schedule (or, if it's been deleted, the
+    /// copy of it kept around after deletion).
+    pub current: Schedule,
+    /// Whether the schedule has been deleted.
+    pub deleted: bool,
+    /// Superseded versions, oldest first.
+    pub versions: Vec<ScheduleVersion>
+}
+
+/// An association between two schedules from NROD - i.e. a join, a divide,
+/// or a next working.
+///
+/// ## Identification
+///
+/// Associations are uniquely identified by their main UID, associated UID,
+/// start date, location (TIPLOC and base suffix), STP indicator and source.
+/// There's no UNIQUE index on these fields, because the suffix can be NULL.
+///
+/// ## Dates
+///
+/// The date range and `days` apply to the *main* train. The associated
+/// train's date may be one day either side of that, depending on
+/// `date_indicator` (e.g. for a next working that departs after midnight).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Association {
+    /// Internal primary key.
+    pub id: i64,
+    /// Schedule UID of the main train.
+    pub main_uid: String,
+    /// Schedule UID of the associated train.
+    pub assoc_uid: String,
+    /// Association start date.
+    pub start_date: NaiveDate,
+    /// Association end date.
+    pub end_date: NaiveDate,
+    /// Days of the week where this association applies.
+    pub days: ScheduleDays,
+    /// Type of association (see CATEGORY_* associated consts)
+    pub category: u8,
+    /// When the associated train runs, relative to the main train
+    /// (see DATE_* associated consts)
+    pub date_indicator: u8,
+    /// Timing Point Location where the association happens.
+    pub tiploc: String,
+    /// Location suffix for the main train, if any.
+    pub base_suffix: Option<String>,
+    /// Location suffix for the associated train, if any.
+    pub assoc_suffix: Option<String>,
+    /// STP indicator from NROD.
+    pub stp_indicator: String,
+    /// Source (see `Schedule`'s SOURCE_* associated consts)
+    pub source: u8,
+    /// The sequence number of the file this was imported from,
+    /// if imported from CIF/ITPS
+    pub file_metaseq: Option<u32>,
+    // NOTE: update `FIELDS` constant below when adding new fields.
+}
+impl Association {
+    /// `category` value for a join (JJ).
+    pub const CATEGORY_JOIN: u8 = 0;
+    /// `category` value for a divide (VV).
+    pub const CATEGORY_DIVIDE: u8 = 1;
+    /// `category` value for a next working (NP).
+    pub const CATEGORY_NEXT: u8 = 2;
+    /// `category` value for associations with no category (usually
+    /// STP cancellations).
+    pub const CATEGORY_NONE: u8 = 3;
+
+    /// `date_indicator` value for an associated train running on the
+    /// same day as the main train.
+    pub const DATE_STANDARD: u8 = 0;
+    /// `date_indicator` value for an associated train running on the
+    /// day after the main train.
+    pub const DATE_NEXT_MIDNIGHT: u8 = 1;
+    /// `date_indicator` value for an associated train running on the
+    /// day before the main train.
+    pub const DATE_PREV_MIDNIGHT: u8 = 2;
+
+    pub const FIELDS: usize = 14;
+
+    /// Given the date on which the associated train runs, returns the date
+    /// on which the main train runs.
+    pub fn main_date_for_assoc_date(&self, date: NaiveDate) -> NaiveDate {
+        match self.date_indicator {
+            Self::DATE_NEXT_MIDNIGHT => date.pred(),
+            Self::DATE_PREV_MIDNIGHT => date.succ(),
+            _ => date
+        }
+    }
+    /// Whether this association applies to a main train running on `date`.
+    pub fn runs_on_main_date(&self, date: NaiveDate) -> bool {
+        self.start_date <= date && self.end_date >= date
+            && self.days.runs_on_iso_weekday(date.weekday().number_from_monday())
+    }
+}
+impl DbType for Association {
+    fn table_name() -> &'static str {
+        "associations"
+    }
+    fn from_row(row: &Row, s: usize) -> RowResult<Self> {
+        let days: u8 = row.get(s + 5)?;
+        Ok(Self {
+            id: row.get(s + 0)?,
+            main_uid: row.get(s + 1)?,
+            assoc_uid: row.get(s + 2)?,
+            start_date: row.get(s + 3)?,
+            end_date: row.get(s + 4)?,
+            days: ScheduleDays::from_bits_truncate(days),
+            category: row.get(s + 6)?,
+            date_indicator: row.get(s + 7)?,
+            tiploc: row.get(s + 8)?,
+            base_suffix: row.get(s + 9)?,
+            assoc_suffix: row.get(s + 10)?,
+            stp_indicator: row.get(s + 11)?,
+            source: row.get(s + 12)?,
+            file_metaseq: row.get(s + 13)?,
+        })
+    }
+}
+impl InsertableDbType for Association {
+    type Id = i64;
+    fn insert_self(&self, conn: &Connection) -> RowResult<i64> {
+        let mut stmt = conn.prepare_cached("INSERT INTO associations
+                                     (main_uid, assoc_uid, start_date, end_date,
+                                      days, category, date_indicator, tiploc,
+                                      base_suffix, assoc_suffix, stp_indicator,
+                                      source, file_metaseq)
+                                     VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")?;
+        let rid = stmt.insert(params![self.main_uid, self.assoc_uid, self.start_date,
+                              self.end_date, self.days.bits(), self.category,
+                              self.date_indicator, self.tiploc, self.base_suffix,
+                              self.assoc_suffix, self.stp_indicator, self.source,
+                              self.file_metaseq])?;
         Ok(rid)
     }
 }
@@ -271,7 +872,8 @@ impl InsertableDbType for ScheduleMvt {
 /// This is used to avoid re-inserting the same update file twice.
 #[derive(Debug, Clone)]
 pub struct ScheduleFile {
-    /// The update file's sequence number, from its header.
+    /// The update file's sequence number (see `TimetableRecord::file_sequence`
+    /// in ntrod-types; this isn't NROD's sequence number).
     pub sequence: u32,
     /// The update file's timestamp, from its header metadata.
     pub timestamp: u32,
@@ -290,7 +892,7 @@ impl DbType for ScheduleFile {
 impl InsertableDbType for ScheduleFile {
     type Id = ();
     fn insert_self(&self, conn: &Connection) -> RowResult<()> {
-        let mut stmt = conn.prepare("INSERT INTO schedule_files
+        let mut stmt = conn.prepare_cached("INSERT INTO schedule_files
                                      (sequence, timestamp) VALUES (?, ?)")?;
         stmt.insert(params![self.sequence, self.timestamp])?;
         Ok(())
@@ -305,6 +907,145 @@ pub struct ScheduleDetails {
     /// Schedule movements, in the proper order.
     pub mvts: Vec<ScheduleMvt>
 }
+impl InsertableDbType for ScheduleDetails {
+    type Id = i64;
+    /// Inserts the schedule and its movements, returning the new schedule ID.
+    ///
+    /// The `id` and `parent_sched` fields are ignored.
+    fn insert_self(&self, conn: &Connection) -> RowResult<i64> {
+        let sid = self.sched.insert_self(conn)?;
+        for mvt in self.mvts.iter() {
+            let mut mvt = mvt.clone();
+            mvt.parent_sched = sid;
+            mvt.insert_self(conn)?;
+        }
+        Ok(sid)
+    }
+}
+
+/// What kind of schedule something is, going by its STP indicator.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StpKind {
+    /// Permanent schedule ("P").
+    Permanent,
+    /// STP overlay of a permanent schedule ("O").
+    Overlay,
+    /// New STP schedule, not overlaying anything ("N").
+    New,
+    /// STP cancellation of a permanent schedule ("C").
+    Cancellation
+}
+impl StpKind {
+    /// Gets the kind of schedule from its STP indicator.
+    ///
+    /// Anything unrecognised is treated as permanent.
+    pub fn from_indicator(stp_indicator: &str) -> Self {
+        match stp_indicator {
+            "C" => StpKind::Cancellation,
+            "N" => StpKind::New,
+            "O" => StpKind::Overlay,
+            _ => StpKind::Permanent
+        }
+    }
+}
+
+/// Why a schedule isn't the effective one.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverrideReason {
+    /// The effective schedule has a higher-precedence STP indicator.
+    Superseded,
+    /// This schedule has the same STP indicator as the effective schedule,
+    /// which won the tie-break (by starting later, or being imported later).
+    TieBreak
+}
+
+/// A schedule overridden by the effective schedule for a given date.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct OverriddenSchedule {
+    /// The schedule that was overridden.
+    pub schedule: Schedule,
+    /// What kind of schedule it is.
+    pub kind: StpKind,
+    /// Why it was overridden.
+    pub reason: OverrideReason
+}
+
+/// The schedule in effect for a train on a given date, along with the
+/// schedules it overrides.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct EffectiveSchedule {
+    /// The date this is for.
+    pub date: NaiveDate,
+    /// The schedule in effect (which might be a cancellation).
+    pub effective: Schedule,
+    /// What kind of schedule `effective` is.
+    pub kind: StpKind,
+    /// Whether the train actually runs on this date - i.e. the effective
+    /// schedule isn't a cancellation, and bank holiday running doesn't
+    /// stop it from running.
+    pub runs: bool,
+    /// Schedules overridden by `effective`, highest precedence first.
+    pub overrides: Vec<OverriddenSchedule>
+}
+
+/// Filters for searching schedules running on a given date.
+///
+/// All of the filters are optional; strings are matched exactly (but
+/// case-insensitively). They're applied to the schedule in effect on
+/// that date, so searching for a permanent schedule's headcode won't find
+/// the train if an overlay with a different headcode applies.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ScheduleSearchQuery {
+    /// Signalling ID or headcode (e.g. "1A23").
+    pub headcode: Option<String>,
+    /// TIPLOC the schedule starts at.
+    pub origin: Option<String>,
+    /// TIPLOC the schedule ends at.
+    pub destination: Option<String>,
+    /// Schedule source (see `Schedule::SOURCE_*`).
+    pub source: Option<u8>,
+    /// STP indicator.
+    ///
+    /// If this isn't provided, cancelled schedules (STP indicator "C")
+    /// are left out.
+    pub stp_indicator: Option<String>,
+    /// ATOC code of the operating TOC.
+    pub atoc_code: Option<String>,
+    /// CIF train category.
+    pub train_category: Option<String>,
+    /// Page of results to return, starting from 0.
+    #[serde(default)]
+    pub page: u32,
+    /// Number of results per page.
+    pub per_page: Option<u32>,
+}
+impl ScheduleSearchQuery {
+    /// Default number of results per page.
+    pub const DEFAULT_PER_PAGE: u32 = 50;
+    /// Maximum number of results per page.
+    pub const MAX_PER_PAGE: u32 = 500;
+
+    /// Gets the number of results per page, clamped to `MAX_PER_PAGE`.
+    pub fn per_page(&self) -> u32 {
+        self.per_page
+            .unwrap_or(Self::DEFAULT_PER_PAGE)
+            .min(Self::MAX_PER_PAGE)
+            .max(1)
+    }
+}
+
+/// The response to the search_schedules() call.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ScheduleSearchResponse {
+    /// Schedules found, ordered by the time they start.
+    pub schedules: Vec<Schedule>,
+    /// Which page of results this is.
+    pub page: u32,
+    /// How many results there are per page.
+    pub per_page: u32,
+    /// Whether there are more results after this page.
+    pub more: bool,
+}
 
 /// The response to the get_mvts_passing_through() call.
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -334,3 +1075,40 @@ pub struct ConnectingMvtQueryResponse {
     pub schedules: HashMap<i64, Schedule>,
 }
 
+
+/// One leg of a journey - i.e. a trip on a single train.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct JourneyLeg {
+    /// The schedule of the train used for this leg.
+    pub schedule: Schedule,
+    /// The movement where the train is boarded (a departure).
+    pub board_mvt: ScheduleMvt,
+    /// The movement where the train is alighted (an arrival).
+    pub alight_mvt: ScheduleMvt,
+    /// The public departure time from `board_mvt`, with the date.
+    pub departure: NaiveDateTime,
+    /// The public arrival time at `alight_mvt`, with the date.
+    pub arrival: NaiveDateTime,
+}
+
+/// A journey between two stations, made up of one or more legs.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Journey {
+    /// The legs of this journey, in order.
+    pub legs: Vec<JourneyLeg>,
+    /// When the journey arrives at its destination.
+    pub arrival: NaiveDateTime,
+    /// The number of changes (interchanges) this journey involves.
+    pub changes: u32,
+}
+
+/// The response to the plan_journeys() call.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct JourneyPlanResponse {
+    /// Journeys found, in order of increasing number of changes.
+    ///
+    /// Each journey here arrives strictly earlier than the one
+    /// before it (i.e. it's only worth changing more times if you get
+    /// there sooner).
+    pub journeys: Vec<Journey>,
+}
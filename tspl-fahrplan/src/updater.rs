@@ -1,7 +1,7 @@
-//! Handles downloading schedule files from GCS, including updated versions when they become
-//! available.
+//! Handles downloading schedule files from object storage, including updated versions when they
+//! become available.
 
-use tspl_gcs::CloudStorage;
+use tspl_gcs::ObjectStorage;
 use tspl_gcs::errors::GcsError;
 use tspl_sqlite::{r2d2, TsplPool};
 use failure::format_err;
@@ -10,16 +10,19 @@ use std::thread;
 use std::time::Duration;
 use std::fs;
 use std::sync::{RwLock, Arc};
+use chrono::Local;
+use tspl_sqlite::traits::*;
 
 use crate::errors::*;
 use crate::config::Config;
 use crate::*;
+use crate::types::Schedule;
 
 pub static OBJECT_NAME: &str = "current.sqlite";
 pub static GCS_CHECK_SECS: u32 = 300;
 
 pub struct DbUpdater {
-    inner: CloudStorage,
+    inner: Box<dyn ObjectStorage>,
     object_name: String,
     last_updated: String,
     sleep_dur: Duration,
@@ -28,7 +31,7 @@ pub struct DbUpdater {
 
 impl DbUpdater {
     pub fn init(cfg: &Config, pool: Arc<RwLock<TsplPool>>) -> Result<Self> {
-        let mut inner = CloudStorage::init(cfg.bucket_name.clone(), &cfg.service_account_key_path)?;
+        let mut inner = cfg.storage.open()?;
         let object_name = cfg.object_name.clone().unwrap_or(OBJECT_NAME.into());
         info!("Downloading initial metadata for '{}'", object_name);
         let obj = inner.get_object(&object_name)?;
@@ -50,6 +53,13 @@ impl DbUpdater {
         // we can overwrite its file.
         let mut lock = self.pool.write().unwrap();
         info!("Acquired lock");
+        // VSTP and Darwin schedules only live in our local database, so
+        // grab them before it gets overwritten.
+        let local_scheds = {
+            let db = lock.get()?;
+            let yesterday = Local::now().naive_local().date().pred();
+            vstp::get_local_schedules(&db, yesterday)?
+        };
         // Make a dummy temporary database.
         let manager = tspl_sqlite::TsplConnectionManager::initialize(DATABASE_PATH_TEMP, &types::MIGRATIONS)?;
         let pool = r2d2::Pool::new(manager)?;
@@ -61,6 +71,18 @@ impl DbUpdater {
         // Load the f'real database.
         let manager = tspl_sqlite::TsplConnectionManager::initialize(DATABASE_PATH, &types::MIGRATIONS)?;
         let pool = r2d2::Pool::new(manager)?;
+        info!("Carrying over {} VSTP and Darwin schedules", local_scheds.len());
+        {
+            let mut db = pool.get()?;
+            let trans = db.transaction()?;
+            // (just in case the new database has some already)
+            trans.execute("DELETE FROM schedules WHERE source IN (?, ?)",
+                          params![Schedule::SOURCE_VSTP, Schedule::SOURCE_DARWIN])?;
+            for details in local_scheds.iter() {
+                details.insert_self(&trans)?;
+            }
+            trans.commit()?;
+        }
         *lock = pool;
         // We're now done here!
         Ok(())
@@ -0,0 +1,228 @@
+//! Applying VSTP (Very Short Term Planning) schedules, sent to us by `tspl-nrod`.
+//!
+//! Unlike CIF/ITPS schedules, these don't come from the updater; they're written
+//! straight into our local database, and carried across whenever a new database
+//! gets downloaded (see `updater.rs`).
+//!
+//! This means that there must only be one `tspl-fahrplan` instance receiving
+//! them (and answering queries), and that its database must be kept on
+//! persistent storage; see `k8s/tspl-fahrplan.yaml`.
+
+use ntrod_types::vstp::{self, VstpScheduleRecord, VstpLocationRecord};
+use tspl_sqlite::traits::*;
+use tspl_sqlite::rusqlite::OptionalExtension;
+use chrono::*;
+use log::*;
+
+use crate::types::*;
+use crate::errors::FahrplanResult;
+
+fn midnight_check(day_offset: &mut u8, last_time: &mut NaiveTime, time: NaiveTime) {
+    if time < *last_time {
+        *day_offset += 1;
+    }
+    *last_time = time;
+}
+
+/// Converts a VSTP schedule's locations into `ScheduleMvt`s (without ids).
+fn vstp_locations_to_mvts(locs: Vec<VstpLocationRecord>) -> Vec<ScheduleMvt> {
+    use self::VstpLocationRecord::*;
+
+    let mut mvts = vec![];
+    let mut day_offset = 0;
+    let mut last_time = NaiveTime::from_hms(0, 0, 0);
+    // Allowances apply between the location that shows them and the next
+    // timing point, so they get attached to the next location's movement.
+    let mut next_allowance = 0;
+    for loc in locs {
+        match loc {
+            Originating(o) => {
+                midnight_check(&mut day_offset, &mut last_time, o.scheduled_departure_time);
+                mvts.push(ScheduleMvt {
+                    tiploc: o.location.tiploc.tiploc_id,
+                    time: o.scheduled_departure_time,
+                    public_time: o.public_departure_time,
+                    action: ScheduleMvt::ACTION_DEPARTURE,
+                    platform: o.platform,
+                    day_offset,
+                    allowance: next_allowance,
+                    activity: o.activity,
+                    ..ScheduleMvt::dummy()
+                });
+                next_allowance = ScheduleMvt::total_allowance(&[&o.engineering_allowance, &o.pathing_allowance, &o.performance_allowance]);
+            },
+            Intermediate(i) => {
+                midnight_check(&mut day_offset, &mut last_time, i.scheduled_arrival_time);
+                mvts.push(ScheduleMvt {
+                    tiploc: i.location.tiploc.tiploc_id.clone(),
+                    time: i.scheduled_arrival_time,
+                    public_time: i.public_arrival_time,
+                    action: ScheduleMvt::ACTION_ARRIVAL,
+                    platform: i.platform.clone(),
+                    day_offset,
+                    allowance: next_allowance,
+                    activity: i.activity.clone(),
+                    ..ScheduleMvt::dummy()
+                });
+                midnight_check(&mut day_offset, &mut last_time, i.scheduled_departure_time);
+                mvts.push(ScheduleMvt {
+                    tiploc: i.location.tiploc.tiploc_id,
+                    time: i.scheduled_departure_time,
+                    public_time: i.public_departure_time,
+                    action: ScheduleMvt::ACTION_DEPARTURE,
+                    platform: i.platform,
+                    day_offset,
+                    activity: i.activity,
+                    ..ScheduleMvt::dummy()
+                });
+                next_allowance = ScheduleMvt::total_allowance(&[&i.engineering_allowance, &i.pathing_allowance, &i.performance_allowance]);
+            },
+            Pass(p) => {
+                midnight_check(&mut day_offset, &mut last_time, p.scheduled_pass_time);
+                mvts.push(ScheduleMvt {
+                    tiploc: p.location.tiploc.tiploc_id,
+                    time: p.scheduled_pass_time,
+                    action: ScheduleMvt::ACTION_PASS,
+                    day_offset,
+                    allowance: next_allowance,
+                    ..ScheduleMvt::dummy()
+                });
+                next_allowance = ScheduleMvt::total_allowance(&[&p.engineering_allowance, &p.pathing_allowance, &p.performance_allowance]);
+            },
+            Terminating(t) => {
+                midnight_check(&mut day_offset, &mut last_time, t.scheduled_arrival_time);
+                mvts.push(ScheduleMvt {
+                    tiploc: t.location.tiploc.tiploc_id,
+                    time: t.scheduled_arrival_time,
+                    public_time: t.public_arrival_time,
+                    action: ScheduleMvt::ACTION_ARRIVAL,
+                    platform: t.platform,
+                    day_offset,
+                    allowance: next_allowance,
+                    activity: t.activity,
+                    ..ScheduleMvt::dummy()
+                });
+            }
+        }
+    }
+    mvts
+}
+
+/// Gets all the schedules in the database that were written to it locally
+/// (i.e. VSTP and Darwin schedules), with their movements.
+///
+/// Schedules that ended before `not_before` are skipped.
+pub fn get_local_schedules(conn: &Connection, not_before: NaiveDate) -> FahrplanResult<Vec<ScheduleDetails>> {
+    let scheds = Schedule::from_select(conn, "WHERE source IN (?, ?) AND end_date >= ?",
+                                       &[&Schedule::SOURCE_VSTP, &Schedule::SOURCE_DARWIN, &not_before])?;
+    let mut ret = vec![];
+    for sched in scheds {
+        let mvts = ScheduleMvt::from_select(conn, "WHERE parent_sched = ? ORDER BY day_offset, time, action ASC", &[&sched.id])?;
+        ret.push(ScheduleDetails { sched, mvts });
+    }
+    Ok(ret)
+}
+
+/// Applies a VSTP message to the database.
+///
+/// Creating a schedule that already exists replaces it (giving it a new
+/// trainsplorer ID) if the movements have changed. Replaced and deleted
+/// schedules are kept as superseded versions, so their trainsplorer IDs
+/// still work. Returns the schedule, if one was created or replaced.
+pub fn apply_vstp_record(conn: &Connection, rec: vstp::Record) -> FahrplanResult<Option<Schedule>> {
+    let vstp::Record::V1(msg) = rec;
+    match msg.schedule {
+        VstpScheduleRecord::Delete { train_uid, schedule_start_date, stp_indicator, .. } => {
+            info!("deleting VSTP schedule (UID {}, start {}, stp_indicator {:?})",
+                  train_uid, schedule_start_date, stp_indicator);
+            let sid: Option<i64> = conn.query_row(
+                "SELECT id FROM schedules
+                 WHERE uid = ? AND start_date = ? AND stp_indicator = ? AND source = ?",
+                 params![train_uid, schedule_start_date, stp_indicator.as_char().to_string(), Schedule::SOURCE_VSTP],
+                 |row| row.get(0))
+                .optional()?;
+            if let Some(sid) = sid {
+                ScheduleVersion::delete_schedule(conn, sid, None)?;
+            }
+            Ok(None)
+        },
+        VstpScheduleRecord::Create {
+            train_uid,
+            schedule_start_date,
+            schedule_end_date,
+            schedule_days_runs,
+            bank_holiday_running,
+            stp_indicator,
+            schedule_segment,
+            ..
+        } => {
+            info!("inserting VSTP schedule (UID {}, start {}, stp_indicator {:?})",
+                  train_uid, schedule_start_date, stp_indicator);
+            let stp_indicator = stp_indicator.as_char().to_string();
+            // VSTP schedules only ever have one segment, in practice.
+            let seg = match schedule_segment.into_iter().nth(0) {
+                Some(s) => s,
+                None => {
+                    warn!("VSTP schedule {} has no segments; ignoring", train_uid);
+                    return Ok(None);
+                }
+            };
+            let meta = ScheduleMeta {
+                train_category: Some(seg.train_category.as_str().to_owned()).filter(|x| x != ""),
+                power_type: Some(seg.power_type.as_str().to_owned()).filter(|x| x != ""),
+                headcode: seg.headcode,
+                business_sector: seg.business_sector,
+                timing_load: seg.timing_load,
+                speed: seg.speed.and_then(|x| x.trim().parse().ok()),
+                operating_characteristics: seg.operating_characteristics,
+                train_class: seg.train_class,
+                sleepers: seg.sleepers,
+                reservations: seg.reservations,
+                catering_code: seg.catering_code,
+                service_branding: seg.service_branding,
+                atoc_code: None,
+                bank_holiday_running: bank_holiday_running.filter(|x| x.trim() != "")
+            };
+            let mut mvts = vstp_locations_to_mvts(seg.schedule_location);
+            let crosses_midnight = mvts.iter().any(|x| x.day_offset > 0);
+            mvts.sort_unstable();
+            let old: Option<i64> = conn.query_row(
+                "SELECT id FROM schedules
+                 WHERE uid = ? AND start_date = ? AND stp_indicator = ? AND source = ?",
+                 params![train_uid, schedule_start_date, stp_indicator, Schedule::SOURCE_VSTP],
+                 |row| row.get(0))
+                .optional()?;
+            if let Some(old) = old {
+                let orig_mvts = ScheduleMvt::from_select(conn, "WHERE parent_sched = ? ORDER BY day_offset, time, action ASC", &[&old])?;
+                if orig_mvts == mvts {
+                    debug!("VSTP schedule identical to existing schedule #{}", old);
+                    return Ok(None);
+                }
+            }
+            let sched = Schedule {
+                id: -1,
+                tspl_id: Uuid::new_v4(),
+                uid: train_uid,
+                start_date: schedule_start_date,
+                end_date: schedule_end_date,
+                days: schedule_days_runs.into(),
+                stp_indicator,
+                signalling_id: seg.signalling_id,
+                source: Schedule::SOURCE_VSTP,
+                file_metaseq: None,
+                darwin_id: None,
+                crosses_midnight,
+                meta
+            };
+            let mut details = ScheduleDetails { sched, mvts };
+            details.sched.id = match old {
+                Some(old) => {
+                    info!("replacing existing VSTP schedule #{}", old);
+                    ScheduleVersion::replace_schedule(conn, old, &details, None)?
+                },
+                None => details.insert_self(conn)?
+            };
+            Ok(Some(details.sched))
+        }
+    }
+}
@@ -5,8 +5,10 @@ use tspl_util::impl_from_for_error;
 use google_storage1::Error as GoogError;
 use std::io::Error as IoError;
 use hyper::status::StatusCode;
+use reqwest::Error as ReqwestError;
+use crate::StorageBackend;
 
-/// Cloud storage error.
+/// Object storage error.
 #[derive(Fail, Debug)]
 pub enum GcsError {
     /// The given entity was not found.
@@ -20,7 +22,19 @@ pub enum GcsError {
     Io(IoError),
     /// Any other error from google_storage1.
     #[fail(display = "google_storage1: {}", _0)]
-    Goog(String)
+    Goog(String),
+    /// A non-success status code was received (S3).
+    #[fail(display = "request returned status {}", _0)]
+    Status(u16),
+    /// reqwest error (S3).
+    #[fail(display = "reqwest: {}", _0)]
+    Reqwest(ReqwestError),
+    /// Any other HTTP-related error (S3).
+    #[fail(display = "HTTP: {}", _0)]
+    Http(String),
+    /// A config option needed for the chosen backend was missing.
+    #[fail(display = "config option `{}` must be set for the {:?} storage backend", _0, _1)]
+    MissingConfig(&'static str, StorageBackend)
 }
 
 impl From<GoogError> for GcsError {
@@ -43,4 +57,5 @@ impl From<GoogError> for GcsError {
 pub type GcsResult<T> = ::std::result::Result<T, GcsError>;
 
 impl_from_for_error!(GcsError,
-                     IoError => Io);
+                     IoError => Io,
+                     ReqwestError => Reqwest);
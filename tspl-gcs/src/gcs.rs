@@ -0,0 +1,65 @@
+//! Google Cloud Storage backend.
+
+use yup_oauth2::ServiceAccountAccess;
+use google_storage1::{Storage, Object};
+use hyper::{Client};
+use hyper::net::HttpsConnector;
+use hyper_rustls::TlsClient;
+use std::fs::File;
+
+use crate::errors::*;
+use crate::{ObjectStorage, ObjectMeta};
+
+/// Google Cloud Storage client.
+pub struct CloudStorage {
+    inner: Storage<Client, ServiceAccountAccess<Client>>,
+    bucket_name: String
+}
+
+impl CloudStorage {
+    /// Set up this GCS client to access a named bucket (`bucket_name`).
+    ///
+    /// `key_path` (unfortunately a `String` due to stupid oauth crate brain
+    /// damages) is a filesystem path to a service account key file, in JSON
+    /// format.
+    pub fn init(bucket_name: String, key_path: &String) -> GcsResult<Self> {
+        let key = yup_oauth2::service_account_key_from_file(key_path)?;
+        let cli = Client::with_connector(HttpsConnector::new(TlsClient::new()));
+        let cli2 = Client::with_connector(HttpsConnector::new(TlsClient::new()));
+        let sa = ServiceAccountAccess::new(key, cli2);
+        let inner = Storage::new(cli, sa);
+        Ok(Self { inner, bucket_name })
+    }
+}
+
+impl ObjectStorage for CloudStorage {
+    fn get_object(&mut self, obj: &str) -> GcsResult<ObjectMeta> {
+        let ret: Object = self.inner.objects()
+            .get(&self.bucket_name, obj)
+            .doit()?
+            .1;
+        Ok(ObjectMeta {
+            name: ret.name.unwrap_or_else(|| obj.to_owned()),
+            updated: ret.updated,
+            size: ret.size.and_then(|x| x.parse().ok())
+        })
+    }
+    fn download_object(&mut self, obj: &str, to_path: &str) -> GcsResult<()> {
+        let mut file = File::create(to_path)?;
+        let mut ret = self.inner.objects()
+            .get(&self.bucket_name, obj)
+            .param("alt", "media")
+            .doit()?;
+        std::io::copy(&mut ret.0, &mut file)?;
+        Ok(())
+    }
+    fn upload_object(&mut self, from_path: &str, obj: &str) -> GcsResult<()> {
+        let mut file = File::open(from_path)?;
+        let mut object = Object::default();
+        object.name = Some(obj.into());
+        self.inner.objects()
+            .insert(object, &self.bucket_name)
+            .upload_resumable(&mut file, "application/octet-stream".parse().unwrap())?;
+        Ok(())
+    }
+}
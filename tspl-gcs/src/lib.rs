@@ -1,64 +1,122 @@
-//! Handles interacting with Google Cloud Storage.
+//! Handles storing and retrieving objects (database files and the like).
+//!
+//! Despite the name, this supports more than just Google Cloud Storage:
+//! objects can also live in a local directory, or in an S3-compatible
+//! bucket (e.g. MinIO). Which one gets used is decided by `StorageConfig`.
 
 pub mod errors;
+pub mod gcs;
+pub mod local;
+pub mod s3;
 
-use yup_oauth2::ServiceAccountAccess;
-use google_storage1::Storage;
-use hyper::{Client};
-use hyper::net::HttpsConnector;
-use hyper_rustls::TlsClient;
-use std::fs::File;
+use serde_derive::Deserialize;
+use log::*;
 
-pub use google_storage1::{Object};
+pub use crate::gcs::CloudStorage;
+pub use crate::local::LocalStorage;
+pub use crate::s3::S3Storage;
 
 use crate::errors::*;
 
-/// Google Cloud Storage client.
-pub struct CloudStorage {
-    inner: Storage<Client, ServiceAccountAccess<Client>>,
-    bucket_name: String
+/// Metadata about a stored object.
+#[derive(Clone, Debug)]
+pub struct ObjectMeta {
+    /// The object's name.
+    pub name: String,
+    /// When the object was last updated.
+    ///
+    /// The format of this depends on the backend, so it's only
+    /// really useful for checking whether an object has changed.
+    pub updated: Option<String>,
+    /// The object's size, in bytes.
+    pub size: Option<u64>
 }
 
-impl CloudStorage {
-    /// Set up this GCS client to access a named bucket (`bucket_name`).
-    ///
-    /// `key_path` (unfortunately a `String` due to stupid oauth crate brain
-    /// damages) is a filesystem path to a service account key file, in JSON
-    /// format.
-    pub fn init(bucket_name: String, key_path: &String) -> GcsResult<Self> {
-        let key = yup_oauth2::service_account_key_from_file(key_path)?;
-        let cli = Client::with_connector(HttpsConnector::new(TlsClient::new()));
-        let cli2 = Client::with_connector(HttpsConnector::new(TlsClient::new()));
-        let sa = ServiceAccountAccess::new(key, cli2);
-        let inner = Storage::new(cli, sa);
-        Ok(Self { inner, bucket_name })
-    }
-    /// Get metadata about an object in the bucket, under the path `obj`.
-    pub fn get_object(&mut self, obj: &str) -> GcsResult<Object> {
-        let ret = self.inner.objects()
-            .get(&self.bucket_name, obj)
-            .doit()?;
-        Ok(ret.1)
-    }
+/// Something objects can be stored in.
+pub trait ObjectStorage: Send {
+    /// Get metadata about the object at the path `obj`.
+    fn get_object(&mut self, obj: &str) -> GcsResult<ObjectMeta>;
     /// Download the object at the path `obj` to the filesystem path `to_path`.
-    pub fn download_object(&mut self, obj: &str, to_path: &str) -> GcsResult<()> {
-        let mut file = File::create(to_path)?;
-        let mut ret = self.inner.objects()
-            .get(&self.bucket_name, obj)
-            .param("alt", "media")
-            .doit()?;
-        std::io::copy(&mut ret.0, &mut file)?;
-        Ok(())
-    }
+    fn download_object(&mut self, obj: &str, to_path: &str) -> GcsResult<()>;
     /// Upload the object at the filesystem path `from_path` to the object path `obj`.
-    pub fn upload_object(&mut self, from_path: &str, obj: &str) -> GcsResult<()> {
-        let mut file = File::open(from_path)?;
-        let mut object = Object::default();
-        object.name = Some(obj.into());
-        self.inner.objects()
-            .insert(object, &self.bucket_name)
-            .upload_resumable(&mut file, "application/octet-stream".parse().unwrap())?;;
-        Ok(())
+    fn upload_object(&mut self, from_path: &str, obj: &str) -> GcsResult<()>;
+}
+
+/// Which storage backend to use.
+#[derive(Deserialize, Copy, Clone, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// Google Cloud Storage.
+    Gcs,
+    /// A directory on the local filesystem.
+    Local,
+    /// An S3-compatible endpoint.
+    S3
+}
+impl Default for StorageBackend {
+    fn default() -> Self {
+        StorageBackend::Gcs
     }
 }
 
+/// Storage configuration, intended to be `#[serde(flatten)]`ed into
+/// crate configuration structs.
+#[derive(Deserialize, Clone, Debug, Default)]
+pub struct StorageConfig {
+    /// Which backend to use (`gcs`, `local` or `s3`; defaults to `gcs`).
+    #[serde(default)]
+    pub storage_backend: StorageBackend,
+    /// Bucket name (GCS and S3).
+    #[serde(default)]
+    pub bucket_name: Option<String>,
+    /// Path to a service account key file (GCS).
+    #[serde(default)]
+    pub service_account_key_path: Option<String>,
+    /// Directory to store objects in (local).
+    #[serde(default)]
+    pub storage_path: Option<String>,
+    /// Endpoint URL, e.g. `http://localhost:9000` (S3).
+    #[serde(default)]
+    pub s3_endpoint: Option<String>,
+    /// Region to sign requests for (S3; defaults to `us-east-1`).
+    #[serde(default)]
+    pub s3_region: Option<String>,
+    /// Access key ID (S3).
+    #[serde(default)]
+    pub s3_access_key_id: Option<String>,
+    /// Secret access key (S3).
+    #[serde(default)]
+    pub s3_secret_access_key: Option<String>
+}
+impl StorageConfig {
+    fn required<'a>(&self, val: &'a Option<String>, name: &'static str) -> GcsResult<&'a String> {
+        val.as_ref()
+            .ok_or(GcsError::MissingConfig(name, self.storage_backend))
+    }
+    /// Sets up the configured storage backend.
+    pub fn open(&self) -> GcsResult<Box<dyn ObjectStorage>> {
+        info!("initialising {:?} storage", self.storage_backend);
+        Ok(match self.storage_backend {
+            StorageBackend::Gcs => {
+                let bucket = self.required(&self.bucket_name, "bucket_name")?;
+                let key = self.required(&self.service_account_key_path, "service_account_key_path")?;
+                info!("using GCS bucket {}", bucket);
+                Box::new(CloudStorage::init(bucket.clone(), key)?)
+            },
+            StorageBackend::Local => {
+                let path = self.required(&self.storage_path, "storage_path")?;
+                info!("using local directory {}", path);
+                Box::new(LocalStorage::new(path))
+            },
+            StorageBackend::S3 => {
+                let endpoint = self.required(&self.s3_endpoint, "s3_endpoint")?;
+                let bucket = self.required(&self.bucket_name, "bucket_name")?;
+                let access_key = self.required(&self.s3_access_key_id, "s3_access_key_id")?;
+                let secret_key = self.required(&self.s3_secret_access_key, "s3_secret_access_key")?;
+                let region = self.s3_region.as_ref().map(|x| x as &str).unwrap_or("us-east-1");
+                info!("using S3 bucket {} at {}", bucket, endpoint);
+                Box::new(S3Storage::new(endpoint, bucket, region, access_key, secret_key)?)
+            }
+        })
+    }
+}
@@ -0,0 +1,66 @@
+//! Local directory backend.
+
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use chrono::{DateTime, Utc};
+
+use crate::errors::*;
+use crate::{ObjectStorage, ObjectMeta};
+
+/// Stores objects as files in a local directory.
+///
+/// Object names containing slashes end up in subdirectories.
+pub struct LocalStorage {
+    root: PathBuf
+}
+
+impl LocalStorage {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        Self { root: root.as_ref().to_owned() }
+    }
+    fn path_for(&self, obj: &str) -> PathBuf {
+        self.root.join(obj)
+    }
+}
+
+fn not_found_if_missing(e: ::std::io::Error) -> GcsError {
+    if e.kind() == ErrorKind::NotFound {
+        GcsError::NotFound
+    }
+    else {
+        GcsError::Io(e)
+    }
+}
+
+impl ObjectStorage for LocalStorage {
+    fn get_object(&mut self, obj: &str) -> GcsResult<ObjectMeta> {
+        let meta = fs::metadata(self.path_for(obj))
+            .map_err(not_found_if_missing)?;
+        let updated = meta.modified()
+            .ok()
+            .map(|x| DateTime::<Utc>::from(x).to_rfc3339());
+        Ok(ObjectMeta {
+            name: obj.to_owned(),
+            updated,
+            size: Some(meta.len())
+        })
+    }
+    fn download_object(&mut self, obj: &str, to_path: &str) -> GcsResult<()> {
+        fs::copy(self.path_for(obj), to_path)
+            .map_err(not_found_if_missing)?;
+        Ok(())
+    }
+    fn upload_object(&mut self, from_path: &str, obj: &str) -> GcsResult<()> {
+        let path = self.path_for(obj);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        // Copy to a temporary file first, so anyone watching the object
+        // never sees it half-written.
+        let tmp = path.with_extension("tmp-upload");
+        fs::copy(from_path, &tmp)?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,145 @@
+//! S3-compatible backend (AWS S3, MinIO, etc.).
+//!
+//! Requests are signed with AWS Signature Version 4, using path-style
+//! URLs (`{endpoint}/{bucket}/{object}`), which is what MinIO expects.
+//! Payloads aren't signed (`UNSIGNED-PAYLOAD`), so we don't have to read
+//! files twice when uploading them.
+
+use reqwest::{Client, Method, Url, Body, Response};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use sha2::{Sha256, Digest};
+use std::fs::File;
+use log::*;
+
+use crate::errors::*;
+use crate::{ObjectStorage, ObjectMeta};
+
+static UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+static SIGNED_HEADERS: &str = "host;x-amz-content-sha256;x-amz-date";
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|x| format!("{:02x}", x)).collect()
+}
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new(key)
+        .expect("HMAC accepts keys of any length");
+    mac.input(data.as_bytes());
+    mac.result().code().to_vec()
+}
+/// URI-encodes an object name, as required for the canonical request.
+fn uri_encode(obj: &str) -> String {
+    let mut ret = String::new();
+    for b in obj.bytes() {
+        match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => ret.push(b as char),
+            b => ret.push_str(&format!("%{:02X}", b))
+        }
+    }
+    ret
+}
+
+/// S3-compatible storage client.
+pub struct S3Storage {
+    cli: Client,
+    endpoint: Url,
+    host: String,
+    bucket_name: String,
+    region: String,
+    access_key: String,
+    secret_key: String
+}
+
+impl S3Storage {
+    pub fn new(endpoint: &str, bucket_name: &str, region: &str, access_key: &str, secret_key: &str) -> GcsResult<Self> {
+        let endpoint = Url::parse(endpoint)
+            .map_err(|e| GcsError::Http(format!("invalid endpoint {}: {}", endpoint, e)))?;
+        let host = match (endpoint.host_str(), endpoint.port()) {
+            (Some(h), Some(p)) => format!("{}:{}", h, p),
+            (Some(h), None) => h.to_owned(),
+            _ => return Err(GcsError::Http(format!("endpoint {} has no host", endpoint)))
+        };
+        Ok(Self {
+            cli: Client::new(),
+            endpoint,
+            host,
+            bucket_name: bucket_name.into(),
+            region: region.into(),
+            access_key: access_key.into(),
+            secret_key: secret_key.into()
+        })
+    }
+    /// Computes the `Authorization` header for a request.
+    fn authorization(&self, method: &Method, path: &str, amz_date: &str) -> String {
+        let date = &amz_date[0..8];
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let canonical_request = format!("{}\n{}\n\nhost:{}\nx-amz-content-sha256:{}\nx-amz-date:{}\n\n{}\n{}",
+                                        method, path, self.host, UNSIGNED_PAYLOAD,
+                                        amz_date, SIGNED_HEADERS, UNSIGNED_PAYLOAD);
+        let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}",
+                                     amz_date, scope, hex(&Sha256::digest(canonical_request.as_bytes())));
+        let key = hmac_sha256(format!("AWS4{}", self.secret_key).as_bytes(), date);
+        let key = hmac_sha256(&key, &self.region);
+        let key = hmac_sha256(&key, "s3");
+        let key = hmac_sha256(&key, "aws4_request");
+        let signature = hex(&hmac_sha256(&key, &string_to_sign));
+        format!("AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+                self.access_key, scope, SIGNED_HEADERS, signature)
+    }
+    /// Makes a signed request for the object `obj`.
+    fn request(&self, method: Method, obj: &str, body: Option<Body>) -> GcsResult<Response> {
+        let path = format!("{}/{}/{}",
+                           self.endpoint.path().trim_end_matches('/'),
+                           uri_encode(&self.bucket_name),
+                           uri_encode(obj));
+        let mut url = self.endpoint.clone();
+        url.set_path(&path);
+        let amz_date = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+        let auth = self.authorization(&method, &path, &amz_date);
+        debug!("S3 request: {} {}", method, url);
+        let mut req = self.cli.request(method, url)
+            .header("x-amz-date", amz_date)
+            .header("x-amz-content-sha256", UNSIGNED_PAYLOAD)
+            .header("authorization", auth);
+        if let Some(b) = body {
+            req = req.body(b);
+        }
+        let resp = req.send()?;
+        let st = resp.status();
+        if st.as_u16() == 404 {
+            return Err(GcsError::NotFound);
+        }
+        if !st.is_success() {
+            return Err(GcsError::Status(st.as_u16()));
+        }
+        Ok(resp)
+    }
+}
+
+impl ObjectStorage for S3Storage {
+    fn get_object(&mut self, obj: &str) -> GcsResult<ObjectMeta> {
+        let resp = self.request(Method::HEAD, obj, None)?;
+        let header = |name: &str| {
+            resp.headers().get(name)
+                .and_then(|x| x.to_str().ok())
+                .map(|x| x.to_owned())
+        };
+        Ok(ObjectMeta {
+            name: obj.to_owned(),
+            updated: header("last-modified"),
+            size: header("content-length").and_then(|x| x.parse().ok())
+        })
+    }
+    fn download_object(&mut self, obj: &str, to_path: &str) -> GcsResult<()> {
+        let mut resp = self.request(Method::GET, obj, None)?;
+        let mut file = File::create(to_path)?;
+        resp.copy_to(&mut file)?;
+        Ok(())
+    }
+    fn upload_object(&mut self, from_path: &str, obj: &str) -> GcsResult<()> {
+        let file = File::open(from_path)?;
+        let len = file.metadata()?.len();
+        self.request(Method::PUT, obj, Some(Body::sized(file, len)))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,13 @@
+-- tspl-nennen: TIPLOC records from the SCHEDULE feed
+
+CREATE TABLE tiploc_entries (
+	tiploc VARCHAR PRIMARY KEY,
+	nalco VARCHAR,
+	stanox VARCHAR,
+	crs VARCHAR,
+	description VARCHAR,
+	tps_description VARCHAR
+);
+
+CREATE INDEX tiploc_entries_stanox ON tiploc_entries (stanox);
+CREATE INDEX tiploc_entries_crs ON tiploc_entries (crs);
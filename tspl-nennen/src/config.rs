@@ -2,11 +2,12 @@
 
 use serde_derive::Deserialize;
 use tspl_util::{ConfigExt, crate_name};
+use tspl_gcs::StorageConfig;
 
 #[derive(Deserialize, Debug)]
 pub struct Config {
-    pub bucket_name: String,
-    pub service_account_key_path: String,
+    #[serde(flatten)]
+    pub storage: StorageConfig,
     pub username: String,
     pub password: String,
     #[serde(default)]
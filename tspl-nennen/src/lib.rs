@@ -3,4 +3,5 @@ pub mod config;
 pub mod types;
 pub mod msn;
 pub mod corpus;
+pub mod tiploc;
 
@@ -1,4 +1,4 @@
-//! Downloads CORPUS and MSN data, then uploads a database containing station
+//! Downloads CORPUS, MSN and TIPLOC data, then uploads a database containing station
 //! and other reference information.
 
 pub mod errors;
@@ -6,13 +6,14 @@ pub mod config;
 pub mod types;
 pub mod msn;
 pub mod corpus;
+pub mod tiploc;
 
 use crate::config::Config;
 use crate::corpus::CorpusDownloader;
+use crate::tiploc::TiplocDownloader;
 use crate::types::*;
 use tspl_sqlite::traits::*;
 use tspl_util::ConfigExt;
-use tspl_gcs::CloudStorage;
 use log::*;
 
 static MSN_FILE_OBJ: &str = "names.msn";
@@ -23,18 +24,20 @@ fn main() -> errors::Result<()> {
     info!("tspl-nennen, but not yet");
     info!("loading config");
     let cfg = Config::load()?;
-    info!("initialising GCS (bucket = {})", cfg.bucket_name);
-    let mut gcs = CloudStorage::init(cfg.bucket_name.clone(), &cfg.service_account_key_path)?;
+    let mut storage = cfg.storage.open()?;
     info!("initialising database");
     let mut db = tspl_sqlite::initialize_db("./current.sqlite", &types::MIGRATIONS)?;
 
     info!("downloading Master Station Names file...");
-    gcs.download_object(MSN_FILE_OBJ, "./names.msn")?;
+    storage.download_object(MSN_FILE_OBJ, "./names.msn")?;
     info!("loading MSN entries");
     msn::load_msn(&mut db, "./names.msn")?;
     info!("loading CORPUS entries");
     let mut cd = CorpusDownloader::new(&mut db, &cfg);
     cd.import()?;
+    info!("loading TIPLOC entries");
+    let mut td = TiplocDownloader::new(&mut db, &cfg);
+    td.import()?;
 
     info!("generating station names");
     for msn in WrappedMsnStation::from_select(&db, "", NO_PARAMS)? {
@@ -78,11 +81,30 @@ fn main() -> errors::Result<()> {
             }.insert_self(&db)?;
         }
     }
+    // TIPLOC descriptions go last, since they're the least pretty of the lot
+    // (and station names only get inserted if there isn't one already).
+    for tpl in TiplocEntry::from_select(&db, "WHERE description IS NOT NULL OR tps_description IS NOT NULL", NO_PARAMS)? {
+        let name = titlecase::titlecase(tpl.name().unwrap());
+        StationName {
+            id: -1,
+            name: name.clone(),
+            crs: None,
+            tiploc: Some(tpl.tiploc)
+        }.insert_self(&db)?;
+        if let Some(crs) = tpl.crs {
+            StationName {
+                id: -1,
+                name,
+                crs: Some(crs),
+                tiploc: None
+            }.insert_self(&db)?;
+        }
+    }
 
     info!("vacuuming into backup file...");
     db.execute_batch("VACUUM INTO './backup.sqlite';")?;
     info!("uploading vacuumed backup file...");
-    gcs.upload_object("./backup.sqlite", CURRENT_OBJ)?;
+    storage.upload_object("./backup.sqlite", CURRENT_OBJ)?;
     info!("done!");
     Ok(())
 }
@@ -0,0 +1,78 @@
+//! Downloading and importing TIPLOC records from the SCHEDULE feed.
+//!
+//! Plenty of timing points (junctions, sidings, etc.) don't appear in
+//! the MSN or in CORPUS with a usable name, but the SCHEDULE feed
+//! carries a TIPLOC record for every one of them. The full extract
+//! puts all of these right at the start, so we only read as far as the
+//! first association or schedule and then drop the connection.
+//!
+//! ## Amends and deletes
+//!
+//! TIPLOC amends (TA) and deletes (TD) only ever turn up in the daily
+//! update files; the full extract is a complete snapshot, so it only has
+//! inserts (TI). Rather than following the update files as well, every run
+//! deletes all the TIPLOC entries and inserts the ones in the full extract
+//! again, in one transaction (so readers never see a half-empty table).
+//! Amended TIPLOCs (including renamed ones, which come through as a
+//! delete of the old code and an insert of the new one) and deleted ones
+//! therefore get picked up by the next day's full extract, and nothing
+//! lingers under an old code.
+//!
+//! The catch is that changes take up to a day to show up, which is fine
+//! for names; a delete in the full extract would be a feed bug, so it
+//! just gets logged.
+
+use tspl_sqlite::traits::*;
+use log::*;
+use ntrod_types::schedule::{Record, CreateOrDelete};
+use tspl_util::nrod::NrodDownloader;
+use std::io::BufRead;
+
+use crate::types::TiplocEntry;
+use crate::errors::*;
+use crate::config::Config;
+
+pub struct TiplocDownloader<'a> {
+    inner: NrodDownloader,
+    db: &'a mut Connection
+}
+impl<'a> TiplocDownloader<'a> {
+    pub fn new<'b>(db: &'a mut Connection, cfg: &'b Config) -> Self {
+        let inner = NrodDownloader::new(cfg.username.clone(), cfg.password.clone(), cfg.base_url.clone());
+        Self { inner, db }
+    }
+    pub fn import(&mut self) -> Result<()> {
+        info!("Requesting SCHEDULE full extract");
+        let mut resp = self.inner.download("/ntrod/CifFileAuthenticate?type=CIF_ALL_FULL_DAILY&day=toc-full")?;
+        info!("Importing TIPLOC records");
+        let mut applied = 0;
+        let mut line = String::new();
+        let trans = self.db.transaction()?;
+        trans.execute("DELETE FROM tiploc_entries", NO_PARAMS)?;
+        while resp.read_line(&mut line)? > 0 {
+            match serde_json::from_str::<Record>(&line) {
+                Ok(Record::Tiploc(rec)) => {
+                    if let CreateOrDelete::Delete = rec.transaction_type {
+                        warn!("Ignoring TIPLOC delete for {} in full extract", rec.tiploc_code);
+                    }
+                    else {
+                        TiplocEntry::from(rec).insert_self(&trans)?;
+                        applied += 1;
+                    }
+                },
+                Ok(Record::Timetable(_)) => {},
+                Ok(_) => {
+                    debug!("Reached the end of the TIPLOC records");
+                    break;
+                },
+                Err(e) => {
+                    warn!("Failed to parse SCHEDULE record: {}", e);
+                }
+            }
+            line.clear();
+        }
+        trans.commit()?;
+        info!("Imported {} TIPLOC records", applied);
+        Ok(())
+    }
+}
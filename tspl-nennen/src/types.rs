@@ -5,11 +5,13 @@ use tspl_sqlite::migrations::Migration;
 use tspl_sqlite::migration;
 
 pub use ntrod_types::reference::CorpusEntry;
+use ntrod_types::schedule::TiplocRecord;
 pub use atoc_msn::types::MsnStation;
 use atoc_msn::types::CateType;
 
-pub static MIGRATIONS: [Migration; 1] = [
-    migration!(0, "initial")
+pub static MIGRATIONS: [Migration; 2] = [
+    migration!(0, "initial"),
+    migration!(1, "tiplocs")
 ];
 
 /// A mapping of station names to TIPLOC and/or CRS codes.
@@ -145,3 +147,67 @@ impl InsertableDbType for WrappedMsnStation {
         Ok(())
     }
 }
+
+/// A TIPLOC record from the SCHEDULE feed.
+pub struct TiplocEntry {
+    /// The Timing Point Location (TIPLOC) code.
+    pub tiploc: String,
+    /// National Location Code.
+    pub nalco: Option<String>,
+    /// TOPS location code.
+    pub stanox: Option<String>,
+    /// Customer Reservation System (CRS) code.
+    pub crs: Option<String>,
+    /// Description of the location, if it has one (usually only stations).
+    pub description: Option<String>,
+    /// TPS description of the location (usually in capitals, and abbreviated).
+    pub tps_description: Option<String>
+}
+impl TiplocEntry {
+    /// Gets the best available name for this location.
+    pub fn name(&self) -> Option<&str> {
+        self.description.as_ref()
+            .or(self.tps_description.as_ref())
+            .map(|x| x as &str)
+    }
+}
+impl From<TiplocRecord> for TiplocEntry {
+    fn from(rec: TiplocRecord) -> Self {
+        Self {
+            tiploc: rec.tiploc_code,
+            nalco: rec.nalco,
+            stanox: rec.stanox,
+            crs: rec.crs_code,
+            description: rec.description,
+            tps_description: rec.tps_description
+        }
+    }
+}
+impl DbType for TiplocEntry {
+    fn table_name() -> &'static str {
+        "tiploc_entries"
+    }
+    fn from_row(row: &Row, s: usize) -> RowResult<Self> {
+        Ok(Self {
+            tiploc: row.get(s + 0)?,
+            nalco: row.get(s + 1)?,
+            stanox: row.get(s + 2)?,
+            crs: row.get(s + 3)?,
+            description: row.get(s + 4)?,
+            tps_description: row.get(s + 5)?,
+        })
+    }
+}
+impl InsertableDbType for TiplocEntry {
+    type Id = ();
+    fn insert_self(&self, conn: &Connection) -> RowResult<()> {
+        // (the full extract shouldn't list a TIPLOC twice, but if it does,
+        // the last one wins)
+        let mut stmt = conn.prepare("INSERT OR REPLACE INTO tiploc_entries
+                        (tiploc, nalco, stanox, crs, description, tps_description)
+                        VALUES (?, ?, ?, ?, ?, ?)")?;
+        stmt.execute(params![self.tiploc, self.nalco, self.stanox, self.crs,
+                             self.description, self.tps_description])?;
+        Ok(())
+    }
+}
@@ -8,6 +8,11 @@ use tspl_util::{ConfigExt, crate_name};
 pub struct Config {
     /// URL of a running tspl-zugfuhrer instance.
     pub service_zugfuhrer: String,
+    /// URL of a running tspl-fahrplan instance, to send VSTP and Darwin schedules to.
+    ///
+    /// If not provided, VSTP and Darwin schedules won't be processed.
+    #[serde(default)]
+    pub service_fahrplan: Option<String>,
     /// NROD/Darwin username.
     pub username: String,
     /// NROD/Darwin password.
@@ -25,7 +25,8 @@ pub trait StompType {
 }
 
 pub struct Nrod {
-    tx: Sender<NrodMessage>
+    tx: Sender<NrodMessage>,
+    vstp: bool
 }
 impl StompType for Nrod {
     fn on_connect(&mut self, sess: &mut Session) -> Result<()> {
@@ -33,6 +34,11 @@ impl StompType for Nrod {
         sess.subscription("/topic/TRAIN_MVT_ALL_TOC")
             .with(Header::new("activemq.subscriptionName", "tspl-nrod"))
             .start();
+        if self.vstp {
+            sess.subscription("/topic/VSTP_ALL")
+                .with(Header::new("activemq.subscriptionName", "tspl-nrod-vstp"))
+                .start();
+        }
         Ok(())
     }
     fn on_message(&mut self, dest: &str, frame: &Frame) -> Result<()> {
@@ -41,6 +47,10 @@ impl StompType for Nrod {
             let st = String::from_utf8_lossy(&frame.body);
             self.tx.send(NrodMessage::Movement(st.into()));
         }
+        else if dest == "/topic/VSTP_ALL" {
+            let st = String::from_utf8_lossy(&frame.body);
+            self.tx.send(NrodMessage::Vstp(st.into()));
+        }
         Ok(())
     }
 }
@@ -82,7 +92,9 @@ pub struct NrodConfig<'a> {
     pub username: &'a str,
     pub password: &'a str,
     pub stomp_host: Option<&'a str>,
-    pub stomp_port: Option<u16>
+    pub stomp_port: Option<u16>,
+    /// Whether to subscribe to the VSTP feed as well.
+    pub vstp: bool
 }
 pub struct DarwinConfig<'a> {
     pub username: &'a str,
@@ -107,7 +119,7 @@ impl StompProcessor<Nrod> {
             .with(Header::new("client-id", "eta@theta.eu.org"))
             .with(HeartBeat(5_000, 2_000))
             .start(hdl.clone())?;
-        let inner = Nrod { tx };
+        let inner = Nrod { tx, vstp: conf.vstp };
         Ok(StompProcessor {
             sess, hdl, inner,
             timeout: None,
@@ -6,8 +6,10 @@ use std::sync::Arc;
 use tspl_sqlite::uuid::Uuid;
 use tspl_util::rpc::{RpcError, MicroserviceRpc};
 use tspl_util::user_agent;
-use tspl_zugfuhrer::types::{Train, TrainMvt};
+use tspl_zugfuhrer::types::{Train, TrainMvt, TrainDetails, DarwinCancellation};
+use tspl_fahrplan::types::{self as fpt, ScheduleDays, ScheduleMeta, ScheduleDetails};
 use darwin_types::pport::{Pport, PportElement};
+use darwin_types::schedule::{Schedule, ScheduleLocation, SchedLocAttributes};
 use darwin_types::forecasts::{Ts, PlatformData, TsTimeData};
 use reqwest::header::HeaderMap;
 use reqwest::Method;
@@ -30,11 +32,168 @@ pub struct DarwinWorker {
     rid_to_tspl: DarwinRidStore,
     /// RPC for `tspl-zugfuhrer`.
     zrpc: MicroserviceRpc,
+    /// RPC for `tspl-fahrplan` (used for Darwin schedules).
+    frpc: Option<MicroserviceRpc>,
+}
+/// Works out the STP indicator to give a Darwin schedule, from its CIF train
+/// status.
+///
+/// Darwin doesn't say whether a schedule is an overlay, but it does say
+/// whether it's permanent (letters, e.g. "P" for a passenger train) or short
+/// term plan (digits, e.g. "1"), so these become "P" and "N" respectively.
+fn darwin_stp_indicator(status: &str) -> &'static str {
+    if !status.is_empty() && status.chars().all(|c| c.is_ascii_digit()) {
+        "N"
+    }
+    else {
+        "P"
+    }
+}
+/// Converts a Darwin schedule into a tspl-fahrplan schedule (without ids),
+/// along with whether each of its movements is cancelled.
+fn darwin_schedule_to_details(sched: Schedule) -> (ScheduleDetails, Vec<DarwinCancellation>) {
+    use self::ScheduleLocation::*;
+
+    let mut mvts = vec![];
+    let mut canxs = vec![];
+    let mut day_offset = 0;
+    let mut last_time: Option<NaiveTime> = None;
+    {
+        // Each location gets turned into (attributes, action, working time, public time).
+        let mut push = |sla: &SchedLocAttributes, action: u8, time: NaiveTime, public_time: Option<NaiveTime>| {
+            // Darwin doesn't tell us about midnight, so guess: going backwards
+            // by more than 6 hours means we've gone past it.
+            if let Some(last) = last_time {
+                if time < last && last.signed_duration_since(time).num_hours() >= 6 {
+                    day_offset += 1;
+                }
+            }
+            last_time = Some(time);
+            canxs.push(DarwinCancellation {
+                tiploc: sla.tpl.clone(),
+                planned_time: time,
+                planned_day_offset: day_offset,
+                planned_action: action,
+                canx: sla.can
+            });
+            mvts.push(fpt::ScheduleMvt {
+                tiploc: sla.tpl.clone(),
+                action,
+                time,
+                public_time,
+                day_offset,
+                ..fpt::ScheduleMvt::dummy()
+            });
+        };
+        for loc in sched.locations {
+            match loc {
+                Or(l) => {
+                    if let Some(wta) = l.wta {
+                        push(&l.sla, fpt::ScheduleMvt::ACTION_ARRIVAL, wta, l.cpa.pta);
+                    }
+                    push(&l.sla, fpt::ScheduleMvt::ACTION_DEPARTURE, l.wtd, l.cpa.ptd);
+                },
+                OpOr(l) => {
+                    if let Some(wta) = l.wta {
+                        push(&l.sla, fpt::ScheduleMvt::ACTION_ARRIVAL, wta, None);
+                    }
+                    push(&l.sla, fpt::ScheduleMvt::ACTION_DEPARTURE, l.wtd, None);
+                },
+                Ip(l) => {
+                    push(&l.sla, fpt::ScheduleMvt::ACTION_ARRIVAL, l.wta, l.cpa.pta);
+                    push(&l.sla, fpt::ScheduleMvt::ACTION_DEPARTURE, l.wtd, l.cpa.ptd);
+                },
+                OpIp(l) => {
+                    push(&l.sla, fpt::ScheduleMvt::ACTION_ARRIVAL, l.wta, None);
+                    push(&l.sla, fpt::ScheduleMvt::ACTION_DEPARTURE, l.wtd, None);
+                },
+                Pp(l) => {
+                    push(&l.sla, fpt::ScheduleMvt::ACTION_PASS, l.wtp, None);
+                },
+                Dt(l) => {
+                    push(&l.sla, fpt::ScheduleMvt::ACTION_ARRIVAL, l.wta, l.cpa.pta);
+                    if let Some(wtd) = l.wtd {
+                        push(&l.sla, fpt::ScheduleMvt::ACTION_DEPARTURE, wtd, l.cpa.ptd);
+                    }
+                },
+                OpDt(l) => {
+                    push(&l.sla, fpt::ScheduleMvt::ACTION_ARRIVAL, l.wta, None);
+                    if let Some(wtd) = l.wtd {
+                        push(&l.sla, fpt::ScheduleMvt::ACTION_DEPARTURE, wtd, None);
+                    }
+                }
+            }
+        }
+    }
+    mvts.sort_unstable();
+    let crosses_midnight = day_offset > 0;
+    let days = ScheduleDays::from_iso_weekday(sched.ssd.weekday().number_from_monday())
+        .unwrap_or(ScheduleDays::all());
+    let sched = fpt::Schedule {
+        id: -1,
+        tspl_id: Uuid::nil(),
+        uid: sched.uid,
+        start_date: sched.ssd,
+        end_date: sched.ssd,
+        days,
+        stp_indicator: darwin_stp_indicator(&sched.status).into(),
+        signalling_id: Some(sched.train_id),
+        source: fpt::Schedule::SOURCE_DARWIN,
+        file_metaseq: None,
+        darwin_id: Some(sched.rid),
+        crosses_midnight,
+        meta: ScheduleMeta {
+            train_category: Some(sched.train_cat),
+            atoc_code: Some(sched.toc),
+            ..Default::default()
+        }
+    };
+    (ScheduleDetails { sched, mvts }, canxs)
 }
 impl DarwinWorker {
-    pub fn new(rx: Receiver<DarwinMessage>, ts: DarwinRidStore, base_url: String) -> Self {
+    pub fn new(rx: Receiver<DarwinMessage>, ts: DarwinRidStore, base_url: String, fahrplan_url: Option<String>) -> Self {
         let zrpc = MicroserviceRpc::new(user_agent!(), "zugfuhrer", base_url);
-        Self { rx, rid_to_tspl: ts, zrpc }
+        let frpc = fahrplan_url
+            .map(|x| MicroserviceRpc::new(user_agent!(), "fahrplan", x));
+        Self { rx, rid_to_tspl: ts, zrpc, frpc }
+    }
+    fn process_schedule(&mut self, sched: Schedule) -> Result<()> {
+        debug!("Processing schedule for RID {} (uid {}, ssd {})", sched.rid, sched.uid, sched.ssd);
+        let frpc = match self.frpc {
+            Some(ref f) => f,
+            None => {
+                debug!("No fahrplan URL configured; ignoring schedule");
+                return Ok(());
+            }
+        };
+        let rid = sched.rid.clone();
+        if sched.deleted {
+            info!("Darwin schedule for RID {} deleted.", rid);
+            match frpc.req::<_, ()>(Method::POST, format!("/schedules/darwin/{}/delete", rid)) {
+                Ok(_) | Err(RpcError::RemoteNotFound) => {},
+                Err(e) => Err(e)?
+            }
+            return Ok(());
+        }
+        let reason = sched.cancel_reason.as_ref().map(|x| x.reason.clone());
+        let (details, canxs) = darwin_schedule_to_details(sched);
+        let body = serde_json::to_string(&details)?;
+        let fsched: fpt::Schedule = frpc.req_with_body(Method::POST, "/schedules/darwin", body)?;
+        let mut hdrs = HeaderMap::new();
+        hdrs.insert("X-tspl-darwin-rid", rid.parse()?);
+        hdrs.insert("X-tspl-schedule-tspl-id", fsched.tspl_id.to_string().parse()?);
+        let train: Train = self.zrpc.req_with_headers(Method::POST, "/trains/darwin-schedule", hdrs)?;
+        // Darwin schedules say which locations are cancelled, too, so pass
+        // that on (including locations that aren't, in case they were before).
+        let mut hdrs = HeaderMap::new();
+        if let Some(r) = reason {
+            hdrs.insert("X-tspl-canx-reason", r.parse()?);
+        }
+        let body = serde_json::to_string(&canxs)?;
+        let _: TrainDetails = self.zrpc.req_with_body_and_headers(Method::POST, format!("/trains/{}/darwin/cancellations", train.tspl_id), body, hdrs)?;
+        info!("Processed Darwin schedule for RID {} ({}) as {}.", rid, fsched.tspl_id, train.tspl_id);
+        self.rid_to_tspl.insert(rid, train.tspl_id);
+        Ok(())
     }
     fn lookup_or_activate_train(&mut self, rid: &str, uid: &str, ssd: NaiveDate) -> Result<Uuid> {
         if let Some(ret) = self.rid_to_tspl.get(rid) {
@@ -200,6 +359,19 @@ impl DarwinWorker {
         match pp.inner {
             PportElement::DataResponse(dr) => {
                 info!("Processing Darwin data response message, origin {:?}, source {:?}, rid {:?}", dr.update_origin, dr.request_source, dr.request_id);
+                // Do schedules first, so that any train status messages
+                // in the same update have movements to apply to.
+                for sched in dr.schedule {
+                    if let Err(e) = self.process_schedule(sched) {
+                        warn!("Failed to process schedule: {}", e);
+                    }
+                }
+                for deac in dr.deactivated {
+                    // Nothing more is going to happen to this train, so
+                    // there's no point caching it any more.
+                    info!("RID {} deactivated.", deac.rid);
+                    self.rid_to_tspl.remove(&deac.rid);
+                }
                 for ts in dr.train_status {
                     if let Err(e) = self.process_ts(ts) {
                         warn!("Failed to process TS: {}", e);
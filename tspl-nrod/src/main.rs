@@ -46,7 +46,7 @@ fn main() -> Result<()> {
         info!("spawning {} worker thread(s)", cfg.n_threads);
         let cmap = Arc::new(CHashMap::new());
         for _ in 0..cfg.n_threads {
-            let mut worker = DarwinWorker::new(rx.clone(), cmap.clone(), cfg.service_zugfuhrer.clone());
+            let mut worker = DarwinWorker::new(rx.clone(), cmap.clone(), cfg.service_zugfuhrer.clone(), cfg.service_fahrplan.clone());
             thread::spawn(move || {
                 worker.run();
             });
@@ -60,13 +60,14 @@ fn main() -> Result<()> {
             username: &cfg.username,
             password: &cfg.password,
             stomp_host: cfg.stomp_host.as_ref().map(|x| x as &str),
-            stomp_port: cfg.stomp_port
+            stomp_port: cfg.stomp_port,
+            vstp: cfg.service_fahrplan.is_some()
         };
         let proc = StompProcessor::new_nrod(&ncfg, tx, hdl)?;
         info!("spawning {} worker thread(s)", cfg.n_threads);
         let cmap = Arc::new(CHashMap::new());
         for _ in 0..cfg.n_threads {
-            let mut worker = NrodWorker::new(rx.clone(), cmap.clone(), cfg.service_zugfuhrer.clone());
+            let mut worker = NrodWorker::new(rx.clone(), cmap.clone(), cfg.service_zugfuhrer.clone(), cfg.service_fahrplan.clone());
             thread::spawn(move || {
                 worker.run();
             });
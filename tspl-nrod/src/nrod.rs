@@ -6,10 +6,10 @@ use std::sync::Arc;
 use tspl_sqlite::uuid::Uuid;
 use tspl_util::rpc::MicroserviceRpc;
 use tspl_util::user_agent;
-use ntrod_types::movements::{Records, MvtBody, self};
-use tspl_zugfuhrer::types::{Train, TrainMvt};
+use ntrod_types::movements::{Records, MvtBody, CanxType, self};
+use tspl_zugfuhrer::types::{Train, TrainMvt, TrainDetails};
 use tspl_fahrplan::types::Schedule;
-use reqwest::header::HeaderMap;
+use reqwest::header::{HeaderMap, HeaderName};
 use reqwest::Method;
 use failure::format_err;
 use chrono::prelude::*;
@@ -20,7 +20,8 @@ use crate::errors::*;
 pub type TrustTsplStore = Arc<CHashMap<String, Uuid>>;
 
 pub enum NrodMessage {
-    Movement(String)
+    Movement(String),
+    Vstp(String)
 }
 pub struct NrodWorker {
     rx: Receiver<NrodMessage>,
@@ -28,6 +29,8 @@ pub struct NrodWorker {
     trust_to_tspl: TrustTsplStore,
     /// RPC for `tspl-zugfuhrer`.
     zrpc: MicroserviceRpc,
+    /// RPC for `tspl-fahrplan` (used for VSTP schedules).
+    frpc: Option<MicroserviceRpc>,
 }
 // The last two digits of a TRUST ID signify the day of the month
 // when the train first set off.
@@ -37,10 +40,31 @@ fn trust_id_extract_day_of_month(tid: &str) -> Result<u32> {
     let as_dom = last_two.parse()?;
     Ok(as_dom)
 }
+/// Works out the day offset of something scheduled to happen at `ts`, for the
+/// train with TRUST ID `tid`.
+fn trust_day_offset(tid: &str, ts: NaiveDateTime) -> Result<u8> {
+    let start_dom = trust_id_extract_day_of_month(tid)?;
+    // If the day of the month is equal to the day of month from the TRUST ID
+    // (i.e. day of month at origination time), day_offset should be 0
+    // (i.e. movement happens on the same day as origination). Otherwise,
+    // assuming trains don't span more than one day, day_offset is 1 (on
+    // the next day).
+    Ok(if ts.day() == start_dom { 0 } else { 1 })
+}
+/// Adds headers describing a location in a train's schedule.
+fn insert_location_headers(hdrs: &mut HeaderMap, prefix: &str, tid: &str, stanox: &str, ts: NaiveDateTime) -> Result<()> {
+    let name = |field: &str| format!("X-tspl-{}-{}", prefix, field).parse::<HeaderName>();
+    hdrs.insert(name("stanox")?, stanox.parse()?);
+    hdrs.insert(name("planned-time")?, ts.time().to_string().parse()?);
+    hdrs.insert(name("planned-day-offset")?, trust_day_offset(tid, ts)?.to_string().parse()?);
+    Ok(())
+}
 impl NrodWorker {
-    pub fn new(rx: Receiver<NrodMessage>, ts: TrustTsplStore, base_url: String) -> Self {
+    pub fn new(rx: Receiver<NrodMessage>, ts: TrustTsplStore, base_url: String, fahrplan_url: Option<String>) -> Self {
         let zrpc = MicroserviceRpc::new(user_agent!(), "zugfuhrer", base_url);
-        Self { rx, trust_to_tspl: ts, zrpc }
+        let frpc = fahrplan_url
+            .map(|x| MicroserviceRpc::new(user_agent!(), "fahrplan", x));
+        Self { rx, trust_to_tspl: ts, zrpc, frpc }
     }
     fn lookup_trust_id(&mut self, tid: &str, date: NaiveDate) -> Result<Uuid> {
         if let Some(ret) = self.trust_to_tspl.get(tid) {
@@ -75,8 +99,68 @@ impl NrodWorker {
     fn handle_cancellation(&mut self, c: movements::Cancellation) -> Result<()> {
         debug!("Processing cancellation of train {}...", c.train_id);
         let tspl_id = self.lookup_trust_id(&c.train_id, c.canx_timestamp.date())?;
-        let _: () = self.zrpc.req(Method::POST, format!("/trains/{}/cancel", tspl_id))?;
-        info!("Train {} ({}) cancelled.", c.train_id, tspl_id);
+        let mut hdrs = HeaderMap::new();
+        hdrs.insert("X-tspl-canx-reason", c.canx_reason_code.parse()?);
+        match c.canx_type {
+            CanxType::EnRoute | CanxType::OffRoute => {
+                // Out-of-plan cancellations happen somewhere the train was never
+                // meant to be, so use where it was meant to be instead.
+                let (stanox, ts) = match (c.orig_loc_stanox.as_ref(), c.orig_loc_timestamp) {
+                    (Some(s), Some(ts)) => (s, ts),
+                    _ => (&c.loc_stanox, c.dep_timestamp)
+                };
+                insert_location_headers(&mut hdrs, "mvt", &c.train_id, stanox, ts)?;
+                let _: TrainDetails = self.zrpc.req_with_headers(Method::POST, format!("/trains/{}/cancel", tspl_id), hdrs)?;
+                info!("Train {} ({}) cancelled from STANOX {} (reason {}).", c.train_id, tspl_id, stanox, c.canx_reason_code);
+            },
+            CanxType::OnActivation | CanxType::AtOrigin => {
+                let _: TrainDetails = self.zrpc.req_with_headers(Method::POST, format!("/trains/{}/cancel", tspl_id), hdrs)?;
+                info!("Train {} ({}) cancelled (reason {}).", c.train_id, tspl_id, c.canx_reason_code);
+            }
+        }
+        Ok(())
+    }
+    fn handle_change_of_origin(&mut self, c: movements::ChangeOfOrigin) -> Result<()> {
+        debug!("Processing change of origin of train {}...", c.train_id);
+        let tspl_id = self.lookup_trust_id(&c.train_id, c.dep_timestamp.date())?;
+        let mut hdrs = HeaderMap::new();
+        insert_location_headers(&mut hdrs, "mvt", &c.train_id, &c.loc_stanox, c.dep_timestamp)?;
+        hdrs.insert("X-tspl-canx-reason", c.reason_code.parse()?);
+        let _: TrainDetails = self.zrpc.req_with_headers(Method::POST, format!("/trains/{}/change-origin", tspl_id), hdrs)?;
+        info!("Train {} ({}) now starts from STANOX {} (reason {}).", c.train_id, tspl_id, c.loc_stanox, c.reason_code);
+        Ok(())
+    }
+    fn handle_change_of_identity(&mut self, c: movements::ChangeOfIdentity) -> Result<()> {
+        // If the identity's been changed before, the train will be under
+        // the last identity it was given.
+        let current = c.current_train_id.as_ref().unwrap_or(&c.train_id);
+        debug!("Processing change of identity of train {} to {}...", current, c.revised_train_id);
+        let tspl_id = self.lookup_trust_id(current, c.event_timestamp.date())?;
+        let _: Train = self.zrpc.req(Method::POST, format!("/trains/{}/change-identity/{}", tspl_id, c.revised_train_id))?;
+        info!("Train {} ({}) is now {}.", current, tspl_id, c.revised_train_id);
+        // Keep the old TRUST ID(s) around, in case later messages use them.
+        self.trust_to_tspl.insert(c.revised_train_id, tspl_id);
+        Ok(())
+    }
+    fn handle_change_of_location(&mut self, c: movements::ChangeOfLocation) -> Result<()> {
+        debug!("Processing change of location of train {}...", c.train_id);
+        let tspl_id = self.lookup_trust_id(&c.train_id, c.dep_timestamp.date())?;
+        let mut hdrs = HeaderMap::new();
+        insert_location_headers(&mut hdrs, "mvt", &c.train_id, &c.loc_stanox, c.dep_timestamp)?;
+        if let (Some(stanox), Some(ts)) = (c.original_loc_stanox.as_ref(), c.original_loc_timestamp) {
+            insert_location_headers(&mut hdrs, "original", &c.train_id, stanox, ts)?;
+        }
+        let _: TrainDetails = self.zrpc.req_with_headers(Method::POST, format!("/trains/{}/change-location", tspl_id), hdrs)?;
+        info!("Train {} ({}) has changed location to STANOX {}.", c.train_id, tspl_id, c.loc_stanox);
+        Ok(())
+    }
+    fn handle_reinstatement(&mut self, r: movements::Reinstatement) -> Result<()> {
+        debug!("Processing reinstatement of train {}...", r.train_id);
+        let tspl_id = self.lookup_trust_id(&r.train_id, r.dep_timestamp.date())?;
+        let mut hdrs = HeaderMap::new();
+        insert_location_headers(&mut hdrs, "mvt", &r.train_id, &r.loc_stanox, r.dep_timestamp)?;
+        let _: TrainDetails = self.zrpc.req_with_headers(Method::POST, format!("/trains/{}/reinstate", tspl_id), hdrs)?;
+        info!("Train {} ({}) reinstated from STANOX {}.", r.train_id, tspl_id, r.loc_stanox);
         Ok(())
     }
     fn handle_movement(&mut self, m: movements::Movement) -> Result<()> {
@@ -97,13 +181,7 @@ impl NrodWorker {
         let mut hdrs = HeaderMap::new();
         hdrs.insert("X-tspl-mvt-stanox", m.loc_stanox.parse()?);
         hdrs.insert("X-tspl-mvt-planned-time", planned_ts.time().to_string().parse()?);
-        let start_dom = trust_id_extract_day_of_month(&m.train_id)?;
-        // If the day of the month is equal to the day of month from the TRUST ID
-        // (i.e. day of month at origination time), day_offset should be 0
-        // (i.e. movement happens on the same day as origination). Otherwise,
-        // assuming trains don't span more than one day, day_offset is 1 (on
-        // the next day).
-        let day_offset = if planned_ts.day() == start_dom { 0 } else { 1 };
+        let day_offset = trust_day_offset(&m.train_id, planned_ts)?;
         hdrs.insert("X-tspl-mvt-planned-day-offset", day_offset.to_string().parse()?);
         let action = match m.event_type {
             EventType::Arrival => 0,
@@ -139,6 +217,10 @@ impl NrodWorker {
                 Activation(r) => self.handle_activation(r),
                 Movement(m) => self.handle_movement(m),
                 Cancellation(c) => self.handle_cancellation(c),
+                ChangeOfOrigin(c) => self.handle_change_of_origin(c),
+                ChangeOfIdentity(c) => self.handle_change_of_identity(c),
+                ChangeOfLocation(c) => self.handle_change_of_location(c),
+                Reinstatement(r) => self.handle_reinstatement(r),
                 _ => Err(format_err!("message type not yet implemented"))
             };
             if let Err(e) = ret {
@@ -146,11 +228,29 @@ impl NrodWorker {
             }
         }
     }
+    fn on_vstp_message(&mut self, st: String) {
+        info!("Processing VSTP message");
+        let frpc = match self.frpc {
+            Some(ref f) => f,
+            None => {
+                warn!("Got a VSTP message, but no fahrplan URL was configured");
+                return;
+            }
+        };
+        // tspl-fahrplan does the actual parsing; we just pass it on.
+        let ret: std::result::Result<Option<Schedule>, _> = frpc.req_with_body(Method::POST, "/vstp", st);
+        match ret {
+            Ok(Some(s)) => info!("Applied VSTP schedule {} (start {}, stp {}) as {}.", s.uid, s.start_date, s.stp_indicator, s.tspl_id),
+            Ok(None) => info!("Applied VSTP message."),
+            Err(e) => warn!("Error applying VSTP message: {}", e)
+        }
+    }
     pub fn run(&mut self) {
         loop {
             let data = self.rx.recv().unwrap();
             match data {
-                NrodMessage::Movement(d) => self.on_mvt_message(&d)
+                NrodMessage::Movement(d) => self.on_mvt_message(&d),
+                NrodMessage::Vstp(d) => self.on_vstp_message(d)
             }
         }
     }
@@ -0,0 +1,52 @@
+-- tspl-punktlich: initial schema
+
+-- Trains seen on the tspl-zugfuhrer live stream.
+CREATE TABLE trains (
+	-- NB: this is tspl-zugfuhrer's internal ID for the train, since that's
+	-- what train movements on the live stream refer to.
+	id INTEGER PRIMARY KEY,
+	tspl_id TEXT UNIQUE NOT NULL,
+	date DATE NOT NULL,
+	uid TEXT NOT NULL,
+	headcode TEXT,
+	atoc_code TEXT,
+	cancelled BOOL NOT NULL
+);
+
+-- The live schedules of those trains, together with what actually happened.
+CREATE TABLE schedule_mvts (
+	-- (again, tspl-zugfuhrer's ID)
+	id INTEGER PRIMARY KEY,
+	parent_train INT NOT NULL REFERENCES trains ON DELETE CASCADE,
+	tiploc TEXT NOT NULL,
+	action INT NOT NULL,
+	time TEXT NOT NULL,
+	public_time TEXT,
+	day_offset INT NOT NULL,
+	canx BOOL NOT NULL,
+	lateness INT
+);
+CREATE INDEX schedule_mvts_parent_train ON schedule_mvts (parent_train);
+
+-- Running totals, kept up to date as things happen.
+CREATE TABLE stats (
+	dimension TEXT NOT NULL,
+	subject TEXT NOT NULL,
+	date DATE NOT NULL,
+	observed INT NOT NULL,
+	on_time INT NOT NULL,
+	ppm INT NOT NULL,
+	cancelled INT NOT NULL,
+	lateness_total INT NOT NULL,
+	PRIMARY KEY (dimension, subject, date)
+);
+
+-- Lateness histograms (counts per whole minute late), for percentiles.
+CREATE TABLE lateness_mins (
+	dimension TEXT NOT NULL,
+	subject TEXT NOT NULL,
+	date DATE NOT NULL,
+	minutes INT NOT NULL,
+	count INT NOT NULL,
+	PRIMARY KEY (dimension, subject, date, minutes)
+);
@@ -0,0 +1,28 @@
+//! Standard configuration module.
+
+use serde_derive::Deserialize;
+use tspl_util::{ConfigExt, crate_name};
+
+/// `tspl-punktlich` configuration.
+#[derive(Deserialize, Debug)]
+pub struct Config {
+    /// Address to listen on, for HTTP requests.
+    pub listen: String,
+    /// Path to SQLite database.
+    pub database_path: String,
+    /// URL of a running tspl-fahrplan instance.
+    pub service_fahrplan: String,
+    /// URL of a running tspl-zugfuhrer instance.
+    pub service_zugfuhrer: String,
+    /// Address of tspl-zugfuhrer's live TCP stream (its `listen_live`).
+    pub service_zugfuhrer_live: String,
+    /// How long to wait before reconnecting to the live stream, in seconds.
+    #[serde(default)]
+    pub reconnect_secs: Option<u64>
+}
+
+impl ConfigExt for Config {
+    fn crate_name() -> &'static str {
+        crate_name!()
+    }
+}
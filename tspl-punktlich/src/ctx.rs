@@ -0,0 +1,84 @@
+//! Main app context.
+
+use tspl_sqlite::TsplPool;
+use tspl_util::user_agent;
+use tspl_util::http::HttpServer;
+use rouille::{Request, Response, router};
+use std::str::FromStr;
+use chrono::*;
+
+use crate::stats;
+use crate::types::*;
+use crate::errors::*;
+
+/// How many days' worth of statistics to return, if not told otherwise.
+pub const DEFAULT_DAYS: i64 = 28;
+
+fn query_param<T: FromStr>(req: &Request, name: &str) -> PunktlichResult<Option<T>> {
+    match req.get_param(name).filter(|x| x.trim() != "") {
+        Some(x) => {
+            x.trim().parse()
+                .map(Some)
+                .map_err(|_| PunktlichError::InvalidQuery(format!("{}={}", name, x)))
+        },
+        None => Ok(None)
+    }
+}
+
+pub struct App {
+    pool: TsplPool
+}
+impl HttpServer for App {
+    type Error = PunktlichError;
+
+    fn on_request(&self, req: &Request) -> PunktlichResult<Response> {
+        router!(req,
+            (GET) (/) => {
+                Ok(Response::text(user_agent!()))
+            },
+            (GET) (/stats/by-uid/{uid}) => {
+                self.get_report(req, Dimension::Uid, uid)
+                    .map(|x| Response::json(&x))
+            },
+            (GET) (/stats/by-headcode/{headcode}) => {
+                self.get_report(req, Dimension::Headcode, headcode)
+                    .map(|x| Response::json(&x))
+            },
+            (GET) (/stats/by-tiploc/{tiploc}) => {
+                self.get_report(req, Dimension::Tiploc, tiploc)
+                    .map(|x| Response::json(&x))
+            },
+            (GET) (/stats/by-toc/{atoc_code}) => {
+                self.get_report(req, Dimension::Toc, atoc_code)
+                    .map(|x| Response::json(&x))
+            },
+            (GET) (/stats/by-day) => {
+                self.get_report(req, Dimension::Day, String::new())
+                    .map(|x| Response::json(&x))
+            },
+            _ => {
+                Err(PunktlichError::InvalidPath)
+            }
+        )
+    }
+}
+impl App {
+    pub fn new(pool: TsplPool) -> Self {
+        Self { pool }
+    }
+    /// Gets a punctuality report, for the date range in the `from` and `to`
+    /// query parameters (defaulting to the last `DEFAULT_DAYS` days).
+    fn get_report(&self, req: &Request, dimension: Dimension, subject: String) -> PunktlichResult<PunctualityReport> {
+        let to: NaiveDate = query_param(req, "to")?
+            .unwrap_or_else(|| Local::now().naive_local().date());
+        let from: NaiveDate = query_param(req, "from")?
+            .unwrap_or_else(|| to - Duration::days(DEFAULT_DAYS - 1));
+        if from > to {
+            return Err(PunktlichError::InvalidQuery("from > to".into()));
+        }
+        let subject = subject.to_uppercase();
+        let db = self.pool.get()?;
+        let ret = stats::get_report(&db, dimension, subject, from, to)?;
+        Ok(ret)
+    }
+}
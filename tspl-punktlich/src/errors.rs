@@ -0,0 +1,55 @@
+//! Error handling.
+
+pub use failure::Error;
+use failure_derive::Fail;
+use tspl_util::impl_from_for_error;
+use tspl_util::http::StatusCode;
+use tspl_sqlite::errors::{SqlError, PoolError};
+use tspl_sqlite::rusqlite::Error as RsqlError;
+use tspl_util::rpc::RpcError;
+
+/// Error that could occur when processing a request.
+#[derive(Fail, Debug)]
+pub enum PunktlichError {
+    /// The API path doesn't exist.
+    #[fail(display = "invalid path")]
+    InvalidPath,
+    /// A query string parameter was invalid.
+    #[fail(display = "invalid query parameter: {}", _0)]
+    InvalidQuery(String),
+    /// RPC error.
+    #[fail(display = "RPC: {}", _0)]
+    Rpc(RpcError),
+    /// SQL error from tspl-sqlite.
+    #[fail(display = "tspl-sqlite: {}", _0)]
+    Sql(SqlError),
+    /// SQL error from rusqlite.
+    #[fail(display = "rusqlite: {}", _0)]
+    Rsql(RsqlError),
+    /// r2d2 database error.
+    #[fail(display = "r2d2: {}", _0)]
+    Pool(PoolError)
+}
+
+impl StatusCode for PunktlichError {
+    fn status_code(&self) -> u16 {
+        use self::PunktlichError::*;
+
+        match *self {
+            InvalidPath => 400,
+            InvalidQuery(_) => 400,
+            Rpc(ref r) => r.status_code(),
+            Pool(_) => 503,
+            _ => 500
+        }
+    }
+}
+
+impl_from_for_error!(PunktlichError,
+                     RpcError => Rpc,
+                     SqlError => Sql,
+                     RsqlError => Rsql,
+                     PoolError => Pool);
+
+pub type PunktlichResult<T, E = PunktlichError> = ::std::result::Result<T, E>;
+pub type Result<T, E = Error> = ::std::result::Result<T, E>;
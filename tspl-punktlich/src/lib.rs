@@ -0,0 +1,11 @@
+//! Historic punctuality statistics, built up from tspl-zugfuhrer's live stream.
+
+pub mod errors;
+pub mod config;
+pub mod types;
+pub mod stats;
+pub mod tracker;
+pub mod ctx;
+
+#[cfg(test)]
+mod tests;
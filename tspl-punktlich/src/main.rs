@@ -0,0 +1,28 @@
+//! Historic punctuality statistics, built up from tspl-zugfuhrer's live stream.
+
+use log::*;
+use tspl_util::ConfigExt;
+use tspl_sqlite::r2d2;
+use tspl_punktlich::types;
+use tspl_punktlich::config::Config;
+use tspl_punktlich::tracker::{Tracker, LiveListener};
+use tspl_punktlich::ctx::App;
+use tspl_punktlich::errors::Result;
+use std::thread;
+
+fn main() -> Result<()> {
+    tspl_util::setup_logging()?;
+    info!("tspl-punktlich, but not yet");
+    info!("loading config");
+    let cfg = Config::load()?;
+    info!("initializing database");
+    let manager = tspl_sqlite::TsplConnectionManager::initialize(&cfg.database_path, &types::MIGRATIONS)?;
+    let pool = r2d2::Pool::new(manager)?;
+    info!("starting live stream listener");
+    let listener = LiveListener::new(Tracker::new(pool.clone(), &cfg), &cfg);
+    thread::spawn(move || {
+        listener.run();
+    });
+    let app = App::new(pool);
+    tspl_util::http::start_server(&cfg.listen, app);
+}
@@ -0,0 +1,294 @@
+//! Keeping the running totals up to date, and reading them back out.
+
+use tspl_sqlite::traits::*;
+use chrono::*;
+use std::collections::BTreeMap;
+
+use crate::types::*;
+use crate::errors::*;
+
+/// ATOC codes of operators that count as long-distance for PPM purposes
+/// (and therefore get 10 minutes' grace, instead of 5).
+pub static LONG_DISTANCE_TOCS: [&str; 6] = ["CS", "GC", "GR", "HT", "VT", "XC"];
+/// Lateness histograms lump everything later than this (in minutes) together.
+pub const MAX_LATENESS_MINS: i64 = 120;
+
+/// Returns the PPM threshold (in seconds) for trains run by `atoc_code`.
+pub fn ppm_threshold(atoc_code: Option<&str>) -> i64 {
+    match atoc_code {
+        Some(x) if LONG_DISTANCE_TOCS.contains(&x) => 10 * 60,
+        _ => 5 * 60
+    }
+}
+
+/// How a train or movement turned out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// It happened, this many seconds late (negative if early).
+    Ran(i64),
+    /// It was cancelled.
+    Cancelled
+}
+impl Outcome {
+    /// The outcome of a single movement, if it's known yet.
+    pub fn for_mvt(mvt: &StatsMvt) -> Option<Self> {
+        if mvt.canx {
+            Some(Outcome::Cancelled)
+        }
+        else {
+            mvt.lateness.map(Outcome::Ran)
+        }
+    }
+    /// The outcome of a whole train, if it's known yet.
+    ///
+    /// Trains cancelled in full or in part count as cancelled; otherwise,
+    /// the train is judged on how late it got to its destination (i.e.
+    /// the last movement in its live schedule).
+    pub fn for_train(train: &StatsTrain, mvts: &[StatsMvt]) -> Option<Self> {
+        if train.cancelled || mvts.iter().any(|x| x.canx) {
+            return Some(Outcome::Cancelled);
+        }
+        mvts.iter()
+            .max_by_key(|x| (x.day_offset, x.time, x.action))
+            .and_then(|x| x.lateness)
+            .map(Outcome::Ran)
+    }
+}
+
+/// One row's worth of contribution to the running totals.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Contribution {
+    pub dimension: Dimension,
+    pub subject: String,
+    pub date: NaiveDate,
+    pub outcome: Outcome,
+    /// PPM threshold, in seconds.
+    pub ppm_threshold: i64
+}
+
+/// Works out what a train (and its movements) contribute to the running totals.
+pub fn contributions(train: &StatsTrain, mvts: &[StatsMvt]) -> Vec<Contribution> {
+    let threshold = ppm_threshold(train.atoc_code.as_ref().map(|x| x as &str));
+    let mut ret = vec![];
+    if let Some(outcome) = Outcome::for_train(train, mvts) {
+        let mut subjects = vec![
+            (Dimension::Uid, train.uid.clone()),
+            (Dimension::Day, String::new())
+        ];
+        if let Some(ref hc) = train.headcode {
+            subjects.push((Dimension::Headcode, hc.clone()));
+        }
+        if let Some(ref toc) = train.atoc_code {
+            subjects.push((Dimension::Toc, toc.clone()));
+        }
+        for (dimension, subject) in subjects {
+            ret.push(Contribution {
+                dimension,
+                subject,
+                date: train.date,
+                outcome,
+                ppm_threshold: threshold
+            });
+        }
+    }
+    for mvt in mvts {
+        if let Some(outcome) = Outcome::for_mvt(mvt) {
+            ret.push(Contribution {
+                dimension: Dimension::Tiploc,
+                subject: mvt.tiploc.clone(),
+                date: train.date,
+                outcome,
+                ppm_threshold: threshold
+            });
+        }
+    }
+    ret
+}
+
+/// Adds (`sign` = 1) or removes (`sign` = -1) a contribution from the
+/// running totals.
+pub fn apply_contribution(conn: &Connection, c: &Contribution, sign: i64) -> PunktlichResult<()> {
+    let (observed, on_time, ppm, cancelled, lateness) = match c.outcome {
+        Outcome::Ran(l) => {
+            let l = ::std::cmp::max(l, 0);
+            (1, (l < 60) as i64, (l < c.ppm_threshold) as i64, 0, Some(l))
+        },
+        Outcome::Cancelled => (0, 0, 0, 1, None)
+    };
+    let dim = c.dimension.as_str();
+    conn.execute("INSERT INTO stats
+                  (dimension, subject, date, observed, on_time, ppm, cancelled, lateness_total)
+                  VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+                  ON CONFLICT (dimension, subject, date) DO UPDATE SET
+                    observed = observed + excluded.observed,
+                    on_time = on_time + excluded.on_time,
+                    ppm = ppm + excluded.ppm,
+                    cancelled = cancelled + excluded.cancelled,
+                    lateness_total = lateness_total + excluded.lateness_total",
+                 params![dim, c.subject, c.date, observed * sign, on_time * sign,
+                         ppm * sign, cancelled * sign, lateness.unwrap_or(0) * sign])?;
+    if let Some(l) = lateness {
+        let mins = ::std::cmp::min(l / 60, MAX_LATENESS_MINS);
+        conn.execute("INSERT INTO lateness_mins
+                      (dimension, subject, date, minutes, count)
+                      VALUES (?, ?, ?, ?, ?)
+                      ON CONFLICT (dimension, subject, date, minutes) DO UPDATE SET
+                        count = count + excluded.count",
+                     params![dim, c.subject, c.date, mins, sign])?;
+    }
+    Ok(())
+}
+
+/// Works out which contributions need to be removed from, and added to,
+/// the running totals to get from `old` to `new`.
+///
+/// The same contribution can turn up more than once (e.g. an arrival and
+/// departure at the same place, with the same lateness), so these are
+/// compared as multisets: each contribution in `new` cancels out at most
+/// one identical contribution in `old`.
+pub fn diff_contributions<'a>(old: &'a [Contribution], new: &'a [Contribution]) -> (Vec<&'a Contribution>, Vec<&'a Contribution>) {
+    let mut removed = old.iter().collect::<Vec<_>>();
+    let mut added = vec![];
+    for c in new {
+        match removed.iter().position(|x| *x == c) {
+            Some(i) => {
+                removed.swap_remove(i);
+            },
+            None => added.push(c)
+        }
+    }
+    (removed, added)
+}
+
+/// Replaces the contributions in `old` with those in `new`.
+pub fn replace_contributions(conn: &Connection, old: &[Contribution], new: &[Contribution]) -> PunktlichResult<()> {
+    // Don't bother touching rows that haven't changed.
+    let (removed, added) = diff_contributions(old, new);
+    for c in removed {
+        apply_contribution(conn, c, -1)?;
+    }
+    for c in added {
+        apply_contribution(conn, c, 1)?;
+    }
+    Ok(())
+}
+
+/// Raw running totals, summed over some number of days.
+#[derive(Default)]
+pub(crate) struct Totals {
+    pub(crate) observed: i64,
+    pub(crate) on_time: i64,
+    pub(crate) ppm: i64,
+    pub(crate) cancelled: i64,
+    pub(crate) lateness_total: i64,
+    pub(crate) lateness_mins: BTreeMap<i64, i64>
+}
+impl Totals {
+    pub(crate) fn add(&mut self, other: &Totals) {
+        self.observed += other.observed;
+        self.on_time += other.on_time;
+        self.ppm += other.ppm;
+        self.cancelled += other.cancelled;
+        self.lateness_total += other.lateness_total;
+        for (mins, count) in other.lateness_mins.iter() {
+            *self.lateness_mins.entry(*mins).or_insert(0) += *count;
+        }
+    }
+    /// Returns the smallest number of minutes late that at least `p` of
+    /// the observed things were within.
+    pub(crate) fn percentile(&self, p: f64) -> Option<u32> {
+        if self.observed <= 0 {
+            return None;
+        }
+        let target = (p * self.observed as f64).ceil() as i64;
+        let mut seen = 0;
+        for (mins, count) in self.lateness_mins.iter() {
+            seen += *count;
+            if seen >= target {
+                return Some(*mins as u32);
+            }
+        }
+        None
+    }
+    pub(crate) fn summarize(&self) -> PunctualitySummary {
+        let planned = self.observed + self.cancelled;
+        let pct = |x: i64| {
+            if planned > 0 {
+                Some(x as f64 * 100.0 / planned as f64)
+            }
+            else {
+                None
+            }
+        };
+        PunctualitySummary {
+            planned: planned as _,
+            observed: self.observed as _,
+            cancelled: self.cancelled as _,
+            on_time_pct: pct(self.on_time),
+            ppm_pct: pct(self.ppm),
+            cancelled_pct: pct(self.cancelled),
+            avg_lateness_secs: if self.observed > 0 {
+                Some(self.lateness_total as f64 / self.observed as f64)
+            }
+            else {
+                None
+            },
+            median_lateness_mins: self.percentile(0.5),
+            p90_lateness_mins: self.percentile(0.9),
+            p95_lateness_mins: self.percentile(0.95)
+        }
+    }
+}
+
+/// Builds a punctuality report for `subject` between `from` and `to` (inclusive).
+pub fn get_report(conn: &Connection, dimension: Dimension, subject: String, from: NaiveDate, to: NaiveDate) -> PunktlichResult<PunctualityReport> {
+    let dim = dimension.as_str();
+    let mut days: BTreeMap<NaiveDate, Totals> = BTreeMap::new();
+    let mut stmt = conn.prepare("SELECT date, observed, on_time, ppm, cancelled, lateness_total
+                                 FROM stats
+                                 WHERE dimension = ? AND subject = ? AND date BETWEEN ? AND ?")?;
+    let rows = stmt.query_map(params![dim, subject, from, to], |row| -> RowResult<(NaiveDate, Totals)> {
+        Ok((row.get(0)?, Totals {
+            observed: row.get(1)?,
+            on_time: row.get(2)?,
+            ppm: row.get(3)?,
+            cancelled: row.get(4)?,
+            lateness_total: row.get(5)?,
+            lateness_mins: BTreeMap::new()
+        }))
+    })?;
+    for row in rows {
+        let (date, totals) = row?;
+        days.insert(date, totals);
+    }
+    let mut stmt = conn.prepare("SELECT date, minutes, count
+                                 FROM lateness_mins
+                                 WHERE dimension = ? AND subject = ? AND date BETWEEN ? AND ?
+                                 AND count > 0")?;
+    let rows = stmt.query_map(params![dim, subject, from, to], |row| -> RowResult<(NaiveDate, i64, i64)> {
+        Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+    })?;
+    for row in rows {
+        let (date, mins, count) = row?;
+        let t = days.entry(date).or_insert_with(Totals::default);
+        t.lateness_mins.insert(mins, count);
+    }
+    let mut total = Totals::default();
+    let mut ret = vec![];
+    for (date, t) in days {
+        if t.observed + t.cancelled <= 0 {
+            // Everything that happened on this day got corrected away.
+            continue;
+        }
+        total.add(&t);
+        ret.push((date, t.summarize()));
+    }
+    Ok(PunctualityReport {
+        dimension,
+        subject,
+        from,
+        to,
+        total: total.summarize(),
+        days: ret
+    })
+}
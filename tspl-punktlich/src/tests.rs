@@ -0,0 +1,201 @@
+use chrono::*;
+use tspl_sqlite::traits::Uuid;
+use tspl_zugfuhrer::types::TrainMvt;
+
+use crate::types::*;
+use crate::stats::*;
+use crate::tracker::lateness;
+
+fn train(atoc_code: Option<&str>, cancelled: bool) -> StatsTrain {
+    StatsTrain {
+        id: 1,
+        tspl_id: Uuid::nil(),
+        date: NaiveDate::from_ymd(2019, 05, 01),
+        uid: "C12345".into(),
+        headcode: Some("1A23".into()),
+        atoc_code: atoc_code.map(|x| x.into()),
+        cancelled
+    }
+}
+fn smvt(tiploc: &str, action: u8, time: NaiveTime, canx: bool, lateness: Option<i64>) -> StatsMvt {
+    StatsMvt {
+        id: 1,
+        parent_train: 1,
+        tiploc: tiploc.into(),
+        action,
+        time,
+        public_time: None,
+        day_offset: 0,
+        canx,
+        lateness
+    }
+}
+fn tmvt(time: NaiveTime, public_time: Option<NaiveTime>, day_offset: u8) -> TrainMvt {
+    TrainMvt {
+        id: 2,
+        parent_train: 1,
+        updates: Some(1),
+        tiploc: "EUSTON".into(),
+        action: 0,
+        actual: true,
+        time,
+        public_time,
+        day_offset,
+        source: TrainMvt::SOURCE_TRUST,
+        platform: None,
+        pfm_suppr: false,
+        unknown_delay: false,
+        canx: false,
+        canx_reason: None,
+        allowance: 0,
+        canx_source: None
+    }
+}
+fn contribution(subject: &str, outcome: Outcome) -> Contribution {
+    Contribution {
+        dimension: Dimension::Tiploc,
+        subject: subject.into(),
+        date: NaiveDate::from_ymd(2019, 05, 01),
+        outcome,
+        ppm_threshold: 300
+    }
+}
+
+#[test]
+fn lateness_simple() {
+    let sched = smvt("EUSTON", 0, NaiveTime::from_hms(12, 00, 00), false, None);
+    let actual = tmvt(NaiveTime::from_hms(12, 03, 30), None, 0);
+    assert_eq!(lateness(&sched, &actual), 210);
+    let actual = tmvt(NaiveTime::from_hms(11, 59, 00), None, 0);
+    assert_eq!(lateness(&sched, &actual), -60);
+}
+#[test]
+fn lateness_prefers_public_times() {
+    let mut sched = smvt("EUSTON", 0, NaiveTime::from_hms(11, 58, 30), false, None);
+    sched.public_time = Some(NaiveTime::from_hms(12, 00, 00));
+    let actual = tmvt(NaiveTime::from_hms(12, 04, 30), Some(NaiveTime::from_hms(12, 05, 00)), 0);
+    assert_eq!(lateness(&sched, &actual), 300);
+}
+#[test]
+fn lateness_midnight_wrap() {
+    // Late past midnight, without the day offset changing.
+    let sched = smvt("EUSTON", 0, NaiveTime::from_hms(23, 58, 00), false, None);
+    let actual = tmvt(NaiveTime::from_hms(00, 03, 00), None, 0);
+    assert_eq!(lateness(&sched, &actual), 300);
+    // ...and with it changing.
+    let actual = tmvt(NaiveTime::from_hms(00, 03, 00), None, 1);
+    assert_eq!(lateness(&sched, &actual), 300);
+    // Early, the other way round.
+    let sched = smvt("EUSTON", 0, NaiveTime::from_hms(00, 02, 00), false, None);
+    let actual = tmvt(NaiveTime::from_hms(23, 59, 00), None, 0);
+    assert_eq!(lateness(&sched, &actual), -180);
+}
+#[test]
+fn ppm_thresholds() {
+    assert_eq!(ppm_threshold(Some("VT")), 600);
+    assert_eq!(ppm_threshold(Some("XC")), 600);
+    assert_eq!(ppm_threshold(Some("GR")), 600);
+    assert_eq!(ppm_threshold(Some("LM")), 300);
+    assert_eq!(ppm_threshold(None), 300);
+}
+#[test]
+fn contributions_use_long_distance_threshold() {
+    let mvts = vec![smvt("EUSTON", 0, NaiveTime::from_hms(12, 00, 00), false, Some(420))];
+    let cs = contributions(&train(Some("VT"), false), &mvts);
+    assert!(cs.iter().all(|c| c.ppm_threshold == 600));
+    let cs = contributions(&train(Some("LM"), false), &mvts);
+    assert!(cs.iter().all(|c| c.ppm_threshold == 300));
+}
+#[test]
+fn percentiles() {
+    let mut totals = Totals::default();
+    assert_eq!(totals.percentile(0.5), None);
+    totals.observed = 10;
+    totals.lateness_mins.insert(0, 5);
+    totals.lateness_mins.insert(2, 3);
+    totals.lateness_mins.insert(15, 2);
+    assert_eq!(totals.percentile(0.5), Some(0));
+    assert_eq!(totals.percentile(0.6), Some(2));
+    assert_eq!(totals.percentile(0.8), Some(2));
+    assert_eq!(totals.percentile(0.9), Some(15));
+    assert_eq!(totals.percentile(0.95), Some(15));
+    let summary = totals.summarize();
+    assert_eq!(summary.median_lateness_mins, Some(0));
+    assert_eq!(summary.p90_lateness_mins, Some(15));
+}
+#[test]
+fn outcome_whole_train_cancelled() {
+    let mvts = vec![smvt("EUSTON", 1, NaiveTime::from_hms(12, 00, 00), false, Some(0))];
+    assert_eq!(Outcome::for_train(&train(None, true), &mvts), Some(Outcome::Cancelled));
+}
+#[test]
+fn outcome_part_cancelled() {
+    let mvts = vec![
+        smvt("EUSTON", 1, NaiveTime::from_hms(12, 00, 00), false, Some(0)),
+        smvt("WATFDJ", 0, NaiveTime::from_hms(12, 15, 00), true, None)
+    ];
+    assert_eq!(Outcome::for_train(&train(None, false), &mvts), Some(Outcome::Cancelled));
+}
+#[test]
+fn outcome_uses_destination() {
+    let mvts = vec![
+        smvt("WATFDJ", 0, NaiveTime::from_hms(12, 15, 00), false, Some(600)),
+        smvt("EUSTON", 1, NaiveTime::from_hms(12, 00, 00), false, Some(0))
+    ];
+    assert_eq!(Outcome::for_train(&train(None, false), &mvts), Some(Outcome::Ran(600)));
+    let mvts = vec![
+        smvt("EUSTON", 1, NaiveTime::from_hms(12, 00, 00), false, Some(0)),
+        smvt("WATFDJ", 0, NaiveTime::from_hms(12, 15, 00), false, None)
+    ];
+    assert_eq!(Outcome::for_train(&train(None, false), &mvts), None);
+}
+#[test]
+fn contributions_cancelled() {
+    let mvts = vec![
+        smvt("EUSTON", 1, NaiveTime::from_hms(12, 00, 00), false, Some(0)),
+        smvt("WATFDJ", 0, NaiveTime::from_hms(12, 15, 00), true, None),
+        smvt("MKC", 0, NaiveTime::from_hms(12, 45, 00), false, None)
+    ];
+    let cs = contributions(&train(Some("LM"), false), &mvts);
+    // uid, day, headcode and TOC for the train, plus two of the movements.
+    assert_eq!(cs.len(), 6);
+    assert!(cs.iter()
+            .filter(|c| c.dimension != Dimension::Tiploc)
+            .all(|c| c.outcome == Outcome::Cancelled));
+    let tiplocs = cs.iter()
+        .filter(|c| c.dimension == Dimension::Tiploc)
+        .map(|c| (c.subject.as_str(), c.outcome))
+        .collect::<Vec<_>>();
+    assert_eq!(tiplocs, vec![("EUSTON", Outcome::Ran(0)), ("WATFDJ", Outcome::Cancelled)]);
+}
+#[test]
+fn diff_contributions_unchanged() {
+    let a = contribution("EUSTON", Outcome::Ran(0));
+    let b = contribution("WATFDJ", Outcome::Ran(60));
+    let (removed, added) = diff_contributions(&[a.clone(), b.clone()], &[b, a]);
+    assert!(removed.is_empty());
+    assert!(added.is_empty());
+}
+#[test]
+fn diff_contributions_changed() {
+    let a = contribution("EUSTON", Outcome::Ran(0));
+    let b = contribution("WATFDJ", Outcome::Ran(60));
+    let b2 = contribution("WATFDJ", Outcome::Cancelled);
+    let (removed, added) = diff_contributions(&[a.clone(), b.clone()], &[a, b2.clone()]);
+    assert_eq!(removed, vec![&b]);
+    assert_eq!(added, vec![&b2]);
+}
+#[test]
+fn diff_contributions_duplicates() {
+    // The arrival and departure at the same place contribute the same thing.
+    let a = contribution("EUSTON", Outcome::Ran(0));
+    let (removed, added) = diff_contributions(&[a.clone()], &[a.clone(), a.clone()]);
+    assert!(removed.is_empty());
+    assert_eq!(added, vec![&a]);
+    let (removed, added) = diff_contributions(&[a.clone(), a.clone()], &[a.clone()]);
+    assert_eq!(removed, vec![&a]);
+    assert!(added.is_empty());
+    let (removed, added) = diff_contributions(&[a.clone(), a.clone()], &[a.clone(), a.clone()]);
+    assert!(removed.is_empty());
+    assert!(added.is_empty());
+}
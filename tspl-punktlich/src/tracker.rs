@@ -0,0 +1,253 @@
+//! Following tspl-zugfuhrer's live stream, and keeping statistics up to date.
+
+use tspl_sqlite::TsplPool;
+use tspl_sqlite::traits::*;
+use tspl_util::rpc::{MicroserviceRpc, RpcError};
+use tspl_util::user_agent;
+use tspl_zugfuhrer::types::{Train, TrainMvt, TrainDetails, BroadcastPacket, BroadcastUpdate};
+use tspl_fahrplan::types as fpt;
+use reqwest::Method;
+use std::net::TcpStream;
+use std::io::{BufRead, BufReader};
+use std::time::Duration;
+use std::thread;
+use log::*;
+
+use crate::config::Config;
+use crate::stats;
+use crate::types::*;
+use crate::errors::*;
+
+/// Works out how late `actual` was, relative to the scheduled movement
+/// `sched`, in seconds.
+///
+/// Public times are compared where both movements have them, since those
+/// are what passengers care about; otherwise, working times are used.
+pub(crate) fn lateness(sched: &StatsMvt, actual: &TrainMvt) -> i64 {
+    let (planned, actual_time) = match (sched.public_time, actual.public_time) {
+        (Some(p), Some(a)) => (p, a),
+        _ => (sched.time, actual.time)
+    };
+    let days = actual.day_offset as i64 - sched.day_offset as i64;
+    let mut ret = actual_time.signed_duration_since(planned).num_seconds() + days * 86400;
+    // Deal with things going over midnight without the day offset
+    // reflecting that.
+    if ret > 43200 {
+        ret -= 86400;
+    }
+    if ret < -43200 {
+        ret += 86400;
+    }
+    ret
+}
+
+fn mvt_from_tmvt(tmvt: &TrainMvt) -> StatsMvt {
+    StatsMvt {
+        id: tmvt.id,
+        parent_train: tmvt.parent_train,
+        tiploc: tmvt.tiploc.clone(),
+        action: tmvt.action,
+        time: tmvt.time,
+        public_time: tmvt.public_time,
+        day_offset: tmvt.day_offset,
+        canx: tmvt.canx,
+        lateness: None
+    }
+}
+
+/// Loads a train and its movements, and works out what it's contributing
+/// to the running totals.
+fn load_contributions(conn: &Connection, id: i64) -> Result<Vec<stats::Contribution>> {
+    let train = match StatsTrain::from_select(conn, "WHERE id = ?", &[&id])?.into_iter().nth(0) {
+        Some(t) => t,
+        None => return Ok(vec![])
+    };
+    let mvts = StatsMvt::from_select(conn, "WHERE parent_train = ?", &[&id])?;
+    Ok(stats::contributions(&train, &mvts))
+}
+
+pub struct Tracker {
+    pool: TsplPool,
+    zrpc: MicroserviceRpc,
+    frpc: MicroserviceRpc
+}
+impl Tracker {
+    pub fn new(pool: TsplPool, cfg: &Config) -> Self {
+        let zrpc = MicroserviceRpc::new(user_agent!(), "zugfuhrer", cfg.service_zugfuhrer.clone());
+        let frpc = MicroserviceRpc::new(user_agent!(), "fahrplan", cfg.service_fahrplan.clone());
+        Self { pool, zrpc, frpc }
+    }
+    /// Runs `func` inside a transaction, replacing whatever train `id`
+    /// was contributing to the running totals beforehand with whatever it
+    /// contributes afterwards.
+    fn with_train<F>(&self, id: i64, func: F) -> Result<()> where F: FnOnce(&Connection) -> Result<()> {
+        let mut conn = self.pool.get()?;
+        let trans = conn.transaction()?;
+        let old = load_contributions(&trans, id)?;
+        func(&*trans)?;
+        let new = load_contributions(&trans, id)?;
+        stats::replace_contributions(&trans, &old, &new)?;
+        trans.commit()?;
+        Ok(())
+    }
+    /// Asks tspl-fahrplan which operator runs `train`.
+    fn get_atoc_code(&self, train: &Train) -> Option<String> {
+        let uri = format!("/schedules/for-activation/{}/{}/{}/{}", train.parent_uid,
+                          train.parent_start_date, train.parent_stp_indicator,
+                          train.parent_source);
+        match self.frpc.req::<_, fpt::Schedule>(Method::GET, uri) {
+            Ok(s) => s.meta.atoc_code,
+            Err(e) => {
+                warn!("Couldn't get schedule for train {}: {}", train.tspl_id, e);
+                None
+            }
+        }
+    }
+    /// Fetches the full details of `train` from tspl-zugfuhrer, and
+    /// replaces whatever we knew about it with them.
+    ///
+    /// This happens when a train is activated, and when we (re)connect to
+    /// the live stream (in case we missed anything while disconnected).
+    fn refresh_train(&self, train: Train) -> Result<()> {
+        let details: TrainDetails = match self.zrpc.req(Method::GET, format!("/train/{}", train.tspl_id)) {
+            Ok(d) => d,
+            Err(RpcError::RemoteNotFound) => {
+                warn!("Train {} disappeared before we could get its details", train.tspl_id);
+                return Ok(());
+            },
+            Err(e) => Err(e)?
+        };
+        let existing = {
+            let conn = self.pool.get()?;
+            StatsTrain::from_select(&conn, "WHERE id = ?", &[&train.id])?
+                .into_iter().nth(0)
+        };
+        let atoc_code = match existing.and_then(|x| x.atoc_code) {
+            Some(x) => Some(x),
+            None => self.get_atoc_code(&details.train)
+        };
+        let strain = StatsTrain {
+            id: details.train.id,
+            tspl_id: details.train.tspl_id,
+            date: details.train.date,
+            uid: details.train.parent_uid.clone(),
+            headcode: details.train.headcode.clone(),
+            atoc_code,
+            cancelled: details.train.cancelled
+        };
+        let mut mvts = details.mvts.iter()
+            .filter(|x| x.updates.is_none())
+            .map(mvt_from_tmvt)
+            .collect::<Vec<_>>();
+        for actual in details.mvts.iter().filter(|x| x.actual) {
+            if let Some(mvt) = mvts.iter_mut().find(|x| Some(x.id) == actual.updates) {
+                mvt.lateness = Some(lateness(mvt, actual));
+            }
+        }
+        debug!("Refreshing train {} ({} live movements)", strain.tspl_id, mvts.len());
+        self.with_train(strain.id, |conn| {
+            conn.execute("DELETE FROM schedule_mvts WHERE parent_train = ?", params![strain.id])?;
+            strain.insert_self(conn)?;
+            for mvt in mvts.iter() {
+                mvt.insert_self(conn)?;
+            }
+            Ok(())
+        })
+    }
+    /// Deals with a single movement from the live stream.
+    fn process_mvt(&self, tmvt: TrainMvt) -> Result<()> {
+        let known = {
+            let conn = self.pool.get()?;
+            !StatsTrain::from_select(&conn, "WHERE id = ?", &[&tmvt.parent_train])?.is_empty()
+        };
+        if !known {
+            debug!("Ignoring movement #{} for unknown train #{}", tmvt.id, tmvt.parent_train);
+            return Ok(());
+        }
+        match tmvt.updates {
+            None => {
+                // A new or changed movement in the live schedule; keep
+                // any lateness we already had for it.
+                self.with_train(tmvt.parent_train, |conn| {
+                    let lateness = StatsMvt::from_select(conn, "WHERE id = ?", &[&tmvt.id])?
+                        .into_iter().nth(0)
+                        .and_then(|x| x.lateness);
+                    let mut mvt = mvt_from_tmvt(&tmvt);
+                    mvt.lateness = lateness;
+                    mvt.insert_self(conn)?;
+                    Ok(())
+                })
+            },
+            Some(orig) if tmvt.actual => {
+                self.with_train(tmvt.parent_train, |conn| {
+                    let sched = StatsMvt::from_select(conn, "WHERE id = ?", &[&orig])?
+                        .into_iter().nth(0);
+                    if let Some(sched) = sched {
+                        conn.execute("UPDATE schedule_mvts SET lateness = ? WHERE id = ?",
+                                     params![lateness(&sched, &tmvt), sched.id])?;
+                    }
+                    Ok(())
+                })
+            },
+            // Estimates don't tell us anything about punctuality.
+            _ => Ok(())
+        }
+    }
+    /// Deals with a packet from the live stream.
+    pub fn process_packet(&self, pkt: BroadcastPacket) -> Result<()> {
+        match pkt.update {
+            BroadcastUpdate::Activation(train) | BroadcastUpdate::SyncTrain(train) => {
+                self.refresh_train(train)
+            },
+            BroadcastUpdate::Movement(tmvt) => {
+                self.process_mvt(tmvt)
+            }
+        }
+    }
+}
+
+/// Connects to tspl-zugfuhrer's live stream, and feeds everything from it
+/// into a `Tracker`, reconnecting if the connection drops.
+pub struct LiveListener {
+    tracker: Tracker,
+    addr: String,
+    reconnect: Duration
+}
+impl LiveListener {
+    pub fn new(tracker: Tracker, cfg: &Config) -> Self {
+        Self {
+            tracker,
+            addr: cfg.service_zugfuhrer_live.clone(),
+            reconnect: Duration::from_secs(cfg.reconnect_secs.unwrap_or(10))
+        }
+    }
+    pub fn run(self) {
+        loop {
+            if let Err(e) = self.run_once() {
+                error!("Live stream from {} failed: {}", self.addr, e);
+            }
+            info!("Reconnecting to live stream in {}s", self.reconnect.as_secs());
+            thread::sleep(self.reconnect);
+        }
+    }
+    fn run_once(&self) -> Result<()> {
+        info!("Connecting to live stream at {}", self.addr);
+        let stream = TcpStream::connect(&self.addr)?;
+        let reader = BufReader::new(stream);
+        for line in reader.lines() {
+            let line = line?;
+            let pkt: BroadcastPacket = match serde_json::from_str(&line) {
+                Ok(p) => p,
+                Err(e) => {
+                    warn!("Failed to parse live stream packet: {}", e);
+                    continue;
+                }
+            };
+            if let Err(e) = self.tracker.process_packet(pkt) {
+                warn!("Failed to process live stream packet: {}", e);
+            }
+        }
+        warn!("Live stream from {} closed", self.addr);
+        Ok(())
+    }
+}
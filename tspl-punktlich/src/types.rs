@@ -0,0 +1,189 @@
+//! Database and API types.
+
+use tspl_sqlite::traits::*;
+use tspl_sqlite::migrations::Migration;
+use tspl_sqlite::migration;
+use serde_derive::{Serialize, Deserialize};
+use chrono::*;
+
+pub static MIGRATIONS: [Migration; 1] = [
+    migration!(0, "initial")
+];
+
+/// A train, as far as punctuality is concerned.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StatsTrain {
+    /// tspl-zugfuhrer's internal ID for the train.
+    pub id: i64,
+    /// Train's trainsplorer ID.
+    pub tspl_id: Uuid,
+    /// Date this train was running on.
+    pub date: NaiveDate,
+    /// Parent schedule UID.
+    pub uid: String,
+    /// Headcode / signalling ID, if there is one.
+    pub headcode: Option<String>,
+    /// ATOC code of the operator, if known.
+    pub atoc_code: Option<String>,
+    /// Whether or not this whole train has been cancelled.
+    pub cancelled: bool
+}
+impl DbType for StatsTrain {
+    fn table_name() -> &'static str {
+        "trains"
+    }
+    fn from_row(row: &Row, s: usize) -> RowResult<Self> {
+        Ok(Self {
+            id: row.get(s + 0)?,
+            tspl_id: row.get(s + 1)?,
+            date: row.get(s + 2)?,
+            uid: row.get(s + 3)?,
+            headcode: row.get(s + 4)?,
+            atoc_code: row.get(s + 5)?,
+            cancelled: row.get(s + 6)?
+        })
+    }
+}
+impl InsertableDbType for StatsTrain {
+    type Id = ();
+    fn insert_self(&self, conn: &Connection) -> RowResult<()> {
+        let mut stmt = conn.prepare("INSERT OR REPLACE INTO trains
+                                     (id, tspl_id, date, uid, headcode, atoc_code, cancelled)
+                                     VALUES (?, ?, ?, ?, ?, ?, ?)")?;
+        stmt.insert(params![self.id, self.tspl_id, self.date, self.uid,
+                            self.headcode, self.atoc_code, self.cancelled])?;
+        Ok(())
+    }
+}
+
+/// A movement in a train's live schedule, and how late it was (if it's
+/// happened yet).
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StatsMvt {
+    /// tspl-zugfuhrer's internal ID for the movement.
+    pub id: i64,
+    /// Train this is a part of.
+    pub parent_train: i64,
+    /// Timing Point Location where this movement happens.
+    pub tiploc: String,
+    /// What happens here (see `TrainMvt::action`).
+    pub action: u8,
+    /// Scheduled (working) time.
+    pub time: NaiveTime,
+    /// Scheduled public (GBTT) time, if any.
+    pub public_time: Option<NaiveTime>,
+    /// Scheduled day offset.
+    pub day_offset: u8,
+    /// Whether this movement has been cancelled.
+    pub canx: bool,
+    /// How late the movement actually happened, in seconds (negative if early).
+    pub lateness: Option<i64>
+}
+impl DbType for StatsMvt {
+    fn table_name() -> &'static str {
+        "schedule_mvts"
+    }
+    fn from_row(row: &Row, s: usize) -> RowResult<Self> {
+        Ok(Self {
+            id: row.get(s + 0)?,
+            parent_train: row.get(s + 1)?,
+            tiploc: row.get(s + 2)?,
+            action: row.get(s + 3)?,
+            time: row.get(s + 4)?,
+            public_time: row.get(s + 5)?,
+            day_offset: row.get(s + 6)?,
+            canx: row.get(s + 7)?,
+            lateness: row.get(s + 8)?
+        })
+    }
+}
+impl InsertableDbType for StatsMvt {
+    type Id = ();
+    fn insert_self(&self, conn: &Connection) -> RowResult<()> {
+        let mut stmt = conn.prepare("INSERT OR REPLACE INTO schedule_mvts
+                                     (id, parent_train, tiploc, action, time,
+                                      public_time, day_offset, canx, lateness)
+                                     VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)")?;
+        stmt.insert(params![self.id, self.parent_train, self.tiploc, self.action,
+                            self.time, self.public_time, self.day_offset,
+                            self.canx, self.lateness])?;
+        Ok(())
+    }
+}
+
+/// What statistics are broken down by.
+///
+/// Statistics for TIPLOCs are about individual movements (did the train
+/// get there on time?), whereas the rest are about whole trains (did
+/// the train get to its destination on time?).
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Dimension {
+    /// By schedule UID.
+    Uid,
+    /// By headcode.
+    Headcode,
+    /// By station / timing point.
+    Tiploc,
+    /// By operator (ATOC code).
+    Toc,
+    /// All trains on each day.
+    Day
+}
+impl Dimension {
+    /// The name this dimension is stored under in the database.
+    pub fn as_str(&self) -> &'static str {
+        use self::Dimension::*;
+
+        match *self {
+            Uid => "uid",
+            Headcode => "headcode",
+            Tiploc => "tiploc",
+            Toc => "toc",
+            Day => "day"
+        }
+    }
+}
+
+/// Punctuality statistics for some set of trains or movements.
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct PunctualitySummary {
+    /// Number of things that were due to happen (i.e. `observed + cancelled`).
+    pub planned: u32,
+    /// Number of things that happened, and that we have an actual time for.
+    pub observed: u32,
+    /// Number of things that were cancelled (for trains, including trains
+    /// cancelled part of the way).
+    pub cancelled: u32,
+    /// Percentage of planned things that happened less than a minute late.
+    pub on_time_pct: Option<f64>,
+    /// Percentage of planned things that happened within the PPM threshold
+    /// (5 minutes, or 10 for long-distance operators).
+    pub ppm_pct: Option<f64>,
+    /// Percentage of planned things that were cancelled.
+    pub cancelled_pct: Option<f64>,
+    /// Average lateness of observed things, in seconds (early running counts
+    /// as zero).
+    pub avg_lateness_secs: Option<f64>,
+    /// Median lateness, in whole minutes.
+    pub median_lateness_mins: Option<u32>,
+    /// 90th percentile lateness, in whole minutes.
+    pub p90_lateness_mins: Option<u32>,
+    /// 95th percentile lateness, in whole minutes.
+    pub p95_lateness_mins: Option<u32>
+}
+
+/// Punctuality statistics for a given dimension and subject (e.g. a UID or
+/// TIPLOC), over a date range.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PunctualityReport {
+    pub dimension: Dimension,
+    pub subject: String,
+    /// First date included.
+    pub from: NaiveDate,
+    /// Last date included.
+    pub to: NaiveDate,
+    /// Statistics for the whole date range.
+    pub total: PunctualitySummary,
+    /// Statistics for each day in the range that had something happen.
+    pub days: Vec<(NaiveDate, PunctualitySummary)>
+}
@@ -8,7 +8,21 @@ use log::*;
 
 #[macro_export]
 macro_rules! extract_headers {
-    ($from:ident, $err:expr, 
+    ($from:ident, $err:expr,
+     $(opt $ovar:ident: $oty:ty => $oheader_name:literal),+
+    ) => {
+        $(
+            let $ovar: Option<$oty> = match $from.header(concat!("X-tspl-", $oheader_name)) {
+                Some(h) => {
+                    Some(h
+                         .parse()
+                         .map_err(|_| $err)?)
+                },
+                None => None
+            };
+        )*
+    };
+    ($from:ident, $err:expr,
      $(let $var:ident: $ty:ty => $header_name:literal),*
      $(,opt $ovar:ident: $oty:ty => $oheader_name:literal)*
     ) => {
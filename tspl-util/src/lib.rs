@@ -19,6 +19,9 @@ pub mod http;
 pub mod nrod;
 pub mod time;
 
+#[cfg(test)]
+mod tests;
+
 #[macro_export]
 macro_rules! user_agent {
     () => { format!("{}/{}", $crate::crate_name!(), env!("CARGO_PKG_VERSION")) }
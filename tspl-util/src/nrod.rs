@@ -26,14 +26,26 @@ pub struct NrodDownloader {
     base_url: String,
     cli: Client,
 }
-// FIXME(perf): Yes, two layers of buffering.
+/// Size of the buffer between the network and the gzip decoder.
+///
+/// `GzDecoder` reads in whatever chunks it gets given, so a bigger buffer
+/// here means fewer (and larger) reads from the socket.
+pub static NETWORK_BUFFER_SIZE: usize = 64 * 1024;
+/// Size of the buffer for decompressed data.
+///
+/// Schedule records are a few KiB each, so this fits plenty of them.
+pub static LINE_BUFFER_SIZE: usize = 256 * 1024;
+
+// Yes, there are two layers of buffering here, and both are needed:
 //
-// - The first layer of buffering is to avoid copious syscalls
-//   when reading from the network. Not sure whether it's really needed.
-// - The second layer of buffering is required by `apply_schedule_records`,
-//   which needs to call read_line().
+// - `flate2::bufread::GzDecoder` wants a `BufRead` to read compressed data
+//   from (and it'd only wrap a `read::GzDecoder` in a `BufReader` internally
+//   anyway), so the network side has to be buffered.
+// - The schedule importer calls `read_line()` on the decompressed data,
+//   which needs a `BufRead` too.
 //
-// I really don't know how performant this is. My guess is "not very"???
+// The default 8 KiB buffers make for lots of small reads, though, so both
+// get explicit (bigger) sizes.
 pub type ResponseReader = BufReader<GzDecoder<BufReader<Response>>>;
 
 impl NrodDownloader {
@@ -57,7 +69,8 @@ impl NrodDownloader {
         if !st.is_success() {
             Err(NrodDownloadError::StatusCode(st.as_u16()))?
         }
-        let resp = BufReader::new(GzDecoder::new(BufReader::new(resp)));
+        let resp = BufReader::with_capacity(NETWORK_BUFFER_SIZE, resp);
+        let resp = BufReader::with_capacity(LINE_BUFFER_SIZE, GzDecoder::new(resp));
         Ok(resp)
     }
 }
@@ -1,6 +1,6 @@
 //! Handling remote procedure calls (RPC) to other microservices.
 
-use reqwest::Client;
+use reqwest::{Client, Response};
 use reqwest::Error as ReqwestError;
 use reqwest::header::HeaderMap;
 pub use reqwest::Method;
@@ -67,10 +67,30 @@ impl MicroserviceRpc {
     pub fn req_with_headers<T, U>(&self, meth: Method, url: T, hdrs: HeaderMap) -> Result<U, RpcError> where T: Display, U: DeserializeOwned {
         let url = format!("{}{}", self.base_url, url);
         debug!("RPC ({}): {} {}", self.name, meth, url);
-        let mut resp = self.cli.request(meth, &url)
+        let resp = self.cli.request(meth, &url)
             .header(reqwest::header::USER_AGENT, &self.user_agent as &str)
             .headers(hdrs)
             .send()?;
+        self.process_response(resp)
+    }
+    /// Make a request with the provided string as the request body.
+    pub fn req_with_body<T, U>(&self, meth: Method, url: T, body: String) -> Result<U, RpcError> where T: Display, U: DeserializeOwned {
+        let hdrs = HeaderMap::new();
+        self.req_with_body_and_headers(meth, url, body, hdrs)
+    }
+    /// Make a request with the provided string as the request body, and
+    /// the provided headers.
+    pub fn req_with_body_and_headers<T, U>(&self, meth: Method, url: T, body: String, hdrs: HeaderMap) -> Result<U, RpcError> where T: Display, U: DeserializeOwned {
+        let url = format!("{}{}", self.base_url, url);
+        debug!("RPC ({}): {} {} ({} byte body)", self.name, meth, url, body.len());
+        let resp = self.cli.request(meth, &url)
+            .header(reqwest::header::USER_AGENT, &self.user_agent as &str)
+            .headers(hdrs)
+            .body(body)
+            .send()?;
+        self.process_response(resp)
+    }
+    fn process_response<U>(&self, mut resp: Response) -> Result<U, RpcError> where U: DeserializeOwned {
         let status = resp.status();
         debug!("RPC ({}): response code {}", self.name, status.as_u16());
         match status.as_u16() {
@@ -0,0 +1,66 @@
+use chrono::*;
+
+use crate::time::*;
+
+fn tuesday() -> NaiveDate {
+    NaiveDate::from_ymd(2019, 05, 07)
+}
+fn window(date: NaiveDate, day_offset: u8, start: (u32, u32), end: (u32, u32, u32)) -> MvtQueryWindow {
+    MvtQueryWindow {
+        date,
+        day_offset,
+        start_time: NaiveTime::from_hms(start.0, start.1, 0),
+        end_time: NaiveTime::from_hms(end.0, end.1, end.2)
+    }
+}
+
+#[test]
+fn non_midnight_aware_times_saturate() {
+    let ts = tuesday().and_hms(23, 50, 0);
+    assert_eq!(calculate_non_midnight_aware_times(ts, Duration::minutes(20)),
+               (NaiveTime::from_hms(23, 30, 0), NaiveTime::from_hms(23, 59, 59)));
+    let ts = tuesday().and_hms(0, 10, 0);
+    assert_eq!(calculate_non_midnight_aware_times(ts, Duration::minutes(20)),
+               (NaiveTime::from_hms(0, 0, 0), NaiveTime::from_hms(0, 30, 0)));
+}
+#[test]
+fn windows_within_one_day() {
+    let ts = tuesday().and_hms(12, 0, 0);
+    assert_eq!(calculate_midnight_aware_windows(ts, Duration::minutes(30)), vec![
+        window(tuesday(), 0, (11, 30), (12, 30, 0)),
+        // Trains that started yesterday, and are still going at lunchtime.
+        window(tuesday().pred(), 1, (11, 30), (12, 30, 0))
+    ]);
+}
+#[test]
+fn windows_across_midnight() {
+    let wed = tuesday().succ();
+    let ts = tuesday().and_hms(23, 50, 0);
+    assert_eq!(calculate_midnight_aware_windows(ts, Duration::minutes(20)), vec![
+        window(tuesday(), 0, (23, 30), (23, 59, 59)),
+        window(tuesday().pred(), 1, (23, 30), (23, 59, 59)),
+        window(wed, 0, (0, 0), (0, 10, 0)),
+        // Tuesday's trains, after they've crossed midnight.
+        window(tuesday(), 1, (0, 0), (0, 10, 0))
+    ]);
+    // Searching from just after midnight gives the same kind of windows.
+    let ts = wed.and_hms(0, 5, 0);
+    assert_eq!(calculate_midnight_aware_windows(ts, Duration::minutes(15)), vec![
+        window(tuesday(), 0, (23, 50), (23, 59, 59)),
+        window(tuesday().pred(), 1, (23, 50), (23, 59, 59)),
+        window(wed, 0, (0, 0), (0, 20, 0)),
+        window(tuesday(), 1, (0, 0), (0, 20, 0))
+    ]);
+}
+#[test]
+fn windows_over_several_days() {
+    let ts = tuesday().and_hms(12, 0, 0);
+    let windows = calculate_midnight_aware_windows(ts, Duration::hours(36));
+    assert_eq!(windows.len(), 8);
+    assert_eq!(windows[0], window(tuesday().pred(), 0, (0, 0), (23, 59, 59)));
+    // Whole days in the middle are searched from start to end.
+    assert_eq!(windows[2], window(tuesday(), 0, (0, 0), (23, 59, 59)));
+    assert_eq!(windows[3], window(tuesday().pred(), 1, (0, 0), (23, 59, 59)));
+    // The last day only goes up to the end of the search.
+    assert_eq!(windows[7], window(tuesday().succ(), 1, (0, 0), (0, 0, 0)));
+}
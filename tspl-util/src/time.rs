@@ -26,3 +26,55 @@ pub fn calculate_non_midnight_aware_times(ts: NaiveDateTime, within_dur: Duratio
     };
     (start_time, end_time)
 }
+
+/// A window of time on one day to search for movements in, produced by
+/// `calculate_midnight_aware_windows`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MvtQueryWindow {
+    /// The date the train (or schedule) should be running on - i.e. the
+    /// date it started its journey.
+    pub date: NaiveDate,
+    /// The day offset of movements to search for (relative to `date`).
+    pub day_offset: u8,
+    /// Start time to search from.
+    pub start_time: NaiveTime,
+    /// End time to search until.
+    pub end_time: NaiveTime
+}
+
+/// Given a timestamp and a duration, calculates the set of windows that need to be searched to
+/// find all movements happening within `within_dur` of `ts`, taking midnight into account.
+///
+/// Each calendar day the search period touches gets split into two windows: one for movements
+/// of trains starting that day (`day_offset = 0`), and one for movements of trains that started
+/// the day before and have since crossed over midnight (`day_offset = 1`).
+pub fn calculate_midnight_aware_windows(ts: NaiveDateTime, within_dur: Duration) -> Vec<MvtQueryWindow> {
+    let start_ts = ts - within_dur;
+    let end_ts = ts + within_dur;
+    let mut ret = vec![];
+    let mut date = start_ts.date();
+    while date <= end_ts.date() {
+        let start_time = if date == start_ts.date() {
+            start_ts.time()
+        }
+        else {
+            NaiveTime::from_hms(0, 0, 0)
+        };
+        let end_time = if date == end_ts.date() {
+            end_ts.time()
+        }
+        else {
+            NaiveTime::from_hms(23, 59, 59)
+        };
+        for day_offset in 0..=1 {
+            ret.push(MvtQueryWindow {
+                date: date - Duration::days(day_offset as _),
+                day_offset,
+                start_time,
+                end_time
+            });
+        }
+        date = date.succ();
+    }
+    ret
+}
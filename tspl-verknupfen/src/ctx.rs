@@ -92,9 +92,17 @@ impl App {
             let mut actual = false;
             let mut tiploc = None;
             let mut action = None;
+            let mut canx = train.cancelled;
+            let mut canx_reason = None;
+            let mut naive_time = None;
             for mvt in tmvts {
                 tiploc = Some(mvt.tiploc);
                 action = Some(mvt.action);
+                // Cancellations are recorded against the original movement.
+                if mvt.updates.is_none() && mvt.canx {
+                    canx = true;
+                    canx_reason = mvt.canx_reason;
+                }
                 match mvt.source {
                     TrainMvt::SOURCE_SCHED_ITPS => {
                         pfm_scheduled = mvt.platform;
@@ -120,6 +128,13 @@ impl App {
                             });
                         }
                     },
+                    TrainMvt::SOURCE_TRUST_NAIVE => {
+                        // Only used if there's nothing better (see below).
+                        naive_time = Some(TimeWithSource {
+                            time: mvt.time,
+                            source: mvt.source
+                        });
+                    },
                     mvts => {
                         warn!("Currently not processing mvts of source {}", mvts);
                     }
@@ -130,6 +145,8 @@ impl App {
                     pfm_suppr = true;
                 }
             }
+            // Naïve TRUST estimates are worse than anything Darwin says.
+            let time = time.or(naive_time);
             let (time, time_scheduled) = if time.is_none() {
                 assert_eq!(actual, false);
                 (time_scheduled.unwrap(), None)
@@ -141,7 +158,7 @@ impl App {
                 src: DeduplicatedMvtSource::Train(tid),
                 tiploc: tiploc.unwrap(),
                 action: action.unwrap(),
-                canx: train.cancelled,
+                canx, canx_reason,
                 time, actual, time_scheduled,
                 pfm_scheduled, pfm_actual, pfm_suppr
             };
@@ -168,6 +185,7 @@ impl App {
                 actual: false,
                 time_scheduled: None,
                 canx: false,
+                canx_reason: None,
                 pfm_scheduled: mvt.platform,
                 pfm_actual: None,
                 pfm_suppr: false
@@ -42,7 +42,12 @@ pub struct DeduplicatedMvt {
     /// scheduled.
     pub time_scheduled: Option<TimeWithSource>,
     /// Is this movement cancelled?
+    ///
+    /// (This is true if either the whole train, or just this part of
+    /// it, is cancelled.)
     pub canx: bool,
+    /// The reason code given for the cancellation, if any.
+    pub canx_reason: Option<String>,
     /// The scheduled platform (i.e. the one from TRUST)
     pub pfm_scheduled: Option<String>,
     /// The current or expected platform for this movement.
@@ -2,6 +2,7 @@
 
 use serde_derive::Deserialize;
 use tspl_util::{ConfigExt, crate_name};
+use tspl_gcs::StorageConfig;
 
 /// `tspl-web` configuration.
 #[derive(Deserialize, Debug)]
@@ -14,8 +15,9 @@ pub struct Config {
     pub service_zugfuhrer: String,
     /// URL of a running tspl-verknupfen instance.
     pub service_verknupfen: String,
-    pub bucket_name: String,
-    pub service_account_key_path: String,
+    /// Where to get the reference database from.
+    #[serde(flatten)]
+    pub storage: StorageConfig,
 }
 
 impl ConfigExt for Config {
@@ -12,7 +12,6 @@ pub mod util;
 use log::*;
 use tspl_util::ConfigExt;
 use tspl_sqlite::r2d2;
-use tspl_gcs::CloudStorage;
 use std::sync::Arc;
 
 use crate::config::Config;
@@ -26,10 +25,9 @@ fn main() -> Result<()> {
     let cfg = Config::load()?;
     info!("initialising Handlebars");
     let hbs = tmpl::handlebars_init()?;
-    info!("initialising GCS (bucket = {})", cfg.bucket_name);
-    let mut gcs = CloudStorage::init(cfg.bucket_name.clone(), &cfg.service_account_key_path)?;
+    let mut storage = cfg.storage.open()?;
     info!("downloading tspl-nennen data...");
-    gcs.download_object("refdata.sqlite", "./refdata.sqlite")?;
+    storage.download_object("refdata.sqlite", "./refdata.sqlite")?;
     info!("initialising reference database");
     let manager = tspl_sqlite::TsplConnectionManager::initialize("./refdata.sqlite", &tspl_nennen::types::MIGRATIONS)?;
     let pool = r2d2::Pool::new(manager)?;
@@ -36,6 +36,21 @@ fn tiploc_nice_name(conn: &Connection, tpl: &str) -> WebResult<String> {
         });
     Ok(ret)
 }
+/// If the end of the train's journey has been cancelled (i.e. it's being
+/// terminated short), returns the TIPLOC it's cancelled from.
+///
+/// Cancelled movements in the middle of the journey (e.g. skipped stops)
+/// don't count.
+fn canx_from(mvts: &[zft::TrainMvt]) -> Option<String> {
+    let live = mvts.iter()
+        .filter(|m| m.updates.is_none())
+        .collect::<Vec<_>>();
+    let start = live.iter()
+        .rposition(|m| !m.canx)
+        .map(|i| i + 1)
+        .unwrap_or(0);
+    live.get(start).map(|m| m.tiploc.clone())
+}
 impl MovementSearcher {
     pub fn new(pool: TsplPool, cfg: &Config) -> Self {
         let frpc = MicroserviceRpc::new(user_agent!(), "fahrplan", cfg.service_fahrplan.clone());
@@ -58,11 +73,19 @@ impl MovementSearcher {
                         return None;
                     }
                 };
+                // If only part of the train is cancelled, note where from.
+                let canx_from = if x.cancelled {
+                    None
+                }
+                else {
+                    canx_from(&tdets.mvts)
+                };
                 Some((*id, MovementOrigDest {
                     orig: tdets.mvts.first().map(|x| x.tiploc.clone())
                         .unwrap_or("nowhere".into()),
                     dest: tdets.mvts.last().map(|x| x.tiploc.clone())
                         .unwrap_or("nowhere".into()),
+                    canx_from
                 }))
             })
             .collect::<HashMap<i64, MovementOrigDest>>();
@@ -82,6 +105,7 @@ impl MovementSearcher {
                         .unwrap_or("nowhere".into()),
                     dest: sdets.mvts.last().map(|x| x.tiploc.clone())
                         .unwrap_or("nowhere".into()),
+                    canx_from: None
                 }))
             })
             .collect::<HashMap<i64, MovementOrigDest>>();
@@ -89,27 +113,30 @@ impl MovementSearcher {
         for (_, odest) in train_origdests.iter_mut() {
             odest.orig = tiploc_nice_name(&db, &odest.orig)?;
             odest.dest = tiploc_nice_name(&db, &odest.dest)?;
+            if let Some(ref cf) = odest.canx_from {
+                odest.canx_from = Some(tiploc_nice_name(&db, cf)?);
+            }
         }
         for (_, odest) in sched_origdests.iter_mut() {
             odest.orig = tiploc_nice_name(&db, &odest.orig)?;
             odest.dest = tiploc_nice_name(&db, &odest.dest)?;
         }
         for mvt in mqr.mvts {
-            let (ps, pt, odest, canx) = match mvt.src {
+            let (ps, pt, odest) = match mvt.src {
                 DeduplicatedMvtSource::Schedule(s) => {
                     let psu = mqr.schedules.get(&s)
                         .ok_or(WebError::RemoteInvariantsViolated)?
                         .tspl_id;
                     let odest = sched_origdests.get(&s)
                         .ok_or(WebError::DetailsFetchFailed)?;
-                    (Some(psu), None, odest.clone(), false)
+                    (Some(psu), None, odest.clone())
                 },
                 DeduplicatedMvtSource::Train(t) => {
                     let train = mqr.trains.get(&t)
                         .ok_or(WebError::RemoteInvariantsViolated)?;
                     let odest = train_origdests.get(&t)
                         .ok_or(WebError::DetailsFetchFailed)?;
-                    (None, Some(train.tspl_id), odest.clone(), train.cancelled)
+                    (None, Some(train.tspl_id), odest.clone())
                 }
             };
             let pfm_changed = mvt.pfm_scheduled.is_some() && mvt.pfm_actual != mvt.pfm_scheduled;
@@ -133,7 +160,7 @@ impl MovementSearcher {
                 pfm_suppr: mvt.pfm_suppr,
                 action_past_tense: util::action_past_tense(mvt.action),
                 delayed: delayed,
-                canx,
+                canx: mvt.canx,
                 orig_dest: odest,
                 _time: mvt.time.time,
                 _action: mvt.action
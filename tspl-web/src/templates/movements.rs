@@ -5,7 +5,8 @@ use chrono::NaiveTime;
 #[derive(Serialize, Debug, Clone)]
 pub struct MovementOrigDest {
     pub orig: String,
-    pub dest: String
+    pub dest: String,
+    pub canx_from: Option<String>
 }
 #[derive(Serialize)]
 pub struct MovementsView {
@@ -0,0 +1,4 @@
+-- tspl-zugfuhrer: cancellations of individual train movements
+
+ALTER TABLE train_movements ADD COLUMN canx BOOL NOT NULL DEFAULT false;
+ALTER TABLE train_movements ADD COLUMN canx_reason TEXT;
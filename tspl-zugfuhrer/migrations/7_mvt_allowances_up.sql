@@ -0,0 +1,3 @@
+-- tspl-zugfuhrer: allowance time for train movements, used for naive delay estimation
+
+ALTER TABLE train_movements ADD COLUMN allowance INT NOT NULL DEFAULT 0;
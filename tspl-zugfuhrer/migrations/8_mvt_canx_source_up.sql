@@ -0,0 +1,3 @@
+-- tspl-zugfuhrer: record where movement cancellations came from
+
+ALTER TABLE train_movements ADD COLUMN canx_source INT;
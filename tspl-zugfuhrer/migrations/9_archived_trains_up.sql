@@ -0,0 +1,9 @@
+-- tspl-zugfuhrer: keep track of which archive file trains got moved to
+
+CREATE TABLE archived_trains (
+	tspl_id BLOB PRIMARY KEY,
+	darwin_rid TEXT,
+	date TEXT NOT NULL
+);
+
+CREATE INDEX archived_trains_darwin_rid ON archived_trains (darwin_rid);
@@ -92,10 +92,14 @@ impl Activator {
         info!("Activated train {} (flag = {}).", train.tspl_id, train.activated);
         Ok(train)
     }
+    pub fn get_schedule_details(&self, tspl_id: Uuid) -> ZugResult<fpt::ScheduleDetails> {
+        let uri = format!("/schedule/{}", tspl_id);
+        let details: fpt::ScheduleDetails = self.rpc.req(Method::GET, uri)?;
+        Ok(details)
+    }
     pub fn get_tmvts_for_schedule(&self, sched: &fpt::Schedule) -> ZugResult<Vec<TrainMvt>> {
         // Get the actual schedule details.
-        let uri = format!("/schedule/{}", sched.tspl_id);
-        let details: fpt::ScheduleDetails = self.rpc.req(Method::GET, uri)?;
+        let details = self.get_schedule_details(sched.tspl_id)?;
         // Convert each movement into a TrainMvt.
         let tmvts = details.mvts
             .into_iter()
@@ -120,6 +124,20 @@ impl Activator {
         }, tmvts)?;
         Ok(ret)
     }
+    /// Activate a train from a Darwin schedule, without an ITPS schedule to go with it.
+    ///
+    /// This creates a stub train; the caller is expected to fill in movements
+    /// from the Darwin schedule afterwards.
+    pub fn activate_train_darwin_only(&self, sched: &fpt::Schedule) -> ZugResult<Train> {
+        info!("Activating a Darwin-only train; (uid, date, rid) = ({}, {}, {:?})", sched.uid, sched.start_date, sched.darwin_id);
+        self.activate_lowlevel(ActivationDetails {
+            uid: sched.uid.clone(),
+            start_date: sched.start_date,
+            stp_indicator: sched.stp_indicator.clone(),
+            source: sched.source as _,
+            run_date: sched.start_date
+        }, vec![])
+    }
     pub fn activate_train_nrod(&self, uid: String, start_date: NaiveDate, stp_indicator: String, source: i32, date: NaiveDate) -> ZugResult<Train> {
         info!("Activating a train from NROD; (uid, start, stp, source, date) = ({}, {}, {}, {}, {})", uid, start_date, stp_indicator, source, date);
         // Ask tspl-fahrplan for a schedule.
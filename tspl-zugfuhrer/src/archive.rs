@@ -0,0 +1,277 @@
+//! Moving old trains out of the live database, into compressed per-day archive files.
+//!
+//! Each archive file contains all the trains that ran on one day, as
+//! newline-delimited JSON `TrainDetails`, gzipped. The `archived_trains`
+//! table remembers which day each archived train is filed under, so that
+//! trains can still be looked up by trainsplorer ID or Darwin RID.
+//!
+//! Queries that fall back to the archive go through an `ArchiveCache`, so
+//! that the same day's file doesn't get decompressed and parsed over and over.
+
+use tspl_sqlite::TsplPool;
+use tspl_sqlite::traits::*;
+use tspl_sqlite::rusqlite::TransactionBehavior;
+use tspl_util::time::MvtQueryWindow;
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use std::collections::{HashSet, VecDeque};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write, ErrorKind};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use std::time::Duration as StdDuration;
+use std::thread;
+use chrono::prelude::*;
+use chrono::Duration;
+use log::*;
+
+use crate::config::Config;
+use crate::errors::*;
+use crate::types::*;
+
+/// Returns the path to the archive file for `date`, in the archive directory `dir`.
+pub fn archive_file_path(dir: &Path, date: NaiveDate) -> PathBuf {
+    dir.join(format!("{}.ndjson.gz", date))
+}
+
+/// Reads all of the trains archived for `date`.
+pub fn read_archive(dir: &Path, date: NaiveDate) -> ZugResult<Vec<TrainDetails>> {
+    let path = archive_file_path(dir, date);
+    if !path.exists() {
+        return Err(ZugError::NotFound);
+    }
+    let reader = BufReader::new(GzDecoder::new(File::open(path)?));
+    let mut ret = vec![];
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim() == "" {
+            continue;
+        }
+        ret.push(serde_json::from_str(&line)?);
+    }
+    Ok(ret)
+}
+
+/// Writes `trains` to a temporary file next to the archive file for `date`,
+/// along with anything already archived for that day (apart from older
+/// copies of `trains`).
+///
+/// Returns the path to the temporary file, which should be renamed over the
+/// archive file to replace it, and how many previously archived trains were kept.
+pub fn prepare_archive(dir: &Path, date: NaiveDate, trains: &[TrainDetails]) -> ZugResult<(PathBuf, usize)> {
+    let ids = trains.iter().map(|x| x.train.id).collect::<HashSet<_>>();
+    let path = archive_file_path(dir, date);
+    let tmp_path = path.with_extension("gz.tmp");
+    let mut enc = GzEncoder::new(BufWriter::new(File::create(&tmp_path)?), Compression::default());
+    let mut kept = 0;
+    if path.exists() {
+        for details in read_archive(dir, date)? {
+            if !ids.contains(&details.train.id) {
+                serde_json::to_writer(&mut enc, &details)?;
+                enc.write_all(b"\n")?;
+                kept += 1;
+            }
+        }
+    }
+    for details in trains {
+        serde_json::to_writer(&mut enc, details)?;
+        enc.write_all(b"\n")?;
+    }
+    let mut writer = enc.finish()?;
+    writer.flush()?;
+    writer.get_ref().sync_all()?;
+    Ok((tmp_path, kept))
+}
+
+/// Cache of the most recently read archive days.
+///
+/// Entries remember the modification time of the file they were read from,
+/// so a day that gets archived to again is read afresh.
+pub struct ArchiveCache {
+    dir: PathBuf,
+    capacity: usize,
+    days: Mutex<VecDeque<(NaiveDate, SystemTime, Arc<Vec<TrainDetails>>)>>
+}
+impl ArchiveCache {
+    pub fn new(dir: PathBuf, capacity: usize) -> Self {
+        Self {
+            dir,
+            capacity,
+            days: Mutex::new(VecDeque::new())
+        }
+    }
+    /// Gets all of the trains archived for `date`, reading them from disk
+    /// if they aren't cached already.
+    pub fn get(&self, date: NaiveDate) -> ZugResult<Arc<Vec<TrainDetails>>> {
+        let modified = match fs::metadata(archive_file_path(&self.dir, date)) {
+            Ok(m) => m.modified()?,
+            Err(ref e) if e.kind() == ErrorKind::NotFound => return Err(ZugError::NotFound),
+            Err(e) => return Err(e.into())
+        };
+        {
+            let mut days = self.days.lock().unwrap();
+            if let Some(pos) = days.iter().position(|x| x.0 == date && x.1 == modified) {
+                // Move it to the front, so it's the last to be evicted.
+                let entry = days.remove(pos).unwrap();
+                let ret = entry.2.clone();
+                days.push_front(entry);
+                return Ok(ret);
+            }
+        }
+        // Don't hold the lock while reading, so other days can still be
+        // looked up in the meantime.
+        let trains = Arc::new(read_archive(&self.dir, date)?);
+        let mut days = self.days.lock().unwrap();
+        days.retain(|x| x.0 != date);
+        days.push_front((date, modified, trains.clone()));
+        days.truncate(self.capacity);
+        Ok(trains)
+    }
+}
+
+/// Finds the movements in the live schedules of the archived trains `trains`
+/// that pass through `tpl` during the window `w` (either as planned, or
+/// because they've been updated to), along with all of their updates.
+///
+/// This does the same job as the queries in `App::get_mvts_passing_through`,
+/// for trains that aren't in the database any more. The original movement
+/// is always first in each list of movements returned.
+pub fn find_mvts_passing_through<'a>(trains: &'a [TrainDetails], tpl: &str, w: &MvtQueryWindow) -> Vec<(&'a TrainDetails, Vec<TrainMvt>)> {
+    let in_window = |m: &TrainMvt| {
+        m.tiploc == tpl && m.day_offset == w.day_offset
+            && m.time >= w.start_time && m.time <= w.end_time
+    };
+    let mut ret = vec![];
+    for details in trains.iter().filter(|x| x.train.date == w.date) {
+        for mvt in details.mvts.iter().filter(|x| x.updates.is_none()) {
+            let updates = details.mvts.iter()
+                .filter(|x| x.updates == Some(mvt.id))
+                .cloned()
+                .collect::<Vec<_>>();
+            if in_window(mvt) || updates.iter().any(|x| in_window(x)) {
+                let mut mvts = vec![mvt.clone()];
+                mvts.extend(updates);
+                ret.push((details, mvts));
+            }
+        }
+    }
+    ret
+}
+
+/// Finds the movements in the live schedule of the archived train `details`
+/// at `tpl`, along with all of their updates.
+pub fn find_mvts_at(details: &TrainDetails, tpl: &str) -> Vec<TrainMvt> {
+    let ids = details.mvts.iter()
+        .filter(|x| x.updates.is_none() && x.tiploc == tpl)
+        .map(|x| x.id)
+        .collect::<HashSet<_>>();
+    details.mvts.iter()
+        .filter(|x| ids.contains(&x.id) || x.updates.map(|u| ids.contains(&u)).unwrap_or(false))
+        .cloned()
+        .collect()
+}
+
+pub struct Archiver {
+    pool: TsplPool,
+    dir: PathBuf,
+    after_days: u32,
+    interval: StdDuration
+}
+impl Archiver {
+    /// Makes a new `Archiver`, if archival is enabled in `cfg`.
+    pub fn new(pool: TsplPool, cfg: &Config) -> Option<Self> {
+        let dir = cfg.archive_path.as_ref()?;
+        Some(Self {
+            pool,
+            dir: dir.into(),
+            after_days: cfg.archive_after_days.unwrap_or(14),
+            interval: StdDuration::from_secs(cfg.archive_interval_secs.unwrap_or(3600))
+        })
+    }
+    pub fn run(self) {
+        info!("Archiving trains older than {} days to {}", self.after_days, self.dir.display());
+        loop {
+            if let Err(e) = self.archive_old() {
+                error!("Failed to archive old trains: {}", e);
+            }
+            thread::sleep(self.interval);
+        }
+    }
+    /// Archives every day that's older than the retention period.
+    pub fn archive_old(&self) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let cutoff = Local::now().naive_local().date() - Duration::days(self.after_days as _);
+        let dates = {
+            let db = self.pool.get()?;
+            let mut stmt = db.prepare("SELECT DISTINCT date FROM trains WHERE date < ? ORDER BY date ASC")?;
+            let rows = stmt.query_map(params![cutoff], |row| row.get(0))?;
+            let mut dates: Vec<NaiveDate> = vec![];
+            for row in rows {
+                dates.push(row?);
+            }
+            dates
+        };
+        if !dates.is_empty() {
+            info!("Archiving {} days of trains from before {}", dates.len(), cutoff);
+        }
+        for date in dates {
+            self.archive_date(date)?;
+        }
+        Ok(())
+    }
+    /// Reads all the trains for `date`, and their movements, out of the live database.
+    fn read_date(conn: &Connection, date: NaiveDate) -> Result<Vec<TrainDetails>> {
+        let trains = Train::from_select(conn, "WHERE date = ? ORDER BY id ASC", &[&date])?;
+        let mut ret = vec![];
+        for train in trains {
+            let mvts = TrainMvt::from_select(conn, "WHERE parent_train = ? ORDER BY day_offset, time, action, updates, id ASC", &[&train.id])?;
+            ret.push(TrainDetails { train, mvts });
+        }
+        Ok(ret)
+    }
+    /// Writes out all the trains for `date` to its archive file, and then
+    /// deletes them from the live database.
+    ///
+    /// If there's already an archive file for `date` (because some trains
+    /// turned up late, or we fell over halfway through last time), its
+    /// contents are kept.
+    ///
+    /// The trains are written out from a read snapshot, so the database
+    /// isn't locked while compressing them. Afterwards, they're read again
+    /// in a short write transaction, and only deleted if nothing happened
+    /// to them in the meantime; otherwise, the day is left for the next run.
+    fn archive_date(&self, date: NaiveDate) -> Result<()> {
+        let mut db = self.pool.get()?;
+        let snapshot = {
+            let trans = db.transaction()?;
+            let ret = Self::read_date(&trans, date)?;
+            trans.commit()?;
+            ret
+        };
+        if snapshot.is_empty() {
+            return Ok(());
+        }
+        let (tmp_path, kept) = prepare_archive(&self.dir, date, &snapshot)?;
+        let trans = db.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let current = Self::read_date(&trans, date)?;
+        if serde_json::to_string(&current)? != serde_json::to_string(&snapshot)? {
+            warn!("Trains for {} changed while archiving them; trying again later", date);
+            fs::remove_file(&tmp_path)?;
+            return Ok(());
+        }
+        let path = archive_file_path(&self.dir, date);
+        fs::rename(&tmp_path, &path)?;
+        for details in snapshot.iter() {
+            let train = &details.train;
+            trans.execute("INSERT OR REPLACE INTO archived_trains (tspl_id, darwin_rid, date)
+                           VALUES (?, ?, ?)",
+                          params![train.tspl_id, train.darwin_rid, train.date])?;
+            trans.execute("DELETE FROM trains WHERE id = ?", params![train.id])?;
+        }
+        trans.commit()?;
+        info!("Archived {} trains from {} to {} (kept {} previously archived)", snapshot.len(), date, path.display(), kept);
+        Ok(())
+    }
+}
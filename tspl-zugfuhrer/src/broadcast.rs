@@ -5,7 +5,6 @@ use std::net::{TcpListener, TcpStream};
 use std::thread;
 use tspl_sqlite::TsplPool;
 use tspl_sqlite::traits::*;
-use serde_derive::{Serialize, Deserialize};
 use chrono::prelude::*;
 use std::io::Write;
 use log::*;
@@ -31,19 +30,9 @@ impl BroadcastSender {
     pub fn send_activation(&self, mvt: Train) {
         self.send_update(BroadcastUpdate::Activation(mvt))
     }
-}
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
-pub enum BroadcastUpdate {
-    Activation(Train),
-    SyncTrain(Train),
-    Movement(TrainMvt),
-}
-
-#[derive(Clone, Debug, Serialize)]
-pub struct BroadcastPacket {
-    ts: NaiveDateTime,
-    update: BroadcastUpdate
+    pub fn send_sync(&self, train: Train) {
+        self.send_update(BroadcastUpdate::SyncTrain(train))
+    }
 }
 
 pub struct LiveTcpListener {
@@ -20,7 +20,30 @@ pub struct Config {
     pub password: String,
     /// NROD base URL.
     #[serde(default)]
-    pub base_url: Option<String>
+    pub base_url: Option<String>,
+    /// Directory to archive old trains into.
+    ///
+    /// If this isn't set, trains are kept in the live database forever.
+    #[serde(default)]
+    pub archive_path: Option<String>,
+    /// Number of days to keep trains in the live database for, before
+    /// archiving them (default 14).
+    #[serde(default)]
+    pub archive_after_days: Option<u32>,
+    /// How often to check for trains to archive, in seconds (default 3600).
+    #[serde(default)]
+    pub archive_interval_secs: Option<u64>,
+    /// How many days of archived trains to keep in memory, for queries
+    /// that look through the archive (default 8).
+    #[serde(default)]
+    pub archive_cache_days: Option<usize>,
+    /// Path to the `stops.txt` of the static GTFS feed made by
+    /// `tspl-fahrplan-updater`.
+    ///
+    /// GTFS-Realtime trip updates only cover the stops in it; if this isn't
+    /// set, they aren't served at all.
+    #[serde(default)]
+    pub gtfs_stops_path: Option<String>
 }
 
 impl ConfigExt for Config {
@@ -1,8 +1,11 @@
 //! Main app context.
 
 use tspl_sqlite::TsplPool;
-use tspl_sqlite::rusqlite::TransactionBehavior;
-use std::collections::HashMap;
+use tspl_sqlite::rusqlite::{Transaction, TransactionBehavior, OptionalExtension};
+use tspl_sqlite::rusqlite::types::ToSql;
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::sync::Arc;
 use rouille::{Request, Response, router};
 use chrono::prelude::*;
 use log::*;
@@ -16,13 +19,16 @@ use chrono::Duration;
 use crate::broadcast::BroadcastSender;
 use crate::config::Config;
 use crate::activation::Activator;
+use crate::archive::ArchiveCache;
 use crate::errors::*;
 use crate::types::*;
 
 pub struct App {
     pool: TsplPool,
     cast: BroadcastSender,
-    activator: Activator
+    activator: Activator,
+    archive: Option<ArchiveCache>,
+    gtfs_stops: Option<HashSet<String>>
 }
 impl HttpServer for App {
     type Error = ZugError;
@@ -41,10 +47,23 @@ impl HttpServer for App {
                 self.get_connecting_mvts(tiploc, ts, Duration::seconds(dur as _), conn)
                     .map(|x| Response::json(&x))
             },
+            (GET) (/gtfs-rt/trip-updates) => {
+                let stops = self.gtfs_stops.as_ref().ok_or(ZugError::NotFound)?;
+                crate::gtfs_rt::build_trip_updates(&self.pool, stops)
+                    .map(|x| Response::from_data("application/x-protobuf", x))
+            },
             (GET) (/train/{tid: Uuid}) => {
                 self.get_train_details(tid)
                     .map(|x| Response::json(&x))
             },
+            (GET) (/archive/{date: NaiveDate}/trains) => {
+                self.get_archived_trains(date)
+                    .map(|x| Response::json(&*x))
+            },
+            (GET) (/archive/{date: NaiveDate}/train/{tid: Uuid}) => {
+                self.get_archived_train_details(date, tid)
+                    .map(|x| Response::json(&x))
+            },
             (GET) (/trains/by-trust-id/{trust_id}/{date: NaiveDate}) => {
                 self.get_train_for_trust_id(trust_id, date)
                     .map(|x| Response::json(&x))
@@ -76,12 +95,85 @@ impl HttpServer for App {
                     .map(|x| Response::json(&x))
 
             },
+            (POST) (/trains/darwin-schedule) => {
+                extract_headers!(req, ZugError::HeadersMissing,
+                                 let rid: String => "darwin-rid",
+                                 let sched_id: Uuid => "schedule-tspl-id");
+                self.process_darwin_schedule(rid, sched_id)
+                    .map(|x| Response::json(&x))
+            },
             (POST) (/trains/{tid: Uuid}/terminate) => {
                 self.terminate_train(tid)
                     .map(|x| Response::json(&x))
             },
             (POST) (/trains/{tid: Uuid}/cancel) => {
-                self.cancel_train(tid)
+                extract_headers!(req, ZugError::HeadersMissing,
+                                 opt stanox: String => "mvt-stanox",
+                                 opt planned_time: NaiveTime => "mvt-planned-time",
+                                 opt planned_day_offset: u8 => "mvt-planned-day-offset",
+                                 opt reason: String => "canx-reason");
+                let from = match (stanox, planned_time, planned_day_offset) {
+                    (Some(stanox), Some(planned_time), Some(planned_day_offset)) => {
+                        Some(TrustLocation { stanox, planned_time, planned_day_offset })
+                    },
+                    (None, None, None) => None,
+                    _ => Err(ZugError::HeadersMissing)?
+                };
+                self.cancel_train(tid, from, reason)
+                    .map(|x| Response::json(&x))
+            },
+            (POST) (/trains/{tid: Uuid}/change-origin) => {
+                extract_headers!(req, ZugError::HeadersMissing,
+                                 let stanox: String => "mvt-stanox",
+                                 let planned_time: NaiveTime => "mvt-planned-time",
+                                 let planned_day_offset: u8 => "mvt-planned-day-offset",
+                                 opt reason: String => "canx-reason");
+                self.change_origin(tid, TrustLocation {
+                    stanox, planned_time, planned_day_offset
+                }, reason)
+                    .map(|x| Response::json(&x))
+            },
+            (POST) (/trains/{tid: Uuid}/change-identity/{trust_id}) => {
+                self.change_identity(tid, trust_id)
+                    .map(|x| Response::json(&x))
+            },
+            (POST) (/trains/{tid: Uuid}/change-location) => {
+                extract_headers!(req, ZugError::HeadersMissing,
+                                 let stanox: String => "mvt-stanox",
+                                 let planned_time: NaiveTime => "mvt-planned-time",
+                                 let planned_day_offset: u8 => "mvt-planned-day-offset",
+                                 opt orig_stanox: String => "original-stanox",
+                                 opt orig_planned_time: NaiveTime => "original-planned-time",
+                                 opt orig_planned_day_offset: u8 => "original-planned-day-offset");
+                let original = match (orig_stanox, orig_planned_time, orig_planned_day_offset) {
+                    (Some(stanox), Some(planned_time), Some(planned_day_offset)) => {
+                        Some(TrustLocation { stanox, planned_time, planned_day_offset })
+                    },
+                    (None, None, None) => None,
+                    _ => Err(ZugError::HeadersMissing)?
+                };
+                self.change_location(tid, TrustLocation {
+                    stanox, planned_time, planned_day_offset
+                }, original)
+                    .map(|x| Response::json(&x))
+            },
+            (POST) (/trains/{tid: Uuid}/reinstate) => {
+                extract_headers!(req, ZugError::HeadersMissing,
+                                 let stanox: String => "mvt-stanox",
+                                 let planned_time: NaiveTime => "mvt-planned-time",
+                                 let planned_day_offset: u8 => "mvt-planned-day-offset");
+                self.reinstate_train(tid, TrustLocation {
+                    stanox, planned_time, planned_day_offset
+                })
+                    .map(|x| Response::json(&x))
+            },
+            (POST) (/trains/{tid: Uuid}/darwin/cancellations) => {
+                extract_headers!(req, ZugError::HeadersMissing,
+                                 opt reason: String => "canx-reason");
+                let body = read_body(req)?;
+                let canxs: Vec<DarwinCancellation> = serde_json::from_str(&body)
+                    .map_err(|e| ZugError::InvalidBody(e.to_string()))?;
+                self.process_darwin_cancellations(tid, canxs, reason)
                     .map(|x| Response::json(&x))
             },
             (POST) (/trains/{tid: Uuid}/darwin/update) => {
@@ -125,36 +217,66 @@ impl HttpServer for App {
         )
     }
 }
+fn read_body(req: &Request) -> ZugResult<String> {
+    let mut body = String::new();
+    if let Some(mut data) = req.data() {
+        data.read_to_string(&mut body)
+            .map_err(|e| ZugError::InvalidBody(e.to_string()))?;
+    }
+    Ok(body)
+}
 impl App {
-    pub fn new(pool: TsplPool, cast: BroadcastSender, cfg: &Config) -> Self {
+    pub fn new(pool: TsplPool, cast: BroadcastSender, cfg: &Config, gtfs_stops: Option<HashSet<String>>) -> Self {
         let rpc = MicroserviceRpc::new(user_agent!(), "fahrplan", cfg.service_fahrplan.clone());
         let activator = Activator::new(rpc, pool.clone());
-        Self { pool, activator, cast }
+        let archive = cfg.archive_path.as_ref()
+            .map(|x| ArchiveCache::new(x.into(), cfg.archive_cache_days.unwrap_or(8)));
+        Self { pool, activator, cast, archive, gtfs_stops }
+    }
+    fn get_archived_trains(&self, date: NaiveDate) -> ZugResult<Arc<Vec<TrainDetails>>> {
+        self.archive.as_ref()
+            .ok_or(ZugError::NotFound)?
+            .get(date)
+    }
+    fn get_archived_train_details(&self, date: NaiveDate, tid: Uuid) -> ZugResult<TrainDetails> {
+        self.get_archived_trains(date)?
+            .iter()
+            .find(|x| x.train.tspl_id == tid)
+            .cloned()
+            .ok_or(ZugError::NotFound)
+    }
+    /// Like `get_archived_trains`, but returns nothing instead of an error
+    /// if there's no archive for `date` (for falling back to the archive
+    /// when querying movements).
+    fn get_archived_trains_if_any(&self, date: NaiveDate) -> ZugResult<Arc<Vec<TrainDetails>>> {
+        match self.get_archived_trains(date) {
+            Err(ZugError::NotFound) => Ok(Arc::new(vec![])),
+            x => x
+        }
+    }
+    /// Looks up an archived train, using the `archived_trains` table to
+    /// find out which archive file it's in.
+    fn find_archived_train(&self, db: &Connection, column: &str, value: &dyn ToSql) -> ZugResult<TrainDetails> {
+        let query = format!("SELECT tspl_id, date FROM archived_trains WHERE {} = ?", column);
+        let (tid, date): (Uuid, NaiveDate) = db.query_row(&query, &[value], |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()?
+            .ok_or(ZugError::NotFound)?;
+        self.get_archived_train_details(date, tid)
     }
     fn get_train_details(&self, tid: Uuid) -> ZugResult<TrainDetails> {
         let db = self.pool.get()?;
         let trains = Train::from_select(&db, "WHERE tspl_id = ?", &[&tid])?;
-        let train = trains.into_iter().nth(0)
-            .ok_or(ZugError::NotFound)?;
+        let train = match trains.into_iter().nth(0) {
+            Some(t) => t,
+            None => return self.find_archived_train(&db, "tspl_id", &tid)
+        };
         let mvts = TrainMvt::from_select(&db, "WHERE parent_train = ? ORDER BY day_offset, time, action, updates ASC", &[&train.id])?;
         Ok(TrainDetails { train, mvts })
     }
-    // FIXME: This function doesn't yet handle trains crossing over midnight.
-    // There's no reason why it couldn't in the future though; I just want to
-    // move fast and break things (at the time of writing).
     fn get_mvts_passing_through(&self, tpl: String, ts: NaiveDateTime, within_dur: Duration) -> ZugResult<MvtQueryResponse> {
-        let (start_time, end_time) = tspl_util::time::calculate_non_midnight_aware_times(ts, within_dur);
-        info!("Finding mvts passing through {} on {} between {} and {}", tpl, ts.date(), start_time, end_time);
+        let windows = tspl_util::time::calculate_midnight_aware_windows(ts, within_dur);
         let db = self.pool.get()?;
         // Warning: not that heavy SQL ahead.
-        //
-        // FIXME: This doesn't handle the case where the original train movement is
-        // outside the time range, but the updated train movement is inside the time range
-        // (e.g. pathologically delayed trains). An attempt was made to fix this, but
-        // it ended up with 90 minutes of fighting the sqlite query planner, which
-        // doesn't seem to understand how any of this works.
-        // Therefore, we're just leaving it for now. In future, we should just do a separate
-        // query for this.
         let mut stmt = db.prepare("SELECT DISTINCT * 
                                               FROM train_movements AS tmvts
 
@@ -169,63 +291,120 @@ impl App {
                                                 -- through the station in the given time period.
                                              WHERE tmvts.tiploc = :tpl
                                                AND tmvts.time BETWEEN :start_time AND :end_time
-                                               AND tmvts.day_offset = 0
+                                               AND tmvts.day_offset = :day_offset
                                                 -- Make sure the train movement is original (i.e.
                                                 -- it doesn't update anything).
                                                AND +tmvts.updates IS NULL
                                                AND t.date = :date")?;
-        let args = named_params! {
-            ":tpl": tpl,
-            ":start_time": start_time,
-            ":end_time": end_time,
-            ":date": ts.date()
-        };
-        let rows = stmt.query_map_named(args, |row| {
-            Ok((
-                // The original train movement, passing through `tpl`.
-                TrainMvt::from_row(row, 0)?,
-                // Its parent train.
-                Train::from_row(row, TrainMvt::FIELDS)?,
-                // An update to the original train movement, if there is one.
-                TrainMvt::from_row(row, TrainMvt::FIELDS + Train::FIELDS).ok()
-            ))
-        })?;
+        // The above query doesn't catch movements that were scheduled outside the
+        // time period, but are now expected inside it (e.g. because the train is
+        // very late). Doing this in one query is a recipe for fighting the sqlite
+        // query planner, so we look for the updates separately, and work backwards
+        // to the original movements.
+        let mut upd_stmt = db.prepare("SELECT DISTINCT tmvts.*, t.*
+                                                  FROM train_movements AS updating
+                                            INNER JOIN train_movements AS tmvts
+                                                    ON tmvts.id = updating.updates
+                                            INNER JOIN trains AS t
+                                                    ON t.id = tmvts.parent_train
+                                                 WHERE updating.tiploc = :tpl
+                                                   AND updating.time BETWEEN :start_time AND :end_time
+                                                   AND updating.day_offset = :day_offset
+                                                   AND t.date = :date")?;
         let mut tmvts: HashMap<i64, Vec<TrainMvt>> = HashMap::new();
         let mut trains = HashMap::new();
         let mut proc = 0;
-        for row in rows {
-            let (tmvt, train, updating_tmvt) = row?;
-            proc += 1;
-            trains.insert(train.id, train);
-            if let Some(tmvts) = tmvts.get_mut(&tmvt.id) {
-                // Entry already exists, so the original tmvt is in there.
-                // We only need to add the updating one, if it exists.
-                tmvts.extend(updating_tmvt);
+        for w in windows.iter() {
+            debug!("Finding mvts passing through {} on {} (day offset {}) between {} and {}",
+                   tpl, w.date, w.day_offset, w.start_time, w.end_time);
+            let args = named_params! {
+                ":tpl": tpl,
+                ":start_time": w.start_time,
+                ":end_time": w.end_time,
+                ":day_offset": w.day_offset,
+                ":date": w.date
+            };
+            let rows = stmt.query_map_named(args, |row| {
+                Ok((
+                    // The original train movement, passing through `tpl`.
+                    TrainMvt::from_row(row, 0)?,
+                    // Its parent train.
+                    Train::from_row(row, TrainMvt::FIELDS)?,
+                    // An update to the original train movement, if there is one.
+                    TrainMvt::from_row(row, TrainMvt::FIELDS + Train::FIELDS).ok()
+                ))
+            })?;
+            for row in rows {
+                let (tmvt, train, updating_tmvt) = row?;
+                proc += 1;
+                trains.insert(train.id, train);
+                if let Some(tmvts) = tmvts.get_mut(&tmvt.id) {
+                    // Entry already exists, so the original tmvt is in there.
+                    // We only need to add the updating one, if it exists.
+                    tmvts.extend(updating_tmvt);
+                }
+                else {
+                    // Entry doesn't exist; create it, and add the updating tmvt
+                    // as well if it exists.
+                    let id = tmvt.id;
+                    let mut ins = vec![tmvt];
+                    ins.extend(updating_tmvt);
+                    tmvts.insert(id, ins);
+                }
             }
-            else {
-                // Entry doesn't exist; create it, and add the updating tmvt
-                // as well if it exists.
+        }
+        for w in windows.iter() {
+            let args = named_params! {
+                ":tpl": tpl,
+                ":start_time": w.start_time,
+                ":end_time": w.end_time,
+                ":day_offset": w.day_offset,
+                ":date": w.date
+            };
+            let rows = upd_stmt.query_map_named(args, |row| {
+                Ok((
+                    // The original train movement, whose update passes through `tpl`.
+                    TrainMvt::from_row(row, 0)?,
+                    // Its parent train.
+                    Train::from_row(row, TrainMvt::FIELDS)?,
+                ))
+            })?;
+            for row in rows {
+                let (tmvt, train) = row?;
+                proc += 1;
+                if tmvts.contains_key(&tmvt.id) {
+                    // Already got it from the first query.
+                    continue;
+                }
+                let updates = TrainMvt::from_select(&db, "WHERE updates = ?", params![tmvt.id])?;
+                trains.insert(train.id, train);
                 let id = tmvt.id;
                 let mut ins = vec![tmvt];
-                ins.extend(updating_tmvt);
+                ins.extend(updates);
                 tmvts.insert(id, ins);
             }
         }
-        info!("Processed {} rows for a total of {} unique tmvts and {} trains.", proc, tmvts.len(), trains.len());
+        // Trains that have been archived aren't in the database any more, so
+        // look through the archive for them as well.
+        for w in windows.iter() {
+            let archived = self.get_archived_trains_if_any(w.date)?;
+            for (details, mvts) in crate::archive::find_mvts_passing_through(&archived, &tpl, w) {
+                proc += 1;
+                trains.insert(details.train.id, details.train.clone());
+                tmvts.insert(mvts[0].id, mvts);
+            }
+        }
+        info!("Processed {} rows for a total of {} unique tmvts and {} trains passing through {} at {}.",
+              proc, tmvts.len(), trains.len(), tpl, ts);
         Ok(MvtQueryResponse {
             mvts: tmvts,
             trains
         })
     }
-    // FIXME: As above, this function doesn't handle midnight well.
     fn get_connecting_mvts(&self, tpl: String, ts: NaiveDateTime, within_dur: Duration, connection: String) -> ZugResult<ConnectingMvtQueryResponse> {
-        let (start_time, end_time) = tspl_util::time::calculate_non_midnight_aware_times(ts, within_dur);
-        info!("Finding mvts passing through {} and {} on {} between {} and {}", tpl, connection, ts.date(), start_time, end_time);
+        let windows = tspl_util::time::calculate_midnight_aware_windows(ts, within_dur);
         let db = self.pool.get()?;
         // Warning: reasonably heavy SQL ahead.
-        //
-        // FIXME: this query also has the same issue as the above with respect to
-        // updated movements.
         let mut stmt = db.prepare("SELECT DISTINCT * 
                                               FROM train_movements AS tmvts
 
@@ -249,70 +428,156 @@ impl App {
                                                 -- the other query.
                                              WHERE tmvts.tiploc = :tpl
                                                AND tmvts.time BETWEEN :start_time AND :end_time
-                                               AND tmvts.day_offset = 0
+                                               AND tmvts.day_offset = :day_offset
 
                                                AND +tmvts.updates IS NULL
                                                AND +connecting.updates IS NULL
                                                AND t.date = :date
                                                AND connecting.tiploc = :tpl_conn")?;
-        let args = named_params! {
-            ":tpl": tpl,
-            ":start_time": start_time,
-            ":end_time": end_time,
-            ":date": ts.date(),
-            ":tpl_conn": connection
-        };
-        let rows = stmt.query_map_named(args, |row| {
-            // Golly gee, Mr. SQLite, that's a lot of columns...
-            Ok((
-                // The original train movement, passing through `tpl`.
-                TrainMvt::from_row(row, 0)?,
-                // Its parent train.
-                Train::from_row(row, TrainMvt::FIELDS)?,
-                // Its corresponding connecting train movement.
-                TrainMvt::from_row(row, TrainMvt::FIELDS + Train::FIELDS)?,
-                // An update for the original train movement, if there is one.
-                TrainMvt::from_row(row, 2 * TrainMvt::FIELDS + Train::FIELDS).ok(),
-                // An update for the connecting train movement, if there is one.
-                TrainMvt::from_row(row, 3 * TrainMvt::FIELDS + Train::FIELDS).ok()
-            ))
-        })?;
+        // As with the non-connecting query, catch late-running trains separately.
+        let mut upd_stmt = db.prepare("SELECT DISTINCT tmvts.*, t.*, connecting.*, updating_connecting.*
+                                                  FROM train_movements AS updating
+                                            INNER JOIN train_movements AS tmvts
+                                                    ON tmvts.id = updating.updates
+                                            INNER JOIN trains AS t
+                                                    ON t.id = tmvts.parent_train
+                                            INNER JOIN train_movements AS connecting
+                                                    ON connecting.parent_train = tmvts.parent_train
+                                       LEFT OUTER JOIN train_movements AS updating_connecting
+                                                    ON updating_connecting.updates = connecting.id
+                                                 WHERE updating.tiploc = :tpl
+                                                   AND updating.time BETWEEN :start_time AND :end_time
+                                                   AND updating.day_offset = :day_offset
+                                                   AND +connecting.updates IS NULL
+                                                   AND t.date = :date
+                                                   AND connecting.tiploc = :tpl_conn")?;
         // NB: Look at the docs for `ConnectingMvtQueryResponse` to understand how
         // these structures work...
         let mut tmvts: HashMap<i64, Vec<TrainMvt>> = HashMap::new();
         let mut connecting_tmvts: HashMap<i64, Vec<TrainMvt>> = HashMap::new();
         let mut trains = HashMap::new();
         let mut proc = 0;
-        for row in rows {
-            let (tmvt, train, conn_tmvt, updating_tmvt, conn_updating_tmvt) = row?;
-            proc += 1;
-            trains.insert(train.id, train);
-            let tmvt_id = tmvt.id;
-            // The logic here is very similar to that used in non-connecting
-            // movement queries. As such, the comments are not repeated.
-            // See the other function if you're lost.
-            if let Some(tmvts) = tmvts.get_mut(&tmvt_id) {
-                tmvts.extend(updating_tmvt);
+        for w in windows.iter() {
+            debug!("Finding mvts passing through {} and {} on {} (day offset {}) between {} and {}",
+                   tpl, connection, w.date, w.day_offset, w.start_time, w.end_time);
+            let args = named_params! {
+                ":tpl": tpl,
+                ":start_time": w.start_time,
+                ":end_time": w.end_time,
+                ":day_offset": w.day_offset,
+                ":date": w.date,
+                ":tpl_conn": connection
+            };
+            let rows = stmt.query_map_named(args, |row| {
+                // Golly gee, Mr. SQLite, that's a lot of columns...
+                Ok((
+                    // The original train movement, passing through `tpl`.
+                    TrainMvt::from_row(row, 0)?,
+                    // Its parent train.
+                    Train::from_row(row, TrainMvt::FIELDS)?,
+                    // Its corresponding connecting train movement.
+                    TrainMvt::from_row(row, TrainMvt::FIELDS + Train::FIELDS)?,
+                    // An update for the original train movement, if there is one.
+                    TrainMvt::from_row(row, 2 * TrainMvt::FIELDS + Train::FIELDS).ok(),
+                    // An update for the connecting train movement, if there is one.
+                    TrainMvt::from_row(row, 3 * TrainMvt::FIELDS + Train::FIELDS).ok()
+                ))
+            })?;
+            for row in rows {
+                let (tmvt, train, conn_tmvt, updating_tmvt, conn_updating_tmvt) = row?;
+                proc += 1;
+                trains.insert(train.id, train);
+                let tmvt_id = tmvt.id;
+                // The logic here is very similar to that used in non-connecting
+                // movement queries. As such, the comments are not repeated.
+                // See the other function if you're lost.
+                if let Some(tmvts) = tmvts.get_mut(&tmvt_id) {
+                    tmvts.extend(updating_tmvt);
+                }
+                else {
+                    let mut ins = vec![tmvt];
+                    ins.extend(updating_tmvt);
+                    tmvts.insert(tmvt_id, ins);
+                }
+                // Same as above, but for the connecting ones.
+                // This time, they key is the **corresponding (original) tmvt**'s key,
+                // not the key of the connecting tmvt.
+                if let Some(conn_tmvts) = connecting_tmvts.get_mut(&tmvt_id) {
+                    conn_tmvts.extend(conn_updating_tmvt);
+                }
+                else {
+                    let mut ins = vec![conn_tmvt];
+                    ins.extend(conn_updating_tmvt);
+                    connecting_tmvts.insert(tmvt_id, ins);
+                }
             }
-            else {
-                let mut ins = vec![tmvt];
-                ins.extend(updating_tmvt);
-                tmvts.insert(tmvt_id, ins);
-            }
-            // Same as above, but for the connecting ones.
-            // This time, they key is the **corresponding (original) tmvt**'s key,
-            // not the key of the connecting tmvt.
-            if let Some(conn_tmvts) = connecting_tmvts.get_mut(&tmvt_id) {
-                conn_tmvts.extend(conn_updating_tmvt);
+        }
+        // Movements found by the first query will have all their updates,
+        // so remember which ones they were.
+        let found_already: HashSet<i64> = tmvts.keys().cloned().collect();
+        for w in windows.iter() {
+            let args = named_params! {
+                ":tpl": tpl,
+                ":start_time": w.start_time,
+                ":end_time": w.end_time,
+                ":day_offset": w.day_offset,
+                ":date": w.date,
+                ":tpl_conn": connection
+            };
+            let rows = upd_stmt.query_map_named(args, |row| {
+                Ok((
+                    // The original train movement, whose update passes through `tpl`.
+                    TrainMvt::from_row(row, 0)?,
+                    // Its parent train.
+                    Train::from_row(row, TrainMvt::FIELDS)?,
+                    // Its corresponding connecting train movement.
+                    TrainMvt::from_row(row, TrainMvt::FIELDS + Train::FIELDS)?,
+                    // An update for the connecting train movement, if there is one.
+                    TrainMvt::from_row(row, 2 * TrainMvt::FIELDS + Train::FIELDS).ok()
+                ))
+            })?;
+            for row in rows {
+                let (tmvt, train, conn_tmvt, conn_updating_tmvt) = row?;
+                proc += 1;
+                let tmvt_id = tmvt.id;
+                if found_already.contains(&tmvt_id) {
+                    continue;
+                }
+                trains.insert(train.id, train);
+                if !tmvts.contains_key(&tmvt_id) {
+                    let updates = TrainMvt::from_select(&db, "WHERE updates = ?", params![tmvt_id])?;
+                    let mut ins = vec![tmvt];
+                    ins.extend(updates);
+                    tmvts.insert(tmvt_id, ins);
+                }
+                if let Some(conn_tmvts) = connecting_tmvts.get_mut(&tmvt_id) {
+                    conn_tmvts.extend(conn_updating_tmvt);
+                }
+                else {
+                    let mut ins = vec![conn_tmvt];
+                    ins.extend(conn_updating_tmvt);
+                    connecting_tmvts.insert(tmvt_id, ins);
+                }
             }
-            else {
-                let mut ins = vec![conn_tmvt];
-                ins.extend(conn_updating_tmvt);
-                connecting_tmvts.insert(tmvt_id, ins);
+        }
+        // As above, look through the archive for trains that aren't in the
+        // database any more.
+        for w in windows.iter() {
+            let archived = self.get_archived_trains_if_any(w.date)?;
+            for (details, mvts) in crate::archive::find_mvts_passing_through(&archived, &tpl, w) {
+                let conn_mvts = crate::archive::find_mvts_at(details, &connection);
+                if conn_mvts.is_empty() {
+                    continue;
+                }
+                proc += 1;
+                let tmvt_id = mvts[0].id;
+                trains.insert(details.train.id, details.train.clone());
+                tmvts.insert(tmvt_id, mvts);
+                connecting_tmvts.insert(tmvt_id, conn_mvts);
             }
         }
-        info!("Processed {} rows for a total of {} unique tmvts, {} connecting, and {} trains.",
-              proc, tmvts.len(), connecting_tmvts.len(), trains.len());
+        info!("Processed {} rows for a total of {} unique tmvts, {} connecting, and {} trains passing through {} and {} at {}.",
+              proc, tmvts.len(), connecting_tmvts.len(), trains.len(), tpl, connection, ts);
         Ok(ConnectingMvtQueryResponse {
             mvts: tmvts, 
             connecting_mvts: connecting_tmvts,
@@ -334,10 +599,11 @@ impl App {
                         AND action = ?
                         AND day_offset = ?
                         AND updates IS NULL
-                        AND source = ?
+                        AND (source = ? OR source = ?)
                         AND tiploc = ?",
                         params![train.id, upd.planned_time, upd.planned_action,
-                        upd.planned_day_offset, TrainMvt::SOURCE_SCHED_ITPS, upd.tiploc])?;
+                        upd.planned_day_offset, TrainMvt::SOURCE_SCHED_ITPS,
+                        TrainMvt::SOURCE_SCHED_DARWIN, upd.tiploc])?;
         if tmvts.len() > 1 {
             error!("Movement is ambiguous!");
             Err(ZugError::MovementsAmbiguous)?
@@ -361,7 +627,11 @@ impl App {
             source: TrainMvt::SOURCE_DARWIN,
             platform: upd.platform,
             pfm_suppr: upd.platsup,
-            unknown_delay: upd.delay_unknown
+            unknown_delay: upd.delay_unknown,
+            canx: false,
+            canx_reason: None,
+            allowance: 0,
+            canx_source: None
         };
         tmvt.id = tmvt.insert_self(&trans)?;
         info!("Inserted new movement #{}", tmvt.id);
@@ -399,7 +669,7 @@ impl App {
             id: -1,
             parent_train: train.id,
             updates: Some(updates.id),
-            tiploc: updates.tiploc,
+            tiploc: updates.tiploc.clone(),
             action: upd.planned_action,
             actual: true,
             time: upd.actual_time,
@@ -408,19 +678,118 @@ impl App {
             source: TrainMvt::SOURCE_TRUST,
             platform: upd.platform,
             pfm_suppr: false,
-            unknown_delay: false
+            unknown_delay: false,
+            canx: false,
+            canx_reason: None,
+            allowance: 0,
+            canx_source: None
         };
         tmvt.id = tmvt.insert_self(&trans)?;
         info!("Inserted new movement #{}", tmvt.id);
+        let estimates = Self::propagate_trust_delay(&trans, &train, &updates, &tmvt)?;
         trans.commit()?;
         self.cast.send_mvt(tmvt.clone());
+        for est in estimates {
+            self.cast.send_mvt(est);
+        }
         Ok(tmvt)
     }
+    /// Makes naïve estimates (`SOURCE_TRUST_NAIVE`) for all of a train's movements
+    /// after `updates`, given that it actually happened at the time in `actual`.
+    ///
+    /// Any old estimates get thrown away first, so the newest TRUST report always
+    /// wins -- unless `updates` comes before a movement that's already been
+    /// reported (i.e. TRUST messages arrived out of order), in which case the
+    /// estimates from the later movement are left alone.
+    fn propagate_trust_delay(conn: &Connection, train: &Train, updates: &TrainMvt, actual: &TrainMvt) -> ZugResult<Vec<TrainMvt>> {
+        let mut stmt = conn.prepare("SELECT updates FROM train_movements
+                                     WHERE parent_train = ? AND source = ? AND updates IS NOT NULL")?;
+        let done = stmt.query_map(params![train.id, TrainMvt::SOURCE_TRUST], |row| row.get(0))?
+            .collect::<RowResult<HashSet<i64>>>()?;
+        let schedule = Self::get_live_schedule(conn, train)?;
+        let latest = schedule.iter()
+            .filter(|x| done.contains(&x.id))
+            .map(|x| (x.day_offset, x.time, x.action))
+            .max();
+        if let Some(latest) = latest {
+            if (updates.day_offset, updates.time, updates.action) < latest {
+                debug!("Not propagating delay for train {} from before its latest movement", train.tspl_id);
+                return Ok(vec![]);
+            }
+        }
+        conn.execute("DELETE FROM train_movements WHERE parent_train = ? AND source = ?",
+                     params![train.id, TrainMvt::SOURCE_TRUST_NAIVE])?;
+        let mut ret = Self::naive_estimates(train, schedule, &done, updates, actual);
+        for tmvt in ret.iter_mut() {
+            tmvt.id = tmvt.insert_self(conn)?;
+        }
+        let crosses_midnight = ret.iter().any(|x| x.day_offset > 0);
+        if crosses_midnight && !train.crosses_midnight {
+            conn.execute("UPDATE trains SET crosses_midnight = true WHERE id = ?",
+                         params![train.id])?;
+        }
+        debug!("Made {} naive estimates for train {}", ret.len(), train.tspl_id);
+        Ok(ret)
+    }
+    /// Works out the estimates for `propagate_trust_delay` (without inserting
+    /// them), given the train's live `schedule` and the IDs of the movements
+    /// in it that are `done` already.
+    ///
+    /// The train's current lateness just gets carried forward, less any allowance
+    /// time in the schedule along the way; it isn't assumed to run early.
+    pub(crate) fn naive_estimates(train: &Train, schedule: Vec<TrainMvt>, done: &HashSet<i64>, updates: &TrainMvt, actual: &TrainMvt) -> Vec<TrainMvt> {
+        let mut lateness = actual.time.signed_duration_since(updates.time);
+        // Deal with the train being late (or early) across midnight.
+        if lateness > Duration::hours(12) {
+            lateness = lateness - Duration::days(1);
+        }
+        else if lateness < Duration::hours(-12) {
+            lateness = lateness + Duration::days(1);
+        }
+        debug!("Train {} is running {}s late", train.tspl_id, lateness.num_seconds());
+        let mut lateness = ::std::cmp::max(lateness, Duration::zero());
+        let mut ret = vec![];
+        for mvt in schedule {
+            if (mvt.day_offset, mvt.time, mvt.action) <= (updates.day_offset, updates.time, updates.action) {
+                continue;
+            }
+            lateness = ::std::cmp::max(lateness - Duration::seconds(mvt.allowance as _), Duration::zero());
+            // Movements that already have an actual time don't need estimating.
+            if mvt.canx || done.contains(&mvt.id) {
+                continue;
+            }
+            let scheduled = NaiveDateTime::new(train.date, mvt.time) + Duration::days(mvt.day_offset as _);
+            let estimated = scheduled + lateness;
+            let day_offset = estimated.date().signed_duration_since(train.date).num_days() as u8;
+            ret.push(TrainMvt {
+                id: -1,
+                parent_train: train.id,
+                updates: Some(mvt.id),
+                tiploc: mvt.tiploc,
+                action: mvt.action,
+                actual: false,
+                time: estimated.time(),
+                public_time: None,
+                day_offset,
+                source: TrainMvt::SOURCE_TRUST_NAIVE,
+                platform: None,
+                pfm_suppr: false,
+                unknown_delay: false,
+                canx: false,
+                canx_reason: None,
+                allowance: 0,
+                canx_source: None
+            });
+        }
+        ret
+    }
     fn get_train_for_darwin_rid(&self, rid: String) -> ZugResult<Train> {
         let db = self.pool.get()?;
-        let train = Train::from_select(&db, "WHERE darwin_rid = ?",
-                                       params![rid])?
-            .into_iter().nth(0).ok_or(ZugError::NotFound)?;
+        let train = match Train::from_select(&db, "WHERE darwin_rid = ?",
+                                             params![rid])?.into_iter().nth(0) {
+            Some(t) => t,
+            None => self.find_archived_train(&db, "darwin_rid", &rid)?.train
+        };
         Ok(train) 
     }
     fn get_train_for_trust_id(&self, trust_id: String, date: NaiveDate) -> ZugResult<Train> {
@@ -428,8 +797,23 @@ impl App {
         let previous_date = date.pred();
         let train = Train::from_select(&db, "WHERE trust_id = ? AND (date = ? OR (crosses_midnight = true AND date = ?))",
                                        params![trust_id, date, previous_date])?
-            .into_iter().nth(0).ok_or(ZugError::NotFound)?;
-        Ok(train)
+            .into_iter().nth(0);
+        if let Some(t) = train {
+            return Ok(t);
+        }
+        // The train might have been archived.
+        for d in &[date, previous_date] {
+            let found = self.get_archived_trains_if_any(*d)?
+                .iter()
+                .map(|x| &x.train)
+                .find(|t| t.trust_id.as_ref() == Some(&trust_id)
+                      && (t.date == date || t.crosses_midnight))
+                .cloned();
+            if let Some(t) = found {
+                return Ok(t);
+            }
+        }
+        Err(ZugError::NotFound)
     }
     fn terminate_train(&self, tid: Uuid) -> ZugResult<()> {
         let db = self.pool.get()?;
@@ -441,15 +825,319 @@ impl App {
         }
         Ok(())
     }
-    fn cancel_train(&self, tid: Uuid) -> ZugResult<()> {
-        let db = self.pool.get()?;
-        info!("Cancelling train {}", tid);
-        let rows = db.execute("UPDATE trains SET cancelled = true WHERE tspl_id = ?",
-                              params![tid])?;
+    /// Processes a TRUST cancellation.
+    ///
+    /// If `from` is given (for en-route and out-of-plan cancellations), the train
+    /// is cancelled from where it was due to depart or pass there onwards;
+    /// otherwise, the whole train is cancelled.
+    fn cancel_train(&self, tid: Uuid, from: Option<TrustLocation>, reason: Option<String>) -> ZugResult<TrainDetails> {
+        let mut db = self.pool.get()?;
+        let trans = db.transaction_with_behavior(TransactionBehavior::Exclusive)?;
+
+        let mut train = Train::from_select(&trans, "WHERE tspl_id = ?", params![tid])?
+            .into_iter().nth(0).ok_or(ZugError::NotFound)?;
+        let pivot = match from {
+            Some(ref loc) => {
+                info!("Cancelling train {} from STANOX {} at {}", tid, loc.stanox, loc.planned_time);
+                let ret = Self::find_departure_at(&trans, &train, loc)?;
+                if ret.is_none() {
+                    // Better to cancel too much than to have the train look
+                    // like it's running normally.
+                    warn!("Couldn't find where train {} was cancelled from; cancelling all of it", tid);
+                }
+                ret
+            },
+            None => {
+                info!("Cancelling train {}", tid);
+                None
+            }
+        };
+        let mut changed = vec![];
+        let mut live = Self::get_live_schedule(&trans, &train)?;
+        for mvt in live.iter_mut() {
+            if let Some(ref p) = pivot {
+                if (mvt.day_offset, mvt.time, mvt.action) < (p.day_offset, p.time, p.action) {
+                    continue;
+                }
+            }
+            if Self::set_mvt_canx(&trans, mvt, true, reason.as_ref().map(|x| x as &str), TrainMvt::SOURCE_TRUST)? {
+                changed.push(mvt.clone());
+            }
+        }
+        let was_cancelled = train.cancelled;
+        Self::update_train_cancelled(&trans, &mut train, &live)?;
+        self.finish_train_change(trans, train, changed, was_cancelled)
+    }
+    /// Processes the cancellation state of the movements in a Darwin schedule,
+    /// cancelling or un-cancelling the matching movements in the train's live
+    /// schedule.
+    ///
+    /// Movements cancelled by TRUST are left alone, since Darwin would
+    /// otherwise un-cancel them again every time it sent the schedule.
+    fn process_darwin_cancellations(&self, tid: Uuid, canxs: Vec<DarwinCancellation>, reason: Option<String>) -> ZugResult<TrainDetails> {
+        let mut db = self.pool.get()?;
+        let trans = db.transaction_with_behavior(TransactionBehavior::Exclusive)?;
+
+        let mut train = Train::from_select(&trans, "WHERE tspl_id = ?", params![tid])?
+            .into_iter().nth(0).ok_or(ZugError::NotFound)?;
+        debug!("Processing {} Darwin cancellation states for train {}", canxs.len(), tid);
+        let mut changed = vec![];
+        let mut live = Self::get_live_schedule(&trans, &train)?;
+        for canx in canxs {
+            let mvt = live.iter_mut()
+                .find(|m| m.tiploc == canx.tiploc && m.action == canx.planned_action
+                      && m.time == canx.planned_time && m.day_offset == canx.planned_day_offset);
+            let mvt = match mvt {
+                Some(m) => m,
+                None => {
+                    debug!("No movement at {} ({}) to (un)cancel", canx.tiploc, canx.planned_time);
+                    continue;
+                }
+            };
+            // Leave cancellations from TRUST alone.
+            if mvt.canx && mvt.canx_source != Some(TrainMvt::SOURCE_DARWIN) {
+                continue;
+            }
+            if Self::set_mvt_canx(&trans, mvt, canx.canx, reason.as_ref().map(|x| x as &str), TrainMvt::SOURCE_DARWIN)? {
+                changed.push(mvt.clone());
+            }
+        }
+        let was_cancelled = train.cancelled;
+        Self::update_train_cancelled(&trans, &mut train, &live)?;
+        self.finish_train_change(trans, train, changed, was_cancelled)
+    }
+    /// Marks the whole train as cancelled if every movement in its live
+    /// schedule `live` is cancelled (and as not cancelled otherwise).
+    fn update_train_cancelled(conn: &Connection, train: &mut Train, live: &[TrainMvt]) -> ZugResult<()> {
+        let cancelled = !live.is_empty() && live.iter().all(|m| m.canx);
+        if cancelled != train.cancelled {
+            conn.execute("UPDATE trains SET cancelled = ? WHERE id = ?",
+                         params![cancelled, train.id])?;
+            train.cancelled = cancelled;
+        }
+        Ok(())
+    }
+    /// Finds the movement in a train's live schedule where it departs from,
+    /// or passes, the location `loc`.
+    pub(crate) fn find_departure_at(conn: &Connection, train: &Train, loc: &TrustLocation) -> ZugResult<Option<TrainMvt>> {
+        let tmvts = TrainMvt::from_select(conn, "WHERE parent_train = ?
+                        AND time = ?
+                        AND (action = 1 OR action = 2)
+                        AND day_offset = ?
+                        AND +updates IS NULL
+                        AND tiploc IN (
+                            SELECT tiploc FROM corpus_entries
+                             WHERE corpus_entries.stanox = ?
+                        )",
+                        params![train.id, loc.planned_time, loc.planned_day_offset, loc.stanox])?;
+        if tmvts.len() > 1 {
+            error!("Movement is ambiguous!");
+            Err(ZugError::MovementsAmbiguous)?
+        }
+        Ok(tmvts.into_iter().nth(0))
+    }
+    /// Cancels (or un-cancels) a movement on behalf of `source`, returning
+    /// whether anything changed.
+    pub(crate) fn set_mvt_canx(conn: &Connection, mvt: &mut TrainMvt, canx: bool, reason: Option<&str>, source: i32) -> ZugResult<bool> {
+        let reason = if canx { reason.map(|x| x.to_owned()) } else { None };
+        let source = if canx { Some(source) } else { None };
+        if mvt.canx == canx && mvt.canx_reason == reason && mvt.canx_source == source {
+            return Ok(false);
+        }
+        mvt.canx = canx;
+        mvt.canx_reason = reason;
+        mvt.canx_source = source;
+        conn.execute("UPDATE train_movements SET canx = ?, canx_reason = ?, canx_source = ? WHERE id = ?",
+                     params![mvt.canx, mvt.canx_reason, mvt.canx_source, mvt.id])?;
+        Ok(true)
+    }
+    /// Gets a train's live schedule, sorted, for `set_mvt_canx` to work on.
+    fn get_live_schedule(conn: &Connection, train: &Train) -> ZugResult<Vec<TrainMvt>> {
+        let mvts = TrainMvt::from_select(conn, "WHERE parent_train = ? AND updates IS NULL
+                                                ORDER BY day_offset, time, action ASC",
+                                         params![train.id])?;
+        Ok(mvts)
+    }
+    /// Finishes off one of the functions below that change a train's movements,
+    /// broadcasting the changes and returning the train's details.
+    fn finish_train_change(&self, trans: Transaction, train: Train, changed: Vec<TrainMvt>, was_cancelled: bool) -> ZugResult<TrainDetails> {
+        let mvts = TrainMvt::from_select(&trans, "WHERE parent_train = ? ORDER BY day_offset, time, action, updates ASC", &[&train.id])?;
+        trans.commit()?;
+        info!("{} movements of train {} changed", changed.len(), train.tspl_id);
+        if was_cancelled != train.cancelled {
+            self.cast.send_sync(train.clone());
+        }
+        for mvt in changed {
+            self.cast.send_mvt(mvt);
+        }
+        Ok(TrainDetails { train, mvts })
+    }
+    /// Processes a TRUST change of origin: the train now starts from `origin`,
+    /// so everything before that gets cancelled.
+    fn change_origin(&self, tid: Uuid, origin: TrustLocation, reason: Option<String>) -> ZugResult<TrainDetails> {
+        let mut db = self.pool.get()?;
+        let trans = db.transaction_with_behavior(TransactionBehavior::Exclusive)?;
+
+        let mut train = Train::from_select(&trans, "WHERE tspl_id = ?", params![tid])?
+            .into_iter().nth(0).ok_or(ZugError::NotFound)?;
+        info!("Changing origin of train {} to STANOX {} at {}", tid, origin.stanox, origin.planned_time);
+        let was_cancelled = train.cancelled;
+        let changed = Self::apply_change_of_origin(&trans, &mut train, &origin, reason.as_ref().map(|x| x as &str))?;
+        self.finish_train_change(trans, train, changed, was_cancelled)
+    }
+    /// Does the work for `change_origin`, returning the movements that changed.
+    pub(crate) fn apply_change_of_origin(conn: &Connection, train: &mut Train, origin: &TrustLocation, reason: Option<&str>) -> ZugResult<Vec<TrainMvt>> {
+        let new_origin = Self::find_departure_at(conn, train, origin)?
+            .ok_or(ZugError::MovementsNotFound)?;
+        let mut changed = vec![];
+        for mut mvt in Self::get_live_schedule(conn, train)? {
+            // NB: this also cancels the arrival at the new origin, if there was one.
+            let before = (mvt.day_offset, mvt.time, mvt.action) <
+                (new_origin.day_offset, new_origin.time, new_origin.action);
+            let canx = if before {
+                true
+            }
+            else if mvt.id == new_origin.id {
+                // The new origin might have been cancelled by an earlier
+                // change of origin further down the line.
+                false
+            }
+            else {
+                continue;
+            };
+            if Self::set_mvt_canx(conn, &mut mvt, canx, reason, TrainMvt::SOURCE_TRUST)? {
+                changed.push(mvt);
+            }
+        }
+        // The train is still running, just from somewhere else (TRUST sometimes
+        // cancels the whole train before sending one of these).
+        conn.execute("UPDATE trains SET cancelled = false WHERE id = ?",
+                     params![train.id])?;
+        train.cancelled = false;
+        Ok(changed)
+    }
+    /// Processes a TRUST change of location: the movement at `original` (if
+    /// given) actually happens at `new` instead.
+    fn change_location(&self, tid: Uuid, new: TrustLocation, original: Option<TrustLocation>) -> ZugResult<TrainDetails> {
+        let mut db = self.pool.get()?;
+        let trans = db.transaction_with_behavior(TransactionBehavior::Exclusive)?;
+
+        let train = Train::from_select(&trans, "WHERE tspl_id = ?", params![tid])?
+            .into_iter().nth(0).ok_or(ZugError::NotFound)?;
+        info!("Changing location of train {} to STANOX {} at {}", tid, new.stanox, new.planned_time);
+        let mut changed = vec![];
+        if let Some(orig) = original {
+            let mut mvt = Self::find_departure_at(&trans, &train, &orig)?
+                .ok_or(ZugError::MovementsNotFound)?;
+            if Self::set_mvt_canx(&trans, &mut mvt, true, None, TrainMvt::SOURCE_TRUST)? {
+                changed.push(mvt);
+            }
+        }
+        match Self::find_departure_at(&trans, &train, &new)? {
+            Some(mut mvt) => {
+                if Self::set_mvt_canx(&trans, &mut mvt, false, None, TrainMvt::SOURCE_TRUST)? {
+                    changed.push(mvt);
+                }
+            },
+            None => {
+                // The train's never been scheduled to go there, so add a
+                // movement for it to its live schedule.
+                let mut stmt = trans.prepare("SELECT tiploc FROM corpus_entries
+                                              WHERE stanox = ? AND tiploc IS NOT NULL")?;
+                let tiplocs = stmt.query_map(params![new.stanox], |row| row.get(0))?
+                    .collect::<RowResult<Vec<String>>>()?;
+                let tiploc = tiplocs.into_iter().nth(0)
+                    .ok_or(ZugError::MovementsNotFound)?;
+                let mut tmvt = TrainMvt {
+                    id: -1,
+                    parent_train: train.id,
+                    updates: None,
+                    tiploc,
+                    action: 1,
+                    actual: false,
+                    time: new.planned_time,
+                    public_time: None,
+                    day_offset: new.planned_day_offset,
+                    source: TrainMvt::SOURCE_TRUST,
+                    platform: None,
+                    pfm_suppr: false,
+                    unknown_delay: false,
+                    canx: false,
+                    canx_reason: None,
+                    allowance: 0,
+                    canx_source: None
+                };
+                tmvt.id = tmvt.insert_self(&trans)?;
+                info!("Inserted new movement #{}", tmvt.id);
+                if tmvt.day_offset > 0 {
+                    trans.execute("UPDATE trains SET crosses_midnight = true WHERE id = ?",
+                                  params![train.id])?;
+                }
+                changed.push(tmvt);
+            }
+        }
+        let was_cancelled = train.cancelled;
+        self.finish_train_change(trans, train, changed, was_cancelled)
+    }
+    /// Processes a TRUST reinstatement: the train (which was cancelled) is
+    /// running again from `from` onwards.
+    fn reinstate_train(&self, tid: Uuid, from: TrustLocation) -> ZugResult<TrainDetails> {
+        let mut db = self.pool.get()?;
+        let trans = db.transaction_with_behavior(TransactionBehavior::Exclusive)?;
+
+        let mut train = Train::from_select(&trans, "WHERE tspl_id = ?", params![tid])?
+            .into_iter().nth(0).ok_or(ZugError::NotFound)?;
+        info!("Reinstating train {} from STANOX {} at {}", tid, from.stanox, from.planned_time);
+        let was_cancelled = train.cancelled;
+        let changed = Self::apply_reinstatement(&trans, &mut train, &from)?;
+        self.finish_train_change(trans, train, changed, was_cancelled)
+    }
+    /// Does the work for `reinstate_train`, returning the movements that changed.
+    pub(crate) fn apply_reinstatement(conn: &Connection, train: &mut Train, from: &TrustLocation) -> ZugResult<Vec<TrainMvt>> {
+        let mut changed = vec![];
+        // If we can't find where it was reinstated from, just reinstate all of it;
+        // that's better than leaving it cancelled.
+        let pivot = Self::find_departure_at(conn, train, from)?;
+        for mut mvt in Self::get_live_schedule(conn, train)? {
+            if let Some(ref p) = pivot {
+                if (mvt.day_offset, mvt.time, mvt.action) < (p.day_offset, p.time, p.action) {
+                    continue;
+                }
+            }
+            if Self::set_mvt_canx(conn, &mut mvt, false, None, TrainMvt::SOURCE_TRUST)? {
+                changed.push(mvt);
+            }
+        }
+        conn.execute("UPDATE trains SET cancelled = false WHERE id = ?",
+                     params![train.id])?;
+        train.cancelled = false;
+        Ok(changed)
+    }
+    /// Processes a TRUST change of identity, which changes the train's TRUST ID
+    /// (and, with it, its headcode).
+    fn change_identity(&self, tid: Uuid, trust_id: String) -> ZugResult<Train> {
+        let mut db = self.pool.get()?;
+        let trans = db.transaction()?;
+        let train = Self::apply_change_of_identity(&trans, tid, &trust_id)?;
+        trans.commit()?;
+        self.cast.send_sync(train.clone());
+        Ok(train)
+    }
+    /// Does the work for `change_identity`, returning the updated train.
+    pub(crate) fn apply_change_of_identity(conn: &Connection, tid: Uuid, trust_id: &str) -> ZugResult<Train> {
+        // The headcode lives in characters 3-6 of the TRUST ID.
+        let headcode = trust_id.get(2..6)
+            .ok_or_else(|| ZugError::InvalidBody(format!("TRUST ID {:?} is too short", trust_id)))?
+            .to_owned();
+        info!("Changing identity of {} to TRUST ID {} (headcode {})", tid, trust_id, headcode);
+        let rows = conn.execute("UPDATE trains SET trust_id = ?, headcode = ? WHERE tspl_id = ?",
+                                params![trust_id, headcode, tid])?;
         if rows == 0 {
             Err(ZugError::NotFound)?
         }
-        Ok(())
+        let train = Train::from_select(conn, "WHERE tspl_id = ?", params![tid])?
+            .into_iter().nth(0).ok_or(ZugError::NotFound)?;
+        Ok(train)
     }
     fn associate_trust_id(&self, tid: Uuid, trust_id: String) -> ZugResult<()> {
         let db = self.pool.get()?;
@@ -470,6 +1158,105 @@ impl App {
         self.cast.send_activation(ret.clone());
         Ok(ret)
     }
+    fn process_darwin_schedule(&self, rid: String, sched_id: Uuid) -> ZugResult<Train> {
+        let details = self.activator.get_schedule_details(sched_id)?;
+        info!("Processing Darwin schedule {} for RID {}", sched_id, rid);
+        let mut train = match self.get_train_for_darwin_rid(rid.clone()).optional()? {
+            Some(t) => t,
+            None => {
+                // Try and find an ITPS schedule first, so the two can be merged;
+                // failing that, this is a Darwin-only service.
+                let uid = details.sched.uid.clone();
+                match self.activate_train_darwin(uid, details.sched.start_date, rid.clone()).optional()? {
+                    Some(t) => t,
+                    None => {
+                        let db = self.pool.get()?;
+                        let mut ret = self.activator.activate_train_darwin_only(&details.sched)?;
+                        db.execute("UPDATE trains SET darwin_rid = ? WHERE id = ?",
+                                   params![rid, ret.id])?;
+                        ret.darwin_rid = Some(rid.clone());
+                        self.cast.send_activation(ret.clone());
+                        ret
+                    }
+                }
+            }
+        };
+        let mut db = self.pool.get()?;
+        let trans = db.transaction_with_behavior(TransactionBehavior::Exclusive)?;
+        let existing = TrainMvt::from_select(&trans, "WHERE parent_train = ? AND source = ?",
+                                             params![train.id, TrainMvt::SOURCE_SCHED_DARWIN])?;
+        let mut originals = TrainMvt::from_select(&trans, "WHERE parent_train = ? AND source = ? AND updates IS NULL",
+                                                  params![train.id, TrainMvt::SOURCE_SCHED_ITPS])?;
+        // Darwin schedule movements that line up with an ITPS one are stored as
+        // updates to the ITPS movement, so they don't get counted twice; anything
+        // else (e.g. extra calls) is part of the train's schedule in its own right.
+        let mut new = vec![];
+        for smvt in details.mvts {
+            let mut tmvt = TrainMvt::from_darwin(smvt);
+            tmvt.parent_train = train.id;
+            tmvt.updates = originals.iter()
+                .find(|o| o.tiploc == tmvt.tiploc && o.action == tmvt.action
+                      && o.time == tmvt.time && o.day_offset == tmvt.day_offset)
+                .map(|o| o.id);
+            new.push(tmvt);
+        }
+        let kept = new.iter()
+            .filter_map(|n| n.updates)
+            .collect::<HashSet<_>>();
+        let same = |a: &TrainMvt, b: &TrainMvt| {
+            a.updates == b.updates && a.tiploc == b.tiploc && a.action == b.action
+                && a.time == b.time && a.day_offset == b.day_offset
+                && a.public_time == b.public_time && a.platform == b.platform
+        };
+        let mut deleted = 0;
+        for old in existing.iter() {
+            if !new.iter().any(|n| same(old, n)) {
+                // NB: this cascades to any live updates for the movement, which
+                // is what we want (the movement doesn't exist any more).
+                trans.execute("DELETE FROM train_movements WHERE id = ?", params![old.id])?;
+                deleted += 1;
+            }
+        }
+        let mut inserted = vec![];
+        for mut tmvt in new {
+            if !existing.iter().any(|e| same(e, &tmvt)) {
+                tmvt.id = tmvt.insert_self(&trans)?;
+                inserted.push(tmvt);
+            }
+        }
+        // ITPS movements that the Darwin schedule doesn't have any more have
+        // been taken out of the train's schedule, so cancel them. (If Darwin
+        // puts them back, its cancellation states will un-cancel them.)
+        let mut changed = vec![];
+        for orig in originals.iter_mut() {
+            if orig.canx || kept.contains(&orig.id) {
+                continue;
+            }
+            if Self::set_mvt_canx(&trans, orig, true, None, TrainMvt::SOURCE_DARWIN)? {
+                changed.push(orig.clone());
+            }
+        }
+        let was_cancelled = train.cancelled;
+        let live = Self::get_live_schedule(&trans, &train)?;
+        Self::update_train_cancelled(&trans, &mut train, &live)?;
+        let crosses_midnight = details.sched.crosses_midnight || inserted.iter().any(|x| x.day_offset > 0);
+        trans.execute("UPDATE trains
+                       SET activated = true,
+                           crosses_midnight = (crosses_midnight OR ?),
+                           headcode = COALESCE(headcode, ?)
+                       WHERE id = ?",
+                       params![crosses_midnight, details.sched.signalling_id, train.id])?;
+        trans.commit()?;
+        info!("Darwin schedule for {}: {} movements inserted, {} deleted, {} cancelled",
+              train.tspl_id, inserted.len(), deleted, changed.len());
+        if was_cancelled != train.cancelled {
+            self.cast.send_sync(train.clone());
+        }
+        for tmvt in inserted.into_iter().chain(changed) {
+            self.cast.send_mvt(tmvt);
+        }
+        Ok(train)
+    }
     fn activate_train(&self, uid: String, start_date: NaiveDate, stp_indicator: String, source: i32, date: NaiveDate) -> ZugResult<Train> {
         let ret = self.activator.activate_train_nrod(uid, start_date, stp_indicator, source, date)?;
         self.cast.send_activation(ret.clone());
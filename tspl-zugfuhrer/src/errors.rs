@@ -7,6 +7,8 @@ use tspl_util::http::StatusCode;
 use tspl_sqlite::errors::{SqlError, PoolError};
 use tspl_sqlite::rusqlite::Error as RsqlError;
 use reqwest::Error as ReqwestError;
+use serde_json::Error as JsonError;
+use std::io::Error as IoError;
 use tspl_util::rpc::RpcError;
 
 /// Error that could occur when processing a request.
@@ -27,6 +29,9 @@ pub enum ZugError {
     /// Headers missing or failed to parse.
     #[fail(display = "bad request")]
     HeadersMissing,
+    /// Request body missing or failed to parse.
+    #[fail(display = "invalid request body: {}", _0)]
+    InvalidBody(String),
     /// RPC error.
     #[fail(display = "RPC: {}", _0)]
     Rpc(RpcError),
@@ -41,7 +46,13 @@ pub enum ZugError {
     Pool(PoolError),
     /// reqwest error.
     #[fail(display = "reqwest: {}", _0)]
-    Reqwest(ReqwestError)
+    Reqwest(ReqwestError),
+    /// I/O error (e.g. when reading archive files).
+    #[fail(display = "I/O error: {}", _0)]
+    Io(IoError),
+    /// JSON (de)serialization error.
+    #[fail(display = "JSON: {}", _0)]
+    Json(JsonError)
 }
 
 impl StatusCode for ZugError {
@@ -53,6 +64,7 @@ impl StatusCode for ZugError {
             MovementsNotFound => 404,
             MovementsAmbiguous => 409,
             HeadersMissing => 400,
+            InvalidBody(_) => 400,
             InvalidPath => 400,
             Rpc(ref r) => r.status_code(),
             Pool(_) => 503,
@@ -84,7 +96,9 @@ impl_from_for_error!(ZugError,
                      ReqwestError => Reqwest,
                      SqlError => Sql,
                      PoolError => Pool,
-                     RpcError => Rpc);
+                     RpcError => Rpc,
+                     IoError => Io,
+                     JsonError => Json);
 
 pub type ZugResult<T> = ::std::result::Result<T, ZugError>;
 pub type Result<T, E = Error> = ::std::result::Result<T, E>;
@@ -0,0 +1,354 @@
+//! Producing GTFS-Realtime TripUpdates from live train data.
+//!
+//! Trip IDs match those produced by the GTFS static exporter in
+//! `tspl-fahrplan-updater` (see `tspl_fahrplan::types::gtfs_trip_id`), so
+//! only trains activated from ITPS schedules are included.
+//!
+//! Calls that have been cancelled are sent as `SKIPPED`, without any times.
+//!
+//! The static exporter leaves out calls at TIPLOCs that it doesn't have
+//! coordinates for, so updates are only sent for the stops listed in its
+//! `stops.txt`, and carry the same `stop_sequence` as in its `stop_times.txt`.
+//!
+//! The protobuf encoding is done by hand, since we only need to write a
+//! handful of message types. Field numbers are taken from
+//! `gtfs-realtime.proto`.
+
+use tspl_sqlite::TsplPool;
+use tspl_sqlite::traits::*;
+use tspl_fahrplan::types as fpt;
+use chrono::prelude::*;
+use chrono::Duration;
+use chrono_tz::Europe::London;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use log::*;
+
+use crate::errors::*;
+use crate::types::*;
+
+/// A protobuf message being written.
+#[derive(Default)]
+pub(crate) struct PbWriter {
+    pub(crate) buf: Vec<u8>
+}
+impl PbWriter {
+    const WIRE_VARINT: u32 = 0;
+    const WIRE_LEN: u32 = 2;
+
+    pub(crate) fn varint(&mut self, mut val: u64) {
+        while val >= 0x80 {
+            self.buf.push((val as u8 & 0x7f) | 0x80);
+            val >>= 7;
+        }
+        self.buf.push(val as u8);
+    }
+    pub(crate) fn key(&mut self, field: u32, wire: u32) {
+        self.varint(((field << 3) | wire) as u64);
+    }
+    pub(crate) fn uint64(&mut self, field: u32, val: u64) {
+        self.key(field, Self::WIRE_VARINT);
+        self.varint(val);
+    }
+    pub(crate) fn int64(&mut self, field: u32, val: i64) {
+        // Negative numbers are sign-extended to 64 bits (and so take
+        // up 10 bytes), as per the protobuf spec for int32/int64.
+        self.uint64(field, val as u64);
+    }
+    pub(crate) fn enumeration(&mut self, field: u32, val: u32) {
+        self.uint64(field, val as u64);
+    }
+    pub(crate) fn string(&mut self, field: u32, val: &str) {
+        self.bytes(field, val.as_bytes());
+    }
+    pub(crate) fn bytes(&mut self, field: u32, val: &[u8]) {
+        self.key(field, Self::WIRE_LEN);
+        self.varint(val.len() as u64);
+        self.buf.extend_from_slice(val);
+    }
+    pub(crate) fn message(&mut self, field: u32, msg: PbWriter) {
+        self.bytes(field, &msg.buf);
+    }
+}
+
+/// `TripDescriptor.ScheduleRelationship.SCHEDULED`
+const TRIP_SCHEDULED: u32 = 0;
+/// `TripDescriptor.ScheduleRelationship.CANCELED`
+const TRIP_CANCELED: u32 = 3;
+/// `TripUpdate.StopTimeUpdate.ScheduleRelationship.SKIPPED`
+const STOP_SKIPPED: u32 = 1;
+/// `FeedHeader.Incrementality.FULL_DATASET`
+const FULL_DATASET: u32 = 0;
+
+/// A `TripUpdate.StopTimeEvent`.
+pub(crate) struct StopTimeEvent {
+    pub(crate) delay: i64,
+    pub(crate) time: i64
+}
+impl StopTimeEvent {
+    pub(crate) fn encode(&self) -> PbWriter {
+        let mut w = PbWriter::default();
+        w.int64(1, self.delay);
+        w.int64(2, self.time);
+        w
+    }
+}
+/// A `TripUpdate.StopTimeUpdate`.
+pub(crate) struct StopTimeUpdate {
+    /// Position of the call in the static feed's `stop_times.txt` (from 1).
+    pub(crate) stop_sequence: u32,
+    pub(crate) stop_id: String,
+    pub(crate) arrival: Option<StopTimeEvent>,
+    pub(crate) departure: Option<StopTimeEvent>,
+    /// Whether the train isn't calling here any more (in which case there
+    /// aren't any times).
+    pub(crate) skipped: bool
+}
+impl StopTimeUpdate {
+    pub(crate) fn encode(self) -> PbWriter {
+        let mut w = PbWriter::default();
+        w.uint64(1, self.stop_sequence as u64);
+        if let Some(a) = self.arrival {
+            w.message(2, a.encode());
+        }
+        if let Some(d) = self.departure {
+            w.message(3, d.encode());
+        }
+        w.string(4, &self.stop_id);
+        if self.skipped {
+            w.enumeration(5, STOP_SKIPPED);
+        }
+        w
+    }
+}
+/// A `FeedEntity` containing a `TripUpdate`.
+pub(crate) struct TripUpdateEntity {
+    pub(crate) id: String,
+    pub(crate) trip_id: String,
+    pub(crate) start_date: NaiveDate,
+    pub(crate) cancelled: bool,
+    pub(crate) updates: Vec<StopTimeUpdate>
+}
+impl TripUpdateEntity {
+    pub(crate) fn encode(self, timestamp: u64) -> PbWriter {
+        let mut trip = PbWriter::default();
+        trip.string(1, &self.trip_id);
+        trip.string(3, &self.start_date.format("%Y%m%d").to_string());
+        trip.enumeration(4, if self.cancelled { TRIP_CANCELED } else { TRIP_SCHEDULED });
+        let mut tu = PbWriter::default();
+        tu.message(1, trip);
+        for stu in self.updates {
+            tu.message(2, stu.encode());
+        }
+        tu.uint64(4, timestamp);
+        let mut ent = PbWriter::default();
+        ent.string(1, &self.id);
+        ent.message(3, tu);
+        ent
+    }
+}
+
+/// Converts a movement's date and time (in UK local time) into a Unix timestamp.
+fn unix_time(date: NaiveDate, day_offset: u8, time: NaiveTime) -> Option<i64> {
+    let ndt = (date + Duration::days(day_offset as _)).and_time(time);
+    London.from_local_datetime(&ndt)
+        .earliest()
+        .map(|x| x.timestamp())
+}
+
+/// Picks the best live update out of a set of updates to a movement.
+///
+/// Actual times are preferred over estimates, and Darwin estimates
+/// over TRUST ones.
+fn best_live_update<'a>(updates: &[&'a TrainMvt]) -> Option<&'a TrainMvt> {
+    fn rank(m: &TrainMvt) -> (bool, u8) {
+        let src = match m.source {
+            TrainMvt::SOURCE_DARWIN => 2,
+            TrainMvt::SOURCE_TRUST => 1,
+            _ => 0
+        };
+        (m.actual, src)
+    }
+    updates.iter()
+        .cloned()
+        .filter(|m| match m.source {
+            TrainMvt::SOURCE_TRUST | TrainMvt::SOURCE_DARWIN | TrainMvt::SOURCE_TRUST_NAIVE => true,
+            _ => false
+        })
+        .max_by_key(|m| rank(m))
+}
+
+/// Whether the call that the movement `live[i]` (in a train's live schedule)
+/// belongs to has been cancelled.
+///
+/// Stops only get skipped if the train isn't arriving at or departing from
+/// them at all; e.g. a train cut short still arrives at its new destination.
+pub(crate) fn call_cancelled(live: &[&TrainMvt], i: usize) -> bool {
+    let mvt = live[i];
+    let prev = if i > 0 { live.get(i - 1) } else { None };
+    mvt.canx && prev.into_iter()
+        .chain(live.get(i + 1))
+        .filter(|m| m.tiploc == mvt.tiploc && m.action != mvt.action
+                && m.action != fpt::ScheduleMvt::ACTION_PASS)
+        .all(|m| m.canx)
+}
+
+/// Reads the stop IDs (i.e. TIPLOCs) out of the static feed's `stops.txt`.
+///
+/// The static exporter always writes `stop_id` as the first column, so
+/// the rest of each line doesn't need parsing.
+pub fn load_stops<P: AsRef<Path>>(path: P) -> ZugResult<HashSet<String>> {
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    let header = lines.next().transpose()?.unwrap_or_default();
+    if header.split(',').next() != Some("stop_id") {
+        let e = io::Error::new(io::ErrorKind::InvalidData, "stops.txt doesn't start with a stop_id column");
+        return Err(e.into());
+    }
+    let mut ret = HashSet::new();
+    for line in lines {
+        let line = line?;
+        if let Some(id) = line.split(',').next() {
+            if id != "" {
+                ret.insert(id.to_owned());
+            }
+        }
+    }
+    Ok(ret)
+}
+
+/// Works out the `stop_sequence` of each movement in a train's live schedule,
+/// for those that are calls in the static feed.
+///
+/// This follows `mvts_to_stop_times` in the static exporter: arrivals and
+/// departures at the same place are one call, and passes, movements without
+/// a public time, and calls at places not in `stops` aren't calls at all.
+pub(crate) fn stop_sequences(live: &[&TrainMvt], stops: &HashSet<String>) -> Vec<Option<u32>> {
+    let mut ret = vec![];
+    let mut seq = 0;
+    let mut last_tiploc: Option<&str> = None;
+    let mut last_was_arrival = false;
+    for mvt in live {
+        if mvt.public_time.is_none() {
+            last_was_arrival = false;
+            ret.push(None);
+            continue;
+        }
+        if !stops.contains(&mvt.tiploc) {
+            ret.push(None);
+            continue;
+        }
+        match mvt.action {
+            fpt::ScheduleMvt::ACTION_ARRIVAL => {
+                seq += 1;
+                ret.push(Some(seq));
+                last_tiploc = Some(mvt.tiploc.as_str());
+                last_was_arrival = true;
+                continue;
+            },
+            fpt::ScheduleMvt::ACTION_DEPARTURE => {
+                if !last_was_arrival || last_tiploc != Some(mvt.tiploc.as_str()) {
+                    seq += 1;
+                }
+                ret.push(Some(seq));
+                last_tiploc = Some(mvt.tiploc.as_str());
+            },
+            _ => ret.push(None)
+        }
+        last_was_arrival = false;
+    }
+    ret
+}
+
+/// Builds the trip update entity for one train, if there's anything to say about it.
+///
+/// `stops` is the set of stops in the static feed.
+pub(crate) fn trip_update_for_train(train: &Train, mvts: &[TrainMvt], stops: &HashSet<String>) -> Option<TripUpdateEntity> {
+    let mut updates: Vec<StopTimeUpdate> = vec![];
+    let live = mvts.iter().filter(|m| m.updates.is_none()).collect::<Vec<_>>();
+    let seqs = stop_sequences(&live, stops);
+    for (i, orig) in live.iter().enumerate() {
+        // Only calls that appear in the static feed get updates.
+        let seq = match seqs[i] {
+            Some(s) => s,
+            None => continue
+        };
+        if orig.canx {
+            let already = updates.last()
+                .map(|x| x.stop_sequence == seq && x.skipped)
+                .unwrap_or(false);
+            if call_cancelled(&live, i) && !already {
+                updates.push(StopTimeUpdate { stop_sequence: seq, stop_id: orig.tiploc.clone(), arrival: None, departure: None, skipped: true });
+            }
+            continue;
+        }
+        let upds = mvts.iter()
+            .filter(|m| m.updates == Some(orig.id))
+            .collect::<Vec<_>>();
+        let live = match best_live_update(&upds) {
+            Some(l) => l,
+            None => continue
+        };
+        let sched_time = unix_time(train.date, orig.day_offset, orig.time);
+        let live_time = unix_time(train.date, live.day_offset, live.time);
+        let evt = match (sched_time, live_time) {
+            (Some(s), Some(l)) => StopTimeEvent { delay: l - s, time: l },
+            _ => continue
+        };
+        // Merge arrivals and departures at the same place into one update.
+        let merge = updates.last()
+            .map(|x| x.stop_sequence == seq && x.departure.is_none() && !x.skipped
+                 && orig.action == fpt::ScheduleMvt::ACTION_DEPARTURE)
+            .unwrap_or(false);
+        if merge {
+            updates.last_mut().unwrap().departure = Some(evt);
+        }
+        else if orig.action == fpt::ScheduleMvt::ACTION_ARRIVAL {
+            updates.push(StopTimeUpdate { stop_sequence: seq, stop_id: orig.tiploc.clone(), arrival: Some(evt), departure: None, skipped: false });
+        }
+        else {
+            updates.push(StopTimeUpdate { stop_sequence: seq, stop_id: orig.tiploc.clone(), arrival: None, departure: Some(evt), skipped: false });
+        }
+    }
+    if updates.len() == 0 && !train.cancelled {
+        return None;
+    }
+    Some(TripUpdateEntity {
+        id: train.tspl_id.to_string(),
+        trip_id: fpt::gtfs_trip_id(&train.parent_uid, train.parent_start_date, &train.parent_stp_indicator),
+        start_date: train.date,
+        cancelled: train.cancelled,
+        updates: if train.cancelled { vec![] } else { updates }
+    })
+}
+
+/// Builds a GTFS-Realtime `FeedMessage` with TripUpdates for all ITPS trains
+/// running today (or yesterday, if they cross midnight), for calls at `stops`.
+pub fn build_trip_updates(pool: &TsplPool, stops: &HashSet<String>) -> ZugResult<Vec<u8>> {
+    let db = pool.get()?;
+    let now = Utc::now();
+    let today = now.with_timezone(&London).date().naive_local();
+    let trains = Train::from_select(&db, "WHERE activated = true AND parent_source = ?
+                                           AND (date = ? OR (date = ? AND crosses_midnight = true))",
+                                    params![fpt::Schedule::SOURCE_ITPS as i32, today, today.pred()])?;
+    let mut stmt = db.prepare_cached("SELECT * FROM train_movements WHERE parent_train = ?
+                                      ORDER BY day_offset, time, action, source ASC")?;
+    let mut feed = PbWriter::default();
+    let mut header = PbWriter::default();
+    header.string(1, "2.0");
+    header.enumeration(2, FULL_DATASET);
+    header.uint64(3, now.timestamp() as u64);
+    feed.message(1, header);
+    let mut n_entities = 0;
+    for train in trains {
+        let mvts = stmt.query_map(params![train.id], |row| TrainMvt::from_row(row, 0))?
+            .collect::<RowResult<Vec<_>>>()?;
+        if let Some(ent) = trip_update_for_train(&train, &mvts, stops) {
+            feed.message(2, ent.encode(now.timestamp() as u64));
+            n_entities += 1;
+        }
+    }
+    debug!("Built GTFS-RT feed with {} trip updates ({} bytes)", n_entities, feed.buf.len());
+    Ok(feed.buf)
+}
@@ -7,6 +7,11 @@ pub mod ctx;
 pub mod corpus;
 pub mod activation;
 pub mod broadcast;
+pub mod gtfs_rt;
+pub mod archive;
+
+#[cfg(test)]
+mod tests;
 
 use log::*;
 use tspl_util::ConfigExt;
@@ -15,6 +20,7 @@ use self::config::Config;
 use crate::corpus::CorpusDownloader;
 use crate::broadcast::{LiveBroadcaster, BroadcastSender};
 use crate::ctx::App;
+use crate::archive::Archiver;
 use std::thread;
 use errors::Result;
 
@@ -35,6 +41,15 @@ fn main() -> Result<()> {
             }
         });
     }
+    if let Some(archiver) = Archiver::new(pool.clone(), &cfg) {
+        info!("setting up archival");
+        thread::spawn(move || {
+            archiver.run();
+        });
+    }
+    else {
+        info!("archival is disabled");
+    }
     let (broadcast, brx) = crossbeam_channel::unbounded();
     if let Some(ll) = cfg.listen_live.as_ref() {
         info!("setting up live broadcasting");
@@ -50,6 +65,18 @@ fn main() -> Result<()> {
             }
         });
     }
-    let app = App::new(pool, BroadcastSender(broadcast), &cfg);
+    let gtfs_stops = match cfg.gtfs_stops_path {
+        Some(ref path) => {
+            info!("loading GTFS stops");
+            let stops = gtfs_rt::load_stops(path)?;
+            info!("GTFS-RT trip updates will cover {} stops", stops.len());
+            Some(stops)
+        },
+        None => {
+            info!("GTFS-RT is disabled");
+            None
+        }
+    };
+    let app = App::new(pool, BroadcastSender(broadcast), &cfg, gtfs_stops);
     tspl_util::http::start_server(&cfg.listen, app);
 }
@@ -0,0 +1,481 @@
+use chrono::*;
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tspl_sqlite::uuid::Uuid;
+use tspl_sqlite::traits::*;
+use tspl_util::time::MvtQueryWindow;
+
+use crate::archive::*;
+use crate::ctx::App;
+use crate::errors::ZugError;
+use crate::gtfs_rt::{PbWriter, StopTimeEvent, StopTimeUpdate, call_cancelled, stop_sequences, trip_update_for_train};
+use crate::types::*;
+
+fn train() -> Train {
+    Train {
+        id: 1,
+        tspl_id: Uuid::nil(),
+        parent_uid: "C12345".into(),
+        parent_start_date: NaiveDate::from_ymd(2019, 01, 01),
+        parent_stp_indicator: "P".into(),
+        date: NaiveDate::from_ymd(2019, 05, 01),
+        trust_id: None,
+        darwin_rid: None,
+        headcode: None,
+        crosses_midnight: false,
+        parent_source: TrainMvt::SOURCE_SCHED_ITPS,
+        terminated: false,
+        cancelled: false,
+        activated: true
+    }
+}
+fn mvt(id: i64, tiploc: &str, action: u8, h: u32, m: u32, day_offset: u8) -> TrainMvt {
+    TrainMvt {
+        id,
+        parent_train: 1,
+        updates: None,
+        tiploc: tiploc.into(),
+        action,
+        actual: false,
+        time: NaiveTime::from_hms(h, m, 0),
+        public_time: None,
+        day_offset,
+        source: TrainMvt::SOURCE_SCHED_ITPS,
+        platform: None,
+        pfm_suppr: false,
+        unknown_delay: false,
+        canx: false,
+        canx_reason: None,
+        allowance: 0,
+        canx_source: None
+    }
+}
+fn actual(of: &TrainMvt, h: u32, m: u32) -> TrainMvt {
+    TrainMvt {
+        id: 100,
+        updates: Some(of.id),
+        actual: true,
+        time: NaiveTime::from_hms(h, m, 0),
+        source: TrainMvt::SOURCE_TRUST,
+        ..of.clone()
+    }
+}
+/// Estimated (time, day offset) for each estimate, keyed by the movement it updates.
+fn estimates(ests: &[TrainMvt]) -> Vec<(i64, NaiveTime, u8)> {
+    ests.iter()
+        .map(|x| (x.updates.unwrap(), x.time, x.day_offset))
+        .collect()
+}
+
+#[test]
+fn naive_estimates_subtract_allowances() {
+    let origin = mvt(1, "EUSTON", 1, 12, 00, 0);
+    let mut a = mvt(2, "WATFDJ", 2, 12, 15, 0);
+    a.allowance = 120;
+    let b = mvt(3, "MKC", 0, 12, 45, 0);
+    let mut c = mvt(4, "RUGBY", 0, 13, 10, 0);
+    c.allowance = 600;
+    let schedule = vec![origin.clone(), a, b, c];
+    let done = vec![origin.id].into_iter().collect::<HashSet<_>>();
+    // Ten minutes late away from Euston.
+    let ests = App::naive_estimates(&train(), schedule, &done, &origin, &actual(&origin, 12, 10));
+    assert_eq!(estimates(&ests), vec![
+        (2, NaiveTime::from_hms(12, 23, 0), 0),
+        (3, NaiveTime::from_hms(12, 53, 0), 0),
+        // The allowance here is more than the remaining lateness, but the
+        // train isn't assumed to run early.
+        (4, NaiveTime::from_hms(13, 10, 0), 0)
+    ]);
+    assert!(ests.iter().all(|x| x.source == TrainMvt::SOURCE_TRUST_NAIVE && !x.actual));
+}
+#[test]
+fn naive_estimates_clamp_early_running() {
+    let origin = mvt(1, "EUSTON", 1, 12, 00, 0);
+    let a = mvt(2, "WATFDJ", 2, 12, 15, 0);
+    let schedule = vec![origin.clone(), a];
+    let done = vec![origin.id].into_iter().collect::<HashSet<_>>();
+    let ests = App::naive_estimates(&train(), schedule, &done, &origin, &actual(&origin, 11, 57));
+    assert_eq!(estimates(&ests), vec![(2, NaiveTime::from_hms(12, 15, 0), 0)]);
+}
+#[test]
+fn naive_estimates_midnight_wrap() {
+    let a = mvt(1, "CREWE", 1, 23, 50, 0);
+    let b = mvt(2, "STAFFRD", 2, 23, 58, 0);
+    let c = mvt(3, "BHAMNWS", 0, 00, 30, 1);
+    let schedule = vec![a.clone(), b, c];
+    let done = vec![a.id].into_iter().collect::<HashSet<_>>();
+    // Fifteen minutes late, reported the day after.
+    let ests = App::naive_estimates(&train(), schedule, &done, &a, &actual(&a, 00, 05));
+    assert_eq!(estimates(&ests), vec![
+        (2, NaiveTime::from_hms(00, 13, 0), 1),
+        (3, NaiveTime::from_hms(00, 45, 0), 1)
+    ]);
+    // Early across midnight the other way: clamped, rather than a day late.
+    let c = mvt(3, "BHAMNWS", 0, 00, 30, 1);
+    let schedule = vec![c.clone(), mvt(4, "WVRMPTN", 0, 00, 50, 1)];
+    let done = vec![c.id].into_iter().collect::<HashSet<_>>();
+    let ests = App::naive_estimates(&train(), schedule, &done, &c, &actual(&c, 23, 59));
+    assert_eq!(estimates(&ests), vec![(4, NaiveTime::from_hms(00, 50, 0), 1)]);
+}
+#[test]
+fn naive_estimates_skip_done_and_cancelled() {
+    let origin = mvt(1, "EUSTON", 1, 12, 00, 0);
+    let mut a = mvt(2, "WATFDJ", 2, 12, 15, 0);
+    a.canx = true;
+    a.allowance = 60;
+    let mut b = mvt(3, "MKC", 0, 12, 45, 0);
+    b.allowance = 60;
+    let c = mvt(4, "RUGBY", 0, 13, 10, 0);
+    let schedule = vec![origin.clone(), a, b, c];
+    let done = vec![origin.id, 3].into_iter().collect::<HashSet<_>>();
+    let ests = App::naive_estimates(&train(), schedule, &done, &origin, &actual(&origin, 12, 10));
+    // Allowances still count, even at movements that aren't estimated.
+    assert_eq!(estimates(&ests), vec![(4, NaiveTime::from_hms(13, 18, 0), 0)]);
+}
+
+/// A train passing EUSTON at 12:00 and WATFDJ at 12:15, with TRUST
+/// reporting it at WATFDJ five minutes late.
+fn archived_train(id: i64) -> TrainDetails {
+    let origin = mvt(1, "EUSTON", 1, 12, 00, 0);
+    let pass = mvt(2, "WATFDJ", 2, 12, 15, 0);
+    let late = actual(&pass, 12, 20);
+    TrainDetails {
+        train: Train { id, tspl_id: Uuid::new_v4(), ..train() },
+        mvts: vec![origin, pass, late]
+    }
+}
+fn window(start: (u32, u32), end: (u32, u32)) -> MvtQueryWindow {
+    MvtQueryWindow {
+        date: train().date,
+        day_offset: 0,
+        start_time: NaiveTime::from_hms(start.0, start.1, 0),
+        end_time: NaiveTime::from_hms(end.0, end.1, 0)
+    }
+}
+fn archive_dir() -> PathBuf {
+    let ret = std::env::temp_dir().join(format!("tspl-zugfuhrer-test-{}", Uuid::new_v4()));
+    fs::create_dir_all(&ret).unwrap();
+    ret
+}
+fn write_archive(dir: &Path, trains: &[TrainDetails]) -> usize {
+    let (tmp, kept) = prepare_archive(dir, train().date, trains).unwrap();
+    fs::rename(tmp, archive_file_path(dir, train().date)).unwrap();
+    kept
+}
+fn ids(mvts: &[TrainMvt]) -> Vec<i64> {
+    mvts.iter().map(|x| x.id).collect()
+}
+
+#[test]
+fn archive_passing_through() {
+    let trains = vec![archived_train(1)];
+    let found = find_mvts_passing_through(&trains, "WATFDJ", &window((12, 10), (12, 16)));
+    assert_eq!(found.len(), 1);
+    // The original movement comes first, followed by its updates.
+    assert_eq!(ids(&found[0].1), vec![2, 100]);
+    // Trains also turn up if they've only been updated to pass through.
+    let found = find_mvts_passing_through(&trains, "WATFDJ", &window((12, 18), (12, 30)));
+    assert_eq!(ids(&found[0].1), vec![2, 100]);
+    assert!(find_mvts_passing_through(&trains, "WATFDJ", &window((12, 21), (12, 30))).is_empty());
+    assert!(find_mvts_passing_through(&trains, "EUSTON", &window((12, 10), (12, 30))).is_empty());
+    let mut tomorrow = window((12, 10), (12, 16));
+    tomorrow.date = tomorrow.date.succ();
+    assert!(find_mvts_passing_through(&trains, "WATFDJ", &tomorrow).is_empty());
+    let mut next_day = window((12, 10), (12, 16));
+    next_day.day_offset = 1;
+    assert!(find_mvts_passing_through(&trains, "WATFDJ", &next_day).is_empty());
+}
+#[test]
+fn archive_mvts_at() {
+    let details = archived_train(1);
+    assert_eq!(ids(&find_mvts_at(&details, "WATFDJ")), vec![2, 100]);
+    assert_eq!(ids(&find_mvts_at(&details, "EUSTON")), vec![1]);
+    assert!(find_mvts_at(&details, "MKC").is_empty());
+}
+#[test]
+fn archive_round_trip() {
+    let dir = archive_dir();
+    let date = train().date;
+    assert!(read_archive(&dir, date).is_err());
+    let a = archived_train(1);
+    let b = archived_train(2);
+    assert_eq!(write_archive(&dir, &[a.clone(), b.clone()]), 0);
+    let read = read_archive(&dir, date).unwrap();
+    assert_eq!(read.iter().map(|x| x.train.tspl_id).collect::<Vec<_>>(), vec![a.train.tspl_id, b.train.tspl_id]);
+    assert_eq!(ids(&read[0].mvts), vec![1, 2, 100]);
+    assert_eq!(read[0].mvts[2].updates, Some(2));
+    // Archiving a train again replaces the old copy, and keeps the rest.
+    let mut b2 = b.clone();
+    b2.train.cancelled = true;
+    assert_eq!(write_archive(&dir, &[b2]), 1);
+    let read = read_archive(&dir, date).unwrap();
+    assert_eq!(read.len(), 2);
+    assert!(read.iter().any(|x| x.train.id == 2 && x.train.cancelled));
+    fs::remove_dir_all(dir).unwrap();
+}
+#[test]
+fn archive_cache() {
+    let dir = archive_dir();
+    let date = train().date;
+    let cache = ArchiveCache::new(dir.clone(), 2);
+    assert!(cache.get(date).is_err());
+    write_archive(&dir, &[archived_train(1)]);
+    let first = cache.get(date).unwrap();
+    assert_eq!(first.len(), 1);
+    assert!(std::sync::Arc::ptr_eq(&first, &cache.get(date).unwrap()));
+    // Rewriting the file invalidates the cached copy.
+    std::thread::sleep(std::time::Duration::from_millis(10));
+    write_archive(&dir, &[archived_train(2)]);
+    assert_eq!(cache.get(date).unwrap().len(), 2);
+    fs::remove_dir_all(dir).unwrap();
+}
+
+/// A database with a train that departs EUSTON at 12:00, passes WATFDJ at
+/// 12:15, calls at MKC from 12:45 to 12:47, and arrives at RUGBY at 13:10.
+fn trust_db() -> (Connection, Train) {
+    let conn = tspl_sqlite::initialize_db(":memory:", &MIGRATIONS).unwrap();
+    for (stanox, tiploc) in &[("72410", "EUSTON"), ("72000", "WATFDJ"), ("72500", "MKC"), ("72600", "RUGBY")] {
+        conn.execute("INSERT INTO corpus_entries (stanox, tiploc) VALUES (?, ?)", params![stanox, tiploc]).unwrap();
+    }
+    let mut t = train();
+    t.id = t.insert_self(&conn).unwrap();
+    let mvts = vec![
+        mvt(-1, "EUSTON", 1, 12, 00, 0),
+        mvt(-1, "WATFDJ", 2, 12, 15, 0),
+        mvt(-1, "MKC", 0, 12, 45, 0),
+        mvt(-1, "MKC", 1, 12, 47, 0),
+        mvt(-1, "RUGBY", 0, 13, 10, 0)
+    ];
+    for mut m in mvts {
+        m.parent_train = t.id;
+        m.insert_self(&conn).unwrap();
+    }
+    (conn, t)
+}
+fn loc(stanox: &str, h: u32, m: u32) -> TrustLocation {
+    TrustLocation {
+        stanox: stanox.into(),
+        planned_time: NaiveTime::from_hms(h, m, 0),
+        planned_day_offset: 0
+    }
+}
+/// Which of the train's movements are cancelled, in order.
+fn cancelled(conn: &Connection, train: &Train) -> Vec<bool> {
+    TrainMvt::from_select(conn, "WHERE parent_train = ? AND updates IS NULL ORDER BY day_offset, time, action ASC", params![train.id])
+        .unwrap()
+        .into_iter()
+        .map(|x| x.canx)
+        .collect()
+}
+fn cancel_all(conn: &Connection, train: &mut Train) {
+    for mut m in TrainMvt::from_select(conn, "WHERE parent_train = ?", params![train.id]).unwrap() {
+        App::set_mvt_canx(conn, &mut m, true, Some("XX"), TrainMvt::SOURCE_TRUST).unwrap();
+    }
+    conn.execute("UPDATE trains SET cancelled = true WHERE id = ?", params![train.id]).unwrap();
+    train.cancelled = true;
+}
+
+#[test]
+fn find_departure_at_departures_and_passes() {
+    let (conn, t) = trust_db();
+    let found = App::find_departure_at(&conn, &t, &loc("72000", 12, 15)).unwrap().unwrap();
+    assert_eq!((&found.tiploc as &str, found.action), ("WATFDJ", 2));
+    let found = App::find_departure_at(&conn, &t, &loc("72500", 12, 47)).unwrap().unwrap();
+    assert_eq!((&found.tiploc as &str, found.action), ("MKC", 1));
+    // Arrivals don't count.
+    assert!(App::find_departure_at(&conn, &t, &loc("72500", 12, 45)).unwrap().is_none());
+    assert!(App::find_departure_at(&conn, &t, &loc("72600", 13, 10)).unwrap().is_none());
+    // Neither do the wrong day, or updates.
+    let mut tomorrow = loc("72000", 12, 15);
+    tomorrow.planned_day_offset = 1;
+    assert!(App::find_departure_at(&conn, &t, &tomorrow).unwrap().is_none());
+    let mut update = actual(&found, 12, 15);
+    update.id = -1;
+    update.insert_self(&conn).unwrap();
+    assert!(App::find_departure_at(&conn, &t, &loc("72500", 12, 47)).unwrap().is_some());
+}
+#[test]
+fn find_departure_at_ambiguous() {
+    let (conn, t) = trust_db();
+    // Two TIPLOCs with the same STANOX, both passed at the same time.
+    conn.execute("INSERT INTO corpus_entries (stanox, tiploc) VALUES ('72000', 'WATFDJN')", NO_PARAMS).unwrap();
+    let mut m = mvt(-1, "WATFDJN", 2, 12, 15, 0);
+    m.parent_train = t.id;
+    m.insert_self(&conn).unwrap();
+    match App::find_departure_at(&conn, &t, &loc("72000", 12, 15)) {
+        Err(ZugError::MovementsAmbiguous) => {},
+        x => panic!("expected ambiguity, got {:?}", x)
+    }
+}
+#[test]
+fn set_mvt_canx_reports_changes() {
+    let (conn, t) = trust_db();
+    let mut m = TrainMvt::from_select(&conn, "WHERE parent_train = ?", params![t.id]).unwrap().remove(0);
+    assert!(!App::set_mvt_canx(&conn, &mut m, false, None, TrainMvt::SOURCE_TRUST).unwrap());
+    assert!(App::set_mvt_canx(&conn, &mut m, true, Some("XX"), TrainMvt::SOURCE_TRUST).unwrap());
+    assert!(!App::set_mvt_canx(&conn, &mut m, true, Some("XX"), TrainMvt::SOURCE_TRUST).unwrap());
+    // A different reason or source is still a change.
+    assert!(App::set_mvt_canx(&conn, &mut m, true, Some("YY"), TrainMvt::SOURCE_TRUST).unwrap());
+    assert!(App::set_mvt_canx(&conn, &mut m, true, Some("YY"), TrainMvt::SOURCE_DARWIN).unwrap());
+    // Un-cancelling forgets why it was cancelled.
+    assert!(App::set_mvt_canx(&conn, &mut m, false, Some("YY"), TrainMvt::SOURCE_DARWIN).unwrap());
+    assert_eq!((m.canx, m.canx_reason.clone(), m.canx_source), (false, None, None));
+    let stored = TrainMvt::from_select(&conn, "WHERE id = ?", params![m.id]).unwrap().remove(0);
+    assert_eq!((stored.canx, stored.canx_reason, stored.canx_source), (false, None, None));
+}
+#[test]
+fn change_of_origin() {
+    let (conn, mut t) = trust_db();
+    let changed = App::apply_change_of_origin(&conn, &mut t, &loc("72500", 12, 47), Some("AB")).unwrap();
+    // Everything before the departure from MKC, including the arrival there.
+    assert_eq!(changed.len(), 3);
+    assert!(changed.iter().all(|x| x.canx_reason.as_ref().map(|x| x as &str) == Some("AB")));
+    assert_eq!(cancelled(&conn, &t), vec![true, true, true, false, false]);
+    assert!(!t.cancelled);
+}
+#[test]
+fn change_of_origin_uncancels_new_origin() {
+    let (conn, mut t) = trust_db();
+    cancel_all(&conn, &mut t);
+    App::apply_change_of_origin(&conn, &mut t, &loc("72000", 12, 15), None).unwrap();
+    // The rest of the train is still cancelled (e.g. by a later change of
+    // origin, or a cancellation from Darwin), but the train isn't.
+    assert_eq!(cancelled(&conn, &t), vec![true, false, true, true, true]);
+    assert!(!t.cancelled);
+    let stored = Train::from_select(&conn, "WHERE id = ?", params![t.id]).unwrap().remove(0);
+    assert!(!stored.cancelled);
+    match App::apply_change_of_origin(&conn, &mut t, &loc("99999", 12, 15), None) {
+        Err(ZugError::MovementsNotFound) => {},
+        x => panic!("expected no movements, got {:?}", x)
+    }
+}
+#[test]
+fn reinstatement_from_pivot() {
+    let (conn, mut t) = trust_db();
+    cancel_all(&conn, &mut t);
+    let changed = App::apply_reinstatement(&conn, &mut t, &loc("72500", 12, 47)).unwrap();
+    assert_eq!(changed.len(), 2);
+    assert_eq!(cancelled(&conn, &t), vec![true, true, true, false, false]);
+    assert!(!t.cancelled);
+}
+#[test]
+fn reinstatement_without_pivot() {
+    let (conn, mut t) = trust_db();
+    cancel_all(&conn, &mut t);
+    // Somewhere the train doesn't go: reinstate all of it.
+    App::apply_reinstatement(&conn, &mut t, &loc("99999", 12, 00)).unwrap();
+    assert_eq!(cancelled(&conn, &t), vec![false; 5]);
+}
+#[test]
+fn change_of_identity() {
+    let (conn, t) = trust_db();
+    let changed = App::apply_change_of_identity(&conn, t.tspl_id, "721Z2345").unwrap();
+    assert_eq!(changed.trust_id.as_ref().map(|x| x as &str), Some("721Z2345"));
+    assert_eq!(changed.headcode.as_ref().map(|x| x as &str), Some("1Z23"));
+    match App::apply_change_of_identity(&conn, t.tspl_id, "721Z2") {
+        Err(ZugError::InvalidBody(_)) => {},
+        x => panic!("expected invalid body, got {:?}", x)
+    }
+    match App::apply_change_of_identity(&conn, Uuid::new_v4(), "721Z2345") {
+        Err(ZugError::NotFound) => {},
+        x => panic!("expected not found, got {:?}", x)
+    }
+}
+
+/// A call for passengers, in the static GTFS feed's terms.
+fn public_mvt(id: i64, tiploc: &str, action: u8, h: u32, m: u32) -> TrainMvt {
+    let mut ret = mvt(id, tiploc, action, h, m, 0);
+    ret.public_time = Some(ret.time);
+    ret
+}
+fn gtfs_stops() -> HashSet<String> {
+    vec!["EUSTON", "MKC", "RUGBY"].into_iter().map(|x| x.into()).collect()
+}
+/// Euston to Rugby, passing Watford and calling at a station without coordinates.
+fn gtfs_schedule() -> Vec<TrainMvt> {
+    vec![
+        public_mvt(1, "EUSTON", 1, 12, 00),
+        mvt(2, "WATFDJ", 2, 12, 15, 0),
+        public_mvt(3, "NOCOORD", 0, 12, 30),
+        public_mvt(4, "NOCOORD", 1, 12, 31),
+        public_mvt(5, "MKC", 0, 12, 45),
+        public_mvt(6, "MKC", 1, 12, 47),
+        public_mvt(7, "RUGBY", 0, 13, 10)
+    ]
+}
+
+#[test]
+fn pb_varints() {
+    let mut w = PbWriter::default();
+    w.varint(1);
+    w.varint(300);
+    assert_eq!(w.buf, vec![0x01, 0xac, 0x02]);
+    // Negative int64s are sign-extended, so always take up ten bytes.
+    let mut w = PbWriter::default();
+    w.int64(1, -1);
+    assert_eq!(w.buf, vec![0x08, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x01]);
+}
+#[test]
+fn pb_stop_time_updates() {
+    let stu = StopTimeUpdate {
+        stop_sequence: 3,
+        stop_id: "MKC".into(),
+        arrival: Some(StopTimeEvent { delay: 60, time: 1000 }),
+        departure: None,
+        skipped: false
+    };
+    assert_eq!(stu.encode().buf, vec![
+        0x08, 0x03,
+        0x12, 0x05, 0x08, 0x3c, 0x10, 0xe8, 0x07,
+        0x22, 0x03, b'M', b'K', b'C'
+    ]);
+    let stu = StopTimeUpdate {
+        stop_sequence: 2,
+        stop_id: "X".into(),
+        arrival: None,
+        departure: None,
+        skipped: true
+    };
+    assert_eq!(stu.encode().buf, vec![0x08, 0x02, 0x22, 0x01, b'X', 0x28, 0x01]);
+}
+#[test]
+fn gtfs_call_cancelled() {
+    let mut arr = public_mvt(1, "MKC", 0, 12, 45);
+    let mut dep = public_mvt(2, "MKC", 1, 12, 47);
+    assert!(!call_cancelled(&[&arr, &dep], 0));
+    // Cut short at Milton Keynes: it still arrives there.
+    dep.canx = true;
+    assert!(!call_cancelled(&[&arr, &dep], 1));
+    arr.canx = true;
+    assert!(call_cancelled(&[&arr, &dep], 0));
+    assert!(call_cancelled(&[&arr, &dep], 1));
+    // An arrival somewhere else doesn't count.
+    let rugby = public_mvt(3, "RUGBY", 0, 13, 10);
+    assert!(call_cancelled(&[&dep, &rugby], 0));
+}
+#[test]
+fn gtfs_stop_sequences() {
+    let mvts = gtfs_schedule();
+    let live = mvts.iter().collect::<Vec<_>>();
+    assert_eq!(stop_sequences(&live, &gtfs_stops()), vec![
+        Some(1), None, None, None, Some(2), Some(2), Some(3)
+    ]);
+}
+#[test]
+fn gtfs_trip_updates() {
+    let mut mvts = gtfs_schedule();
+    mvts[6].canx = true;
+    let late = actual(&mvts[4], 12, 47);
+    let mut nocoord = actual(&mvts[2], 12, 31);
+    nocoord.id = 101;
+    mvts.push(late);
+    mvts.push(nocoord);
+    assert!(trip_update_for_train(&train(), &mvts[0..7], &HashSet::new()).is_none());
+    let ent = trip_update_for_train(&train(), &mvts, &gtfs_stops()).unwrap();
+    assert!(!ent.cancelled);
+    let updates = ent.updates.iter()
+        .map(|x| (x.stop_sequence, x.stop_id.as_str(), x.arrival.as_ref().map(|x| x.delay), x.skipped))
+        .collect::<Vec<_>>();
+    assert_eq!(updates, vec![(2, "MKC", Some(120), false), (3, "RUGBY", None, true)]);
+}
@@ -10,13 +10,17 @@ use chrono::*;
 
 pub use ntrod_types::reference::CorpusEntry;
 
-pub static MIGRATIONS: [Migration; 6] = [
+pub static MIGRATIONS: [Migration; 10] = [
     migration!(0, "initial"),
     migration!(1, "indexes"),
     migration!(2, "tmvt_unique_fix"),
     migration!(3, "delete_idx"),
     migration!(4, "mvt_query_idx"),
-    migration!(5, "trust_mvt_idx")
+    migration!(5, "trust_mvt_idx"),
+    migration!(6, "mvt_cancellations"),
+    migration!(7, "mvt_allowances"),
+    migration!(8, "mvt_canx_source"),
+    migration!(9, "archived_trains")
 ];
 
 /// A live train object, representing a live or historic running of a train.
@@ -208,6 +212,26 @@ pub struct TrainMvt {
     /// estimated in this movement is just a best guess, and the delay cannot be
     /// accurately predicted).
     pub unknown_delay: bool,
+    /// Whether this movement has been cancelled (i.e. the train isn't going
+    /// to call at, or pass, this location any more).
+    ///
+    /// Only movements in the train's live schedule (without an `updates`
+    /// value) get cancelled.
+    pub canx: bool,
+    /// TRUST reason code for the cancellation, if there was one.
+    pub canx_reason: Option<String>,
+    /// Allowance time before this movement in the schedule, in seconds
+    /// (see `ScheduleMvt::allowance` in tspl-fahrplan).
+    ///
+    /// Only set on movements from schedules.
+    pub allowance: u32,
+    /// Where the cancellation came from (`SOURCE_TRUST` or `SOURCE_DARWIN`),
+    /// if this movement is cancelled.
+    ///
+    /// Darwin only gets to un-cancel movements it cancelled itself, so it
+    /// can't undo cancellations from TRUST.
+    #[serde(default)]
+    pub canx_source: Option<i32>,
     // NOTE: update `FIELDS` constant below when adding new fields.
 }
 impl TrainMvt {
@@ -217,7 +241,7 @@ impl TrainMvt {
     pub const SOURCE_TRUST: i32 = 3;
     pub const SOURCE_DARWIN: i32 = 4;
     pub const SOURCE_TRUST_NAIVE: i32 = 5;
-    pub const FIELDS: usize = 13;
+    pub const FIELDS: usize = 17;
     /// Generate a `TrainMvt` from an ITPS `ScheduleMvt`.
     pub fn from_itps(sched: fpt::ScheduleMvt) -> Self {
         Self {
@@ -233,7 +257,18 @@ impl TrainMvt {
             platform: sched.platform,
             public_time: sched.public_time,
             pfm_suppr: false,
-            unknown_delay: false
+            unknown_delay: false,
+            canx: false,
+            canx_reason: None,
+            allowance: sched.allowance,
+            canx_source: None
+        }
+    }
+    /// Generate a `TrainMvt` from a `ScheduleMvt` belonging to a Darwin schedule.
+    pub fn from_darwin(sched: fpt::ScheduleMvt) -> Self {
+        Self {
+            source: Self::SOURCE_SCHED_DARWIN,
+            ..Self::from_itps(sched)
         }
     }
 }
@@ -255,7 +290,11 @@ impl DbType for TrainMvt {
             source: row.get(s + 9)?,
             platform: row.get(s + 10)?,
             pfm_suppr: row.get(s + 11)?,
-            unknown_delay: row.get(s + 12)?
+            unknown_delay: row.get(s + 12)?,
+            canx: row.get(s + 13)?,
+            canx_reason: row.get(s + 14)?,
+            allowance: row.get(s + 15)?,
+            canx_source: row.get(s + 16)?
         })
     }
 }
@@ -266,13 +305,16 @@ impl InsertableDbType for TrainMvt {
                                      (parent_train, updates, tiploc, action,
                                       actual, time, public_time,
                                       day_offset, source, platform,
-                                      pfm_suppr, unknown_delay)
-                                     VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")?;
+                                      pfm_suppr, unknown_delay, canx, canx_reason,
+                                      allowance, canx_source)
+                                     VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)")?;
         let rid = stmt.insert(params![self.parent_train, self.updates,
                               self.tiploc, self.action, self.actual, self.time,
                               self.public_time, self.day_offset,
                               self.source, self.platform,
-                              self.pfm_suppr, self.unknown_delay])?;
+                              self.pfm_suppr, self.unknown_delay,
+                              self.canx, self.canx_reason, self.allowance,
+                              self.canx_source])?;
         Ok(rid)
     }
 }
@@ -319,6 +361,58 @@ pub struct TrustMvtUpdate {
     pub platform: Option<String>
 }
 
+/// A location in a train's schedule, as reported by TRUST.
+///
+/// Used to describe where changes of origin, changes of location and
+/// reinstatements happen.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TrustLocation {
+    /// STANOX of the location.
+    pub stanox: String,
+    /// Scheduled (WTT) departure or passing time.
+    pub planned_time: NaiveTime,
+    /// Scheduled day offset.
+    pub planned_day_offset: u8
+}
+
+/// Whether one movement in a Darwin schedule is cancelled or not.
+///
+/// A Darwin schedule says this about every one of its locations, so these
+/// get sent in bulk (as the body of a request) instead of one at a time.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DarwinCancellation {
+    /// TIPLOC where the movement was scheduled to occur.
+    pub tiploc: String,
+    /// Scheduled time.
+    pub planned_time: NaiveTime,
+    /// Scheduled day offset.
+    pub planned_day_offset: u8,
+    /// Scheduled action.
+    pub planned_action: u8,
+    /// Whether the movement is cancelled.
+    pub canx: bool
+}
+
+/// An update sent out over the live TCP stream.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum BroadcastUpdate {
+    /// A train was activated.
+    Activation(Train),
+    /// Something about a train changed (or a client just connected, and
+    /// is being told about the trains running today).
+    SyncTrain(Train),
+    /// A train movement was inserted or changed.
+    Movement(TrainMvt),
+}
+
+/// One line of the live TCP stream, serialized as JSON.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct BroadcastPacket {
+    /// When the update was sent.
+    pub ts: NaiveDateTime,
+    pub update: BroadcastUpdate
+}
+
 /// Wrapper for the CorpusEntry type, in order to not violate
 /// the orphan rules.
 pub struct WrappedCorpusEntry(pub CorpusEntry);
@@ -379,7 +473,7 @@ pub struct ConnectingMvtQueryResponse {
     pub trains: HashMap<i64, Train>,
 }
 /// The complete details about a train stored in the database.
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TrainDetails {
     /// Actual train object.
     pub train: Train,